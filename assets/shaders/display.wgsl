@@ -0,0 +1,142 @@
+#import bevy_sprite::mesh2d_vertex_output::VertexOutput
+
+struct DisplayParams {
+    // 0 is no symmetry, 1 mirrors X, 2 mirrors Y, 3 mirrors both and 4 is radial.
+    mode: u32,
+    folds: u32,
+    aspect: f32,
+    // 0 displays the channels as RGB, 1 maps the luminance through the palette and
+    // 2 to 5 map the channel of index 2 less.
+    palette_source: u32,
+    // 0 displays the channels as they are, 1 their maximum and 2 their sum.
+    blend: u32,
+    // The indices of the channels displayed as red, green and blue. The hidden
+    // channels follow the alpha channel.
+    red_source: u32,
+    green_source: u32,
+    blue_source: u32,
+    // The size of a cell on screen in pixels from which on the grid is drawn, 0 if
+    // the grid is hidden.
+    grid_cell_size: f32,
+}
+
+@group(2) @binding(0)
+var<uniform> params: DisplayParams;
+@group(2) @binding(1)
+var texture: texture_2d<f32>;
+@group(2) @binding(2)
+var texture_sampler: sampler;
+@group(2) @binding(3)
+var palette: texture_2d<f32>;
+@group(2) @binding(4)
+var palette_sampler: sampler;
+@group(2) @binding(5)
+var hidden: texture_2d<f32>;
+
+const TAU: f32 = 6.28318530717958647692;
+
+@fragment
+fn fragment(mesh: VertexOutput) -> @location(0) vec4<f32> {
+    let uv = source_uv(mesh.uv);
+    let cell = textureSample(texture, texture_sampler, uv);
+    // The derivatives have to be taken before branching on the parameters.
+    let position = uv * vec2<f32>(textureDimensions(texture));
+    let cells_per_pixel = fwidth(position);
+    let rgb = vec3<f32>(
+        channel_value(cell, uv, params.red_source),
+        channel_value(cell, uv, params.green_source),
+        channel_value(cell, uv, params.blue_source),
+    );
+    var color = vec4<f32>(blend_channels(rgb), cell.a);
+    switch params.palette_source {
+        case 0u: {}
+        case 1u: {
+            color = apply_palette(dot(color.rgb, vec3<f32>(0.2126, 0.7152, 0.0722)));
+        }
+        // Single channels are taken from the cell as it is.
+        default: {
+            color = apply_palette(cell[params.palette_source - 2u]);
+        }
+    }
+    return draw_grid(color, position, cells_per_pixel);
+}
+
+// Draws lines of one pixel at the boundaries of the cells, once a cell covers at
+// least the grid cell size on screen. The lines contrast with the cell below.
+fn draw_grid(color: vec4<f32>, position: vec2<f32>, cells_per_pixel: vec2<f32>) -> vec4<f32> {
+    let pixels_per_cell = 1.0 / max(max(cells_per_pixel.x, cells_per_pixel.y), 1e-6);
+    if params.grid_cell_size <= 0.0 || pixels_per_cell < params.grid_cell_size {
+        return color;
+    }
+    let edge = fract(position);
+    if all(min(edge, 1.0 - edge) >= cells_per_pixel * 0.5) {
+        return color;
+    }
+    let luminance = dot(color.rgb, vec3<f32>(0.2126, 0.7152, 0.0722));
+    let line = select(vec3<f32>(1.0), vec3<f32>(0.0), luminance > 0.5);
+    return vec4<f32>(mix(color.rgb, line, 0.5), max(color.a, 0.5));
+}
+
+// Returns the channel of the given index, the hidden channels following the alpha
+// channel of the cell. The hidden channels are stored four per tile, the tiles below
+// each other, so they are loaded from the tile of the channel instead of sampled.
+fn channel_value(cell: vec4<f32>, uv: vec2<f32>, index: u32) -> f32 {
+    if index < 4u {
+        return cell[index];
+    }
+    let dims = textureDimensions(texture);
+    let texel = min(vec2<u32>(uv * vec2<f32>(dims)), dims - 1u);
+    let channel = index - 4u;
+    let loc = texel + vec2<u32>(0u, channel / 4u * dims.y);
+    return textureLoad(hidden, min(loc, textureDimensions(hidden) - 1u), 0)[channel % 4u];
+}
+
+// Combines the color channels of a cell according to the blend.
+fn blend_channels(rgb: vec3<f32>) -> vec3<f32> {
+    switch params.blend {
+        case 1u: {
+            return vec3<f32>(max(rgb.r, max(rgb.g, rgb.b)));
+        }
+        case 2u: {
+            return vec3<f32>(min(rgb.r + rgb.g + rgb.b, 1.0));
+        }
+        default: {
+            return rgb;
+        }
+    }
+}
+
+// Maps the scalar from 0 to 1 through the palette.
+fn apply_palette(value: f32) -> vec4<f32> {
+    let u = clamp(value, 0.0, 1.0) * (255.0 / 256.0) + 0.5 / 256.0;
+    return textureSample(palette, palette_sampler, vec2<f32>(u, 0.5));
+}
+
+// Returns the texture coordinates displayed at the given ones. Matches
+// DisplaySymmetry::source_uv, which maps the input the same way.
+fn source_uv(uv: vec2<f32>) -> vec2<f32> {
+    var source = uv;
+    switch params.mode {
+        case 1u: {
+            source.x = min(uv.x, 1.0 - uv.x);
+        }
+        case 2u: {
+            source.y = min(uv.y, 1.0 - uv.y);
+        }
+        case 3u: {
+            source = min(uv, 1.0 - uv);
+        }
+        case 4u: {
+            let scale = vec2<f32>(params.aspect, 1.0);
+            let p = (uv - 0.5) * scale;
+            let wedge = TAU / f32(max(params.folds, 1u));
+            var angle = atan2(p.y, p.x);
+            angle = angle - wedge * floor(angle / wedge);
+            angle = min(angle, wedge - angle);
+            let folded = length(p) * vec2<f32>(cos(angle), sin(angle));
+            source = clamp(folded / scale + 0.5, vec2<f32>(0.0), vec2<f32>(1.0));
+        }
+        default: {}
+    }
+    return source;
+}
@@ -3,40 +3,114 @@ struct PushConstants {
     draw_end: vec2<f32>,
     brush_size: f32,
     brush_type: u32,
-    brush_color: array<f32, 3>,
+    // Three scalars rather than an array, as arrays in uniform buffers need a
+    // stride of 16 bytes.
+    brush_red: f32,
+    brush_green: f32,
+    brush_blue: f32,
+    brush_opacity: f32,
+    brush_hardness: f32,
+    image_scale: f32,
+    // The written color channels, one bit per channel starting with red.
+    channel_mask: u32,
+    // Counts the frames, so that the noise brush changes every frame.
+    frame: u32,
 }
+
+// Without push constants or read-write storage textures, the parameters are
+// read from a uniform buffer. The canvas is read from the current state and every
+// pixel is written to a scratch texture, which is copied back.
+#ifdef DRAW_FALLBACK
+@group(0) @binding(2)
+var<uniform> pc: PushConstants;
+
+#ifdef TEXTURE_FORMAT_RGBA16FLOAT
+@group(0) @binding(0)
+var texture_in: texture_storage_2d<rgba16float, read>;
+@group(0) @binding(1)
+var texture: texture_storage_2d<rgba16float, write>;
+#else
+@group(0) @binding(0)
+var texture_in: texture_storage_2d<rgba8unorm, read>;
+@group(0) @binding(1)
+var texture: texture_storage_2d<rgba8unorm, write>;
+#endif
+#else
 var<push_constant> pc: PushConstants;
 
+#ifdef TEXTURE_FORMAT_RGBA16FLOAT
+@group(0) @binding(0)
+var texture: texture_storage_2d<rgba16float, read_write>;
+#else
 @group(0) @binding(0)
 var texture: texture_storage_2d<rgba8unorm, read_write>;
+#endif
+#endif
 
-@compute @workgroup_size(8, 8, 1)
+// The image stamped by the image brush.
+@group(0) @binding(3)
+var brush_image: texture_2d<f32>;
+
+// The opaque color of the brush.
+fn brush_color() -> vec4<f32> {
+    return vec4<f32>(pc.brush_red, pc.brush_green, pc.brush_blue, 1.);
+}
+
+// Reads the canvas before drawing.
+fn load(loc: vec2<i32>) -> vec4<f32> {
+#ifdef DRAW_FALLBACK
+    return textureLoad(texture_in, loc);
+#else
+    return textureLoad(texture, loc);
+#endif
+}
+
+@compute @workgroup_size(#{WORKGROUP_SIZE_X}, #{WORKGROUP_SIZE_Y}, 1)
 fn draw(@builtin(global_invocation_id) invocation_id: vec3<u32>)
 {
     let pixel = vec2<u32>(invocation_id.xy);
     let dims = vec2<u32>(textureDimensions(texture));
-    if (pixel.x >= dims.x && pixel.y >= dims.y) {
+    if (pixel.x >= dims.x || pixel.y >= dims.y) {
         return ;
     }
 
+#ifdef DRAW_FALLBACK
+    // Carry the pixel over, the brush overwrites it below.
+    textureStore(texture, vec2<i32>(pixel), load(vec2<i32>(pixel)));
+#endif
+
     if (pc.brush_size > 0.0) {
         let pos = vec2<f32>(pixel);
         let point_on_line = closest_point_on_line(pc.draw_start, pc.draw_end, pos);
         switch pc.brush_type {
             case 0u: {
-                draw_particle_circle(
-                    pos,
-                    point_on_line,
-                    pc.brush_size,
-                    vec4<f32>(pc.brush_color[0], pc.brush_color[1], pc.brush_color[2], 1.)
-                );
+                draw_particle_circle(pos, point_on_line, pc.brush_size, brush_color());
             }
             case 1u: {
-                draw_particle_square(
+                draw_particle_square(pos, point_on_line, pc.brush_size, brush_color());
+            }
+            case 2u: {
+                draw_particle_ring(pos, point_on_line, pc.brush_size, brush_color());
+            }
+            case 3u: {
+                draw_particle_triangle(pos, point_on_line, pc.brush_size, brush_color());
+            }
+            case 4u: {
+                draw_particle_line(pos, point_on_line, pc.brush_size, brush_color());
+            }
+            case 5u: {
+                draw_particle_image(pos, point_on_line, pc.image_scale);
+            }
+            case 6u: {
+                draw_particle_noise(pos, point_on_line, pc.brush_size);
+            }
+            case 7u, 8u, 9u: {
+                draw_particle_pattern(
                     pos,
                     point_on_line,
                     pc.brush_size,
-                    vec4<f32>(pc.brush_color[0], pc.brush_color[1], pc.brush_color[2], 1.)
+                    brush_color(),
+                    pc.brush_type,
                 );
             }
             default: {}
@@ -54,7 +128,7 @@ fn draw_particle_circle(pos: vec2<f32>, draw_pos: vec2<f32>, radius: f32, color:
         let diff = pos - draw_pos;
         let dist = length(diff);
         if (round(dist) <= radius) {
-            textureStore(texture, vec2<i32>(pos), color);
+            blend(vec2<i32>(pos), color, falloff(dist, radius));
         }
     }
 }
@@ -66,10 +140,164 @@ fn draw_particle_square(pos: vec2<f32>, draw_pos: vec2<f32>, radius: f32, color:
     let x_end = draw_pos.x + radius;
     if (pos.x >= x_start && pos.x <= x_end && pos.y >= y_start && pos.y <= y_end) {
         let diff = pos - draw_pos;
-        textureStore(texture, vec2<i32>(pos), color);
+        blend(vec2<i32>(pos), color, 1.0);
     }
 }
 
+// The inner radius of the ring brush relative to its outer radius.
+const RING_INNER_RATIO: f32 = 0.5;
+
+fn draw_particle_ring(pos: vec2<f32>, draw_pos: vec2<f32>, radius: f32, color: vec4<f32>) {
+    let y_start = draw_pos.y - radius;
+    let y_end = draw_pos.y + radius;
+    let x_start = draw_pos.x - radius;
+    let x_end = draw_pos.x + radius;
+    if (pos.x >= x_start && pos.x <= x_end && pos.y >= y_start && pos.y <= y_end) {
+        let diff = pos - draw_pos;
+        let dist = round(length(diff));
+        if (dist <= radius && dist >= radius * RING_INNER_RATIO) {
+            blend(vec2<i32>(pos), color, 1.0);
+        }
+    }
+}
+
+// An equilateral triangle pointing up with the radius as its circumradius.
+fn draw_particle_triangle(pos: vec2<f32>, draw_pos: vec2<f32>, radius: f32, color: vec4<f32>) {
+    let y_start = draw_pos.y - radius;
+    let y_end = draw_pos.y + radius;
+    let x_start = draw_pos.x - radius;
+    let x_end = draw_pos.x + radius;
+    if (pos.x >= x_start && pos.x <= x_end && pos.y >= y_start && pos.y <= y_end) {
+        // The y-axis of the canvas points down.
+        let diff = (pos - draw_pos) * vec2<f32>(1., -1.);
+        // The center is half the circumradius away from each edge.
+        let inradius = radius / 2.;
+        if (dot(diff, vec2<f32>(0., -1.)) <= inradius
+            && dot(diff, vec2<f32>(0.8660254, 0.5)) <= inradius
+            && dot(diff, vec2<f32>(-0.8660254, 0.5)) <= inradius)
+        {
+            blend(vec2<i32>(pos), color, 1.0);
+        }
+    }
+}
+
+// A horizontal line one pixel thick, like the nib of a pen.
+fn draw_particle_line(pos: vec2<f32>, draw_pos: vec2<f32>, radius: f32, color: vec4<f32>) {
+    let diff = abs(pos - draw_pos);
+    if (diff.x <= radius && diff.y <= 0.5) {
+        blend(vec2<i32>(pos), color, 1.0);
+    }
+}
+
+// The brush image centered on the draw position and scaled by the given factor.
+// Its colors are blended by their alpha.
+fn draw_particle_image(pos: vec2<f32>, draw_pos: vec2<f32>, scale: f32) {
+    let dims = vec2<f32>(textureDimensions(brush_image));
+    let texel = floor((pos - draw_pos) / max(scale, 0.01) + dims / 2.);
+    if (all(texel >= vec2<f32>(0.)) && all(texel < dims)) {
+        let color = textureLoad(brush_image, vec2<i32>(texel), 0);
+        blend(vec2<i32>(pos), vec4<f32>(color.rgb, 1.), color.a);
+    }
+}
+
+// A circle of random colors, which change every frame.
+fn draw_particle_noise(pos: vec2<f32>, draw_pos: vec2<f32>, radius: f32) {
+    let dist = length(pos - draw_pos);
+    if (round(dist) <= radius) {
+        let cell = vec2<u32>(pos);
+        let seed = hash(cell.x ^ hash(cell.y ^ hash(pc.frame)));
+        let color = vec4<f32>(
+            random_float(seed),
+            random_float(seed + 1u),
+            random_float(seed + 2u),
+            1.,
+        );
+        blend(vec2<i32>(pos), color, falloff(dist, radius));
+    }
+}
+
+// A seed pattern in the brush color, blended by its intensity at the distance from
+// the draw position. 7 is a bright dot with a soft ring, 8 a cross fading out
+// towards the ends of its arms and 9 a blob fading out from its center.
+fn draw_particle_pattern(
+    pos: vec2<f32>,
+    draw_pos: vec2<f32>,
+    radius: f32,
+    color: vec4<f32>,
+    pattern: u32,
+) {
+    let diff = (pos - draw_pos) / max(radius, 1.);
+    let dist = length(diff);
+    var intensity = 0.;
+    switch pattern {
+        case 7u: {
+            let center = 1. - smoothstep(0.15, 0.3, dist);
+            let ring_dist = (dist - 0.7) / 0.12;
+            let ring = 0.6 * exp(-ring_dist * ring_dist);
+            intensity = max(center, ring);
+        }
+        case 8u: {
+            // The arms are at least one pixel wide.
+            let width = max(0.2, 0.5 / max(radius, 1.));
+            let arm = max(abs(diff.x), abs(diff.y));
+            if (min(abs(diff.x), abs(diff.y)) <= width && arm <= 1.) {
+                intensity = 1. - 0.5 * arm;
+            }
+        }
+        default: {
+            let falloff = max(1. - dist * dist, 0.);
+            intensity = falloff * falloff;
+        }
+    }
+    if (dist <= 1. && intensity > 0.) {
+        blend(vec2<i32>(pos), color, intensity);
+    }
+}
+
+// Hashes a value into a seemingly random one, same as in the NCA shader.
+fn hash(value: u32) -> u32 {
+    var state = value;
+    state = state ^ 2747636419u;
+    state = state * 2654435769u;
+    state = state ^ state >> 16u;
+    state = state * 2654435769u;
+    state = state ^ state >> 16u;
+    state = state * 2654435769u;
+    return state;
+}
+
+// Returns a random number from 0 to 1 for the given seed.
+fn random_float(seed: u32) -> f32 {
+    return f32(hash(seed)) / 4294967295.0;
+}
+
+// Fades the brush from its center towards its radius according to its hardness.
+// A hardness of 1 keeps the hard edge.
+fn falloff(dist: f32, radius: f32) -> f32 {
+    if (pc.brush_hardness >= 1.0) {
+        return 1.0;
+    }
+    return 1.0 - smoothstep(radius * pc.brush_hardness, radius, dist);
+}
+
+// Blends the color into the texture according to the opacity of the brush,
+// scaled by the given strength. Color channels masked out keep their values.
+fn blend(loc: vec2<i32>, color: vec4<f32>, strength: f32) {
+    let existing = load(loc);
+    let mask = vec4<bool>(
+        (pc.channel_mask & 1u) != 0u,
+        (pc.channel_mask & 2u) != 0u,
+        (pc.channel_mask & 4u) != 0u,
+        true,
+    );
+    let blended = mix(existing, color, pc.brush_opacity * strength);
+    textureStore(texture, loc, select(existing, blended, mask));
+}
+
+// Returns the point on the segment from v to w closest to p. The brushes are
+// centered on it, so that a stroke covers every pixel within the brush radius of
+// the segment between two frames, e.g. a capsule for the circle brush, instead of
+// only the shapes at its ends. Tested on the GPU in src/nca/pipeline/draw.rs.
 fn closest_point_on_line(v: vec2<f32>, w: vec2<f32>, p: vec2<f32>) -> vec2<f32> {
     let c = v - w;
 
@@ -0,0 +1,84 @@
+struct EffectParams {
+    effect: u32,
+    brightness: f32,
+    contrast: f32,
+    _padding: f32,
+}
+
+// The effect reads the current state and writes the other texture, which is copied
+// back onto the current state afterwards.
+#ifdef TEXTURE_FORMAT_RGBA16FLOAT
+@group(0) @binding(0)
+var texture_in: texture_storage_2d<rgba16float, read>;
+@group(0) @binding(1)
+var texture_out: texture_storage_2d<rgba16float, write>;
+#else
+@group(0) @binding(0)
+var texture_in: texture_storage_2d<rgba8unorm, read>;
+@group(0) @binding(1)
+var texture_out: texture_storage_2d<rgba8unorm, write>;
+#endif
+
+@group(0) @binding(2)
+var<uniform> params: EffectParams;
+
+// The hidden channels are read from the texture belonging to the current state and
+// written to the one belonging to the other texture, like the colors.
+@group(0) @binding(3)
+var hidden_in: texture_storage_2d<rgba16float, read>;
+@group(0) @binding(4)
+var hidden_out: texture_storage_2d<rgba16float, write>;
+
+@compute @workgroup_size(#{WORKGROUP_SIZE_X}, #{WORKGROUP_SIZE_Y}, 1)
+fn apply(@builtin(global_invocation_id) invocation_id: vec3<u32>)
+{
+    let pixel = vec2<i32>(invocation_id.xy);
+    let dims = vec2<i32>(textureDimensions(texture_in));
+    if (pixel.x >= dims.x || pixel.y >= dims.y) {
+        return ;
+    }
+
+    var source = pixel;
+    if (params.effect == 4u) {
+        source.x = dims.x - 1 - pixel.x;
+    } else if (params.effect == 5u) {
+        source.y = dims.y - 1 - pixel.y;
+    }
+    let color = textureLoad(texture_in, source);
+
+    // The hidden channels move along with the cells, tile by tile. Without hidden
+    // channels, their texture is a placeholder of another width.
+    let hidden_dims = vec2<i32>(textureDimensions(hidden_in));
+    if (hidden_dims.x == dims.x) {
+        for (var tile = 0; tile < hidden_dims.y / dims.y; tile++) {
+            let offset = vec2<i32>(0, tile * dims.y);
+            textureStore(hidden_out, pixel + offset, textureLoad(hidden_in, source + offset));
+        }
+    }
+
+    var rgb = color.rgb;
+    switch params.effect {
+        // Invert
+        case 0u: {
+            rgb = vec3<f32>(1.0) - rgb;
+        }
+        // Grayscale, weighted by the perceived luminance of the channels.
+        case 1u: {
+            rgb = vec3<f32>(dot(rgb, vec3<f32>(0.2126, 0.7152, 0.0722)));
+        }
+        // Swap channels, red takes green, green takes blue and blue takes red.
+        case 2u: {
+            rgb = rgb.gbr;
+        }
+        // Brightness and contrast, scaled around the middle gray.
+        case 3u: {
+            rgb = clamp(
+                (rgb - 0.5) * params.contrast + 0.5 + params.brightness,
+                vec3<f32>(0.0),
+                vec3<f32>(1.0),
+            );
+        }
+        default: {}
+    }
+    textureStore(texture_out, pixel, vec4<f32>(rgb, color.a));
+}
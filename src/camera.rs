@@ -1,12 +1,43 @@
 //! Camera functionalities
 
-use bevy::{input::mouse::{MouseScrollUnit, MouseWheel}, prelude::*};
+use bevy::{
+    ecs::system::SystemParam,
+    input::mouse::{MouseScrollUnit, MouseWheel},
+    prelude::*,
+    window::PrimaryWindow,
+};
+use bevy_egui::EguiContexts;
+
+use crate::nca::{
+    input::{brush_size_modifier_pressed, PIXELS_PER_LINE},
+    nca_control::bindings::{Action, ActionInput},
+    CanvasSize,
+    NCADisplaySize,
+    NCASimSize,
+};
 
 // ================================= Constants ================================== //
 
-/// Movement speed of the camera.
+/// Maximum movement speed of the camera.
 const CAMERA_MOVE_SPEED: f32 = 500.0;
 
+/// Duration of the animation when the camera moves to a new view, in seconds.
+const CAMERA_ANIMATION_DURATION: f32 = 0.2;
+
+/// The default smallest scale of the orthographic projection, i.e. the largest zoom.
+pub const MIN_CAMERA_SCALE: f32 = 0.15;
+
+/// The default largest scale of the orthographic projection, i.e. the smallest zoom.
+pub const MAX_CAMERA_SCALE: f32 = 5.;
+
+/// The size in pixels a single cell covers at the largest zoom at least, when the
+/// zoom limits adapt to the simulation.
+const CELL_PIXELS_AT_MAX_ZOOM: f32 = 128.;
+
+/// How many times the simulation fits into the window at the smallest zoom at
+/// least, when the zoom limits adapt to the simulation.
+const ZOOM_OUT_MARGIN: f32 = 1.5;
+
 // =================================== Plugin =================================== //
 
 /// A plugin to manage the camera.
@@ -14,40 +45,199 @@ pub(super) struct CameraPlugin;
 
 impl Plugin for CameraPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, camera_controller);
+        app
+            .init_resource::<CameraMotion>()
+            .init_resource::<CameraView>()
+            .init_resource::<CameraZoomLimits>()
+            .add_event::<ResetCamera>()
+            .add_event::<FitCamera>()
+            .add_systems(Update, (
+                update_zoom_limits,
+                camera_controller.after(update_zoom_limits),
+                reset_camera_hotkey,
+                on_reset_camera.after(update_zoom_limits),
+                animate_camera.after(camera_controller).after(on_reset_camera),
+            ));
+    }
+}
+
+// ================================ Resources =================================== //
+
+/// A resource holding the velocity of the camera moved with WASD. The camera
+/// accelerates towards the pressed direction and slows down due to friction once
+/// the keys are released.
+#[derive(Resource, Debug, Clone)]
+pub struct CameraMotion {
+    /// The acceleration towards the pressed direction, in pixels per second squared.
+    pub acceleration: f32,
+    /// The rate at which the velocity decays without input, per second.
+    pub friction: f32,
+    velocity: Vec2,
+}
+
+impl Default for CameraMotion {
+    fn default() -> Self {
+        Self {
+            acceleration: 3000.,
+            friction: 8.,
+            velocity: Vec2::ZERO,
+        }
+    }
+}
+
+/// A resource holding the default view of the camera and the animation towards a
+/// new view, if any.
+#[derive(Resource, Debug, Clone)]
+pub struct CameraView {
+    /// The position of the camera in the default view.
+    pub default_translation: Vec2,
+    /// The scale of the orthographic projection in the default view.
+    pub default_scale: f32,
+    animation: Option<CameraAnimation>,
+}
+
+impl Default for CameraView {
+    fn default() -> Self {
+        Self {
+            default_translation: Vec2::ZERO,
+            default_scale: 1.,
+            animation: None,
+        }
     }
 }
 
+/// A resource holding the limits of the zoom of the camera, as scales of the
+/// orthographic projection. When adapting to the simulation, the limits are widened
+/// so that large simulations fit into the window when zoomed out and single cells
+/// of small simulations are large when zoomed in.
+#[derive(Resource, Debug, Clone)]
+pub struct CameraZoomLimits {
+    /// The smallest scale, i.e. the largest zoom.
+    pub min_scale: f32,
+    /// The largest scale, i.e. the smallest zoom.
+    pub max_scale: f32,
+    pub adapt_to_simulation: bool,
+    /// The smallest and largest scale in effect.
+    range: (f32, f32),
+}
+
+impl Default for CameraZoomLimits {
+    fn default() -> Self {
+        Self {
+            min_scale: MIN_CAMERA_SCALE,
+            max_scale: MAX_CAMERA_SCALE,
+            adapt_to_simulation: true,
+            range: (MIN_CAMERA_SCALE, MAX_CAMERA_SCALE),
+        }
+    }
+}
+
+impl CameraZoomLimits {
+    /// Returns the smallest and largest scale in effect, which may be wider than the
+    /// configured ones when adapting to the simulation.
+    pub fn range(&self) -> (f32, f32) {
+        self.range
+    }
+
+    /// Clamps the scale to the limits in effect.
+    pub fn clamp(&self, scale: f32) -> f32 {
+        scale.clamp(self.range.0, self.range.1)
+    }
+}
+
+/// An animation of the camera between two views.
+#[derive(Debug, Clone, Copy)]
+struct CameraAnimation {
+    from_translation: Vec2,
+    from_scale: f32,
+    to_translation: Vec2,
+    to_scale: f32,
+    elapsed: f32,
+}
+
+// ================================== Events ==================================== //
+
+/// An event that moves the camera back to its default view.
+#[derive(Event, Debug)]
+pub struct ResetCamera;
+
+/// An event that centers the camera on the simulation and zooms so that the whole
+/// simulation is visible.
+#[derive(Event, Debug)]
+pub struct FitCamera;
+
+/// The keys, mouse buttons, cursor and mouse wheel moving and zooming the camera.
+#[derive(SystemParam)]
+struct CameraInput<'w, 's> {
+    keys: Res<'w, ButtonInput<KeyCode>>,
+    actions: ActionInput<'w>,
+    window_query: Query<'w, 's, &'static Window, With<PrimaryWindow>>,
+    mouse_wheel_events: EventReader<'w, 's, MouseWheel>,
+}
+
 // ================================== Systems =================================== //
 
 /// A system for camera control.
 /// 
-/// The camera can be moved around by using WASD. The mouse wheel can be used to
-/// zoom in and out.
+/// The camera can be moved around by using WASD, with some inertia, or by dragging
+/// with the middle mouse button, unless they are bound differently. The mouse wheel
+/// can be used to zoom in and out, unless it changes the brush size while Ctrl or
+/// Shift is held.
 fn camera_controller(
     time: Res<Time>,
-    keys: Res<ButtonInput<KeyCode>>,
-    mut mouse_wheel_events: EventReader<MouseWheel>,
+    mut motion: ResMut<CameraMotion>,
+    input: CameraInput,
     mut query: Query<(&mut Transform, &mut OrthographicProjection), With<Camera>>,
+    mut prev_cursor: Local<Option<Vec2>>,
+    limits: Res<CameraZoomLimits>,
 ) {
+    let CameraInput { keys, actions, window_query, mut mouse_wheel_events } = input;
+    let cursor = window_query
+        .get_single()
+        .ok()
+        .and_then(|window| window.cursor_position());
+    let mut drag_delta = Vec2::ZERO;
+    if actions.pressed(Action::DragPan) {
+        if let (Some(cursor), Some(prev_cursor)) = (cursor, *prev_cursor) {
+            drag_delta = cursor - prev_cursor;
+        }
+    }
+    *prev_cursor = cursor;
+    if brush_size_modifier_pressed(&keys) {
+        mouse_wheel_events.clear();
+    }
+
     for (mut transform, mut ortho) in query.iter_mut() {
-        let up = keys.pressed(KeyCode::KeyW);
-        let down = keys.pressed(KeyCode::KeyS);
-        let left = keys.pressed(KeyCode::KeyA);
-        let right = keys.pressed(KeyCode::KeyD);
+        let up = actions.pressed(Action::PanUp);
+        let down = actions.pressed(Action::PanDown);
+        let left = actions.pressed(Action::PanLeft);
+        let right = actions.pressed(Action::PanRight);
 
         let x_axis = right as i8 - left as i8;
         let y_axis = up as i8 - down as i8;
-        let mut move_delta = Vec2::new(x_axis as f32, y_axis as f32);
+        let move_delta = Vec2::new(x_axis as f32, y_axis as f32).normalize_or_zero();
 
+        let dt = time.delta_seconds();
         if move_delta != Vec2::ZERO {
-            move_delta /= move_delta.length();
+            let target = move_delta * CAMERA_MOVE_SPEED;
+            let change = (target - motion.velocity).clamp_length_max(motion.acceleration * dt);
+            motion.velocity += change;
+        } else {
+            let decay = (-motion.friction * dt).exp();
+            motion.velocity *= decay;
+            if motion.velocity.length() < 1. {
+                motion.velocity = Vec2::ZERO;
+            }
+        }
 
-            let z = transform.translation.z;
-            transform.translation +=
-                move_delta.extend(z) * CAMERA_MOVE_SPEED * time.delta_seconds();
+        if motion.velocity != Vec2::ZERO {
+            transform.translation += (motion.velocity * dt).extend(0.);
+        }
 
-            transform.translation.z = z;
+        // The cursor moves down the screen, while the world's y-axis points up.
+        // Scaling by the zoom keeps the canvas under the cursor.
+        if drag_delta != Vec2::ZERO {
+            transform.translation += (drag_delta * Vec2::new(-1., 1.) * ortho.scale).extend(0.);
         }
 
         for event in mouse_wheel_events.read() {
@@ -60,8 +250,6 @@ fn camera_controller(
                     y_scroll_diff += event.y;
                 }
                 MouseScrollUnit::Pixel => {
-                    const PIXELS_PER_LINE: f32 = 38.0;
-
                     y_scroll_diff += event.y / PIXELS_PER_LINE;
                     x_scroll_diff += event.x / PIXELS_PER_LINE;
                 }
@@ -74,8 +262,114 @@ fn camera_controller(
                     ortho.scale *= 1.0 / 1.05;
                 }
 
-                ortho.scale = ortho.scale.clamp(0.15, 5.);
+                ortho.scale = limits.clamp(ortho.scale);
             }
         }
     }
-}
\ No newline at end of file
+}
+
+/// A system that updates the zoom limits in effect. When adapting to the
+/// simulation, the configured limits are widened to the size of the simulation, its
+/// display size and the size of the window.
+fn update_zoom_limits(
+    mut limits: ResMut<CameraZoomLimits>,
+    sim_size: Res<NCASimSize>,
+    display_size: Res<NCADisplaySize>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+) {
+    let mut range = (limits.min_scale, limits.max_scale.max(limits.min_scale));
+    if limits.adapt_to_simulation {
+        if let Ok(window) = window_query.get_single() {
+            let window_size = Vec2::new(window.width(), window.height()).max(Vec2::ONE);
+            let display = display_size.resolve(sim_size.size);
+            let cell = (display / sim_size.size.max(UVec2::ONE).as_vec2()).min_element();
+            let fit = (display / window_size).max_element();
+            range.0 = range.0.min(cell / CELL_PIXELS_AT_MAX_ZOOM);
+            range.1 = range.1.max(fit * ZOOM_OUT_MARGIN);
+        }
+    }
+    if limits.range != range {
+        limits.range = range;
+    }
+}
+
+/// A system that resets the camera to its default view when R (or the key bound to
+/// it) is pressed, unless the UI is using the keyboard.
+fn reset_camera_hotkey(
+    mut contexts: EguiContexts,
+    actions: ActionInput,
+    mut ev_writer_reset_camera: EventWriter<ResetCamera>,
+) {
+    if actions.just_pressed(Action::ResetCamera) && !contexts.ctx_mut().wants_keyboard_input() {
+        ev_writer_reset_camera.send(ResetCamera);
+    }
+}
+
+/// System triggered by the ResetCamera and FitCamera events. Starts the animation
+/// of the camera towards the default view or the view fitting the simulation into
+/// the window.
+fn on_reset_camera(
+    mut ev_reader_reset_camera: EventReader<ResetCamera>,
+    mut ev_reader_fit_camera: EventReader<FitCamera>,
+    mut view: ResMut<CameraView>,
+    limits: Res<CameraZoomLimits>,
+    canvas: CanvasSize,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    query: Query<(&Transform, &OrthographicProjection), With<Camera>>,
+) {
+    let mut target = None;
+    if ev_reader_reset_camera.read().count() > 0 {
+        target = Some((view.default_translation, view.default_scale));
+    }
+    if ev_reader_fit_camera.read().count() > 0 {
+        let Ok(window) = window_query.get_single() else { return };
+        let window_size = Vec2::new(window.width(), window.height()).max(Vec2::ONE);
+        let scale = limits.clamp((canvas.displayed() / window_size).max_element());
+        target = Some((Vec2::ZERO, scale));
+    }
+    let Some((to_translation, to_scale)) = target else { return };
+    let Ok((transform, ortho)) = query.get_single() else { return };
+
+    view.animation = Some(CameraAnimation {
+        from_translation: transform.translation.truncate(),
+        from_scale: ortho.scale,
+        to_translation,
+        to_scale,
+        elapsed: 0.,
+    });
+}
+
+/// A system that moves the camera towards the target of the current animation.
+fn animate_camera(
+    time: Res<Time>,
+    mut view: ResMut<CameraView>,
+    mut query: Query<(&mut Transform, &mut OrthographicProjection), With<Camera>>,
+) {
+    let Some(animation) = view.animation.as_mut() else { return };
+    animation.elapsed += time.delta_seconds();
+    let t = (animation.elapsed / CAMERA_ANIMATION_DURATION).min(1.);
+
+    for (mut transform, mut ortho) in query.iter_mut() {
+        let translation = animation.from_translation.lerp(animation.to_translation, t);
+        transform.translation = translation.extend(transform.translation.z);
+        ortho.scale = animation.from_scale + (animation.to_scale - animation.from_scale) * t;
+    }
+
+    if t >= 1. {
+        view.animation = None;
+    }
+}
+
+// =================================== Utils ==================================== //
+
+/// Returns the zoom in percent at the given scale of the orthographic projection.
+/// At 100%, one unit of the display covers one pixel of the window.
+pub fn zoom_percent(scale: f32) -> f32 {
+    100. / scale
+}
+
+/// Returns the scale of the orthographic projection showing the given zoom in
+/// percent, clamped to the zoom limits in effect.
+pub fn zoom_scale(percent: f32, limits: &CameraZoomLimits) -> f32 {
+    limits.clamp(100. / percent.max(f32::EPSILON))
+}
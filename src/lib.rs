@@ -7,23 +7,46 @@
 pub mod camera;
 pub mod nca;
 
-use bevy::app::{App, Plugin};
+use bevy::{
+    app::{App, Plugin},
+    asset::Handle,
+    render::render_resource::Shader,
+};
 
 // ================================= Constants ================================== //
 
-/// The file path from ./assets/ to the shader for the NCA.
-const SHADER_ASSET_PATH: &str = "shaders/nca.wgsl";
+/// The handle of the shader for the NCA. The shader is generated from the NCA
+/// settings and added to the shader assets in memory, no file is involved.
+const NCA_SHADER_HANDLE: Handle<Shader> =
+    Handle::weak_from_u128(318265349831100406613915047944092625967);
 
-/// Size of the simulation in pixels.
+/// The handle of the shader running the frozen rule of an A/B comparison. Like the
+/// NCA shader, it is generated from the NCA settings.
+const NCA_COMPARE_SHADER_HANDLE: Handle<Shader> =
+    Handle::weak_from_u128(214006139447289631804472196359371102583);
+
+/// The file path from ./assets/ to the shader for drawing on the NCA.
+const DRAW_SHADER_ASSET_PATH: &str = "shaders/draw.wgsl";
+
+/// The file path from ./assets/ to the shader for displaying the NCA.
+const DISPLAY_SHADER_ASSET_PATH: &str = "shaders/display.wgsl";
+
+/// The file path from ./assets/ to the shader for applying effects to the NCA.
+const EFFECT_SHADER_ASSET_PATH: &str = "shaders/effects.wgsl";
+
+/// Size of the simulation in pixels on startup.
 const SIM_SIZE: (u32, u32) = (1920, 1080);
 
 /// Size of the workgroups on the GPU for the compute shaders.
 const WORKGROUP_SIZE: u32 = 8;
 
+/// The maximum number of update steps of the NCA per frame.
+const MAX_STEPS_PER_FRAME: u32 = 16;
+
 // =================================== Plugin =================================== //
 
 /// Main plugin, containing the NCA functionalities, input and camera control as
-/// well as a UI.
+/// well as a UI and the display of the NCA.
 pub struct NCAPlaygroundPlugin;
 
 impl Plugin for NCAPlaygroundPlugin {
@@ -31,7 +54,8 @@ impl Plugin for NCAPlaygroundPlugin {
         app
             .add_plugins((
                 camera::CameraPlugin,
-                nca::NCAPlugin,
+                nca::NCAPlugin::new(),
+                nca::display::NCADisplayPlugin,
             ));
     }
 }
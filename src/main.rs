@@ -1,8 +1,15 @@
 use bevy::prelude::*;
+use nca_playground::nca::nca_control::launch::LaunchOptions;
 
 fn main() {
+    let (launch_options, warnings) = LaunchOptions::from_args(std::env::args().skip(1));
+    for warning in warnings {
+        eprintln!("{}", warning);
+    }
+
     App::new()
         .insert_resource(ClearColor(Color::srgb(0.5, 0.5, 0.5)))
+        .insert_resource(launch_options)
         .add_plugins((
             DefaultPlugins.set(ImagePlugin::default_nearest()),
             nca_playground::NCAPlaygroundPlugin,
@@ -0,0 +1,365 @@
+//! Displaying the NCA on screen
+
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{
+            AsBindGroup,
+            Extent3d,
+            ShaderRef,
+            TextureDimension,
+            TextureFormat,
+        },
+        texture::ImageSampler,
+    },
+    sprite::{Material2d, Material2dPlugin, MaterialMesh2dBundle},
+};
+
+use crate::DISPLAY_SHADER_ASSET_PATH;
+use super::{
+    pipeline::{compare::NCACompare, nca::NCAImages},
+    CanvasSize,
+    DisplayBlend,
+    DisplayChannels,
+    DisplayGrid,
+    DisplayPalette,
+    DisplaySymmetry,
+    NCADisplaySize,
+    NCAFrontTexture,
+    NCASimSize,
+    Palette,
+    PaletteSource,
+};
+
+// =================================== Plugin =================================== //
+
+/// A plugin that displays the NCA in the primary window. Spawns a camera as well as
+/// a rectangle showing the current front texture of the NCA, mirrored according to
+/// the [`DisplaySymmetry`], with the [`DisplayChannels`] combined by the
+/// [`DisplayBlend`], colored by the [`DisplayPalette`] and overlaid with the
+/// [`DisplayGrid`]. During an A/B comparison, the frozen rule is shown the same way
+/// to the right of it.
+///
+/// Leave this plugin out to run the NCA without any display of its own, e.g. to
+/// use the [`NCAFrontTexture`] as a texture in a larger application.
+pub struct NCADisplayPlugin;
+
+impl Plugin for NCADisplayPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_plugins(Material2dPlugin::<NCADisplayMaterial>::default())
+            .add_systems(PostStartup, setup)
+            .add_systems(PostUpdate, (
+                sync_display_material,
+                sync_sprite_size,
+                sync_compare_sprite.after(sync_display_material),
+            ));
+    }
+}
+
+// ================================= Constants ================================== //
+
+/// The gap between the canvas and the comparison next to it, relative to the width
+/// of the canvas.
+const COMPARE_GAP: f32 = 0.02;
+
+// ================================= Components ================================= //
+
+/// Marker component for the entity displaying the NCA.
+#[derive(Component, Debug)]
+pub struct NCASprite;
+
+/// Marker component for the entity displaying the frozen rule of an A/B comparison.
+#[derive(Component, Debug)]
+pub struct NCACompareSprite;
+
+// ================================= Materials ================================== //
+
+/// The material showing the front texture of the NCA with the display symmetry,
+/// channels, blend, palette and grid applied. The hidden channels are read from
+/// the texture belonging to the front texture. The uniform fields are combined into
+/// the parameters of the display shader, in the order they are declared.
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone, PartialEq)]
+struct NCADisplayMaterial {
+    /// 0 is no symmetry, 1 mirrors X, 2 mirrors Y, 3 mirrors both and 4 is radial.
+    #[uniform(0)]
+    mode: u32,
+    #[uniform(0)]
+    folds: u32,
+    #[uniform(0)]
+    aspect: f32,
+    /// 0 displays the channels as RGB, 1 maps the luminance through the palette and
+    /// 2 to 5 map the channel of index 2 less.
+    #[uniform(0)]
+    palette_source: u32,
+    /// 0 displays the channels as they are, 1 their maximum and 2 their sum.
+    #[uniform(0)]
+    blend: u32,
+    /// The indices of the channels displayed as red, green and blue. The hidden
+    /// channels follow the alpha channel.
+    #[uniform(0)]
+    red_source: u32,
+    #[uniform(0)]
+    green_source: u32,
+    #[uniform(0)]
+    blue_source: u32,
+    /// The size of a cell on screen in pixels from which on the grid is drawn, 0 if
+    /// the grid is hidden.
+    #[uniform(0)]
+    grid_cell_size: f32,
+    #[texture(1)]
+    #[sampler(2)]
+    texture: Handle<Image>,
+    #[texture(3)]
+    #[sampler(4)]
+    palette: Option<Handle<Image>>,
+    #[texture(5)]
+    hidden: Handle<Image>,
+}
+
+impl NCADisplayMaterial {
+    /// Returns the material showing the texture and its hidden channels as they
+    /// are, until the parameters are set.
+    fn new(texture: Handle<Image>, hidden: Handle<Image>) -> Self {
+        Self {
+            mode: 0,
+            folds: 0,
+            aspect: 1.,
+            palette_source: 0,
+            blend: 0,
+            red_source: 0,
+            green_source: 1,
+            blue_source: 2,
+            grid_cell_size: 0.,
+            texture,
+            palette: None,
+            hidden,
+        }
+    }
+
+    /// Sets the parameters of the display shader from the display settings for a
+    /// simulation of the given size.
+    fn set_params(
+        &mut self,
+        symmetry: DisplaySymmetry,
+        palette: DisplayPalette,
+        blend: DisplayBlend,
+        channels: DisplayChannels,
+        grid: DisplayGrid,
+        sim_size: UVec2,
+    ) {
+        (self.mode, self.folds) = match symmetry {
+            DisplaySymmetry::None => (0, 0),
+            DisplaySymmetry::MirrorX => (1, 0),
+            DisplaySymmetry::MirrorY => (2, 0),
+            DisplaySymmetry::Quad => (3, 0),
+            DisplaySymmetry::Radial(folds) => (4, folds),
+        };
+        self.palette_source = match (palette.palette, palette.source) {
+            (None, _) => 0,
+            (Some(_), PaletteSource::Luminance) => 1,
+            (Some(_), PaletteSource::Channel(channel)) => 2 + channel.min(3),
+        };
+        self.aspect = sim_size.x as f32 / sim_size.y as f32;
+        self.blend = blend as u32;
+        self.red_source = channels.sources[0] as u32;
+        self.green_source = channels.sources[1] as u32;
+        self.blue_source = channels.sources[2] as u32;
+        self.grid_cell_size = if grid.enabled { grid.min_cell_size.max(1.) } else { 0. };
+    }
+}
+
+impl Material2d for NCADisplayMaterial {
+    fn fragment_shader() -> ShaderRef {
+        DISPLAY_SHADER_ASSET_PATH.into()
+    }
+}
+
+/// The settings the NCA is displayed with.
+#[derive(SystemParam)]
+struct DisplaySettings<'w> {
+    symmetry: Res<'w, DisplaySymmetry>,
+    palette: Res<'w, DisplayPalette>,
+    blend: Res<'w, DisplayBlend>,
+    channels: Res<'w, DisplayChannels>,
+    grid: Res<'w, DisplayGrid>,
+}
+
+impl DisplaySettings<'_> {
+    /// Sets the parameters of the display material for a simulation of the given
+    /// size.
+    fn apply(&self, material: &mut NCADisplayMaterial, sim_size: UVec2) {
+        let Self { symmetry, palette, blend, channels, grid } = self;
+        material.set_params(**symmetry, **palette, **blend, **channels, **grid, sim_size);
+    }
+}
+
+// ================================== Systems =================================== //
+
+/// After the NCA textures have been created on startup, this system spawns the
+/// camera and a rectangle showing the front texture.
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<NCADisplayMaterial>>,
+    front: Res<NCAFrontTexture>,
+    nca_images: Res<NCAImages>,
+    canvas: CanvasSize,
+    symmetry: Res<DisplaySymmetry>,
+) {
+    let mut material = NCADisplayMaterial::new(
+        front.handle.clone_weak(),
+        nca_images.hidden_of(&front.handle).clone_weak(),
+    );
+    material.set_params(
+        *symmetry,
+        DisplayPalette::default(),
+        DisplayBlend::default(),
+        DisplayChannels::default(),
+        DisplayGrid::default(),
+        canvas.sim_size.size,
+    );
+    commands.spawn((
+        MaterialMesh2dBundle {
+            mesh: meshes.add(Rectangle::default()).into(),
+            material: materials.add(material),
+            transform: Transform::from_scale(canvas.displayed().extend(1.)),
+            ..default()
+        },
+        NCASprite,
+    ));
+
+    commands.spawn(Camera2dBundle::default());
+}
+
+/// A system that keeps the material of the display in sync with the front texture
+/// of the NCA, the display symmetry, the channels, the blend, the palette and the
+/// grid. The palette texture is recreated whenever the palette changes.
+fn sync_display_material(
+    front: Res<NCAFrontTexture>,
+    nca_images: Res<NCAImages>,
+    sim_size: Res<NCASimSize>,
+    settings: DisplaySettings,
+    displayed: Query<&Handle<NCADisplayMaterial>, With<NCASprite>>,
+    mut materials: ResMut<Assets<NCADisplayMaterial>>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let hidden = nca_images.hidden_of(&front.handle);
+    let palette = &settings.palette;
+    let palette_image = palette
+        .is_changed()
+        .then(|| palette.palette.map(|palette| images.add(create_palette_image(palette))));
+    for handle in displayed.iter() {
+        let Some(current) = materials.get(handle) else { continue };
+        let mut material = current.clone();
+        material.texture = front.handle.clone_weak();
+        material.hidden = hidden.clone_weak();
+        settings.apply(&mut material, sim_size.size);
+        if let Some(palette_image) = &palette_image {
+            material.palette.clone_from(palette_image);
+        }
+        if material != *current {
+            materials.insert(handle, material);
+        }
+    }
+}
+
+/// A system that resizes the display when the size of the simulation or the
+/// display size changes.
+fn sync_sprite_size(
+    sim_size: Res<NCASimSize>,
+    display_size: Res<NCADisplaySize>,
+    mut sprites: Query<&mut Transform, With<NCASprite>>,
+) {
+    if !sim_size.is_changed() && !display_size.is_changed() {
+        return;
+    }
+    for mut transform in sprites.iter_mut() {
+        transform.scale = display_size.resolve(sim_size.size).extend(1.);
+    }
+}
+
+/// A system that shows the frozen rule of an A/B comparison to the right of the
+/// canvas while comparing. The comparison is displayed with the same material as
+/// the canvas, only the textures differ.
+fn sync_compare_sprite(
+    mut commands: Commands,
+    compare: Res<NCACompare>,
+    canvas: CanvasSize,
+    displayed: Query<&Handle<NCADisplayMaterial>, With<NCASprite>>,
+    mut compared: Query<
+        (Entity, &Handle<NCADisplayMaterial>, &mut Transform),
+        With<NCACompareSprite>,
+    >,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<NCADisplayMaterial>>,
+) {
+    let (Some(texture), Some(hidden)) = (compare.front_texture(), compare.front_hidden_texture())
+    else {
+        for (entity, ..) in compared.iter() {
+            commands.entity(entity).despawn();
+        }
+        return;
+    };
+    let Some(mut material) = displayed
+        .get_single()
+        .ok()
+        .and_then(|handle| materials.get(handle))
+        .cloned()
+    else {
+        return;
+    };
+    material.texture = texture.clone_weak();
+    material.hidden = hidden.clone_weak();
+
+    let scale = canvas.displayed();
+    let transform = Transform::from_xyz(scale.x * (1. + COMPARE_GAP), 0., 0.)
+        .with_scale(scale.extend(1.));
+    let Ok((_, handle, mut compare_transform)) = compared.get_single_mut() else {
+        commands.spawn((
+            MaterialMesh2dBundle {
+                mesh: meshes.add(Rectangle::default()).into(),
+                material: materials.add(material),
+                transform,
+                ..default()
+            },
+            NCACompareSprite,
+        ));
+        return;
+    };
+
+    if *compare_transform != transform {
+        *compare_transform = transform;
+    }
+    if materials.get(handle).is_some_and(|current| *current != material) {
+        materials.insert(handle, material);
+    }
+}
+
+// =================================== Utils ==================================== //
+
+/// Helper function to create a texture of 256x1 pixels holding the colors of the
+/// palette. Its colors are given in sRGB, so that they are displayed as they are.
+fn create_palette_image(palette: Palette) -> Image {
+    let data = (0..256)
+        .flat_map(|i| {
+            let [r, g, b] = palette.sample(i as f32 / 255.);
+            [r, g, b, 1.].map(|c| (c * 255.).round() as u8)
+        })
+        .collect();
+    let mut image = Image::new(
+        Extent3d {
+            width: 256,
+            height: 1,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    image.sampler = ImageSampler::linear();
+    image
+}
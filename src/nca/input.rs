@@ -1,18 +1,44 @@
 //! Input management
 
 use bevy::{
-    input::{mouse::MouseButtonInput, ButtonState},
+    ecs::system::SystemParam,
+    input::mouse::{MouseScrollUnit, MouseWheel},
     prelude::*,
     render::extract_resource::{ExtractResource, ExtractResourcePlugin},
 };
 use bevy_egui::EguiContexts;
 
-use super::pipeline::draw::NCADrawSettings;
+use super::{
+    nca_control::{
+        bindings::{Action, ActionInput},
+        fill::FloodFill,
+        history::{Redo, SnapshotCanvas, Undo},
+        screenshot::TakeScreenshot,
+        ClearCanvas,
+        StepOnce,
+    },
+    pipeline::{
+        draw::{DrawTool, NCADrawSettings, MAX_BRUSH_SIZE},
+        readback::{NCAReadback, ReadbackComplete, ReadbackPurpose},
+    },
+    utils::to_rgba8,
+    CanvasSize,
+    DisplaySymmetry,
+    NCAFrontTexture,
+    NCASimSize,
+    SimulationControl,
+};
 
 // =================================== Plugin =================================== //
 
 /// A plugin to manage user input. Tracks the users mouse movement and passes the
-/// information to the shader for drawing on screen.
+/// information to the shader for drawing on screen or picks the brush color from
+/// the canvas or fills regions of it. Erases with the right mouse button. Also
+/// toggles pausing the simulation with the spacebar, steps the paused simulation
+/// with the period key, takes screenshots with F12, undoes or redoes strokes with
+/// Ctrl+Z and Ctrl+Y, clears the canvas with Delete and changes the brush size with
+/// Ctrl or Shift and the mouse wheel. All but undoing, redoing and changing the
+/// brush size can be bound to other keys.
 pub(super) struct InputPlugin;
 
 impl Plugin for InputPlugin {
@@ -20,10 +46,35 @@ impl Plugin for InputPlugin {
         app
             .init_resource::<NCAMouseParams>()
             .add_plugins(ExtractResourcePlugin::<NCAMouseParams>::default())
-            .add_systems(Update, update_input_state);
+            .add_systems(Update, (
+                update_input_state,
+                toggle_pause,
+                step_hotkey,
+                screenshot_hotkey,
+                history_hotkeys,
+                clear_canvas_hotkey,
+                brush_size_wheel,
+                apply_picked_color,
+            ))
+            // The front texture is switched during the update, so only request it
+            // afterwards.
+            .add_systems(PostUpdate, pick_color);
     }
 }
 
+// ================================= Constants ================================== //
+
+/// The number of pixels scrolled by touchpads that count as a line of a mouse
+/// wheel.
+pub const PIXELS_PER_LINE: f32 = 38.0;
+
+/// How much the speed of the cursor follows its latest measurement each frame, from
+/// 0 to 1. Lower values smooth out the jitter of the cursor.
+const SPEED_SMOOTHING: f32 = 0.3;
+
+/// The factor the brush size is scaled by per line scrolled with the mouse wheel.
+const BRUSH_SIZE_WHEEL_FACTOR: f32 = 1.1;
+
 // ================================ Resources =================================== //
 
 /// A resource to hold relevant information 
@@ -31,37 +82,124 @@ impl Plugin for InputPlugin {
 pub(super) struct NCAMouseParams {
     /// True if drawing is enabled, false otherwise.
     pub is_drawing: bool,
+    /// True while the brush erases with the right mouse button, false otherwise.
+    pub is_erasing: bool,
     /// The current mouse position in the coordinate system of the canvas.
     pub mouse_pos: Vec2,
     /// The previous mouse position in the coordinate system of the canvas.
     pub prev_mouse_pos: Vec2,
-
+    /// The smoothed speed of the cursor in cells per second.
+    pub speed: f32,
+    /// True in the frame a stroke begins, false otherwise. Nothing is drawn in this
+    /// frame, so that the canvas can be saved for undoing the stroke first.
+    pub stroke_start: bool,
+    /// True in the frame a seed is placed with the seed tool, false otherwise.
+    pub place_seed: bool,
+    /// True in the frame a color is picked with the eyedropper, false otherwise.
+    pub pick_color: bool,
 }
 
 impl Default for NCAMouseParams {
     fn default() -> Self {
         Self {
             is_drawing: false,
+            is_erasing: false,
             mouse_pos: Vec2::ZERO,
             prev_mouse_pos: Vec2::ZERO,
+            speed: 0.,
+            stroke_start: false,
+            place_seed: false,
+            pick_color: false,
         }
     }
 }
 
+impl NCAMouseParams {
+    /// Moves the cursor to the given position on the canvas, the given number of
+    /// seconds after it was last tracked. The segment drawn next starts at the
+    /// position it was moved from.
+    fn track_cursor(&mut self, mouse_pos: Vec2, delta_seconds: f32) {
+        self.prev_mouse_pos = self.mouse_pos;
+        self.mouse_pos = mouse_pos;
+
+        let distance = self.mouse_pos.distance(self.prev_mouse_pos);
+        let speed = distance / delta_seconds.max(0.001);
+        self.speed += (speed - self.speed) * SPEED_SMOOTHING;
+    }
+}
+
+/// The window, the camera and the layout of the canvas, to map the cursor onto the
+/// canvas.
+#[derive(SystemParam)]
+struct CanvasCursor<'w, 's> {
+    window_query: Query<'w, 's, &'static Window>,
+    camera_q: Query<'w, 's, (&'static Camera, &'static GlobalTransform)>,
+    canvas: CanvasSize<'w>,
+    symmetry: Res<'w, DisplaySymmetry>,
+}
+
+/// The event writers for the history and the fill tool, sent when a stroke or fill
+/// begins.
+#[derive(SystemParam)]
+struct StrokeEvents<'w> {
+    snapshot: EventWriter<'w, SnapshotCanvas>,
+    flood_fill: EventWriter<'w, FloodFill>,
+}
+
 // ================================== Systems =================================== //
 
 /// A system to react to user inputs other than interacting with the UI.
 fn update_input_state(
     mut contexts: EguiContexts,
-    window_query: Query<&Window>,
+    cursor: CanvasCursor,
     mut input_state: ResMut<NCAMouseParams>,
     mut params: ResMut<NCADrawSettings>,
-    camera_q: Query<(&Camera, &GlobalTransform)>,
-    mut mouse_button_input_events: EventReader<MouseButtonInput>,
+    actions: ActionInput,
+    mut events: StrokeEvents,
+    time: Res<Time>,
 ) {
+    let CanvasCursor { window_query, camera_q, canvas, symmetry } = cursor;
     let Ok(primary_window) = window_query.get_single() else { return };
     let Ok((camera, camera_transform)) = camera_q.get_single() else { return };
 
+    input_state.stroke_start = false;
+    input_state.place_seed = false;
+    input_state.pick_color = false;
+    // Releasing the button over the UI ends the stroke as well.
+    if !actions.pressed(Action::Draw) {
+        input_state.is_drawing = false;
+    }
+    if !actions.pressed(Action::Erase) {
+        input_state.is_erasing = false;
+    }
+
+    // Track the cursor every frame, also over the UI, so that the segment drawn
+    // next always starts where the cursor was in the previous frame.
+    let cursor_world_position = primary_window
+        .cursor_position()
+        .and_then(|cursor| camera.viewport_to_world(camera_transform, cursor))
+        .map(|ray| ray.origin.truncate());
+    if let Some(world_position) = cursor_world_position {
+        let mouse_pos = world_pos_to_canvas_pos(
+            world_position * Vec2::new(1.0, -1.0),
+            canvas.displayed(),
+            canvas.sim_size.size,
+            *symmetry,
+        );
+        input_state.track_cursor(mouse_pos, time.delta_seconds());
+    }
+
+    // Strokes end when the cursor leaves the canvas or the window loses focus, so
+    // that returning doesn't draw at stale or clamped positions.
+    let on_canvas = cursor_world_position.is_some()
+        && is_on_canvas(input_state.mouse_pos, canvas.sim_size.size);
+    if !on_canvas || !primary_window.focused {
+        params.is_drawing = false;
+        input_state.is_drawing = false;
+        input_state.is_erasing = false;
+        return;
+    }
+
     let ctx = contexts.ctx_mut();
     if ctx.wants_pointer_input()
         || ctx.is_pointer_over_area()
@@ -74,31 +212,232 @@ fn update_input_state(
         params.is_drawing = true;
     }
 
-    for event in mouse_button_input_events.read() {
-        if event.button == MouseButton::Left {
-            input_state.is_drawing = event.state == ButtonState::Pressed;
+    let was_stroking = input_state.is_drawing || input_state.is_erasing;
+    let mut fill = false;
+    if actions.just_pressed(Action::Draw) {
+        match params.tool {
+            DrawTool::Brush | DrawTool::Mask => input_state.is_drawing = true,
+            DrawTool::Seed => input_state.place_seed = true,
+            DrawTool::Eyedropper => input_state.pick_color = true,
+            DrawTool::Fill => fill = true,
         }
     }
-    
-    if let Some(world_position) = primary_window
-        .cursor_position()
-        .and_then(|cursor| camera.viewport_to_world(camera_transform, cursor))
-        .map(|ray| ray.origin.truncate())
+    if actions.just_pressed(Action::Erase) {
+        input_state.is_erasing = true;
+    }
+    if !matches!(params.tool, DrawTool::Brush | DrawTool::Mask) {
+        input_state.is_drawing = false;
+    }
+    if !was_stroking && (input_state.is_drawing || input_state.is_erasing) {
+        input_state.stroke_start = true;
+        // Strokes of the mask tool leave the canvas unchanged.
+        if params.tool != DrawTool::Mask {
+            events.snapshot.send(SnapshotCanvas);
+        }
+    }
+    if fill {
+        events.snapshot.send(SnapshotCanvas);
+        events.flood_fill.send(FloodFill { position: input_state.mouse_pos.as_uvec2() });
+    }
+}
+
+/// A system that requests the pixel under the cursor from the front texture when
+/// a color is picked with the eyedropper.
+fn pick_color(
+    input_state: Res<NCAMouseParams>,
+    sim_size: Res<NCASimSize>,
+    front: Res<NCAFrontTexture>,
+    mut readback: ResMut<NCAReadback>,
+) {
+    if !input_state.pick_color || !is_on_canvas(input_state.mouse_pos, sim_size.size) {
+        return;
+    }
+    let pos = input_state.mouse_pos.as_uvec2();
+    readback.request_region(
+        ReadbackPurpose::Eyedropper,
+        front.handle.clone_weak(),
+        URect::from_corners(pos, pos + UVec2::ONE),
+    );
+}
+
+/// A system that sets the brush color to the picked pixel and switches back to the
+/// brush.
+fn apply_picked_color(
+    mut ev_reader_readback: EventReader<ReadbackComplete>,
+    mut params: ResMut<NCADrawSettings>,
+) {
+    for event in ev_reader_readback.read() {
+        if event.purpose != ReadbackPurpose::Eyedropper {
+            continue;
+        }
+        let Ok(readback) = &event.result else { continue };
+        let Some(pixel) = to_rgba8(&readback.data, readback.format) else { continue };
+        let Some(pixel) = pixel.get(..3) else { continue };
+        params.brush_color = [0, 1, 2].map(|i| pixel[i] as f32 / 255.);
+        if params.tool == DrawTool::Eyedropper {
+            params.tool = DrawTool::Brush;
+        }
+    }
+}
+
+/// A system that pauses or resumes the simulation when the spacebar (or the key
+/// bound to pausing) is pressed, unless the UI is using the keyboard.
+fn toggle_pause(
+    mut contexts: EguiContexts,
+    actions: ActionInput,
+    mut control: ResMut<SimulationControl>,
+) {
+    if actions.just_pressed(Action::Pause) && !contexts.ctx_mut().wants_keyboard_input() {
+        control.paused = !control.paused;
+    }
+}
+
+/// A system that advances the paused simulation by a single step when the period
+/// key (or the key bound to stepping) is pressed, unless the UI is using the
+/// keyboard.
+fn step_hotkey(
+    mut contexts: EguiContexts,
+    actions: ActionInput,
+    mut ev_writer_step_once: EventWriter<StepOnce>,
+) {
+    if actions.just_pressed(Action::Step) && !contexts.ctx_mut().wants_keyboard_input() {
+        ev_writer_step_once.send(StepOnce);
+    }
+}
+
+/// A system that takes a screenshot when F12 (or the key bound to it) is pressed.
+fn screenshot_hotkey(
+    actions: ActionInput,
+    mut ev_writer_screenshot: EventWriter<TakeScreenshot>,
+) {
+    if actions.just_pressed(Action::Screenshot) {
+        ev_writer_screenshot.send(TakeScreenshot);
+    }
+}
+
+/// A system that undoes the last stroke when Ctrl+Z is pressed and redoes it when
+/// Ctrl+Y is pressed, unless the UI is using the keyboard.
+fn history_hotkeys(
+    mut contexts: EguiContexts,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut ev_writer_undo: EventWriter<Undo>,
+    mut ev_writer_redo: EventWriter<Redo>,
+) {
+    if !keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight])
+        || contexts.ctx_mut().wants_keyboard_input()
     {
-        input_state.prev_mouse_pos = input_state.mouse_pos;
-        input_state.mouse_pos =
-            world_pos_to_canvas_pos(world_position * Vec2::new(1.0, -1.0));
+        return;
+    }
+    if keys.just_pressed(KeyCode::KeyZ) {
+        ev_writer_undo.send(Undo);
+    }
+    if keys.just_pressed(KeyCode::KeyY) {
+        ev_writer_redo.send(Redo);
+    }
+}
+
+/// A system that clears the canvas when Delete (or the key bound to it) is pressed,
+/// unless the UI is using the keyboard.
+fn clear_canvas_hotkey(
+    mut contexts: EguiContexts,
+    actions: ActionInput,
+    mut ev_writer_clear_canvas: EventWriter<ClearCanvas>,
+) {
+    if actions.just_pressed(Action::ClearCanvas) && !contexts.ctx_mut().wants_keyboard_input() {
+        ev_writer_clear_canvas.send(ClearCanvas);
+    }
+}
+
+/// A system that changes the brush size when the mouse wheel is scrolled over the
+/// canvas while Ctrl or Shift is held. The camera doesn't zoom meanwhile.
+fn brush_size_wheel(
+    mut contexts: EguiContexts,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut mouse_wheel_events: EventReader<MouseWheel>,
+    mut params: ResMut<NCADrawSettings>,
+) {
+    if !brush_size_modifier_pressed(&keys) || contexts.ctx_mut().is_pointer_over_area() {
+        mouse_wheel_events.clear();
+        return;
+    }
+    // Some platforms turn vertical scrolling into horizontal one while Shift is
+    // held.
+    let lines: f32 = mouse_wheel_events
+        .read()
+        .map(|event| {
+            let delta = if event.y != 0. { event.y } else { event.x };
+            match event.unit {
+                MouseScrollUnit::Line => delta,
+                MouseScrollUnit::Pixel => delta / PIXELS_PER_LINE,
+            }
+        })
+        .sum();
+    if lines != 0. {
+        // Scaling keeps the steps fine for small brushes, a brush of size 0 grows
+        // like one of size 1.
+        let size = params.brush_size.max(1.) * BRUSH_SIZE_WHEEL_FACTOR.powf(lines);
+        params.brush_size = size.round().clamp(0., MAX_BRUSH_SIZE);
     }
 }
 
 // =================================== Utils ==================================== //
 
+/// Helper function that returns true if the key that makes the mouse wheel change
+/// the brush size instead of zooming is held, i.e. Ctrl or Shift.
+pub fn brush_size_modifier_pressed(keys: &ButtonInput<KeyCode>) -> bool {
+    keys.any_pressed([
+        KeyCode::ControlLeft,
+        KeyCode::ControlRight,
+        KeyCode::ShiftLeft,
+        KeyCode::ShiftRight,
+    ])
+}
+
 /// Helper function to translate the world position from the cursor to a canvas
-/// position to be used be the draw shader.
-fn world_pos_to_canvas_pos(world_pos: Vec2) -> Vec2 {
-    world_pos
-        + Vec2::new(
-            crate::SIM_SIZE.0 as f32 / 2.0,
-            crate::SIM_SIZE.1 as f32 / 2.0,
-        )
-}
\ No newline at end of file
+/// position to be used be the draw shader. Scales from the displayed size of the
+/// NCA to the size of the simulation and maps positions on the canvas to the cells
+/// displayed there.
+fn world_pos_to_canvas_pos(
+    world_pos: Vec2,
+    display_size: Vec2,
+    sim_size: UVec2,
+    symmetry: DisplaySymmetry,
+) -> Vec2 {
+    let size = sim_size.as_vec2();
+    let pos = (world_pos + display_size / 2.0) * size / display_size;
+    if pos.min_element() < 0. || pos.x > size.x || pos.y > size.y {
+        return pos;
+    }
+    symmetry.source_uv(pos / size, size.x / size.y) * size
+}
+
+/// Helper function to check whether a canvas position lies within the simulation.
+pub(super) fn is_on_canvas(canvas_pos: Vec2, sim_size: UVec2) -> bool {
+    canvas_pos.min_element() >= 0. && canvas_pos.cmplt(sim_size.as_vec2()).all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracked_segments_connect() {
+        let positions = [
+            Vec2::new(10., 10.),
+            Vec2::new(14., 12.),
+            Vec2::new(14., 12.),
+            Vec2::new(80., 5.),
+            Vec2::new(2., 40.),
+        ];
+        let mut input_state = NCAMouseParams::default();
+        let mut segment_end = input_state.mouse_pos;
+        for mouse_pos in positions {
+            input_state.track_cursor(mouse_pos, 1. / 60.);
+            // Each segment starts where the previous one ended, so no position is
+            // skipped, however far the cursor moved.
+            assert_eq!(input_state.prev_mouse_pos, segment_end);
+            assert_eq!(input_state.mouse_pos, mouse_pos);
+            segment_end = mouse_pos;
+        }
+    }
+}
@@ -1,5 +1,6 @@
 //! Neural cellular automaton
 
+pub mod display;
 pub mod input;
 pub mod nca_control;
 pub mod pipeline;
@@ -7,6 +8,7 @@ pub mod ui;
 pub mod utils;
 
 use bevy::{
+    ecs::system::SystemParam,
     prelude::*,
     render::{
         extract_resource::{ExtractResource, ExtractResourcePlugin},
@@ -23,101 +25,591 @@ use bevy::{
         renderer::RenderDevice,
     },
 };
+use std::sync::{Arc, Mutex};
 
-use crate::SIM_SIZE;
-use pipeline::{draw::NCADrawSettings, nca::{NCAFilter, NCAImages}};
+use crate::{DRAW_SHADER_ASSET_PATH, MAX_STEPS_PER_FRAME, SIM_SIZE, WORKGROUP_SIZE};
+use nca_control::settings::{NCASettings, MAX_HIDDEN_CHANNELS};
+use pipeline::{draw::NCADrawSettings, nca::NCAImages};
 
 // =================================== Plugin =================================== //
 
 /// A plugin that manages everything related to the NCA. Contains the infrastructure
 /// for the rendering pipelines, control over the NCA settings, a UI as well as
 /// input management.
-pub struct NCAPlugin;
+///
+/// This plugin does not display the simulation on its own. Add the
+/// [`display::NCADisplayPlugin`] for a camera and a rectangle showing the NCA, or read
+/// the [`NCAFrontTexture`] resource to render the texture yourself.
+///
+/// Configure the plugin with its builder methods, e.g.
+/// `NCAPlugin::new().with_size(512, 512).without_ui()` to embed a small simulation
+/// into an application with a UI of its own. The NCA is then controlled through the
+/// [`nca_control::settings::NCASettings`] and [`pipeline::draw::NCADrawSettings`]
+/// resources and events like [`nca_control::Reinitialize`] and
+/// [`nca_control::UpdateFilter`].
+#[derive(Debug, Clone)]
+pub struct NCAPlugin {
+    size: UVec2,
+    draw_shader: String,
+    ui: bool,
+}
+
+impl Default for NCAPlugin {
+    fn default() -> Self {
+        Self {
+            size: UVec2::new(SIM_SIZE.0, SIM_SIZE.1),
+            draw_shader: DRAW_SHADER_ASSET_PATH.to_owned(),
+            ui: true,
+        }
+    }
+}
+
+impl NCAPlugin {
+    /// Creates the plugin with a simulation of 1920x1080 pixels and the UI windows.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the size of the simulation in pixels on startup.
+    pub fn with_size(mut self, width: u32, height: u32) -> Self {
+        self.size = UVec2::new(width, height).max(UVec2::ONE);
+        self
+    }
+
+    /// Sets the asset path of the shader for drawing on the NCA.
+    pub fn with_draw_shader(mut self, path: impl Into<String>) -> Self {
+        self.draw_shader = path.into();
+        self
+    }
+
+    /// Leaves out the UI windows, e.g. to control the NCA from a UI of your own.
+    pub fn without_ui(mut self) -> Self {
+        self.ui = false;
+        self
+    }
+}
 
 impl Plugin for NCAPlugin {
     fn build(&self, app: &mut App) {
+        // The input checks whether the pointer is over an egui window, with or
+        // without the UI of the NCA.
+        if !app.is_plugin_added::<bevy_egui::EguiPlugin>() {
+            app.add_plugins(bevy_egui::EguiPlugin);
+        }
+        if self.ui {
+            app.add_plugins(ui::UIPlugin);
+        }
+
         app
+            .insert_resource(NCASimSize { size: self.size })
+            .init_resource::<NCADisplaySize>()
+            .init_resource::<DisplaySymmetry>()
+            .init_resource::<DisplayPalette>()
+            .init_resource::<DisplayBlend>()
+            .init_resource::<DisplayChannels>()
+            .init_resource::<DisplayGrid>()
+            .init_resource::<NCAPipelineMonitor>()
+            .init_resource::<NCATextureFormat>()
+            .init_resource::<NCAWorkgroupSize>()
             .init_resource::<ReinitPipeline>()
+            .init_resource::<SimulationControl>()
             .add_plugins((
                 ExtractResourcePlugin::<NCABuffers>::default(),
                 ExtractResourcePlugin::<NCADrawSettings>::default(),
                 ExtractResourcePlugin::<NCAImages>::default(),
+                ExtractResourcePlugin::<NCAPipelineMonitor>::default(),
+                ExtractResourcePlugin::<NCASimSize>::default(),
+                ExtractResourcePlugin::<NCATextureFormat>::default(),
+                ExtractResourcePlugin::<NCAWorkgroupSize>::default(),
                 ExtractResourcePlugin::<ReinitPipeline>::default(),
+                ExtractResourcePlugin::<SimulationControl>::default(),
                 input::InputPlugin,
                 nca_control::NCAControlPlugin,
-                pipeline::PipelinesPlugin,
-                ui::UIPlugin,
+                pipeline::PipelinesPlugin { draw_shader: self.draw_shader.clone() },
             ))
             .add_systems(Startup, setup)
             .add_systems(Update, switch_textures);
     }
 }
 
+// ================================= Constants ================================== //
+
+/// The format of the textures holding the hidden channels of the NCA. Unlike the
+/// displayed channels, hidden state keeps its full range and precision.
+pub const HIDDEN_TEXTURE_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+/// The number of hidden channels sharing a tile of the texture of the hidden
+/// channels, one per color channel of its format.
+pub const HIDDEN_CHANNELS_PER_TILE: usize = 4;
+
 // ================================ Resources =================================== //
 
-/// Resource with a flag to reinitialize the rendering pipeline.
+/// Resource holding the handle to the texture the NCA has most recently written
+/// to. Use this to display the simulation on a surface of your own.
+#[derive(Resource, Debug, Clone)]
+pub struct NCAFrontTexture {
+    pub handle: Handle<Image>,
+}
+
+/// Resource counting the requests to reinitialize the rendering pipeline. The NCA
+/// node reinitializes once for every change of the count it sees, so requests are
+/// neither lost nor repeated, whatever state the pipeline is in.
 #[derive(Resource, ExtractResource, Debug, Default, Clone)]
 pub struct ReinitPipeline {
-    pub reinit: bool,
+    requests: u64,
+}
+
+impl ReinitPipeline {
+    /// Requests the pipeline to be reinitialized.
+    pub fn request(&mut self) {
+        self.requests += 1;
+    }
+
+    /// Returns the number of requests made so far.
+    pub fn requests(&self) -> u64 {
+        self.requests
+    }
+}
+
+/// Resource to control how the simulation advances.
+#[derive(Resource, ExtractResource, Debug, Clone)]
+pub struct SimulationControl {
+    /// If true, the NCA is not updated and the current texture stays visible.
+    pub paused: bool,
+    /// The number of update steps per frame, at most 16.
+    pub steps_per_frame: u32,
+    /// True if a single step was requested while paused.
+    pub(crate) step: bool,
+    /// The number of steps the NCA advances in the current frame.
+    pub(crate) steps: u32,
+    /// The time in seconds the NCA has been running since it was initialized,
+    /// passed to the activation functions as t.
+    pub(crate) time: f32,
+}
+
+impl Default for SimulationControl {
+    fn default() -> Self {
+        Self {
+            paused: false,
+            steps_per_frame: 1,
+            step: false,
+            steps: 0,
+            time: 0.,
+        }
+    }
+}
+
+/// Resource holding the size of the simulation in pixels. Change it with the
+/// [`nca_control::ResizeSimulation`] event, which also recreates the textures.
+#[derive(Resource, ExtractResource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NCASimSize {
+    pub size: UVec2,
+}
+
+impl Default for NCASimSize {
+    fn default() -> Self {
+        Self { size: UVec2::new(SIM_SIZE.0, SIM_SIZE.1) }
+    }
+}
+
+/// Resource holding the size of the displayed NCA in world units, independent of the
+/// size of the simulation. Without a size, every cell is displayed as one unit.
+/// Cells are upscaled with nearest neighbor filtering, so that small simulations
+/// stay crisp.
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq)]
+pub struct NCADisplaySize {
+    pub size: Option<Vec2>,
+}
+
+impl NCADisplaySize {
+    /// Returns the size of the displayed NCA for a simulation of the given size.
+    pub fn resolve(&self, sim_size: UVec2) -> Vec2 {
+        self.size.unwrap_or(sim_size.as_vec2()).max(Vec2::ONE)
+    }
+}
+
+/// The size of the simulation together with the size it is displayed with.
+#[derive(SystemParam)]
+pub struct CanvasSize<'w> {
+    pub sim_size: Res<'w, NCASimSize>,
+    pub display_size: Res<'w, NCADisplaySize>,
+}
+
+impl CanvasSize<'_> {
+    /// Returns the size of the displayed NCA in world units.
+    pub fn displayed(&self) -> Vec2 {
+        self.display_size.resolve(self.sim_size.size)
+    }
+}
+
+/// Resource holding the symmetry the NCA is displayed with. The displayed texture
+/// is mirrored accordingly, while the simulation itself stays untouched. Input on
+/// the canvas is mapped the same way, so that drawing lands on the displayed cells.
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DisplaySymmetry {
+    #[default]
+    None,
+    /// Mirrors the left half onto the right half.
+    MirrorX,
+    /// Mirrors the top half onto the bottom half.
+    MirrorY,
+    /// Mirrors the top left quarter onto the other quarters.
+    Quad,
+    /// Folds the canvas into the given number of wedges around its center, each
+    /// mirroring the first half wedge.
+    Radial(u32),
+}
+
+impl DisplaySymmetry {
+    /// Returns the texture coordinates displayed at the given ones. Coordinates are
+    /// between 0 and 1 with the origin at the top left, the aspect ratio is the
+    /// width of the canvas divided by its height.
+    pub fn source_uv(&self, uv: Vec2, aspect: f32) -> Vec2 {
+        match *self {
+            Self::None => uv,
+            Self::MirrorX => Vec2::new(uv.x.min(1. - uv.x), uv.y),
+            Self::MirrorY => Vec2::new(uv.x, uv.y.min(1. - uv.y)),
+            Self::Quad => uv.min(1. - uv),
+            Self::Radial(folds) => {
+                let scale = Vec2::new(aspect, 1.);
+                let p = (uv - 0.5) * scale;
+                let wedge = std::f32::consts::TAU / folds.max(1) as f32;
+                let angle = p.y.atan2(p.x).rem_euclid(wedge);
+                let angle = angle.min(wedge - angle);
+                (p.length() * Vec2::from_angle(angle) / scale + 0.5).clamp(Vec2::ZERO, Vec2::ONE)
+            }
+        }
+    }
+}
+
+/// Resource holding the palette the NCA is displayed through. Without a palette,
+/// the channels are displayed as RGB. Otherwise a scalar is taken from the channels
+/// and mapped through the palette. Only the display is affected.
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq)]
+pub struct DisplayPalette {
+    pub palette: Option<Palette>,
+    pub source: PaletteSource,
+}
+
+/// Resource holding how the color channels of the NCA are combined into the
+/// displayed color. Only the display is affected. Blends other than RGB show a gray
+/// value, which a palette with the luminance as its source maps through its colors.
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DisplayBlend {
+    /// The channels are displayed as red, green and blue.
+    #[default]
+    Rgb,
+    /// The strongest channel of a cell is displayed.
+    Max,
+    /// The sum of the channels is displayed, clamped to white.
+    Additive,
 }
 
-/// A buffer to hold the filter data of the NCA. Is passed to the shader as a
-/// uniform.
+/// Resource holding the channels of the NCA displayed as red, green and blue, by
+/// their index among all channels. Indices past the alpha channel show the hidden
+/// channels. Only the display is affected.
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayChannels {
+    pub sources: [usize; 3],
+}
+
+impl Default for DisplayChannels {
+    fn default() -> Self {
+        Self { sources: [0, 1, 2] }
+    }
+}
+
+/// Resource holding the settings of the grid drawn over the display at the
+/// boundaries of the cells, e.g. to inspect single cells. Only the display is
+/// affected.
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct DisplayGrid {
+    pub enabled: bool,
+    /// The size of a cell on screen in pixels from which on the grid is drawn, so
+    /// that it doesn't clutter the view when zoomed out.
+    pub min_cell_size: f32,
+}
+
+impl Default for DisplayGrid {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_cell_size: 8.,
+        }
+    }
+}
+
+/// The scalar of a cell mapped through the palette.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PaletteSource {
+    /// The luminance of the color channels.
+    #[default]
+    Luminance,
+    /// A single channel, 0 to 3 for red, green, blue and alpha.
+    Channel(u32),
+}
+
+/// The color ramps available for the display.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Palette {
+    Grayscale,
+    Viridis,
+    Magma,
+    Inferno,
+    /// A gradient between two sRGB colors.
+    Gradient([f32; 3], [f32; 3]),
+}
+
+impl Palette {
+    /// The built-in palettes.
+    pub const BUILT_IN: [Palette; 4] = [
+        Palette::Grayscale,
+        Palette::Viridis,
+        Palette::Magma,
+        Palette::Inferno,
+    ];
+
+    /// Returns the sRGB color of the palette at the given position from 0 to 1.
+    pub fn sample(&self, t: f32) -> [f32; 3] {
+        let stops: &[u32] = match self {
+            Self::Grayscale => &[0x000000, 0xffffff],
+            Self::Viridis => &[
+                0x440154, 0x482878, 0x3e4a89, 0x31688e, 0x26828e,
+                0x1f9e89, 0x35b779, 0x6dcd59, 0xb4de2c, 0xfde725,
+            ],
+            Self::Magma => &[
+                0x000004, 0x180f3d, 0x440f76, 0x721f81, 0x9e2f7f,
+                0xcd4071, 0xf1605d, 0xfd9668, 0xfec98d, 0xfcfdbf,
+            ],
+            Self::Inferno => &[
+                0x000004, 0x1b0c41, 0x4a0c6b, 0x781c6d, 0xa52c60,
+                0xcf4446, 0xed6925, 0xfb9b06, 0xf7d13d, 0xfcffa4,
+            ],
+            Self::Gradient(start, end) => {
+                return [0, 1, 2].map(|i| start[i] + (end[i] - start[i]) * t.clamp(0., 1.));
+            }
+        };
+        let color = |hex: u32| [16, 8, 0].map(|shift| ((hex >> shift) & 0xff) as f32 / 255.);
+        let position = t.clamp(0., 1.) * (stops.len() - 1) as f32;
+        let index = (position as usize).min(stops.len() - 2);
+        let (a, b) = (color(stops[index]), color(stops[index + 1]));
+        let f = position - index as f32;
+        [0, 1, 2].map(|i| a[i] + (b[i] - a[i]) * f)
+    }
+}
+
+/// Resource holding the format of the NCA textures. Rgba8Unorm quantizes every
+/// channel to 8 bits, which erases values that drift slowly. Rgba16Float keeps them
+/// at twice the memory. By default the shader clamps the channels to [0, 1], so both
+/// formats display the same without tonemapping. Unclamped output only persists in
+/// Rgba16Float textures. Change it with the
+/// [`nca_control::ChangeTextureFormat`] event, which also recreates the textures.
+/// The pipelines are rebuilt whenever it changes.
+#[derive(Resource, ExtractResource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NCATextureFormat {
+    pub format: TextureFormat,
+}
+
+impl Default for NCATextureFormat {
+    fn default() -> Self {
+        Self { format: TextureFormat::Rgba8Unorm }
+    }
+}
+
+impl NCATextureFormat {
+    /// The texture formats the NCA supports.
+    pub const SUPPORTED: [TextureFormat; 2] = [
+        TextureFormat::Rgba8Unorm,
+        TextureFormat::Rgba16Float,
+    ];
+}
+
+/// Resource holding the workgroup size of the NCA compute shader. The NCA pipeline
+/// is rebuilt whenever it changes.
+#[derive(Resource, ExtractResource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NCAWorkgroupSize {
+    pub size: UVec2,
+}
+
+impl Default for NCAWorkgroupSize {
+    fn default() -> Self {
+        Self { size: UVec2::splat(WORKGROUP_SIZE) }
+    }
+}
+
+/// Resource to monitor the NCA rendering pipeline. The status is shared with the
+/// render world, which reports the state of the pipeline back to the main world.
+#[derive(Resource, ExtractResource, Debug, Clone)]
+pub struct NCAPipelineMonitor {
+    status: Arc<Mutex<NCAPipelineStatus>>,
+    error: Arc<Mutex<Option<String>>>,
+}
+
+impl Default for NCAPipelineMonitor {
+    fn default() -> Self {
+        Self {
+            status: Arc::new(Mutex::new(NCAPipelineStatus::default())),
+            error: Arc::new(Mutex::new(None)),
+        }
+    }
+}
+
+impl NCAPipelineMonitor {
+    /// Returns the status of the pipeline last reported by the render world.
+    pub fn status(&self) -> NCAPipelineStatus {
+        *self.status.lock().unwrap()
+    }
+
+    /// Sets the state of the pipeline.
+    pub(crate) fn set_state(&self, state: NCAPipelineState) {
+        self.status.lock().unwrap().state = state;
+    }
+
+    /// Marks the pipeline as reinitialized and loading.
+    pub(crate) fn reinitialized(&self) {
+        let mut status = self.status.lock().unwrap();
+        status.state = NCAPipelineState::Loading;
+        status.reinit_count += 1;
+        status.generation = 0;
+    }
+
+    /// Counts the update steps dispatched by the pipeline.
+    pub(crate) fn advanced(&self, steps: u32) {
+        self.status.lock().unwrap().generation += steps as u64;
+    }
+
+    /// Returns the error the pipeline last failed to compile with, if it hasn't
+    /// compiled successfully since.
+    pub fn error(&self) -> Option<String> {
+        self.error.lock().unwrap().clone()
+    }
+
+    /// Reports the error the pipeline failed to compile with, or None once it
+    /// compiled successfully.
+    pub(crate) fn set_error(&self, error: Option<String>) {
+        *self.error.lock().unwrap() = error;
+    }
+
+    /// Reports the index of the bind group the pipeline used last.
+    pub(crate) fn set_current_bind_group(&self, index: usize) {
+        self.status.lock().unwrap().current_bind_group = index;
+    }
+}
+
+/// The status of the NCA rendering pipeline.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct NCAPipelineStatus {
+    /// The current state of the pipeline.
+    pub state: NCAPipelineState,
+    /// The number of times the pipeline has been reinitialized.
+    pub reinit_count: u64,
+    /// The number of update steps since the last reinitialization.
+    pub generation: u64,
+    /// The index of the bind group the pipeline used last. The texture it wrote to
+    /// holds the current state of the NCA.
+    pub current_bind_group: usize,
+}
+
+/// The state of the NCA rendering pipeline.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum NCAPipelineState {
+    /// The pipeline is waiting for its shaders to compile.
+    #[default]
+    Loading,
+    /// The pipeline did not finish loading within the timeout of the
+    /// [`nca_control::ShaderWatchdog`].
+    Stuck,
+    /// The pipeline is up and running.
+    Running,
+}
+
+/// Buffers to hold the filter data of the NCA. Are passed to the shader as storage
+/// buffers. Each holds the filters of one channel applied to all channels, one
+/// after the other. The filters of all hidden channels share a single buffer. The
+/// parameters of the initial state and the time passed to the activation functions
+/// are passed as uniform buffers.
 #[derive(Resource, Clone, ExtractResource)]
 pub(super) struct NCABuffers {
     pub buffer_red: Buffer,
     pub buffer_green: Buffer,
     pub buffer_blue: Buffer,
+    pub buffer_alpha: Buffer,
+    pub buffer_hidden: Buffer,
+    pub buffer_init: Buffer,
+    pub buffer_time: Buffer,
+}
+
+/// The parameters of the initial state of the NCA as passed to the shader.
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+struct NCAInitParams {
+    seed: u32,
+    mode: u32,
 }
 
 // ================================== Systems =================================== //
 
 /// On startup, this system adds two images (in- and output for the NCA compute
-/// shader), spawns a sprite bundle corresponding to one of the images and adds
-/// .
+/// shader) and two placeholders for the hidden channels, marks one of them as the
+/// front texture and adds the buffers holding the NCA filters.
 fn setup(
     mut commands: Commands,
     mut images: ResMut<Assets<Image>>,
-    render_device: Res<RenderDevice>
+    render_device: Res<RenderDevice>,
+    sim_size: Res<NCASimSize>,
+    format: Res<NCATextureFormat>,
 ) {
-    let texture_a = create_image(SIM_SIZE.0, SIM_SIZE.1);
-    let texture_b = create_image(SIM_SIZE.0, SIM_SIZE.1);
+    let texture_a = create_image(sim_size.size.x, sim_size.size.y, format.format);
+    let texture_b = create_image(sim_size.size.x, sim_size.size.y, format.format);
     let texture_a = images.add(texture_a);
     let texture_b = images.add(texture_b);
+    // The hidden channels are set up once the settings are loaded and the NCA is
+    // initialized.
+    let hidden_a = images.add(create_hidden_image(sim_size.size, 0));
+    let hidden_b = images.add(create_hidden_image(sim_size.size, 0));
 
-    commands.spawn(SpriteBundle {
-        sprite: Sprite {
-            custom_size: Some(Vec2::new(SIM_SIZE.0 as f32, SIM_SIZE.1 as f32)),
-            ..default()
-        },
-        texture: texture_a.clone(),
-        ..default()
-    });
-
-    commands.spawn(Camera2dBundle::default());
-    commands.insert_resource(NCAImages{ texture_a, texture_b });
+    // The initial state is written to the second texture.
+    commands.insert_resource(NCAFrontTexture { handle: texture_b.clone_weak() });
+    commands.insert_resource(NCAImages{ texture_a, texture_b, hidden_a, hidden_b });
     commands.insert_resource(
-        create_uniform_nca_buffer(NCAFilter::empty(), &render_device)
+        create_filter_buffers(&NCASettings::default(), &render_device)
     );
 }
 
-/// A system that switches the pointer of the displayed image after each compute
-/// shader pass.
+/// A system that determines the number of steps the NCA advances in this frame and
+/// points the front texture to the texture the NCA node reported to have written
+/// last. Only the render world knows which texture holds the current state, e.g.
+/// across initializing the NCA, so the front texture follows its reports instead
+/// of toggling on its own. The time of the NCA advances in frames it is updated in.
 fn switch_textures(
     images: Res<NCAImages>,
-    mut displayed: Query<&mut Handle<Image>>,
+    monitor: Res<NCAPipelineMonitor>,
+    time: Res<Time>,
+    mut control: ResMut<SimulationControl>,
+    mut front: ResMut<NCAFrontTexture>,
 ) {
-    let mut displayed = displayed.single_mut();
-    if *displayed == images.texture_a {
-        *displayed = images.texture_b.clone_weak();
+    let control = control.as_mut();
+    control.steps = if !control.paused {
+        control.steps_per_frame.min(MAX_STEPS_PER_FRAME)
     } else {
-        *displayed = images.texture_a.clone_weak();
+        std::mem::take(&mut control.step) as u32
+    };
+    if control.steps > 0 {
+        control.time += time.delta_seconds();
+    }
+    let current = images.written_by(monitor.status().current_bind_group);
+    if front.handle != *current {
+        front.handle = current.clone_weak();
     }
 }
 
 // =================================== Utils ==================================== //
 
-fn create_image(width: u32, height: u32) -> Image {
+fn create_image(width: u32, height: u32, format: TextureFormat) -> Image {
+    create_filled_image(width, height, format, [0., 0., 0., 1.])
+}
+
+fn create_filled_image(width: u32, height: u32, format: TextureFormat, fill: [f32; 4]) -> Image {
     let mut image = Image::new_fill(
         Extent3d {
             width,
@@ -125,47 +617,125 @@ fn create_image(width: u32, height: u32) -> Image {
             depth_or_array_layers: 1,
         },
         TextureDimension::D2,
-        &[0, 0, 0, 255],
-        TextureFormat::Rgba8Unorm,
+        &utils::encode_pixel(fill, format),
+        format,
         RenderAssetUsages::RENDER_WORLD,
     );
 
-    image.texture_descriptor.usage =
-        TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
+    image.texture_descriptor.usage = TextureUsages::COPY_DST
+        | TextureUsages::COPY_SRC
+        | TextureUsages::STORAGE_BINDING
+        | TextureUsages::TEXTURE_BINDING;
 
     image
 }
 
-fn create_uniform_buffer<T: bytemuck::Pod + bytemuck::Zeroable>(
+/// Creates a texture holding the given number of hidden channels for a simulation of
+/// the given size, all zero.
+fn create_hidden_image(sim_size: UVec2, hidden_channels: usize) -> Image {
+    let size = hidden_image_size(sim_size, hidden_channels);
+    create_filled_image(size.x, size.y, HIDDEN_TEXTURE_FORMAT, [0.; 4])
+}
+
+/// Returns the size of the texture holding the given number of hidden channels. The
+/// tiles of the hidden channels are stacked below each other, each the size of the
+/// simulation. Without hidden channels, the texture is a placeholder of a single
+/// pixel, as the pipelines always bind one.
+fn hidden_image_size(sim_size: UVec2, hidden_channels: usize) -> UVec2 {
+    match hidden_tiles(hidden_channels) {
+        0 => UVec2::ONE,
+        tiles => UVec2::new(sim_size.x, sim_size.y * tiles as u32),
+    }
+}
+
+/// Returns the number of tiles holding the given number of hidden channels.
+fn hidden_tiles(hidden_channels: usize) -> usize {
+    hidden_channels.div_ceil(HIDDEN_CHANNELS_PER_TILE)
+}
+
+/// Returns the largest number of hidden channels whose tiles fit into a texture of
+/// the given largest dimension for a simulation of the given size.
+fn max_hidden_channels(sim_size: UVec2, max_dimension: u32) -> usize {
+    let tiles = (max_dimension / sim_size.y.max(1)) as usize;
+    (tiles * HIDDEN_CHANNELS_PER_TILE).min(MAX_HIDDEN_CHANNELS)
+}
+
+fn create_filter_buffer(
     device: &RenderDevice,
-    data: &[T],
+    filter: &[f32],
     label: Option<&str>,
 ) -> Buffer {
     device.create_buffer_with_data(&BufferInitDescriptor {
         label,
-        contents: bytemuck::cast_slice(data),
+        contents: bytemuck::cast_slice(filter),
+        usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+    })
+}
+
+fn create_init_buffer(device: &RenderDevice, settings: &NCASettings) -> Buffer {
+    let init_params = NCAInitParams {
+        seed: settings.seed,
+        mode: settings.init_mode as u32,
+    };
+    device.create_buffer_with_data(&BufferInitDescriptor {
+        label: Some("Init Params"),
+        contents: bytemuck::bytes_of(&init_params),
         usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
     })
 }
 
-fn create_uniform_nca_buffer(
-    filter: NCAFilter,
+fn create_time_buffer(device: &RenderDevice) -> Buffer {
+    device.create_buffer_with_data(&BufferInitDescriptor {
+        label: Some("Time"),
+        contents: bytemuck::bytes_of(&0f32),
+        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+    })
+}
+
+fn create_filter_buffers(
+    settings: &NCASettings,
     device: &RenderDevice,
 ) -> NCABuffers {
-    let buffer_red = create_uniform_buffer(
+    let buffer_red = create_filter_buffer(
+        device,
+        &settings.red.filters.concat(),
+        Some("Red Filter"),
+    );
+    let buffer_green = create_filter_buffer(
         device,
-        &[filter.red],
-        Some("Red Uniform"),
+        &settings.green.filters.concat(),
+        Some("Green Filter"),
     );
-    let buffer_green = create_uniform_buffer(
+    let buffer_blue = create_filter_buffer(
         device,
-        &[filter.green],
-        Some("Green Uniform"),
+        &settings.blue.filters.concat(),
+        Some("Blue Filter"),
     );
-    let buffer_blue = create_uniform_buffer(
+    let buffer_alpha = create_filter_buffer(
         device,
-        &[filter.blue],
-        Some("Blue Uniform"),
+        &settings.alpha.filters.concat(),
+        Some("Alpha Filter"),
     );
-    NCABuffers{ buffer_red, buffer_green, buffer_blue }
-}
\ No newline at end of file
+    // Storage buffers can't be empty, so there is a single weight without hidden
+    // channels.
+    let mut hidden: Vec<f32> = settings
+        .hidden
+        .iter()
+        .flat_map(|channel| channel.filters.concat())
+        .collect();
+    if hidden.is_empty() {
+        hidden.push(0.);
+    }
+    let buffer_hidden = create_filter_buffer(device, &hidden, Some("Hidden Filters"));
+    let buffer_init = create_init_buffer(device, settings);
+    let buffer_time = create_time_buffer(device);
+    NCABuffers {
+        buffer_red,
+        buffer_green,
+        buffer_blue,
+        buffer_alpha,
+        buffer_hidden,
+        buffer_init,
+        buffer_time,
+    }
+}
@@ -0,0 +1,284 @@
+//! Configurable key bindings
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt};
+
+use super::preferences::UIPreferences;
+
+// ================================= Constants ================================== //
+
+/// The keys that can be bound to actions. Escape is left out, it cancels
+/// rebinding in the UI.
+const BINDABLE_KEYS: [KeyCode; 72] = [
+    KeyCode::KeyA, KeyCode::KeyB, KeyCode::KeyC, KeyCode::KeyD, KeyCode::KeyE,
+    KeyCode::KeyF, KeyCode::KeyG, KeyCode::KeyH, KeyCode::KeyI, KeyCode::KeyJ,
+    KeyCode::KeyK, KeyCode::KeyL, KeyCode::KeyM, KeyCode::KeyN, KeyCode::KeyO,
+    KeyCode::KeyP, KeyCode::KeyQ, KeyCode::KeyR, KeyCode::KeyS, KeyCode::KeyT,
+    KeyCode::KeyU, KeyCode::KeyV, KeyCode::KeyW, KeyCode::KeyX, KeyCode::KeyY,
+    KeyCode::KeyZ,
+    KeyCode::Digit0, KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3, KeyCode::Digit4,
+    KeyCode::Digit5, KeyCode::Digit6, KeyCode::Digit7, KeyCode::Digit8, KeyCode::Digit9,
+    KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4, KeyCode::F5, KeyCode::F6,
+    KeyCode::F7, KeyCode::F8, KeyCode::F9, KeyCode::F10, KeyCode::F11, KeyCode::F12,
+    KeyCode::Space, KeyCode::Enter, KeyCode::Tab, KeyCode::Backspace, KeyCode::Delete,
+    KeyCode::Insert, KeyCode::Home, KeyCode::End, KeyCode::PageUp, KeyCode::PageDown,
+    KeyCode::ArrowUp, KeyCode::ArrowDown, KeyCode::ArrowLeft, KeyCode::ArrowRight,
+    KeyCode::Minus, KeyCode::Equal, KeyCode::BracketLeft, KeyCode::BracketRight,
+    KeyCode::Comma, KeyCode::Period, KeyCode::Slash, KeyCode::Semicolon, KeyCode::Quote,
+    KeyCode::Backslash,
+];
+
+/// The mouse buttons that can be bound to actions.
+const BINDABLE_MOUSE_BUTTONS: [MouseButton; 5] = [
+    MouseButton::Left,
+    MouseButton::Right,
+    MouseButton::Middle,
+    MouseButton::Back,
+    MouseButton::Forward,
+];
+
+// ================================ Resources =================================== //
+
+/// The actions that can be bound to a key or mouse button.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    PanUp,
+    PanDown,
+    PanLeft,
+    PanRight,
+    DragPan,
+    Draw,
+    Erase,
+    Pause,
+    Step,
+    ResetCamera,
+    Screenshot,
+    ClearCanvas,
+}
+
+impl Action {
+    /// All actions, in the order they are shown in the UI.
+    pub const ALL: [Action; 12] = [
+        Action::PanUp,
+        Action::PanDown,
+        Action::PanLeft,
+        Action::PanRight,
+        Action::DragPan,
+        Action::Draw,
+        Action::Erase,
+        Action::Pause,
+        Action::Step,
+        Action::ResetCamera,
+        Action::Screenshot,
+        Action::ClearCanvas,
+    ];
+
+    /// Returns the name of the action shown in the UI.
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::PanUp => "Pan Up",
+            Action::PanDown => "Pan Down",
+            Action::PanLeft => "Pan Left",
+            Action::PanRight => "Pan Right",
+            Action::DragPan => "Drag To Pan",
+            Action::Draw => "Draw",
+            Action::Erase => "Erase",
+            Action::Pause => "Pause",
+            Action::Step => "Step",
+            Action::ResetCamera => "Reset Camera",
+            Action::Screenshot => "Screenshot",
+            Action::ClearCanvas => "Clear Canvas",
+        }
+    }
+
+    /// Returns the name of the action in the settings file.
+    fn key(self) -> &'static str {
+        match self {
+            Action::PanUp => "pan_up",
+            Action::PanDown => "pan_down",
+            Action::PanLeft => "pan_left",
+            Action::PanRight => "pan_right",
+            Action::DragPan => "drag_pan",
+            Action::Draw => "draw",
+            Action::Erase => "erase",
+            Action::Pause => "pause",
+            Action::Step => "step",
+            Action::ResetCamera => "reset_camera",
+            Action::Screenshot => "screenshot",
+            Action::ClearCanvas => "clear_canvas",
+        }
+    }
+
+    /// Returns the key or mouse button the action is bound to by default.
+    fn default_binding(self) -> Binding {
+        match self {
+            Action::PanUp => Binding::Key(KeyCode::KeyW),
+            Action::PanDown => Binding::Key(KeyCode::KeyS),
+            Action::PanLeft => Binding::Key(KeyCode::KeyA),
+            Action::PanRight => Binding::Key(KeyCode::KeyD),
+            Action::DragPan => Binding::Mouse(MouseButton::Middle),
+            Action::Draw => Binding::Mouse(MouseButton::Left),
+            Action::Erase => Binding::Mouse(MouseButton::Right),
+            Action::Pause => Binding::Key(KeyCode::Space),
+            Action::Step => Binding::Key(KeyCode::Period),
+            Action::ResetCamera => Binding::Key(KeyCode::KeyR),
+            Action::Screenshot => Binding::Key(KeyCode::F12),
+            Action::ClearCanvas => Binding::Key(KeyCode::Delete),
+        }
+    }
+}
+
+/// A key or mouse button an action is bound to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Binding::Key(key) => {
+                let name = format!("{:?}", key);
+                let name = name
+                    .strip_prefix("Key")
+                    .or_else(|| name.strip_prefix("Digit"))
+                    .unwrap_or(&name);
+                write!(f, "{}", name)
+            }
+            Binding::Mouse(button) => write!(f, "Mouse {:?}", button),
+        }
+    }
+}
+
+impl Binding {
+    /// Returns the binding with the given name as shown by its Display
+    /// implementation, or None if there is no bindable key or mouse button of that
+    /// name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        BINDABLE_KEYS
+            .into_iter()
+            .map(Binding::Key)
+            .chain(BINDABLE_MOUSE_BUTTONS.into_iter().map(Binding::Mouse))
+            .find(|binding| binding.to_string() == name)
+    }
+
+    /// Returns the first bindable key or mouse button pressed in this frame.
+    pub fn just_pressed(
+        keys: &ButtonInput<KeyCode>,
+        mouse: &ButtonInput<MouseButton>,
+    ) -> Option<Self> {
+        BINDABLE_KEYS
+            .into_iter()
+            .find(|&key| keys.just_pressed(key))
+            .map(Binding::Key)
+            .or_else(|| {
+                BINDABLE_MOUSE_BUTTONS
+                    .into_iter()
+                    .find(|&button| mouse.just_pressed(button))
+                    .map(Binding::Mouse)
+            })
+    }
+}
+
+/// The keys and mouse buttons bound to the actions. Stored in the settings file as
+/// the names of the actions and bindings. Actions missing from the file or bound
+/// to unknown keys keep their default binding.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "BTreeMap<String, String>", into = "BTreeMap<String, String>")]
+pub struct KeyBindings {
+    bindings: [Binding; Action::ALL.len()],
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self { bindings: Action::ALL.map(Action::default_binding) }
+    }
+}
+
+impl From<BTreeMap<String, String>> for KeyBindings {
+    fn from(names: BTreeMap<String, String>) -> Self {
+        let mut bindings = Self::default();
+        for (action, name) in names {
+            let Some(action) = Action::ALL.into_iter().find(|a| a.key() == action) else {
+                warn!("Ignoring key binding of unknown action \"{}\".", action);
+                continue;
+            };
+            match Binding::from_name(&name) {
+                Some(binding) => bindings.set(action, binding),
+                None => warn!("Can't bind \"{}\" to {}.", name, action.name()),
+            }
+        }
+        bindings
+    }
+}
+
+impl From<KeyBindings> for BTreeMap<String, String> {
+    fn from(bindings: KeyBindings) -> Self {
+        Action::ALL
+            .into_iter()
+            .map(|action| (action.key().to_owned(), bindings.get(action).to_string()))
+            .collect()
+    }
+}
+
+impl KeyBindings {
+    /// Returns the key or mouse button the action is bound to.
+    pub fn get(&self, action: Action) -> Binding {
+        self.bindings[action as usize]
+    }
+
+    /// Binds the action to a key or mouse button.
+    pub fn set(&mut self, action: Action, binding: Binding) {
+        self.bindings[action as usize] = binding;
+    }
+
+    /// Returns the pairs of actions bound to the same key or mouse button.
+    pub fn conflicts(&self) -> Vec<(Action, Action)> {
+        let mut conflicts = Vec::new();
+        for (i, &first) in Action::ALL.iter().enumerate() {
+            for &second in &Action::ALL[i + 1..] {
+                if self.get(first) == self.get(second) {
+                    conflicts.push((first, second));
+                }
+            }
+        }
+        conflicts
+    }
+}
+
+/// The input state of the keys and mouse buttons together with the bindings, to
+/// query actions instead of keys.
+#[derive(SystemParam)]
+pub struct ActionInput<'w> {
+    preferences: Res<'w, UIPreferences>,
+    keys: Res<'w, ButtonInput<KeyCode>>,
+    mouse: Res<'w, ButtonInput<MouseButton>>,
+}
+
+impl ActionInput<'_> {
+    /// Returns true while the key or mouse button of the action is held.
+    pub fn pressed(&self, action: Action) -> bool {
+        match self.preferences.key_bindings.get(action) {
+            Binding::Key(key) => self.keys.pressed(key),
+            Binding::Mouse(button) => self.mouse.pressed(button),
+        }
+    }
+
+    /// Returns true in the frame the key or mouse button of the action is pressed.
+    pub fn just_pressed(&self, action: Action) -> bool {
+        match self.preferences.key_bindings.get(action) {
+            Binding::Key(key) => self.keys.just_pressed(key),
+            Binding::Mouse(button) => self.mouse.just_pressed(button),
+        }
+    }
+
+    /// Returns true in the frame the key or mouse button of the action is released.
+    pub fn just_released(&self, action: Action) -> bool {
+        match self.preferences.key_bindings.get(action) {
+            Binding::Key(key) => self.keys.just_released(key),
+            Binding::Mouse(button) => self.mouse.just_released(button),
+        }
+    }
+}
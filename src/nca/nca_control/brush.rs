@@ -0,0 +1,79 @@
+//! Loading images to stamp with the brush
+
+use bevy::{
+    prelude::*,
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+};
+use std::path::PathBuf;
+
+// =================================== Plugin =================================== //
+
+/// A plugin to load images that the image brush stamps onto the canvas.
+pub(super) struct BrushPlugin;
+
+impl Plugin for BrushPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<NCABrushImage>()
+            .add_plugins(ExtractResourcePlugin::<NCABrushImage>::default())
+            .add_event::<LoadBrushImage>()
+            .add_systems(Update, on_load_brush_image);
+    }
+}
+
+// ================================ Resources =================================== //
+
+/// Resource holding the image stamped by the image brush. Its colors are blended
+/// into the canvas by their alpha, like the brush color by the brush opacity.
+#[derive(Resource, ExtractResource, Debug, Default, Clone)]
+pub struct NCABrushImage {
+    pub handle: Option<Handle<Image>>,
+}
+
+// ================================== Events ==================================== //
+
+/// An event that loads the image at the given path as the image of the image brush.
+#[derive(Event, Debug)]
+pub struct LoadBrushImage {
+    pub path: PathBuf,
+}
+
+// ================================== Systems =================================== //
+
+/// System triggered by the LoadBrushImage event. Reads the image from the file and
+/// makes it the image of the image brush.
+fn on_load_brush_image(
+    mut ev_reader_load_brush_image: EventReader<LoadBrushImage>,
+    mut images: ResMut<Assets<Image>>,
+    mut brush_image: ResMut<NCABrushImage>,
+) {
+    for event in ev_reader_load_brush_image.read() {
+        let loaded = match image::open(&event.path) {
+            Ok(loaded) => loaded.into_rgba8(),
+            Err(err) => {
+                warn!("Couldn't load brush image: {}", err);
+                continue;
+            }
+        };
+
+        info!("Loading brush image from {}.", event.path.display());
+        let (width, height) = loaded.dimensions();
+        // The values are stamped as they are, like the states of the NCA.
+        let image = Image::new(
+            Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            loaded.into_raw(),
+            TextureFormat::Rgba8Unorm,
+            RenderAssetUsages::RENDER_WORLD,
+        );
+        brush_image.handle = Some(images.add(image));
+    }
+}
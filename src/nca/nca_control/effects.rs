@@ -0,0 +1,65 @@
+//! Applying one-shot effects to the canvas
+
+use bevy::prelude::*;
+use std::collections::VecDeque;
+
+use super::{
+    super::pipeline::effect::{ImageEffect, NCAEffectRequest},
+    history::SnapshotCanvas,
+};
+
+// =================================== Plugin =================================== //
+
+/// A plugin to apply one-shot effects like inverting the colors to the canvas. The
+/// canvas is saved to the history before each effect, so that it can be undone like
+/// a stroke. Effects are applied one per frame, in the order they were requested.
+pub(super) struct EffectsPlugin;
+
+impl Plugin for EffectsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<PendingEffects>()
+            .add_event::<ApplyEffect>()
+            .add_systems(Update, (on_apply_effect, request_effect).chain());
+    }
+}
+
+// ================================ Resources =================================== //
+
+/// A resource holding the effects waiting to be applied, in the order they were
+/// requested.
+#[derive(Resource, Debug, Default)]
+struct PendingEffects(VecDeque<ImageEffect>);
+
+// ================================== Events ==================================== //
+
+/// An event that applies the effect to the current state of the canvas.
+#[derive(Event, Debug)]
+pub struct ApplyEffect {
+    pub effect: ImageEffect,
+}
+
+// ================================== Systems =================================== //
+
+/// System triggered by the ApplyEffect event. Queues the effect.
+fn on_apply_effect(
+    mut ev_reader_apply_effect: EventReader<ApplyEffect>,
+    mut pending: ResMut<PendingEffects>,
+) {
+    for event in ev_reader_apply_effect.read() {
+        pending.0.push_back(event.effect);
+    }
+}
+
+/// A system that requests the next pending effect from the render world. The
+/// snapshot is read back in the same frame, before the effect is applied.
+fn request_effect(
+    mut pending: ResMut<PendingEffects>,
+    mut request: ResMut<NCAEffectRequest>,
+    mut ev_writer_snapshot: EventWriter<SnapshotCanvas>,
+) {
+    let Some(effect) = pending.0.pop_front() else { return };
+    info!("Applying effect {:?}.", effect);
+    ev_writer_snapshot.send(SnapshotCanvas);
+    request.request(effect);
+}
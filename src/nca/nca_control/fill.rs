@@ -0,0 +1,163 @@
+//! Filling regions of the canvas
+
+use bevy::{prelude::*, utils::HashMap};
+
+use super::super::{
+    create_image,
+    pipeline::{
+        draw::NCADrawSettings,
+        nca::NCAImages,
+        readback::{NCAReadback, ReadbackComplete, ReadbackId, ReadbackPurpose},
+    },
+    utils::{encode_pixel, to_rgba8},
+    NCAFrontTexture,
+    NCASimSize,
+    NCATextureFormat,
+};
+
+// =================================== Plugin =================================== //
+
+/// A plugin to fill connected regions of similar color with the brush color. The
+/// canvas is read back from the GPU, filled on the CPU and uploaded into both
+/// textures of the NCA. The round trip takes a few frames, so filling is bound to
+/// single clicks. The region is found on an RGBA8 copy of the canvas, so that only
+/// the filled cells lose the precision of floating point textures.
+pub(super) struct FillPlugin;
+
+impl Plugin for FillPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<PendingFills>()
+            .add_event::<FloodFill>()
+            // The front texture is switched during the update, so only request it
+            // afterwards.
+            .add_systems(PostUpdate, on_flood_fill)
+            .add_systems(Update, apply_fills);
+    }
+}
+
+// ================================ Resources =================================== //
+
+/// A fill waiting for the readback of the canvas.
+#[derive(Debug, Clone, Copy)]
+struct PendingFill {
+    position: UVec2,
+    color: [f32; 3],
+    tolerance: f32,
+}
+
+/// A resource holding the fills waiting to be read back by the id of their
+/// readback.
+#[derive(Resource, Debug, Default)]
+struct PendingFills(HashMap<ReadbackId, PendingFill>);
+
+// ================================== Events ==================================== //
+
+/// An event that fills the region around the given canvas position with the brush
+/// color. A cell belongs to the region if it is connected to the position and no
+/// color channel differs from the cell at the position by more than the fill
+/// tolerance.
+#[derive(Event, Debug)]
+pub struct FloodFill {
+    pub position: UVec2,
+}
+
+// ================================== Systems =================================== //
+
+/// System triggered by the FloodFill event. Requests a readback of the front
+/// texture and remembers the brush color and tolerance at the time of the click.
+fn on_flood_fill(
+    mut ev_reader_flood_fill: EventReader<FloodFill>,
+    mut readback: ResMut<NCAReadback>,
+    mut pending: ResMut<PendingFills>,
+    front: Res<NCAFrontTexture>,
+    draw_params: Res<NCADrawSettings>,
+) {
+    for event in ev_reader_flood_fill.read() {
+        let id = readback.request(ReadbackPurpose::Fill, front.handle.clone_weak());
+        pending.0.insert(id, PendingFill {
+            position: event.position,
+            color: draw_params.brush_color,
+            tolerance: draw_params.fill_tolerance,
+        });
+    }
+}
+
+/// A system that fills the read back canvases and uploads them into both textures
+/// of the NCA.
+fn apply_fills(
+    mut ev_reader_readback: EventReader<ReadbackComplete>,
+    mut pending: ResMut<PendingFills>,
+    mut images: ResMut<Assets<Image>>,
+    nca_images: Res<NCAImages>,
+    sim_size: Res<NCASimSize>,
+    format: Res<NCATextureFormat>,
+) {
+    for event in ev_reader_readback.read() {
+        let Some(fill) = pending.0.remove(&event.id) else { continue };
+        let Ok(canvas) = &event.result else { continue };
+        let Some(rgba8) = to_rgba8(&canvas.data, canvas.format) else {
+            warn!("Can't fill canvases of format {:?}.", canvas.format);
+            continue;
+        };
+        if canvas.size != sim_size.size || canvas.format != format.format {
+            continue;
+        }
+
+        let tolerance = (fill.tolerance.clamp(0., 1.) * 255.).round() as u8;
+        let region = flood_fill_region(&rgba8, canvas.size, fill.position, tolerance);
+        let [r, g, b] = fill.color;
+        let pixel = encode_pixel([r, g, b, 1.], format.format);
+        let mut data = canvas.data.clone();
+        for (cell, _) in region.iter().enumerate().filter(|(_, &filled)| filled) {
+            data[cell * pixel.len()..(cell + 1) * pixel.len()].copy_from_slice(&pixel);
+        }
+
+        let mut image = create_image(sim_size.size.x, sim_size.size.y, format.format);
+        image.data = data;
+        images.insert(&nca_images.texture_a, image.clone());
+        images.insert(&nca_images.texture_b, image);
+    }
+}
+
+// =================================== Utils ==================================== //
+
+/// Helper function to find the 4-connected region of similar color around a
+/// position of an RGBA8 image. Only the color channels are compared. Returns for
+/// every cell whether it belongs to the region.
+fn flood_fill_region(data: &[u8], size: UVec2, start: UVec2, tolerance: u8) -> Vec<bool> {
+    let (width, height) = (size.x as usize, size.y as usize);
+    let mut visited = vec![false; width * height];
+    if start.x >= size.x || start.y >= size.y {
+        return visited;
+    }
+    let index = |x: usize, y: usize| 4 * (y * width + x);
+
+    let start_index = index(start.x as usize, start.y as usize);
+    let target: [u8; 3] = [0, 1, 2].map(|i| data[start_index + i]);
+    let similar = |pixel: &[u8]| {
+        (0..3).all(|i| pixel[i].abs_diff(target[i]) <= tolerance)
+    };
+
+    let mut stack = vec![(start.x as usize, start.y as usize)];
+    visited[start.y as usize * width + start.x as usize] = true;
+    while let Some((x, y)) = stack.pop() {
+        let neighbors = [
+            x.checked_sub(1).map(|x| (x, y)),
+            (x + 1 < width).then_some((x + 1, y)),
+            y.checked_sub(1).map(|y| (x, y)),
+            (y + 1 < height).then_some((x, y + 1)),
+        ];
+        for (nx, ny) in neighbors.into_iter().flatten() {
+            if visited[ny * width + nx] {
+                continue;
+            }
+            let n = index(nx, ny);
+            if similar(&data[n..n + 4]) {
+                visited[ny * width + nx] = true;
+                stack.push((nx, ny));
+            }
+        }
+    }
+    visited
+}
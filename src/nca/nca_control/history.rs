@@ -0,0 +1,288 @@
+//! Undoing and redoing brush strokes
+
+use bevy::prelude::*;
+use std::collections::VecDeque;
+
+use super::{
+    super::{
+        pipeline::{
+            nca::NCAImages,
+            readback::{NCAReadback, ReadbackComplete, ReadbackData, ReadbackId, ReadbackPurpose},
+        },
+        NCAFrontTexture,
+        NCASimSize,
+        NCATextureFormat,
+    },
+    settings::NCASettings,
+};
+
+// =================================== Plugin =================================== //
+
+/// A plugin to undo and redo brush strokes. The canvas is read back from the GPU
+/// before each stroke and kept in a bounded history, together with the hidden
+/// channels. Undoing uploads the saved canvas into both textures of the NCA and of
+/// the hidden channels, so that it continues from the restored state.
+pub(super) struct HistoryPlugin;
+
+impl Plugin for HistoryPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<DrawHistory>()
+            .add_event::<SnapshotCanvas>()
+            .add_event::<Undo>()
+            .add_event::<Redo>()
+            // The front texture is switched during the update, so only request it
+            // afterwards.
+            .add_systems(PostUpdate, on_history_event)
+            .add_systems(Update, (clear_on_canvas_change, apply_snapshots).chain());
+    }
+}
+
+// ================================ Resources =================================== //
+
+/// An action waiting for the readback of the canvas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HistoryAction {
+    Snapshot,
+    Undo,
+    Redo,
+}
+
+/// A canvas saved in the history together with its hidden channels.
+#[derive(Debug)]
+struct Snapshot {
+    color: ReadbackData,
+    hidden: ReadbackData,
+}
+
+/// An action waiting for the readbacks of the canvas and the hidden channels.
+#[derive(Debug)]
+struct PendingAction {
+    action: HistoryAction,
+    color: (ReadbackId, Option<ReadbackData>),
+    hidden: (ReadbackId, Option<ReadbackData>),
+}
+
+/// A resource holding the saved canvases for undoing and redoing brush strokes.
+///
+/// Every snapshot holds the whole canvas, i.e. 4 bytes per pixel, which is about
+/// 8 MB at a resolution of 1920x1080. With the default depth of 8, the undo and
+/// redo history together take up to about 130 MB. Textures of format Rgba16Float
+/// take twice as much, and hidden channels add another 8 bytes per pixel.
+#[derive(Resource, Debug)]
+pub struct DrawHistory {
+    /// The maximum number of strokes that can be undone.
+    depth: usize,
+    undo: VecDeque<Snapshot>,
+    redo: Vec<Snapshot>,
+    /// The actions whose readbacks are in flight.
+    pending: Vec<PendingAction>,
+}
+
+impl Default for DrawHistory {
+    fn default() -> Self {
+        Self {
+            depth: 8,
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl DrawHistory {
+    /// Returns the maximum number of strokes that can be undone.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Returns true if there is a stroke to undo.
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    /// Returns true if there is an undone stroke to redo.
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Sets the maximum number of strokes that can be undone, dropping the oldest
+    /// ones beyond it right away.
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+        self.trim();
+    }
+
+    /// Adds a snapshot to the undo history, dropping the oldest ones beyond the
+    /// depth of the history.
+    fn push_undo(&mut self, snapshot: Snapshot) {
+        self.undo.push_back(snapshot);
+        self.trim();
+    }
+
+    /// Drops the oldest snapshots beyond the depth of the history.
+    fn trim(&mut self) {
+        while self.undo.len() > self.depth {
+            self.undo.pop_front();
+        }
+    }
+
+    /// Forgets all snapshots and the actions waiting for their readbacks.
+    fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.pending.clear();
+    }
+}
+
+// ================================== Events ==================================== //
+
+/// An event that saves the current canvas to the history before a stroke begins.
+/// Clears the strokes that could be redone.
+#[derive(Event, Debug)]
+pub struct SnapshotCanvas;
+
+/// An event that restores the canvas from before the last stroke.
+#[derive(Event, Debug)]
+pub struct Undo;
+
+/// An event that restores the canvas from before the last undo.
+#[derive(Event, Debug)]
+pub struct Redo;
+
+// ================================== Systems =================================== //
+
+/// System triggered by the SnapshotCanvas, Undo and Redo events. Requests a
+/// readback of the front texture and of the hidden channels belonging to it.
+/// Undoing and redoing need the current canvas as well, so that the restored one
+/// can be swapped back.
+fn on_history_event(
+    mut ev_reader_snapshot: EventReader<SnapshotCanvas>,
+    mut ev_reader_undo: EventReader<Undo>,
+    mut ev_reader_redo: EventReader<Redo>,
+    mut history: ResMut<DrawHistory>,
+    mut readback: ResMut<NCAReadback>,
+    front: Res<NCAFrontTexture>,
+    nca_images: Res<NCAImages>,
+) {
+    let actions = ev_reader_snapshot.read().map(|_| HistoryAction::Snapshot)
+        .chain(ev_reader_undo.read().map(|_| HistoryAction::Undo))
+        .chain(ev_reader_redo.read().map(|_| HistoryAction::Redo));
+    for action in actions {
+        let available = match action {
+            HistoryAction::Snapshot => true,
+            HistoryAction::Undo => history.can_undo(),
+            HistoryAction::Redo => history.can_redo(),
+        };
+        if !available {
+            continue;
+        }
+        let hidden = nca_images.hidden_of(&front.handle).clone_weak();
+        history.pending.push(PendingAction {
+            action,
+            color: (readback.request(ReadbackPurpose::History, front.handle.clone_weak()), None),
+            hidden: (readback.request(ReadbackPurpose::History, hidden), None),
+        });
+    }
+}
+
+/// A system that clears the history when the size or format of the canvas changes,
+/// as the saved canvases can't be restored anymore.
+fn clear_on_canvas_change(
+    mut history: ResMut<DrawHistory>,
+    sim_size: Res<NCASimSize>,
+    format: Res<NCATextureFormat>,
+) {
+    if (sim_size.is_changed() || format.is_changed())
+        && (history.can_undo() || history.can_redo() || !history.pending.is_empty())
+    {
+        info!("Clearing the undo history, the canvas changed.");
+        history.clear();
+    }
+}
+
+/// A system that stores the read back canvases in the history and restores the
+/// canvas when undoing or redoing, once both the canvas and the hidden channels
+/// were read back. The hidden channels are reset if their number changed since the
+/// restored canvas was saved.
+fn apply_snapshots(
+    mut ev_reader_readback: EventReader<ReadbackComplete>,
+    mut history: ResMut<DrawHistory>,
+    mut images: ResMut<Assets<Image>>,
+    nca_images: Res<NCAImages>,
+    sim_size: Res<NCASimSize>,
+    format: Res<NCATextureFormat>,
+    params: Res<NCASettings>,
+) {
+    for event in ev_reader_readback.read() {
+        let Some(index) = history.pending.iter().position(|pending| {
+            pending.color.0 == event.id || pending.hidden.0 == event.id
+        }) else {
+            continue;
+        };
+        let Ok(readback) = &event.result else {
+            history.pending.remove(index);
+            warn!("Couldn't read back the canvas, the history is unchanged.");
+            continue;
+        };
+        let pending = &mut history.pending[index];
+        if pending.color.0 == event.id {
+            pending.color.1 = Some(readback.clone());
+        } else {
+            pending.hidden.1 = Some(readback.clone());
+        }
+        if pending.color.1.is_none() || pending.hidden.1.is_none() {
+            continue;
+        }
+        let PendingAction { action, color: (_, Some(color)), hidden: (_, Some(hidden)) } =
+            history.pending.remove(index)
+        else {
+            continue;
+        };
+        let current = Snapshot { color, hidden };
+
+        if action == HistoryAction::Snapshot {
+            history.push_undo(current);
+            history.redo.clear();
+            continue;
+        }
+        let target = match action {
+            HistoryAction::Undo => history.undo.back(),
+            _ => history.redo.last(),
+        };
+        let Some(target) = target else {
+            info!("Nothing to {}.", if action == HistoryAction::Undo { "undo" } else { "redo" });
+            continue;
+        };
+        // Check the restored canvas before touching the history, so that it stays
+        // unchanged if the canvas can't be restored.
+        if target.color.size != sim_size.size {
+            warn!(
+                "Can't restore a canvas of size {}, the simulation was resized.",
+                target.color.size,
+            );
+            continue;
+        }
+        if target.color.format != format.format {
+            warn!(
+                "Can't restore a canvas of format {:?}, the texture format was changed.",
+                target.color.format,
+            );
+            continue;
+        }
+        let restored = if action == HistoryAction::Undo {
+            history.redo.push(current);
+            history.undo.pop_back()
+        } else {
+            history.push_undo(current);
+            history.redo.pop()
+        };
+        let Some(Snapshot { color, hidden }) = restored else {
+            continue;
+        };
+        nca_images.upload_canvas(&mut images, color);
+        if !nca_images.upload_hidden(&mut images, hidden, sim_size.size, params.hidden.len()) {
+            info!("The number of hidden channels changed, resetting them.");
+        }
+    }
+}
@@ -0,0 +1,139 @@
+//! Options given on launch, e.g. on the command line
+
+use bevy::prelude::*;
+use std::path::{Path, PathBuf};
+
+use super::{
+    super::utils::kernel_size,
+    presets::NCAPresets,
+    settings::NCASettings,
+    ConfigPaths,
+    UpdateActivationFunction,
+    UpdateFilter,
+};
+
+// =================================== Plugin =================================== //
+
+/// A plugin that applies the options the app was launched with. Insert the
+/// [`LaunchOptions`] resource before adding the plugins to use them.
+pub(super) struct LaunchPlugin;
+
+impl Plugin for LaunchPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<LaunchOptions>()
+            // The settings are read on startup, so the path has to be set before.
+            .add_systems(PreStartup, apply_settings_file)
+            // The preset is applied on top of the settings read on startup.
+            .add_systems(PostStartup, apply_preset);
+    }
+}
+
+// ================================ Resources =================================== //
+
+/// A resource holding the options the app was launched with.
+#[derive(Resource, Debug, Default, Clone)]
+pub struct LaunchOptions {
+    /// The settings file to read and write instead of the default one.
+    pub settings: Option<PathBuf>,
+    /// The name of a filter or activation function preset to apply to all channels
+    /// on startup.
+    pub preset: Option<String>,
+}
+
+impl LaunchOptions {
+    /// Parses the options from command line arguments, without the name of the
+    /// executable. Supports `--settings <path>` and `--preset <name>`, also in the
+    /// form `--settings=<path>`. Returns the options together with a warning for
+    /// each argument that couldn't be parsed.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> (Self, Vec<String>) {
+        let mut options = Self::default();
+        let mut warnings = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (flag, value) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_owned(), Some(value.to_owned())),
+                None => (arg, None),
+            };
+            if flag != "--settings" && flag != "--preset" {
+                warnings.push(format!("Ignoring unknown argument \"{}\".", flag));
+                continue;
+            }
+            let Some(value) = value.or_else(|| args.next()) else {
+                warnings.push(format!("Missing value of {}.", flag));
+                continue;
+            };
+            if flag == "--settings" {
+                options.settings = Some(PathBuf::from(value));
+            } else {
+                options.preset = Some(value);
+            }
+        }
+        (options, warnings)
+    }
+}
+
+// ================================== Systems =================================== //
+
+/// A system that points the settings path to the file given on launch. The brush
+/// and camera view are kept in the same directory, so that they don't end up in
+/// the working directory. Falls back to the default files if the settings file
+/// doesn't exist, so that no file is created by mistake.
+fn apply_settings_file(
+    options: Res<LaunchOptions>,
+    mut paths: ResMut<ConfigPaths>,
+) {
+    let Some(path) = &options.settings else { return };
+    if path.is_file() {
+        info!("Using settings file {}.", path.display());
+        let dir = ConfigPaths::in_dir(path.parent().unwrap_or(Path::new("")));
+        paths.settings.clone_from(path);
+        paths.brush = dir.brush;
+        paths.camera = dir.camera;
+    } else {
+        warn!(
+            "Settings file {} doesn't exist, using {} instead.",
+            path.display(),
+            paths.settings.display(),
+        );
+    }
+}
+
+/// A system that applies the preset given on launch to all channels. A filter
+/// preset replaces the filter each channel applies to itself, an activation
+/// function preset replaces the activation functions. Nothing is changed if there
+/// is no preset of that name.
+fn apply_preset(
+    mut ev_writer_update_fn: EventWriter<UpdateActivationFunction>,
+    mut ev_writer_update_filter: EventWriter<UpdateFilter>,
+    mut settings: ResMut<NCASettings>,
+    options: Res<LaunchOptions>,
+    presets: Res<NCAPresets>,
+) {
+    let Some(name) = &options.preset else { return };
+    let activation_fn = presets.activation_fn_presets().iter().find(|(preset, _)| preset == name);
+    let filter = presets.filter_presets().iter().find(|(preset, _)| preset == name);
+
+    if let Some((_, activation_fn)) = activation_fn {
+        info!("Applying activation function preset \"{}\".", name);
+        for channel in settings.channels_mut() {
+            channel.activation_fn.clone_from(activation_fn);
+        }
+        ev_writer_update_fn.send(UpdateActivationFunction);
+    } else if let Some((_, filter)) = filter {
+        let Some(size) = kernel_size(filter.len()) else {
+            warn!("Filter preset has {} weights, which is not an odd square.", filter.len());
+            return;
+        };
+        info!("Applying filter preset \"{}\".", name);
+        for (index, channel) in settings.channels_mut().into_iter().enumerate() {
+            channel.resize_kernel(size);
+            channel.filters[index].clone_from(filter);
+        }
+        // The loop bounds of the shader depend on the kernel size.
+        ev_writer_update_fn.send(UpdateActivationFunction);
+        ev_writer_update_filter.send(UpdateFilter);
+    } else {
+        warn!("There is no preset \"{}\".", name);
+    }
+}
@@ -0,0 +1,177 @@
+//! Freezing regions of the canvas
+
+use bevy::{
+    prelude::*,
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+};
+
+use super::super::{
+    input::NCAMouseParams,
+    pipeline::draw::{DrawTool, NCADrawSettings},
+    NCASimSize,
+};
+
+// =================================== Plugin =================================== //
+
+/// A plugin managing the freeze mask, a single channel texture of the size of the
+/// canvas. While the mask is enabled, the cells painted into it keep their values
+/// in the update of the NCA, e.g. to keep a drawn logo static. The mask is painted
+/// with the mask tool on the CPU and uploaded whenever it changes. Disabling it
+/// binds an empty mask instead, so that the shader and the canvas stay as they
+/// are.
+pub(super) struct MaskPlugin;
+
+impl Plugin for MaskPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_plugins(ExtractResourcePlugin::<NCAFreezeMask>::default())
+            .add_event::<ClearMask>()
+            .add_systems(Startup, setup_mask)
+            .add_systems(Update, (on_clear_mask, resize_mask, paint_mask));
+    }
+}
+
+// ================================ Resources =================================== //
+
+/// A resource holding the image of the freeze mask. Cells with a value above 0.5
+/// are frozen while the mask is enabled.
+#[derive(Resource, ExtractResource, Debug, Clone)]
+pub struct NCAFreezeMask {
+    pub enabled: bool,
+    pub image: Handle<Image>,
+    /// A single empty cell, bound while the mask is disabled.
+    empty: Handle<Image>,
+}
+
+impl NCAFreezeMask {
+    /// Returns the image the NCA reads the mask from.
+    pub fn bound_image(&self) -> &Handle<Image> {
+        if self.enabled {
+            &self.image
+        } else {
+            &self.empty
+        }
+    }
+}
+
+// ================================== Events ==================================== //
+
+/// An event that unfreezes all cells.
+#[derive(Event, Debug)]
+pub struct ClearMask;
+
+// ================================== Systems =================================== //
+
+/// On startup, this system adds an empty freeze mask of the size of the canvas.
+fn setup_mask(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    sim_size: Res<NCASimSize>,
+) {
+    let image = images.add(create_mask_image(sim_size.size));
+    let empty = images.add(create_mask_image(UVec2::ONE));
+    commands.insert_resource(NCAFreezeMask { enabled: false, image, empty });
+}
+
+/// System triggered by the ClearMask event. Replaces the mask with an empty one.
+fn on_clear_mask(
+    mut ev_reader_clear_mask: EventReader<ClearMask>,
+    mut images: ResMut<Assets<Image>>,
+    mask: Res<NCAFreezeMask>,
+    sim_size: Res<NCASimSize>,
+) {
+    if ev_reader_clear_mask.read().count() == 0 {
+        return;
+    }
+    info!("Clearing freeze mask.");
+    images.insert(&mask.image, create_mask_image(sim_size.size));
+}
+
+/// A system that replaces the mask with an empty one of the new size when the
+/// canvas is resized.
+fn resize_mask(
+    mut images: ResMut<Assets<Image>>,
+    mask: Res<NCAFreezeMask>,
+    sim_size: Res<NCASimSize>,
+) {
+    if !sim_size.is_changed() {
+        return;
+    }
+    let outdated = images
+        .get(&mask.image)
+        .is_none_or(|image| image.size() != sim_size.size);
+    if outdated {
+        images.insert(&mask.image, create_mask_image(sim_size.size));
+    }
+}
+
+/// A system that paints the mask along the stroke of the mask tool, with the size
+/// and symmetry of the brush. Drawing freezes cells, erasing unfreezes them.
+fn paint_mask(
+    mut images: ResMut<Assets<Image>>,
+    mask: Res<NCAFreezeMask>,
+    input_state: Res<NCAMouseParams>,
+    draw_params: Res<NCADrawSettings>,
+    sim_size: Res<NCASimSize>,
+) {
+    let stroking = (input_state.is_drawing || input_state.is_erasing) && !input_state.stroke_start;
+    if draw_params.tool != DrawTool::Mask || !stroking {
+        return;
+    }
+    let size = sim_size.size;
+    let Some(image) = images.get_mut(&mask.image) else { return };
+    if image.size() != size {
+        return;
+    }
+
+    let value = if input_state.is_erasing || draw_params.erase { 0 } else { 255 };
+    let radius = draw_params.brush_size_at_speed(input_state.speed);
+    let starts = draw_params.symmetry.copies(input_state.mouse_pos, size.as_vec2());
+    let ends = draw_params.symmetry.copies(input_state.prev_mouse_pos, size.as_vec2());
+    for (start, end) in starts.into_iter().zip(ends) {
+        paint_segment(&mut image.data, size, start, end, radius, value);
+    }
+}
+
+// =================================== Utils ==================================== //
+
+/// Helper function to create an empty mask of the given size.
+fn create_mask_image(size: UVec2) -> Image {
+    Image::new_fill(
+        Extent3d {
+            width: size.x.max(1),
+            height: size.y.max(1),
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0],
+        TextureFormat::R8Unorm,
+        RenderAssetUsages::MAIN_WORLD | RenderAssetUsages::RENDER_WORLD,
+    )
+}
+
+/// Helper function to set all cells of the mask within the radius of the segment
+/// to the value. Cells are measured like in the draw shader, from their corner.
+fn paint_segment(data: &mut [u8], size: UVec2, start: Vec2, end: Vec2, radius: f32, value: u8) {
+    let last = size.as_vec2() - Vec2::ONE;
+    let min = (start.min(end) - radius).floor().clamp(Vec2::ZERO, last).as_uvec2();
+    let max = (start.max(end) + radius).ceil().clamp(Vec2::ZERO, last).as_uvec2();
+    let segment = end - start;
+    for y in min.y..=max.y {
+        for x in min.x..=max.x {
+            let pos = UVec2::new(x, y).as_vec2();
+            let t = if segment.length_squared() > 0. {
+                ((pos - start).dot(segment) / segment.length_squared()).clamp(0., 1.)
+            } else {
+                0.
+            };
+            if pos.distance(start + t * segment).round() <= radius {
+                data[(y * size.x + x) as usize] = value;
+            }
+        }
+    }
+}
@@ -1,19 +1,66 @@
 //! NCA control
 
+pub mod bindings;
+pub mod brush;
+pub mod effects;
+pub mod fill;
+pub mod history;
+pub mod launch;
+pub mod mask;
+pub mod preferences;
 pub mod presets;
+pub mod probe;
+pub mod profiles;
+pub mod randomize;
+pub mod recording;
+pub mod replay;
+pub mod screenshot;
 pub mod settings;
+pub mod stagnation;
+pub mod stamp;
+pub mod state;
+pub mod workspaces;
 
-use bevy::{prelude::*, render::renderer::RenderDevice};
-use settings::NCASettings;
-use std::fs::write;
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::*,
+    render::{render_resource::TextureFormat, renderer::RenderDevice},
+};
+use preferences::UIPreferences;
+use settings::{
+    channel_name,
+    BoundaryMode,
+    NCAChannel,
+    CHANNELS,
+    NCASettings,
+    Neighborhood,
+    OutputMapping,
+    SaveSettings,
+};
+use std::path::{Path, PathBuf};
 
-use crate::SHADER_ASSET_PATH;
+use crate::NCA_SHADER_HANDLE;
 use super::{
-    pipeline::draw::NCADrawSettings,
+    pipeline::{
+        draw::NCADrawSettings,
+        nca::{nca_shader_defs, resolve_shader_defs, NCAImages},
+    },
     NCABuffers,
+    NCAPipelineMonitor,
+    NCAPipelineState,
+    NCASimSize,
+    NCATextureFormat,
+    NCAWorkgroupSize,
     ReinitPipeline,
-    create_uniform_buffer,
-    utils::mat3_to_buffer_array,
+    SimulationControl,
+    create_filled_image,
+    create_filter_buffers,
+    create_hidden_image,
+    create_init_buffer,
+    hidden_image_size,
+    hidden_tiles,
+    max_hidden_channels,
+    HIDDEN_CHANNELS_PER_TILE,
 };
 
 // =================================== Plugin =================================== //
@@ -24,28 +71,231 @@ pub(super) struct NCAControlPlugin;
 impl Plugin for NCAControlPlugin {
     fn build(&self, app: &mut App) {
         app
+            .init_resource::<ConfigPaths>()
             .add_plugins((
+                brush::BrushPlugin,
+                effects::EffectsPlugin,
+                fill::FillPlugin,
+                history::HistoryPlugin,
+                launch::LaunchPlugin,
+                mask::MaskPlugin,
                 settings::SettingsPlugin,
-                presets::PresetPlugin
+                presets::PresetPlugin,
+                probe::ProbePlugin,
+                profiles::ProfilePlugin,
+                recording::RecordingPlugin,
+                replay::ReplayPlugin,
+                screenshot::ScreenshotPlugin,
+                state::StatePlugin,
+                workspaces::WorkspacePlugin,
             ))
+            .add_plugins((
+                preferences::PreferencesPlugin,
+                stagnation::StagnationPlugin,
+                stamp::StampPlugin,
+            ))
+            .add_event::<ChangeTextureFormat>()
+            .add_event::<ClearCanvas>()
+            .add_event::<ExportShader>()
             .add_event::<Reinitialize>()
+            .add_event::<ResizeSimulation>()
+            .add_event::<RevertShader>()
+            .add_event::<SetHiddenChannels>()
+            .add_event::<StepOnce>()
             .add_event::<UpdateActivationFunction>()
             .add_event::<UpdateFilter>()
+            .init_resource::<ActivationFnErrors>()
+            .init_resource::<ConfigWriteError>()
+            .init_resource::<LastGoodShader>()
+            .init_resource::<ShaderWatchdog>()
+            .init_resource::<randomize::FilterRandomizer>()
             .init_resource::<NCADrawSettings>()
             .add_systems(Update, (
                 on_update_activation_fn,
                 on_update_filter,
-                on_reinitialize,
+                // Replace the textures of the new size or format right away.
+                on_reinitialize.after(on_resize_simulation).after(on_change_texture_format),
+                on_clear_canvas,
+                on_resize_simulation,
+                on_change_texture_format,
+                on_step_once,
+                on_set_hidden_channels,
+                sync_hidden_images.after(on_reinitialize),
+                on_export_shader,
+                on_shader_reloaded,
+                on_revert_shader,
+                track_last_good_shader.after(on_update_activation_fn),
+                shader_watchdog.after(track_last_good_shader),
             ));
     }
 }
 
+// ================================= Constants ================================== //
+
+/// The WGSL declaration of the time in seconds the activation functions can use
+/// as t.
+const TIME_DECLARATION: &str = "@group(0) @binding(9)
+var<uniform> t: f32;
+";
+
+/// The WGSL declarations of the hidden channels and their filters, only part of
+/// shaders with hidden channels.
+const HIDDEN_DECLARATIONS: &str = "
+@group(0) @binding(10)
+var hidden_in: texture_storage_2d<rgba16float, read>;
+
+@group(0) @binding(11)
+var hidden_out: texture_storage_2d<rgba16float, write>;
+
+@group(0) @binding(12)
+var<storage, read> filter_hidden: array<f32>;
+
+// The hidden channels are stored four per tile, the tiles below each other.
+fn hidden_loc(loc: vec2<i32>, tile: i32) -> vec2<i32> {
+    return loc + vec2<i32>(0, tile * i32(textureDimensions(texture_in).y));
+}
+";
+
+// ================================ Resources =================================== //
+
+/// A resource holding the paths of the files the NCA settings and presets are read
+/// from and written to, along with the brush settings and the camera view saved in
+/// a workspace.
+#[derive(Resource, Debug, Clone)]
+pub struct ConfigPaths {
+    pub settings: PathBuf,
+    pub presets: PathBuf,
+    pub brush: PathBuf,
+    pub camera: PathBuf,
+}
+
+impl Default for ConfigPaths {
+    fn default() -> Self {
+        Self::in_dir(Path::new(""))
+    }
+}
+
+impl ConfigPaths {
+    /// Returns the paths of the settings, presets, brush and camera files inside a
+    /// directory.
+    pub fn in_dir(dir: &Path) -> Self {
+        Self {
+            settings: dir.join("settings.json"),
+            presets: dir.join("presets.json"),
+            brush: dir.join("brush.json"),
+            camera: dir.join("camera.json"),
+        }
+    }
+}
+
+/// A resource holding the message of the last failed write of the settings or
+/// presets file. The UI shows it until it is dismissed. Failed writes aren't fatal,
+/// the NCA keeps running with the settings and presets in memory.
+#[derive(Resource, Debug, Default)]
+pub struct ConfigWriteError {
+    pub message: Option<String>,
+}
+
+impl ConfigWriteError {
+    /// Logs the error of a failed write and keeps it for the UI. Returns true if the
+    /// write succeeded.
+    pub fn report(&mut self, result: Result<(), String>) -> bool {
+        if let Err(err) = result {
+            warn!("{}", err);
+            self.message = Some(err);
+            return false;
+        }
+        true
+    }
+}
+
+/// A resource holding the error messages of the activation functions that failed
+/// to compile, for each channel.
+#[derive(Resource, Debug, Default)]
+pub struct ActivationFnErrors {
+    pub red: Option<String>,
+    pub green: Option<String>,
+    pub blue: Option<String>,
+    pub alpha: Option<String>,
+    /// The errors of the hidden channels, in their order.
+    pub hidden: Vec<Option<String>>,
+}
+
+impl ActivationFnErrors {
+    /// Returns true if any activation function failed to compile.
+    pub fn any(&self) -> bool {
+        self.red.is_some()
+            || self.green.is_some()
+            || self.blue.is_some()
+            || self.alpha.is_some()
+            || self.hidden.iter().any(Option::is_some)
+    }
+}
+
+/// A resource holding the settings of the last shader that was loaded successfully
+/// by the NCA pipeline. Used to recover from broken activation functions.
+#[derive(Resource, Debug, Default)]
+pub struct LastGoodShader {
+    /// The settings the last working shader was generated from.
+    pub settings: Option<NCASettings>,
+    /// The settings of a shader that waits to be loaded, together with the number
+    /// of reinitializations of the pipeline at the time it was written.
+    pending: Option<(u64, NCASettings)>,
+}
+
+/// A resource holding the settings of the watchdog that recovers from shaders that
+/// fail to load. If the pipeline doesn't run within the timeout, it is reported as
+/// stuck. If enabled and a changed shader is to blame, the watchdog also reverts to
+/// the last good shader and reinitializes the NCA.
+#[derive(Resource, Debug)]
+pub struct ShaderWatchdog {
+    /// If true, the watchdog reverts changed shaders that don't load in time.
+    pub enabled: bool,
+    /// The time in seconds a shader may take to load.
+    pub timeout: f32,
+    /// The time in seconds the pipeline has been loading.
+    elapsed: f32,
+}
+
+impl Default for ShaderWatchdog {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            timeout: 5.,
+            elapsed: 0.,
+        }
+    }
+}
+
 // ================================== Events ==================================== //
 
 /// An event to reinitialize the NCA.
 #[derive(Event, Debug)]
 pub struct Reinitialize;
 
+/// An event to fill the canvas with the background color of the preferences. Unlike
+/// reinitializing, the NCA keeps running from the blank canvas.
+#[derive(Event, Debug)]
+pub struct ClearCanvas;
+
+/// An event to change the size of the simulation in pixels. Recreates the textures
+/// and reinitializes the NCA.
+#[derive(Event, Debug)]
+pub struct ResizeSimulation {
+    pub size: UVec2,
+}
+
+/// An event to change the format of the NCA textures. Recreates the textures and
+/// reinitializes the NCA. Formats the NCA doesn't support are ignored.
+#[derive(Event, Debug)]
+pub struct ChangeTextureFormat {
+    pub format: TextureFormat,
+}
+
+/// An event to advance the paused NCA by a single step.
+#[derive(Event, Debug)]
+pub struct StepOnce;
+
 /// An event to update the NCA's activation functions.
 #[derive(Event, Debug)]
 pub struct UpdateActivationFunction;
@@ -54,41 +304,438 @@ pub struct UpdateActivationFunction;
 #[derive(Event, Debug)]
 pub struct UpdateFilter;
 
+/// An event to revert the activation functions to the last shader that was loaded
+/// successfully.
+#[derive(Event, Debug)]
+pub struct RevertShader;
+
+/// An event to set the number of hidden channels of the NCA. Regenerates the shader
+/// and reinitializes the NCA, as the hidden channels start at zero.
+#[derive(Event, Debug)]
+pub struct SetHiddenChannels {
+    pub count: usize,
+}
+
+/// An event to write the shader generated from the current settings to the given
+/// path, e.g. to use it in another tool or to attach it to a bug report.
+#[derive(Event, Debug)]
+pub struct ExportShader {
+    pub path: PathBuf,
+}
+
+/// The textures of the canvas together with the size, format and background color
+/// they are created with.
+#[derive(SystemParam)]
+struct CanvasTextures<'w> {
+    images: ResMut<'w, Assets<Image>>,
+    nca_images: Res<'w, NCAImages>,
+    sim_size: Res<'w, NCASimSize>,
+    format: Res<'w, NCATextureFormat>,
+    preferences: Res<'w, UIPreferences>,
+}
+
+/// The size of the simulation together with the render device, which limits the
+/// number of hidden channels fitting into a single texture.
+#[derive(SystemParam)]
+struct HiddenChannelLimit<'w> {
+    sim_size: Res<'w, NCASimSize>,
+    render_device: Res<'w, RenderDevice>,
+}
+
+impl HiddenChannelLimit<'_> {
+    /// Returns the largest number of hidden channels at the size of the simulation.
+    fn max_count(&self) -> usize {
+        let max_dimension = self.render_device.limits().max_texture_dimension_2d;
+        max_hidden_channels(self.sim_size.size, max_dimension)
+    }
+}
+
 // ================================== Systems =================================== //
 
-/// A system triggered by the Reinitialize event. Reinitializes the NCA.
+/// A system triggered by the Reinitialize event. Writes the current seed and initial
+/// state to their buffer, resets the time and reinitializes the NCA. Both textures
+/// are replaced with blank ones in the same frame, so that the old canvas doesn't
+/// stay visible while the pipeline is loading. The textures of the hidden channels
+/// are recreated for the current number of hidden channels.
 fn on_reinitialize(
     mut ev_reader_update_filter: EventReader<Reinitialize>,
     mut reinit_res: ResMut<ReinitPipeline>,
+    render_device: Res<RenderDevice>,
+    mut buffers: ResMut<NCABuffers>,
+    canvas: CanvasTextures,
+    params: Res<NCASettings>,
+    mut control: ResMut<SimulationControl>,
 ) {
-    for _ in ev_reader_update_filter.read() {
-        info!("Reinitializing nca rendering pipeline.");
-        reinit_res.reinit = true;
+    if ev_reader_update_filter.read().count() == 0 {
+        return;
+    }
+    let CanvasTextures { mut images, nca_images, sim_size, format, preferences } = canvas;
+    info!("Reinitializing nca rendering pipeline with seed {}.", params.seed);
+    buffers.buffer_init = create_init_buffer(&render_device, &params);
+    control.time = 0.;
+    reinit_res.request();
+    let size = sim_size.size;
+    let image = create_filled_image(size.x, size.y, format.format, preferences.background());
+    images.insert(&nca_images.texture_a, image.clone());
+    images.insert(&nca_images.texture_b, image);
+    let hidden = create_hidden_image(size, params.hidden.len());
+    images.insert(&nca_images.hidden_a, hidden.clone());
+    images.insert(&nca_images.hidden_b, hidden);
+}
+
+/// A system triggered by the SetHiddenChannels event. Adds or removes hidden
+/// channels, updates the shader and the filters and reinitializes the NCA. The
+/// number of hidden channels is limited by the size of the simulation, as their
+/// tiles have to fit into a single texture.
+fn on_set_hidden_channels(
+    mut ev_reader_set_hidden: EventReader<SetHiddenChannels>,
+    mut ev_writer_update_fn: EventWriter<UpdateActivationFunction>,
+    mut ev_writer_update_filter: EventWriter<UpdateFilter>,
+    mut ev_writer_save_settings: EventWriter<SaveSettings>,
+    mut ev_writer_reinitialize: EventWriter<Reinitialize>,
+    mut params: ResMut<NCASettings>,
+    limit: HiddenChannelLimit,
+) {
+    let max_count = limit.max_count();
+    for event in ev_reader_set_hidden.read() {
+        if event.count > max_count {
+            warn!("The NCA has at most {} hidden channels at this size.", max_count);
+        }
+        let count = event.count.min(max_count);
+        if count == params.hidden.len() {
+            continue;
+        }
+        params.set_hidden_channels(count);
+        info!("Setting number of hidden channels to {}.", params.hidden.len());
+        ev_writer_update_fn.send(UpdateActivationFunction);
+        ev_writer_update_filter.send(UpdateFilter);
+        ev_writer_save_settings.send(SaveSettings);
+        ev_writer_reinitialize.send(Reinitialize);
+    }
+}
+
+/// A system that recreates the textures of the hidden channels if their size
+/// doesn't match the number of hidden channels, e.g. after settings with another
+/// number of them were loaded. The hidden channels start at zero. If more hidden
+/// channels than fit at the size of the simulation were loaded, or the simulation
+/// was resized, they are reduced to as many as fit instead.
+fn sync_hidden_images(
+    mut ev_writer_set_hidden: EventWriter<SetHiddenChannels>,
+    mut images: ResMut<Assets<Image>>,
+    nca_images: Res<NCAImages>,
+    sim_size: Res<NCASimSize>,
+    params: Res<NCASettings>,
+    limit: HiddenChannelLimit,
+) {
+    if !params.is_changed() && !sim_size.is_changed() {
+        return;
+    }
+    if params.hidden.len() > limit.max_count() {
+        ev_writer_set_hidden.send(SetHiddenChannels { count: limit.max_count() });
+        return;
+    }
+    let size = hidden_image_size(sim_size.size, params.hidden.len());
+    let outdated = images
+        .get(&nca_images.hidden_a)
+        .is_none_or(|image| image.size() != size);
+    if !outdated {
+        return;
+    }
+    let hidden = create_hidden_image(sim_size.size, params.hidden.len());
+    images.insert(&nca_images.hidden_a, hidden.clone());
+    images.insert(&nca_images.hidden_b, hidden);
+}
+
+/// A system triggered by the ExportShader event. Writes the shader source built
+/// from the current settings to the path of the event, resolved for the current
+/// workgroup size and texture format, headed by a comment listing the filters and
+/// activation functions.
+fn on_export_shader(
+    mut ev_reader_export_shader: EventReader<ExportShader>,
+    settings: Res<NCASettings>,
+    workgroup_size: Res<NCAWorkgroupSize>,
+    format: Res<NCATextureFormat>,
+) {
+    for event in ev_reader_export_shader.read() {
+        info!("Exporting shader to {}.", event.path.display());
+        let contents = exported_shader_source(&settings, workgroup_size.size, format.format);
+        if let Err(err) = std::fs::write(&event.path, contents) {
+            warn!("Couldn't export shader: {}", err);
+        }
+    }
+}
+
+/// A system triggered by the ClearCanvas event. Replaces both textures of the NCA
+/// with ones filled with the background color, so that the canvas is blank
+/// whichever of them is displayed. The hidden channels are reset to zero.
+fn on_clear_canvas(
+    mut ev_reader_clear_canvas: EventReader<ClearCanvas>,
+    mut images: ResMut<Assets<Image>>,
+    nca_images: Res<NCAImages>,
+    sim_size: Res<NCASimSize>,
+    format: Res<NCATextureFormat>,
+    settings: Res<NCASettings>,
+    preferences: Res<UIPreferences>,
+) {
+    if ev_reader_clear_canvas.read().count() == 0 {
+        return;
+    }
+    info!("Clearing canvas.");
+    let size = sim_size.size;
+    let image = create_filled_image(size.x, size.y, format.format, preferences.background());
+    images.insert(&nca_images.texture_a, image.clone());
+    images.insert(&nca_images.texture_b, image);
+    let hidden = create_hidden_image(size, settings.hidden.len());
+    images.insert(&nca_images.hidden_a, hidden.clone());
+    images.insert(&nca_images.hidden_b, hidden);
+}
+
+/// A system triggered by the ResizeSimulation event. Replaces both textures of the
+/// NCA with blank ones of the new size and reinitializes the NCA.
+fn on_resize_simulation(
+    mut ev_reader_resize: EventReader<ResizeSimulation>,
+    mut ev_writer_reinitialize: EventWriter<Reinitialize>,
+    mut images: ResMut<Assets<Image>>,
+    mut sim_size: ResMut<NCASimSize>,
+    nca_images: Res<NCAImages>,
+    format: Res<NCATextureFormat>,
+    preferences: Res<UIPreferences>,
+) {
+    for event in ev_reader_resize.read() {
+        let size = event.size.max(UVec2::ONE);
+        if size == sim_size.size {
+            continue;
+        }
+
+        info!("Resizing simulation to {}x{}.", size.x, size.y);
+        sim_size.size = size;
+        let image = create_filled_image(size.x, size.y, format.format, preferences.background());
+        images.insert(&nca_images.texture_a, image.clone());
+        images.insert(&nca_images.texture_b, image);
+        ev_writer_reinitialize.send(Reinitialize);
+    }
+}
+
+/// A system triggered by the ChangeTextureFormat event. Replaces both textures of
+/// the NCA with blank ones of the new format and reinitializes the NCA.
+fn on_change_texture_format(
+    mut ev_reader_change_format: EventReader<ChangeTextureFormat>,
+    mut ev_writer_reinitialize: EventWriter<Reinitialize>,
+    mut images: ResMut<Assets<Image>>,
+    mut format: ResMut<NCATextureFormat>,
+    nca_images: Res<NCAImages>,
+    sim_size: Res<NCASimSize>,
+    preferences: Res<UIPreferences>,
+) {
+    for event in ev_reader_change_format.read() {
+        if event.format == format.format {
+            continue;
+        }
+        if !NCATextureFormat::SUPPORTED.contains(&event.format) {
+            warn!("Unsupported texture format {:?}.", event.format);
+            continue;
+        }
+
+        info!("Changing texture format to {:?}.", event.format);
+        format.format = event.format;
+        let size = sim_size.size;
+        let image = create_filled_image(size.x, size.y, format.format, preferences.background());
+        images.insert(&nca_images.texture_a, image.clone());
+        images.insert(&nca_images.texture_b, image);
+        ev_writer_reinitialize.send(Reinitialize);
+    }
+}
+
+/// A system triggered by the StepOnce event. Lets the NCA advance by a single step
+/// if it is paused.
+fn on_step_once(
+    mut ev_reader_step_once: EventReader<StepOnce>,
+    mut control: ResMut<SimulationControl>,
+) {
+    for _ in ev_reader_step_once.read() {
+        if control.paused {
+            control.step = true;
+        } else {
+            warn!("Can only step while the NCA is paused.");
+        }
     }
 }
 
-/// A system triggered by the UpdateActivationFunction event. Rewrites the shader
-/// file to contain the current activation functions, reloads the asset server and
-/// sets the flag to reinitialize the render graph node of the NCA.
+/// A system triggered by the UpdateActivationFunction event. Validates the
+/// activation functions and, if they compile, generates the shader containing them
+/// and replaces the shader asset in memory. The render graph node of the NCA is
+/// reinitialized once the shader is replaced.
 fn on_update_activation_fn(
     mut ev_reader_update_filter: EventReader<UpdateActivationFunction>,
-    asset_server: Res<AssetServer>,
+    mut shaders: ResMut<Assets<Shader>>,
     params: ResMut<NCASettings>,
-    mut reinit_res: ResMut<ReinitPipeline>,
+    mut errors: ResMut<ActivationFnErrors>,
 ) {
     for _ in ev_reader_update_filter.read() {
-        reinit_res.reinit = true;
+        errors.red = validate_activation_fn("red", &params.red.activation_fn).err();
+        // The green and blue channels aren't part of the shader in grayscale mode.
+        if params.grayscale {
+            errors.green = None;
+            errors.blue = None;
+        } else {
+            errors.green = validate_activation_fn("green", &params.green.activation_fn).err();
+            errors.blue = validate_activation_fn("blue", &params.blue.activation_fn).err();
+        }
+        errors.alpha = validate_activation_fn("alpha", &params.alpha.activation_fn).err();
+        errors.hidden = params
+            .hidden
+            .iter()
+            .enumerate()
+            .map(|(i, channel)| {
+                validate_activation_fn(&format!("hidden_{i}"), &channel.activation_fn).err()
+            })
+            .collect();
+        if errors.any() {
+            warn!("Not updating nca shader, an activation function doesn't compile.");
+            continue;
+        }
+
+        info!("Updating nca shader.");
+        shaders.insert(
+            &NCA_SHADER_HANDLE,
+            Shader::from_wgsl(build_shader_source(&params), "nca.wgsl"),
+        );
+    }
+}
+
+/// A system that sets the flag to reinitialize the render graph node of the NCA
+/// whenever the NCA shader has been added or replaced.
+fn on_shader_reloaded(
+    mut ev_reader_shader: EventReader<AssetEvent<Shader>>,
+    mut reinit_res: ResMut<ReinitPipeline>,
+) {
+    for event in ev_reader_shader.read() {
+        match event {
+            AssetEvent::Added { id } | AssetEvent::Modified { id }
+                if *id == NCA_SHADER_HANDLE.id() =>
+            {
+                reinit_res.request();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A system that remembers the settings of each shader written by the
+/// UpdateActivationFunction event and marks them as the last good shader once the
+/// pipeline has been reinitialized and runs again.
+fn track_last_good_shader(
+    mut ev_reader_update_fn: EventReader<UpdateActivationFunction>,
+    monitor: Res<NCAPipelineMonitor>,
+    params: Res<NCASettings>,
+    errors: Res<ActivationFnErrors>,
+    mut last_good: ResMut<LastGoodShader>,
+) {
+    let status = monitor.status();
+    for _ in ev_reader_update_fn.read() {
+        if errors.any() {
+            continue;
+        }
+        last_good.pending = Some((status.reinit_count, params.clone()));
+    }
+
+    if let Some((reinit_count, _)) = last_good.pending {
+        if status.reinit_count > reinit_count
+            && status.state == NCAPipelineState::Running
+        {
+            last_good.settings = last_good.pending.take().map(|(_, settings)| settings);
+        }
+    }
+}
+
+/// A system that reports the pipeline as stuck if it fails to compile or doesn't
+/// load within the timeout of the watchdog. If the watchdog is enabled and a changed
+/// shader is loading, it reverts to the last good shader and reinitializes the NCA
+/// instead.
+fn shader_watchdog(
+    mut ev_writer_revert: EventWriter<RevertShader>,
+    mut ev_writer_reinitialize: EventWriter<Reinitialize>,
+    mut watchdog: ResMut<ShaderWatchdog>,
+    mut last_good: ResMut<LastGoodShader>,
+    monitor: Res<NCAPipelineMonitor>,
+    time: Res<Time>,
+) {
+    let state = monitor.status().state;
+    if state == NCAPipelineState::Running && monitor.error().is_none() {
+        watchdog.elapsed = 0.;
+        return;
+    }
 
-        info!("Writing nca shader.");
-        write_shader(&params);
+    watchdog.elapsed += time.delta_seconds();
+    if watchdog.elapsed < watchdog.timeout {
+        return;
+    }
+    let changed = last_good.pending.is_some() && last_good.settings.is_some();
+    if watchdog.enabled && changed {
+        warn!(
+            "NCA shader didn't load within {} seconds, reverting to the last working shader.",
+            watchdog.timeout,
+        );
+        watchdog.elapsed = 0.;
+        last_good.pending = None;
+        ev_writer_revert.send(RevertShader);
+        ev_writer_reinitialize.send(Reinitialize);
+    } else if state == NCAPipelineState::Loading {
+        warn!("NCA pipeline didn't finish loading within {} seconds.", watchdog.timeout);
+        monitor.set_state(NCAPipelineState::Stuck);
+    }
+}
 
-        info!("Reloading shader asset.");
-        asset_server.reload(SHADER_ASSET_PATH);
+/// A system triggered by the RevertShader event. Restores the activation functions,
+/// filter kernels, boundary, neighborhood, grayscale mode and hidden channels of
+/// the last shader that was loaded successfully. Reinitializes the NCA if the
+/// number of hidden channels changed.
+fn on_revert_shader(
+    mut ev_reader_revert: EventReader<RevertShader>,
+    mut ev_writer_update_fn: EventWriter<UpdateActivationFunction>,
+    mut ev_writer_update_filter: EventWriter<UpdateFilter>,
+    mut ev_writer_save_settings: EventWriter<SaveSettings>,
+    mut ev_writer_reinitialize: EventWriter<Reinitialize>,
+    last_good: Res<LastGoodShader>,
+    mut params: ResMut<NCASettings>,
+) {
+    for _ in ev_reader_revert.read() {
+        let Some(last_good) = &last_good.settings else {
+            warn!("No working shader to revert to.");
+            continue;
+        };
+        info!("Reverting to last working shader.");
+        let params = params.as_mut();
+        for (channel, good_channel) in [
+            (&mut params.red, &last_good.red),
+            (&mut params.green, &last_good.green),
+            (&mut params.blue, &last_good.blue),
+            (&mut params.alpha, &last_good.alpha),
+        ] {
+            channel.activation_fn.clone_from(&good_channel.activation_fn);
+            channel.levels = good_channel.levels;
+            channel.output_mapping = good_channel.output_mapping;
+            channel.update_divisor = good_channel.update_divisor;
+            // The loop bounds of the shader depend on the kernel size.
+            channel.filters.clone_from(&good_channel.filters);
+            channel.kernel_size = good_channel.kernel_size;
+        }
+        params.boundary = last_good.boundary;
+        params.grayscale = last_good.grayscale;
+        params.neighborhood = last_good.neighborhood;
+        params.custom_neighborhood.clone_from(&last_good.custom_neighborhood);
+        if params.hidden.len() != last_good.hidden.len() {
+            ev_writer_reinitialize.send(Reinitialize);
+        }
+        params.hidden.clone_from(&last_good.hidden);
+        ev_writer_update_fn.send(UpdateActivationFunction);
+        ev_writer_update_filter.send(UpdateFilter);
+        ev_writer_save_settings.send(SaveSettings);
     }
 }
 
 /// A system triggered by the UpdateFilter event. Writes the current filters to
-/// the uniform buffer to pass the data to the shader.
+/// the storage buffers to pass the data to the shader.
 fn on_update_filter(
     mut ev_reader_update_filter: EventReader<UpdateFilter>,
     render_device: Res<RenderDevice>,
@@ -97,47 +744,136 @@ fn on_update_filter(
 ) {
     for _ in ev_reader_update_filter.read() {
         info!("Writing nca filter buffers.");
-        buffers.buffer_red = 
-        create_uniform_buffer(
-            &render_device,
-            &[mat3_to_buffer_array(params.red.filter)],
-            Some("Red Uniform"),
-        );
-        buffers.buffer_green = 
-        create_uniform_buffer(
-            &render_device,
-            &[mat3_to_buffer_array(params.green.filter)],
-            Some("Green Uniform"),
-        );
-        buffers.buffer_blue = 
-        create_uniform_buffer(
-            &render_device,
-            &[mat3_to_buffer_array(params.blue.filter)],
-            Some("Blue Uniform"),
-        );
+        *buffers = create_filter_buffers(&params, &render_device);
     }
 }
 
 // =================================== Utils ==================================== //
 
-/// Helper function to write the shader file.
-pub fn write_shader(
+/// Helper function to check whether an activation function compiles. Checks the
+/// structure of the function first, then parses and validates it inside the same
+/// scaffold as in the shader, including the time t. Returns the error message
+/// otherwise.
+pub fn validate_activation_fn(name: &str, activation_fn: &str) -> Result<(), String> {
+    check_activation_fn_structure(activation_fn)?;
+    let source = format!(
+        "{TIME_DECLARATION}\nfn activation_fn_{name}(x: f32) -> f32 {{\n\t{activation_fn}\n}}\n"
+    );
+    let module = naga::front::wgsl::parse_str(&source)
+        .map_err(|err| err.emit_to_string(&source))?;
+    naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::all(),
+    )
+        .validate(&module)
+        .map_err(|err| err.emit_to_string(&source))?;
+    Ok(())
+}
+
+/// Helper function to check that an activation function stays inside the body of
+/// the function it is inlined into. The function has to return a value, its braces
+/// have to be balanced and it must not declare items of its own, which would only
+/// be valid outside of the function. Comments are ignored. Returns a message
+/// explaining the rejection otherwise.
+fn check_activation_fn_structure(activation_fn: &str) -> Result<(), String> {
+    let code: String = activation_fn
+        .lines()
+        .map(|line| line.split("//").next().unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join("\n");
+    if code.contains("/*") {
+        return Err("Block comments aren't supported in activation functions.".to_owned());
+    }
+
+    let mut depth = 0;
+    for c in code.chars() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => {
+                return Err("Unexpected closing brace, activation functions can't close \
+                    the function they are part of.".to_owned());
+            }
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return Err("Unbalanced braces, a block isn't closed.".to_owned());
+    }
+
+    let words: Vec<&str> = code
+        .split(|c: char| !c.is_alphanumeric() && c != '_' && c != '@')
+        .filter(|word| !word.is_empty())
+        .collect();
+    if let Some(word) = words.iter().find(|word| {
+        word.starts_with('@') || ["fn", "struct", "alias", "override", "enable"].contains(word)
+    }) {
+        return Err(format!(
+            "Activation functions can't declare items, found \"{word}\". Only the body of \
+            the function is allowed."
+        ));
+    }
+    if !words.contains(&"return") {
+        return Err("Activation functions have to return a value.".to_owned());
+    }
+    Ok(())
+}
+
+/// Helper function to build the source of the NCA shader from the settings. Only
+/// depends on the settings, so the same source is loaded into the pipeline, the
+/// A/B comparison and exported shaders.
+pub fn build_shader_source(
     params: &NCASettings,
-) {
-    write(
-        "assets/".to_owned() + SHADER_ASSET_PATH,
-        "@group(0) @binding(0)
+) -> String {
+    // Frozen cells keep their hidden channels as well.
+    let frozen_hidden = hidden_store(params, |tile| {
+        format!("textureLoad(hidden_in, hidden_loc(loc, {tile}))")
+    });
+    // The hidden channels start at zero.
+    let init_hidden = hidden_store(params, |_| "vec4<f32>(0.)".to_owned());
+    "#ifdef TEXTURE_FORMAT_RGBA16FLOAT
+@group(0) @binding(0)
+var texture_in: texture_storage_2d<rgba16float, read>;
+
+@group(0) @binding(1)
+var texture_out: texture_storage_2d<rgba16float, write>;
+#else
+@group(0) @binding(0)
 var texture_in: texture_storage_2d<rgba8unorm, read>;
 
 @group(0) @binding(1)
 var texture_out: texture_storage_2d<rgba8unorm, write>;
+#endif
 
 @group(0) @binding(2)
-var<uniform> filter_red: mat3x3f;
+var<storage, read> filter_red: array<f32>;
 @group(0) @binding(3)
-var<uniform> filter_green: mat3x3f;
+var<storage, read> filter_green: array<f32>;
 @group(0) @binding(4)
-var<uniform> filter_blue: mat3x3f;
+var<storage, read> filter_blue: array<f32>;
+@group(0) @binding(5)
+var<storage, read> filter_alpha: array<f32>;
+
+struct InitParams {
+    seed: u32,
+    mode: u32,
+}
+
+@group(0) @binding(6)
+var<uniform> init_params: InitParams;
+
+struct StepParams {
+    generation: u32,
+}
+
+@group(0) @binding(7)
+var<uniform> step_params: StepParams;
+
+// Cells with a value above 0.5 in the freeze mask keep their values in the update.
+@group(0) @binding(8)
+var freeze_mask: texture_2d<f32>;
+
+".to_owned() + TIME_DECLARATION + hidden_declarations(params) + "
 
 fn hash(value: u32) -> u32 {
     var state = value;
@@ -154,62 +890,603 @@ fn randomFloat(value: u32) -> f32 {
     return f32(hash(value)) / 4294967295.0;
 }
 
-@compute @workgroup_size(8, 8, 1)
+fn quantize(value: f32, levels: f32) -> f32 {
+    return round(value * (levels - 1.)) / (levels - 1.);
+}
+
+fn fold(value: f32) -> f32 {
+    return 1. - abs(1. - (value - 2. * floor(value / 2.)));
+}
+
+@compute @workgroup_size(#{WORKGROUP_SIZE_X}, #{WORKGROUP_SIZE_Y}, 1)
 fn init(@builtin(global_invocation_id) invocation_id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
     let loc = vec2<i32>(invocation_id.xy);
     let dims = textureDimensions(texture_in);
+    if (invocation_id.x >= dims.x || invocation_id.y >= dims.y) {
+        return;
+    }
     let total_pixels = dims.x * dims.y;
+    let offset = hash(init_params.seed);
 
-    let random_red = randomFloat(invocation_id.y * dims.x + invocation_id.x);
-    let random_green = randomFloat(total_pixels + invocation_id.y * dims.x + invocation_id.x);
-    let random_blue = randomFloat(u32(2) * total_pixels + invocation_id.y * dims.x + invocation_id.x);
-    let color = vec4<f32>(random_red, random_green, random_blue, 1.0);
+    let random_red = randomFloat(offset + invocation_id.y * dims.x + invocation_id.x);
+    let random_green = randomFloat(offset + total_pixels + invocation_id.y * dims.x + invocation_id.x);
+    let random_blue = randomFloat(offset + u32(2) * total_pixels + invocation_id.y * dims.x + invocation_id.x);
+    let random_color = vec4<f32>(random_red, random_green, random_blue, 1.0);
 
-    textureStore(texture_out, loc, color);
+    var color = random_color;
+    switch init_params.mode {
+        // Black
+        case 1u: {
+            color = vec4<f32>(0., 0., 0., 1.);
+        }
+        // White
+        case 2u: {
+            color = vec4<f32>(1.);
+        }
+        // A single white cell in the center
+        case 3u: {
+            color = vec4<f32>(0., 0., 0., 1.);
+            if (all(invocation_id.xy == dims / 2u)) {
+                color = vec4<f32>(1.);
+            }
+        }
+        // Sparse random dots
+        case 4u: {
+            color = vec4<f32>(0., 0., 0., 1.);
+            if (randomFloat(offset + u32(3) * total_pixels + invocation_id.y * dims.x + invocation_id.x) < 0.01) {
+                color = random_color;
+            }
+        }
+        // Horizontal gradient
+        case 5u: {
+            let value = f32(invocation_id.x) / f32(max(dims.x - 1u, 1u));
+            color = vec4<f32>(value, value, value, 1.);
+        }
+        // Uniform noise
+        default: {}
+    }
+
+    textureStore(texture_out, loc, color);" + &init_hidden + "
 }
 
-fn get_cell(loc: vec2<i32>, offset_x: i32, offset_y: i32) -> vec3<f32> {
-    let dims = vec2<i32>(textureDimensions(texture_in));
-    var offset_loc = (loc + vec2<i32>(offset_x, offset_y) + dims) % dims;
-    let value: vec4<f32> = textureLoad(texture_in, offset_loc);
-    return value.xyz;
+"
++ &get_cell(params)
++ &color_convolutions(params)
++ &channel_convolution("alpha", "filter_alpha", 0, &params.alpha, params)
++ &hidden_convolutions(params)
++ &nca_step(params.grayscale)
++ &hidden_step(params)
++ &activation_fns(params) +
+"@compute @workgroup_size(#{WORKGROUP_SIZE_X}, #{WORKGROUP_SIZE_Y}, 1)
+fn update(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let dims = textureDimensions(texture_in);
+    if (invocation_id.x >= dims.x || invocation_id.y >= dims.y) {
+        return;
+    }
+    let loc = vec2<i32>(invocation_id.xy);
+    if (textureLoad(freeze_mask, loc, 0).r > 0.5) {
+        textureStore(texture_out, loc, textureLoad(texture_in, loc));" + &frozen_hidden + "
+        return;
+    }
+    let val = nca_step(loc);
+    let prior = textureLoad(texture_in, loc);
+    " + &color_output(params) + "
+    textureStore(texture_out, loc, color);" + &hidden_output(params) + "
+}\n"
 }
 
-fn nca_step(loc: vec2<i32>) -> vec3<f32> {
-    var new_val = vec3<f32>(0., 0., 0.);
-    for (var i: i32 = -1; i <= 1; i++) {
-        for (var j: i32 = -1; j <= 1; j++) {
-            new_val[0] += get_cell(loc, i, j)[0] * filter_red[i+1][j+1];
-            new_val[1] += get_cell(loc, i, j)[1] * filter_green[i+1][j+1];
-            new_val[2] += get_cell(loc, i, j)[2] * filter_blue[i+1][j+1];
+/// Helper function to build the NCA shader as it runs with the given workgroup size
+/// and texture format, i.e. with its shader defs resolved to plain WGSL, headed by
+/// the comment of [`shader_header`].
+fn exported_shader_source(
+    params: &NCASettings,
+    workgroup_size: UVec2,
+    texture_format: TextureFormat,
+) -> String {
+    let shader_defs = nca_shader_defs(workgroup_size, texture_format);
+    shader_header(params, workgroup_size, texture_format)
+        + &resolve_shader_defs(&build_shader_source(params), &shader_defs)
+}
+
+/// Helper function to build the comment heading an exported shader. Lists the
+/// workgroup size and texture format the shader was resolved for, and the filters
+/// and activation functions of the channels taking part in the NCA.
+fn shader_header(
+    params: &NCASettings,
+    workgroup_size: UVec2,
+    texture_format: TextureFormat,
+) -> String {
+    let mut header = format!("// NCA shader exported from the NCA Playground.
+// Runs on {} textures with a workgroup size of {}x{}. The time t is in seconds.
+",
+        format!("{texture_format:?}").to_lowercase(),
+        workgroup_size.x,
+        workgroup_size.y,
+    );
+    // Green and blue don't take part in grayscale NCAs.
+    let skipped = |index: usize| params.grayscale && (index == 1 || index == 2);
+    for (index, channel) in params.all_channels().into_iter().enumerate() {
+        if skipped(index) {
+            continue;
+        }
+        let size = channel.kernel_size as usize;
+        header += &format!("//\n// {} channel, {size}x{size} filters:\n", channel_name(index));
+        for (input, filter) in channel.filters.iter().enumerate() {
+            if skipped(input) || filter.iter().all(|weight| *weight == 0.) {
+                continue;
+            }
+            header += &format!("//   applied to {}:\n", channel_name(input).to_lowercase());
+            for row in filter.chunks(size.max(1)) {
+                let row: Vec<String> = row.iter().map(|weight| format!("{weight:>7.3}")).collect();
+                header += &format!("//     {}\n", row.join(" "));
+            }
+        }
+        header += "//   activation function:\n";
+        for line in channel.activation_fn.lines() {
+            header += &format!("//     {line}\n");
         }
     }
-    return new_val;
+    header + "\n"
 }
 
-fn activation_fn_red(x: f32) -> f32 {\n\t".to_owned()
-+ &params.red.activation_fn.to_owned() +
-"\n}
+/// Helper function to build the WGSL functions reading a neighbor of a cell, from
+/// the displayed channels and from the hidden channels, if there are any.
+fn get_cell(params: &NCASettings) -> String {
+    let get_cell = neighbor_fn("get_cell", "texture_in", false, params.boundary);
+    if params.hidden.is_empty() {
+        return get_cell;
+    }
+    get_cell + &neighbor_fn("get_hidden", "hidden_in", true, params.boundary)
+}
 
-fn activation_fn_green(x: f32) -> f32 {\n\t"
-+ &params.green.activation_fn.to_owned() +
-"\n}
+/// Helper function to build a WGSL function reading a neighbor of a cell from the
+/// given texture. The handling of neighbors outside of the simulation is given by
+/// the boundary mode. Tiled textures are read from the tile given to the function.
+fn neighbor_fn(name: &str, texture: &str, tiled: bool, boundary: BoundaryMode) -> String {
+    let offset_loc = match boundary {
+        BoundaryMode::Wrap => "let offset_loc = (loc + vec2<i32>(offset_x, offset_y) + dims) % dims;",
+        BoundaryMode::Clamp => "let offset_loc = clamp(
+        loc + vec2<i32>(offset_x, offset_y),
+        vec2<i32>(0),
+        dims - vec2<i32>(1),
+    );",
+        BoundaryMode::Zero => "let offset_loc = loc + vec2<i32>(offset_x, offset_y);
+    if (any(offset_loc < vec2<i32>(0)) || any(offset_loc >= dims)) {
+        return vec4<f32>(0.);
+    }",
+    };
+    let (tile, load_loc) = if tiled {
+        (", tile: i32", "hidden_loc(offset_loc, tile)")
+    } else {
+        ("", "offset_loc")
+    };
+    format!("fn {name}(loc: vec2<i32>, offset_x: i32, offset_y: i32{tile}) -> vec4<f32> {{
+    let dims = vec2<i32>(textureDimensions(texture_in));
+    {offset_loc}
+    return textureLoad({texture}, {load_loc});
+}}
 
-fn activation_fn_blue(x: f32) -> f32 {\n\t"
-+ &params.blue.activation_fn.to_owned() +
-"\n}
+")
+}
 
-@compute @workgroup_size(8, 8, 1)
-fn update(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
-    let loc = vec2<i32>(invocation_id.xy);
-    let val = nca_step(loc);
-    let color = vec4<f32>(
-        clamp(activation_fn_red(val[0]), 0., 1.),
-        clamp(activation_fn_green(val[1]), 0., 1.),
-        clamp(activation_fn_blue(val[2]), 0., 1.),
-        1.,
+/// Helper function to build the WGSL function convolving the neighborhood with the
+/// filters of a channel. Each channel of the neighborhood is weighted by its own
+/// filter, which follow each other in the buffer from the given offset on. The
+/// cells are given by the neighborhood within the channel's kernel size.
+fn channel_convolution(
+    name: &str,
+    buffer: &str,
+    offset: usize,
+    channel: &NCAChannel,
+    params: &NCASettings,
+) -> String {
+    let len = (channel.kernel_size * channel.kernel_size) as usize;
+    let weight = |input: usize| match offset + input * len {
+        0 => format!("{buffer}[weight]"),
+        start => format!("{buffer}[{start} + weight]"),
+    };
+    let sum = format!("sum += dot(get_cell(loc, dx, dy), vec4<f32>(
+                {},
+                {},
+                {},
+                {},
+            ));", weight(0), weight(1), weight(2), weight(3))
+        + &hidden_sum(buffer, offset, len, params.hidden.len());
+    let convolution = neighborhood_loop(channel.kernel_size, params, &sum);
+    format!("fn convolve_{name}(loc: vec2<i32>) -> f32 {{
+    var sum = 0.;
+    {convolution}
+    return sum;
+}}
+
+")
+}
+
+/// Helper function to build the WGSL functions convolving the neighborhood for the
+/// color channels. In grayscale mode, only the red channel is convolved, and only
+/// with the red channel of the neighborhood, like a single channel automaton. In
+/// either mode, the hidden channels of the neighborhood take part as well.
+fn color_convolutions(params: &NCASettings) -> String {
+    if !params.grayscale {
+        return channel_convolution("red", "filter_red", 0, &params.red, params)
+            + &channel_convolution("green", "filter_green", 0, &params.green, params)
+            + &channel_convolution("blue", "filter_blue", 0, &params.blue, params);
+    }
+    let len = (params.red.kernel_size * params.red.kernel_size) as usize;
+    let sum = "sum += get_cell(loc, dx, dy).r * filter_red[weight];".to_owned()
+        + &hidden_sum("filter_red", 0, len, params.hidden.len());
+    let convolution = neighborhood_loop(params.red.kernel_size, params, &sum);
+    format!("fn convolve_red(loc: vec2<i32>) -> f32 {{
+    var sum = 0.;
+    {convolution}
+    return sum;
+}}
+
+")
+}
+
+/// Helper function to build the WGSL statement adding the hidden channels of the
+/// neighborhood to the sum of a convolution. Their filters follow the filters of
+/// the displayed channels in the buffer. One statement per tile of the hidden
+/// channels, empty without hidden channels.
+fn hidden_sum(buffer: &str, offset: usize, len: usize, hidden: usize) -> String {
+    (0..hidden_tiles(hidden))
+        .map(|tile| {
+            let weights: Vec<String> = tile_channels(tile)
+                .map(|i| if i < hidden {
+                    format!("{buffer}[{} + weight]", offset + (CHANNELS + i) * len)
+                } else {
+                    "0.".to_owned()
+                })
+                .collect();
+            format!("
+            sum += dot(get_hidden(loc, dx, dy, {tile}), vec4<f32>({}));", weights.join(", "))
+        })
+        .collect()
+}
+
+/// Helper function to get the indices of the hidden channels in the given tile.
+fn tile_channels(tile: usize) -> std::ops::Range<usize> {
+    tile * HIDDEN_CHANNELS_PER_TILE..(tile + 1) * HIDDEN_CHANNELS_PER_TILE
+}
+
+/// Helper function to build the WGSL functions convolving the neighborhood for the
+/// hidden channels. Their filters share a single buffer, one channel after the
+/// other.
+fn hidden_convolutions(params: &NCASettings) -> String {
+    let mut offset = 0;
+    let mut convolutions = String::new();
+    for (i, channel) in params.hidden.iter().enumerate() {
+        let name = format!("hidden_{i}");
+        convolutions += &channel_convolution(&name, "filter_hidden", offset, channel, params);
+        offset += params.channel_count() * (channel.kernel_size * channel.kernel_size) as usize;
+    }
+    convolutions
+}
+
+/// Helper function to build the WGSL code running the body for each cell of the
+/// neighborhood within the kernel size, with its offset dx, dy and the index of
+/// its weight. Moore and von Neumann neighborhoods loop over the kernel, the cells
+/// of custom neighborhoods are unrolled.
+fn neighborhood_loop(size: u32, params: &NCASettings, body: &str) -> String {
+    let radius = size / 2;
+    let skip = match params.neighborhood {
+        Neighborhood::Moore => String::new(),
+        Neighborhood::VonNeumann => format!("
+            if (abs(dx) + abs(dy) > {radius}) {{
+                continue;
+            }}"),
+        Neighborhood::Custom => {
+            let r = radius as i32;
+            return (-r..=r)
+                .flat_map(|dy| (-r..=r).map(move |dx| [dx, dy]))
+                .filter(|&offset| params.in_neighborhood(offset, r))
+                .map(|[dx, dy]| {
+                    let weight = (dy + r) * size as i32 + r - dx;
+                    format!("{{
+        let dx = {dx};
+        let dy = {dy};
+        let weight = {weight};
+        {body}
+    }}
+    ")
+                })
+                .collect();
+        }
+    };
+    format!("for (var dy: i32 = -{radius}; dy <= {radius}; dy++) {{
+        for (var dx: i32 = -{radius}; dx <= {radius}; dx++) {{{skip}
+            let weight = (dy + {radius}) * {size} + {radius} - dx;
+            {body}
+        }}
+    }}")
+}
+
+/// Helper function to build the WGSL function convolving the neighborhood of a cell
+/// for all channels. In grayscale mode, green and blue aren't convolved, as they
+/// show the output of the red channel.
+fn nca_step(grayscale: bool) -> String {
+    let (green, blue) = if grayscale {
+        ("0.", "0.")
+    } else {
+        ("convolve_green(loc)", "convolve_blue(loc)")
+    };
+    format!("fn nca_step(loc: vec2<i32>) -> vec4<f32> {{
+    return vec4<f32>(
+        convolve_red(loc),
+        {green},
+        {blue},
+        convolve_alpha(loc),
     );
-    textureStore(texture_out, loc, color);
-}\n"
-    ).expect("Couldn't write shader.");
-}
\ No newline at end of file
+}}
+
+")
+}
+
+/// Helper function to build the WGSL functions convolving the neighborhood of a
+/// cell for the hidden channels, one per tile. Empty without hidden channels.
+fn hidden_step(params: &NCASettings) -> String {
+    (0..hidden_tiles(params.hidden.len()))
+        .map(|tile| {
+            let convolutions: Vec<String> = tile_channels(tile)
+                .map(|i| if i < params.hidden.len() {
+                    format!("convolve_hidden_{i}(loc)")
+                } else {
+                    "0.".to_owned()
+                })
+                .collect();
+            format!("fn hidden_step_{tile}(loc: vec2<i32>) -> vec4<f32> {{
+    return vec4<f32>({});
+}}
+
+", convolutions.join(", "))
+        })
+        .collect()
+}
+
+/// Helper function to build the WGSL activation functions of the channels that are
+/// used by the shader.
+fn activation_fns(params: &NCASettings) -> String {
+    let mut channels = vec![("red".to_owned(), &params.red)];
+    if !params.grayscale {
+        channels.extend([("green".to_owned(), &params.green), ("blue".to_owned(), &params.blue)]);
+    }
+    channels.push(("alpha".to_owned(), &params.alpha));
+    channels.extend(params.hidden.iter().enumerate().map(|(i, channel)| {
+        (format!("hidden_{i}"), channel)
+    }));
+    channels
+        .into_iter()
+        .map(|(name, channel)| {
+            let activation_fn = &channel.activation_fn;
+            format!("fn activation_fn_{name}(x: f32) -> f32 {{\n\t{activation_fn}\n}}\n\n")
+        })
+        .collect()
+}
+
+/// Helper function to build the WGSL statements computing the new color of a cell.
+/// In grayscale mode, the output of the red channel is written to all color
+/// channels.
+fn color_output(params: &NCASettings) -> String {
+    let alpha = channel_output("alpha", ["val", "prior"], 3, &params.alpha);
+    if params.grayscale {
+        let gray = channel_output("red", ["val", "prior"], 0, &params.red);
+        return format!("let gray = {gray};
+    let color = vec4<f32>(gray, gray, gray, {alpha});");
+    }
+    let red = channel_output("red", ["val", "prior"], 0, &params.red);
+    let green = channel_output("green", ["val", "prior"], 1, &params.green);
+    let blue = channel_output("blue", ["val", "prior"], 2, &params.blue);
+    format!("let color = vec4<f32>(
+        {red},
+        {green},
+        {blue},
+        {alpha},
+    );")
+}
+
+/// Helper function to build the WGSL statements computing the new values of the
+/// hidden channels and writing them, tile by tile. Empty without hidden channels.
+fn hidden_output(params: &NCASettings) -> String {
+    let outputs: String = (0..hidden_tiles(params.hidden.len()))
+        .map(|tile| {
+            let vars = [format!("hidden_val_{tile}"), format!("hidden_prior_{tile}")];
+            let outputs: Vec<String> = tile_channels(tile)
+                .map(|i| match params.hidden.get(i) {
+                    Some(channel) => channel_output(
+                        &format!("hidden_{i}"),
+                        [&vars[0], &vars[1]],
+                        i % HIDDEN_CHANNELS_PER_TILE,
+                        channel,
+                    ),
+                    None => "0.".to_owned(),
+                })
+                .collect();
+            format!("
+    let hidden_val_{tile} = hidden_step_{tile}(loc);
+    let hidden_prior_{tile} = textureLoad(hidden_in, hidden_loc(loc, {tile}));
+    let hidden_{tile} = vec4<f32>(
+        {},
+    );", outputs.join(",\n        "))
+        })
+        .collect();
+    outputs + &hidden_store(params, |tile| format!("hidden_{tile}"))
+}
+
+/// Helper function to build the WGSL statements writing the values given for each
+/// tile to the hidden channels of the cell. Empty without hidden channels.
+fn hidden_store(params: &NCASettings, value: impl Fn(usize) -> String) -> String {
+    (0..hidden_tiles(params.hidden.len()))
+        .map(|tile| {
+            format!("\n    textureStore(hidden_out, hidden_loc(loc, {tile}), {});", value(tile))
+        })
+        .collect()
+}
+
+/// Helper function to get the WGSL declarations of the hidden channels, if there
+/// are any.
+fn hidden_declarations(params: &NCASettings) -> &'static str {
+    if params.hidden.is_empty() {
+        return "";
+    }
+    HIDDEN_DECLARATIONS
+}
+
+/// Helper function to build the WGSL expression for the output value of a channel.
+/// Applies the activation function to the convolution result, maps it according to
+/// the channel's output mapping and quantizes it to the channel's number of levels,
+/// if set. Channels with an update divisor keep their prior value in the
+/// generations they aren't recomputed in. The convolution results and prior values
+/// are read from the given WGSL variables.
+fn channel_output(
+    name: &str,
+    [val, prior]: [&str; 2],
+    index: usize,
+    channel: &NCAChannel,
+) -> String {
+    let x = format!("activation_fn_{name}({val}[{index}])");
+    let activated = match channel.output_mapping {
+        OutputMapping::Clamp => format!("clamp({x}, 0., 1.)"),
+        OutputMapping::Wrap => format!("fract({x})"),
+        OutputMapping::Fold => format!("fold({x})"),
+        OutputMapping::Unclamped => x,
+    };
+    let output = match channel.levels {
+        0 => activated,
+        levels => format!("quantize({activated}, {}.)", levels.max(2)),
+    };
+    match channel.update_divisor {
+        0 | 1 => output,
+        divisor => format!(
+            "select({prior}[{index}], {output}, step_params.generation % {divisor}u == 0u)"
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+    use std::time::Duration;
+
+    use super::*;
+    use settings::MAX_HIDDEN_CHANNELS;
+
+    /// Resolves, parses and validates the NCA shader built from the settings for
+    /// both texture formats and a non-square workgroup size.
+    fn assert_shader_validates(params: &NCASettings) {
+        for format in NCATextureFormat::SUPPORTED {
+            let source = exported_shader_source(params, UVec2::new(16, 4), format);
+            assert!(!source.contains("#ifdef") && !source.contains("#{"), "{source}");
+            let module = naga::front::wgsl::parse_str(&source)
+                .unwrap_or_else(|err| panic!("{}", err.emit_to_string(&source)));
+            naga::valid::Validator::new(
+                naga::valid::ValidationFlags::all(),
+                naga::valid::Capabilities::all(),
+            )
+                .validate(&module)
+                .unwrap_or_else(|err| panic!("{}", err.emit_to_string(&source)));
+        }
+    }
+
+    #[test]
+    fn shader_contains_activation_fns() {
+        let mut params = NCASettings::default();
+        params.set_hidden_channels(2);
+        let bodies = [
+            "return x * 0.5;",
+            "return sin(x + t);",
+            "let y = x - 0.25;\n\treturn y * y;",
+            "return 1. - x;",
+            "return tanh(x);",
+            "if (x > 0.5) {\n\t\treturn 1.;\n\t}\n\treturn 0.;",
+        ];
+        for (channel, body) in params.all_channels_mut().into_iter().zip(bodies) {
+            channel.activation_fn = body.to_owned();
+        }
+
+        let source = build_shader_source(&params);
+        for body in bodies {
+            assert!(source.contains(body), "missing activation function {body:?}");
+        }
+    }
+
+    #[test]
+    fn shader_validates() {
+        assert_shader_validates(&NCASettings::default());
+
+        let mut hidden = NCASettings::default();
+        hidden.set_hidden_channels(MAX_HIDDEN_CHANNELS);
+        hidden.hidden[0].update_divisor = 3;
+        hidden.hidden[1].levels = 4;
+        assert_shader_validates(&hidden);
+
+        let grayscale = NCASettings { grayscale: true, ..NCASettings::default() };
+        assert_shader_validates(&grayscale);
+
+        let mut custom = NCASettings {
+            neighborhood: Neighborhood::Custom,
+            custom_neighborhood: vec![[0, 0], [-2, 1], [1, -1]],
+            boundary: BoundaryMode::Zero,
+            ..NCASettings::default()
+        };
+        custom.set_hidden_channels(1);
+        for channel in custom.all_channels_mut() {
+            channel.resize_kernel(5);
+        }
+        assert_shader_validates(&custom);
+    }
+
+    #[test]
+    fn activation_fns_stay_inside_their_body() {
+        let accepted = "// Comments may mention fn and struct.\n\
+            if (x > 0.5) {\n\treturn 1.;\n}\nreturn x;";
+        assert_eq!(check_activation_fn_structure(accepted), Ok(()));
+
+        // The activation function and a part of the expected message.
+        let rejected = [
+            ("return x; }\nfn escape(x: f32) -> f32 {\n\treturn x;", "closing brace"),
+            ("if (x > 0.5) {\n\treturn 1.;\nreturn x;", "Unbalanced braces"),
+            ("fn inner() {}\nreturn x;", "\"fn\""),
+            ("struct Cell { value: f32 }\nreturn x;", "\"struct\""),
+            ("override scale: f32;\nreturn x;", "\"override\""),
+            ("enable f16;\nreturn x;", "\"enable\""),
+            ("@must_use\nreturn x;", "\"@must_use\""),
+            ("/* return 0.; */\nreturn x;", "Block comments"),
+            ("let y = x * 2.;", "return a value"),
+            ("// return x;", "return a value"),
+        ];
+        for (activation_fn, expected) in rejected {
+            let err = check_activation_fn_structure(activation_fn)
+                .expect_err(&format!("accepted {activation_fn:?}"));
+            assert!(err.contains(expected), "{err:?} for {activation_fn:?}");
+        }
+    }
+
+    #[test]
+    fn watchdog_reports_stuck_pipelines_or_reverts_changed_shaders() {
+        // Runs the watchdog once after the timeout with or without a changed shader
+        // and returns the state of the pipeline and whether the shader was reverted.
+        let run = |changed: bool| {
+            let mut world = World::new();
+            world.init_resource::<Events<RevertShader>>();
+            world.init_resource::<Events<Reinitialize>>();
+            world.init_resource::<ShaderWatchdog>();
+            world.init_resource::<NCAPipelineMonitor>();
+            let mut last_good = LastGoodShader::default();
+            if changed {
+                last_good.settings = Some(NCASettings::identity());
+                last_good.pending = Some((0, NCASettings::default()));
+            }
+            world.insert_resource(last_good);
+            let mut time = Time::<()>::default();
+            time.advance_by(Duration::from_secs(6));
+            world.insert_resource(time);
+            world.run_system_once(shader_watchdog);
+            let state = world.resource::<NCAPipelineMonitor>().status().state;
+            (state, !world.resource::<Events<RevertShader>>().is_empty())
+        };
+
+        assert_eq!(run(false), (NCAPipelineState::Stuck, false));
+        assert_eq!(run(true), (NCAPipelineState::Loading, true));
+    }
+}
@@ -0,0 +1,182 @@
+//! UI preferences
+
+use bevy::{app::AppExit, prelude::*};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+use super::{
+    bindings::KeyBindings,
+    settings::SAVE_DEBOUNCE_SECS,
+    ConfigPaths,
+    ConfigWriteError,
+};
+
+// =================================== Plugin =================================== //
+
+/// A plugin that manages the preferences of the UI. Unlike the NCA settings, they
+/// are kept in their own file shared by all workspaces, so that switching profiles
+/// or loading settings doesn't change them.
+pub(super) struct PreferencesPlugin;
+
+impl Plugin for PreferencesPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<UIPreferences>()
+            .init_resource::<PendingPreferencesWrite>()
+            .add_event::<SavePreferences>()
+            .add_systems(Startup, setup)
+            .add_systems(Update, on_save_preferences)
+            .add_systems(Last, flush_preferences_on_exit);
+    }
+}
+
+// ================================ Resources =================================== //
+
+/// The file the UI preferences are read from and written to.
+pub const PREFERENCES_PATH: &str = "preferences.json";
+
+/// A resource holding the timer of a pending write of the preferences file. Every
+/// SavePreferences event restarts it, like the one of the settings.
+#[derive(Resource, Debug, Default)]
+struct PendingPreferencesWrite(Option<Timer>);
+
+/// The themes available for the UI.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UITheme {
+    #[default]
+    Dark,
+    Light,
+    /// Follows the theme of the operating system.
+    System,
+}
+
+/// The accent colors available for the UI, used for selections and highlights.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UIAccent {
+    /// The accent color of the theme.
+    #[default]
+    Default,
+    Blue,
+    Green,
+    Orange,
+    Purple,
+}
+
+/// A resource holding the preferences of the UI, which belong to the user rather
+/// than to the NCA.
+#[derive(Resource, Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UIPreferences {
+    /// The theme of the UI.
+    pub theme: UITheme,
+    /// The accent color of the UI.
+    pub accent: UIAccent,
+    /// The color blank canvases are filled with, e.g. when the canvas is cleared.
+    pub background_color: [f32; 3],
+    /// If true, the window around the canvas is cleared with the background color
+    /// as well.
+    pub background_fills_window: bool,
+    /// The keys and mouse buttons bound to the actions of the app.
+    pub key_bindings: KeyBindings,
+}
+
+impl UIPreferences {
+    /// Returns the opaque background color as RGBA.
+    pub fn background(&self) -> [f32; 4] {
+        let [r, g, b] = self.background_color;
+        [r, g, b, 1.]
+    }
+}
+
+// ================================== Events ==================================== //
+
+/// An event that saves the UI preferences to their file. The file is written once
+/// the preferences haven't been saved again for a short time.
+#[derive(Event, Debug)]
+pub struct SavePreferences;
+
+// ================================== Systems =================================== //
+
+/// On startup, this system loads the UI preferences from their file. Without one,
+/// the preferences are taken from the settings file, where they were kept before.
+fn setup(
+    mut preferences: ResMut<UIPreferences>,
+    paths: Res<ConfigPaths>,
+) {
+    *preferences = read_preferences(Path::new(PREFERENCES_PATH), &paths.settings);
+}
+
+/// System triggered by the SavePreferences event. (Re)starts the timer of the
+/// pending write and saves the current preferences once it finishes.
+fn on_save_preferences(
+    mut ev_reader_save_preferences: EventReader<SavePreferences>,
+    mut pending: ResMut<PendingPreferencesWrite>,
+    mut write_error: ResMut<ConfigWriteError>,
+    time: Res<Time>,
+    preferences: Res<UIPreferences>,
+) {
+    if ev_reader_save_preferences.read().count() > 0 {
+        pending.0 = Some(Timer::from_seconds(SAVE_DEBOUNCE_SECS, TimerMode::Once));
+    }
+    let Some(timer) = pending.0.as_mut() else { return };
+    if timer.tick(time.delta()).finished() {
+        pending.0 = None;
+        write_error.report(write_preferences(Path::new(PREFERENCES_PATH), &preferences));
+    }
+}
+
+/// A system that writes the pending preferences when the app exits, so that the
+/// last changes aren't lost.
+fn flush_preferences_on_exit(
+    mut ev_reader_exit: EventReader<AppExit>,
+    mut pending: ResMut<PendingPreferencesWrite>,
+    preferences: Res<UIPreferences>,
+) {
+    if ev_reader_exit.read().count() > 0 && pending.0.take().is_some() {
+        if let Err(err) = write_preferences(Path::new(PREFERENCES_PATH), &preferences) {
+            warn!("{}", err);
+        }
+    }
+}
+
+// =================================== Utils ==================================== //
+
+/// Tries to load the UI preferences from the specified file path. A missing file is
+/// created with the preferences found in the settings file, or the default ones. A
+/// file that can't be parsed is left as it is and the default preferences are used.
+pub fn read_preferences(path: &Path, settings_path: &Path) -> UIPreferences {
+    if path.is_file() {
+        return match parse_preferences(path) {
+            Ok(preferences) => preferences,
+            Err(err) => {
+                warn!("{} Using the default preferences instead.", err);
+                UIPreferences::default()
+            }
+        };
+    }
+    info!("Moving the UI preferences out of the settings file.");
+    let preferences = parse_preferences(settings_path).unwrap_or_default();
+    if let Err(err) = write_preferences(path, &preferences) {
+        warn!("{}", err);
+    }
+    preferences
+}
+
+/// Helper function to read and parse the UI preferences from a file. Fields that
+/// aren't preferences are ignored, so the settings files of older versions work as
+/// well.
+fn parse_preferences(path: &Path) -> Result<UIPreferences, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| format!("Couldn't read preferences from {}: {}", path.display(), err))?;
+    serde_json::from_str(&contents)
+        .map_err(|err| format!("Couldn't parse preferences from {}: {}", path.display(), err))
+}
+
+/// Tries to write the UI preferences to a specified file path. Returns an error
+/// message if writing fails.
+pub fn write_preferences(path: &Path, preferences: &UIPreferences) -> Result<(), String> {
+    let contents = serde_json::to_string_pretty(preferences)
+        .map_err(|err| format!("Couldn't serialize preferences: {}", err))?;
+    fs::write(path, contents)
+        .map_err(|err| format!("Couldn't write preferences to {}: {}", path.display(), err))
+}
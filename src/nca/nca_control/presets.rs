@@ -2,7 +2,9 @@
 
 use bevy::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::fs;
+use std::{fs, path::{Path, PathBuf}};
+
+use super::{ConfigPaths, ConfigWriteError};
 
 // =================================== Plugin =================================== //
 
@@ -15,24 +17,78 @@ impl Plugin for PresetPlugin {
             .init_resource::<NCAPresets>()
             .add_event::<AddPresetFilter>()
             .add_event::<AddPresetFn>()
+            .add_event::<RemovePresetFilter>()
+            .add_event::<RemovePresetFn>()
+            .add_event::<RenamePreset>()
+            .add_event::<ExportPresets>()
+            .add_event::<ImportPresets>()
             .add_systems(Startup, setup)
-            .add_systems(Update, (on_safe_preset_filter, on_safe_preset_fn));
+            .add_systems(Update, (
+                on_safe_preset_filter,
+                on_safe_preset_fn,
+                on_remove_preset_filter,
+                on_remove_preset_fn,
+                on_rename_preset,
+                on_export_presets,
+                on_import_presets,
+            ));
     }
 }
 
+// ================================= Constants ================================== //
+
+/// A library of activation functions, used as the presets if there is no preset
+/// file yet.
+const ACTIVATION_FN_LIBRARY: [(&str, &str); 8] = [
+    ("Identity", "return x;"),
+    ("Absolute", "return abs(x);"),
+    ("Gaussian", "return exp(-x*x);"),
+    ("Inverse Gaussian", "return -1./pow(2., (0.6*pow(x, 2.)))+1.;"),
+    ("Sine", "return sin(x);"),
+    ("Tanh", "return tanh(x);"),
+    ("Sigmoid", "return 1./(1. + exp(-x));"),
+    ("Sawtooth", "return fract(x);"),
+];
+
+/// A library of standard 3x3 convolution kernels, used as the filter presets if
+/// there is no preset file yet.
+const FILTER_LIBRARY: [(&str, [f32; 9]); 6] = [
+    ("Identity", [0., 0., 0., 0., 1., 0., 0., 0., 0.]),
+    ("Gaussian Blur", [0.0625, 0.125, 0.0625, 0.125, 0.25, 0.125, 0.0625, 0.125, 0.0625]),
+    ("Sobel X", [-1., 0., 1., -2., 0., 2., -1., 0., 1.]),
+    ("Sobel Y", [-1., -2., -1., 0., 0., 0., 1., 2., 1.]),
+    ("Laplacian", [0., 1., 0., 1., -4., 1., 0., 1., 0.]),
+    ("Sharpen", [0., -1., 0., -1., 5., -1., 0., -1., 0.]),
+];
+
 // ================================ Resources =================================== //
 
 /// A resource holding all available presets.
-#[derive(Resource, Debug, Default, Serialize, Deserialize)]
+#[derive(Resource, Debug, Serialize, Deserialize)]
 pub struct NCAPresets {
-    filter_presets: Vec<(String, [f32; 9])>,
+    filter_presets: Vec<(String, Vec<f32>)>,
     activation_fn_presets: Vec<(String, String)>,
 }
 
+impl Default for NCAPresets {
+    fn default() -> Self {
+        Self {
+            filter_presets: FILTER_LIBRARY
+                .iter()
+                .map(|(name, filter)| (name.to_string(), filter.to_vec()))
+                .collect(),
+            activation_fn_presets: ACTIVATION_FN_LIBRARY
+                .iter()
+                .map(|(name, activation_fn)| (name.to_string(), activation_fn.to_string()))
+                .collect(),
+        }
+    }
+}
+
 impl NCAPresets {
     /// Returns a vector of available presets for NCA filters, each as a tuple with
     /// the name of the preset in the 0th component, and ...
-    pub fn filter_presets(&self) -> &Vec<(String, [f32; 9])> {
+    pub fn filter_presets(&self) -> &Vec<(String, Vec<f32>)> {
         &self.filter_presets
     }
 
@@ -44,12 +100,19 @@ impl NCAPresets {
     }
 }
 
+/// The kinds of presets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PresetKind {
+    Filter,
+    ActivationFn,
+}
+
 // ================================== Events ==================================== //
 
 /// An event that triggers adding a new filter preset to the available presets.
 #[derive(Event, Debug)]
 pub struct AddPresetFilter {
-    pub name_and_filter: (String, [f32; 9]),
+    pub name_and_filter: (String, Vec<f32>),
 }
 
 /// An event that triggers adding a new actiovation function preset to the available
@@ -59,13 +122,48 @@ pub struct AddPresetFn {
     pub name_and_function: (String, String),
 }
 
+/// An event that triggers removing the filter preset at the given index.
+#[derive(Event, Debug)]
+pub struct RemovePresetFilter {
+    pub index: usize,
+}
+
+/// An event that triggers removing the activation function preset at the given
+/// index.
+#[derive(Event, Debug)]
+pub struct RemovePresetFn {
+    pub index: usize,
+}
+
+/// An event that triggers renaming the preset of the given kind at the given index.
+#[derive(Event, Debug)]
+pub struct RenamePreset {
+    pub kind: PresetKind,
+    pub index: usize,
+    pub name: String,
+}
+
+/// An event that triggers writing all presets to a preset pack at the given path.
+#[derive(Event, Debug)]
+pub struct ExportPresets {
+    pub path: PathBuf,
+}
+
+/// An event that triggers merging the presets of the preset pack at the given path
+/// into the available presets.
+#[derive(Event, Debug)]
+pub struct ImportPresets {
+    pub path: PathBuf,
+}
+
 // ================================== Systems =================================== //
 
 /// On startup, this system loads the available presets from a JSON-file.
 fn setup(
     mut presets: ResMut<NCAPresets>,
+    paths: Res<ConfigPaths>,
 ) {
-    *presets = read_presets(String::from("presets.json"));
+    *presets = read_presets(&paths.presets);
 }
 
 /// System triggered by the AddPresetFilter event. Adds the events contents as a new
@@ -74,10 +172,14 @@ fn setup(
 fn on_safe_preset_filter (
     mut ev_reader_safe_fitler: EventReader<AddPresetFilter>,
     mut presets: ResMut<NCAPresets>,
+    mut write_error: ResMut<ConfigWriteError>,
+    paths: Res<ConfigPaths>,
 ) {
     for event in ev_reader_safe_fitler.read() {
-        presets.filter_presets.push(event.name_and_filter.clone());
-        write_presets(String::from("presets.json"), &presets);
+        let (name, filter) = event.name_and_filter.clone();
+        let name = unique_name(&presets.filter_presets, name);
+        presets.filter_presets.push((name, filter));
+        write_error.report(write_presets(&paths.presets, &presets));
     }
 }
 
@@ -87,44 +189,207 @@ fn on_safe_preset_filter (
 fn on_safe_preset_fn (
     mut ev_reader_safe_fn: EventReader<AddPresetFn>,
     mut presets: ResMut<NCAPresets>,
+    mut write_error: ResMut<ConfigWriteError>,
+    paths: Res<ConfigPaths>,
 ) {
     for event in ev_reader_safe_fn.read() {
-        presets.activation_fn_presets.push(event.name_and_function.clone());
-        write_presets(String::from("presets.json"), &presets);
+        let (name, function) = event.name_and_function.clone();
+        let name = unique_name(&presets.activation_fn_presets, name);
+        presets.activation_fn_presets.push((name, function));
+        write_error.report(write_presets(&paths.presets, &presets));
+    }
+}
+
+/// System triggered by the RemovePresetFilter event. Removes the filter preset and
+/// writes the remaining presets to the preset file.
+fn on_remove_preset_filter(
+    mut ev_reader_remove_filter: EventReader<RemovePresetFilter>,
+    mut presets: ResMut<NCAPresets>,
+    mut write_error: ResMut<ConfigWriteError>,
+    paths: Res<ConfigPaths>,
+) {
+    for event in ev_reader_remove_filter.read() {
+        if event.index >= presets.filter_presets.len() {
+            warn!("No filter preset at index {}.", event.index);
+            continue;
+        }
+        let (name, _) = presets.filter_presets.remove(event.index);
+        info!("Removed filter preset {}.", name);
+        write_error.report(write_presets(&paths.presets, &presets));
+    }
+}
+
+/// System triggered by the RemovePresetFn event. Removes the activation function
+/// preset and writes the remaining presets to the preset file.
+fn on_remove_preset_fn(
+    mut ev_reader_remove_fn: EventReader<RemovePresetFn>,
+    mut presets: ResMut<NCAPresets>,
+    mut write_error: ResMut<ConfigWriteError>,
+    paths: Res<ConfigPaths>,
+) {
+    for event in ev_reader_remove_fn.read() {
+        if event.index >= presets.activation_fn_presets.len() {
+            warn!("No activation function preset at index {}.", event.index);
+            continue;
+        }
+        let (name, _) = presets.activation_fn_presets.remove(event.index);
+        info!("Removed activation function preset {}.", name);
+        write_error.report(write_presets(&paths.presets, &presets));
+    }
+}
+
+/// System triggered by the RenamePreset event. Renames the preset, keeping the name
+/// unique among the presets of its kind, and writes the presets to the preset file.
+fn on_rename_preset(
+    mut ev_reader_rename: EventReader<RenamePreset>,
+    mut presets: ResMut<NCAPresets>,
+    mut write_error: ResMut<ConfigWriteError>,
+    paths: Res<ConfigPaths>,
+) {
+    for event in ev_reader_rename.read() {
+        let renamed = match event.kind {
+            PresetKind::Filter => rename(&mut presets.filter_presets, event),
+            PresetKind::ActivationFn => rename(&mut presets.activation_fn_presets, event),
+        };
+        if renamed {
+            write_error.report(write_presets(&paths.presets, &presets));
+        } else {
+            warn!("No preset at index {}.", event.index);
+        }
+    }
+}
+
+/// System triggered by the ExportPresets event. Writes the available presets to
+/// the path of the event.
+fn on_export_presets(
+    mut ev_reader_export: EventReader<ExportPresets>,
+    presets: Res<NCAPresets>,
+) {
+    for event in ev_reader_export.read() {
+        info!("Exporting presets to {}.", event.path.display());
+        let contents = serde_json::to_string_pretty(&*presets)
+            .expect("Couldn't serialize presets.");
+        if let Err(err) = fs::write(&event.path, contents) {
+            warn!("Couldn't export presets: {}", err);
+        }
+    }
+}
+
+/// System triggered by the ImportPresets event. Reads the preset pack at the path of
+/// the event and adds its presets to the available presets. Presets that already
+/// exist are skipped, presets whose name is taken by a different preset get a
+/// number appended to their name. Writes the resulting presets to the preset file.
+fn on_import_presets(
+    mut ev_reader_import: EventReader<ImportPresets>,
+    mut presets: ResMut<NCAPresets>,
+    mut write_error: ResMut<ConfigWriteError>,
+    paths: Res<ConfigPaths>,
+) {
+    for event in ev_reader_import.read() {
+        info!("Importing presets from {}.", event.path.display());
+        let imported = match fs::read_to_string(&event.path)
+            .map_err(|err| err.to_string())
+            .and_then(|contents| {
+                serde_json::from_str::<NCAPresets>(&contents).map_err(|err| err.to_string())
+            })
+        {
+            Ok(imported) => imported,
+            Err(err) => {
+                warn!("Couldn't import presets: {}", err);
+                continue;
+            }
+        };
+
+        merge_presets(&mut presets.filter_presets, imported.filter_presets);
+        merge_presets(&mut presets.activation_fn_presets, imported.activation_fn_presets);
+        write_error.report(write_presets(&paths.presets, &presets));
     }
 }
 
 // =================================== Utils ==================================== //
 
+/// Adds the imported presets to the presets, skipping exact duplicates and renaming
+/// presets whose name is already taken.
+fn merge_presets<T: PartialEq>(presets: &mut Vec<(String, T)>, imported: Vec<(String, T)>) {
+    for (name, preset) in imported {
+        if presets.iter().any(|existing| existing.0 == name && existing.1 == preset) {
+            continue;
+        }
+        let name = unique_name(presets, name);
+        presets.push((name, preset));
+    }
+}
+
+/// Returns the name, followed by a number if it is already taken by a preset.
+fn unique_name<T>(presets: &[(String, T)], name: String) -> String {
+    let taken = |name: &str| presets.iter().any(|(preset_name, _)| preset_name == name);
+    if !taken(&name) {
+        return name;
+    }
+    (2..)
+        .map(|number| format!("{name} ({number})"))
+        .find(|numbered| !taken(numbered))
+        .unwrap()
+}
+
+/// Renames the preset at the index of the event. Returns false if there is no such
+/// preset.
+fn rename<T>(presets: &mut [(String, T)], event: &RenamePreset) -> bool {
+    if event.index >= presets.len() {
+        return false;
+    }
+    if presets[event.index].0 == event.name {
+        return true;
+    }
+    let name = unique_name(presets, event.name.clone());
+    info!("Renamed preset {} to {}.", presets[event.index].0, name);
+    presets[event.index].0 = name;
+    true
+}
+
 /// Tries to load presets from the specified file path. Returns the obtained presets
-/// if loading is successful, returns empty presets otherwise.
-fn read_presets(path: String) -> NCAPresets {
+/// if loading is successful, returns the default presets otherwise. A file without
+/// any presets, e.g. one written before the libraries existed, gets the libraries
+/// of filters and activation functions as well.
+pub fn read_presets(path: &Path) -> NCAPresets {
     info!("Reading presets.");
-    let contents_res = fs::read_to_string(path.clone());
+    let contents_res = fs::read_to_string(path);
     if let Ok(contents) = contents_res {
         let presets_res = serde_json::from_str::<NCAPresets>(&contents);
         if let Ok(presets) = presets_res {
+            if !presets.filter_presets.is_empty() || !presets.activation_fn_presets.is_empty() {
+                return presets;
+            }
+            info!("The preset file is empty, adding the preset libraries.");
+            let presets = NCAPresets::default();
+            if let Err(err) = write_presets(path, &presets) {
+                warn!("{}", err);
+            }
             presets
         } else {
             info!("Failed to parse presets, returning default value instead.");
             let presets = NCAPresets::default();
-            write_presets(path, &NCAPresets::default());
+            if let Err(err) = write_presets(path, &presets) {
+                warn!("{}", err);
+            }
             presets
         }
     } else {
         info!("Failed to read preset file, returning default value instead.");
         let presets = NCAPresets::default();
-        write_presets(path, &NCAPresets::default());
+        if let Err(err) = write_presets(path, &presets) {
+            warn!("{}", err);
+        }
         presets
     }
 }
 
-/// Tries to write the presets to a specified file path. Panics if writing fails.
-fn write_presets(path: String, presets: &NCAPresets) {
+/// Tries to write the presets to a specified file path. Returns an error message
+/// if writing fails.
+pub fn write_presets(path: &Path, presets: &NCAPresets) -> Result<(), String> {
     info!("Writing presets.");
-    fs::write(
-        path,
-        serde_json::to_string_pretty(presets)
-            .expect("Couldn't deserialize settings."))
-            .expect("Could not write to file.");
+    let contents = serde_json::to_string_pretty(presets)
+        .map_err(|err| format!("Couldn't serialize presets: {}", err))?;
+    fs::write(path, contents)
+        .map_err(|err| format!("Couldn't write presets to {}: {}", path.display(), err))
 }
\ No newline at end of file
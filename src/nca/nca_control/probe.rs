@@ -0,0 +1,101 @@
+//! Probing the value of the cell under the cursor
+
+use bevy::prelude::*;
+
+use super::super::{
+    input::{is_on_canvas, NCAMouseParams},
+    pipeline::readback::{NCAReadback, ReadbackComplete, ReadbackId, ReadbackPurpose},
+    utils::decode_pixel,
+    NCAFrontTexture,
+    NCASimSize,
+};
+
+// =================================== Plugin =================================== //
+
+/// A plugin to read the value of the cell under the cursor back from the GPU while
+/// the probe is enabled. Only a single cell is read back per frame, and only once
+/// the previous one arrived, so that probing barely costs anything.
+pub(super) struct ProbePlugin;
+
+impl Plugin for ProbePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<NCAProbe>()
+            .add_systems(Update, receive_probe)
+            // The front texture is switched during the update, so only request it
+            // afterwards.
+            .add_systems(PostUpdate, request_probe);
+    }
+}
+
+// ================================ Resources =================================== //
+
+/// A resource holding whether the probe is enabled and the last probed cell.
+#[derive(Resource, Debug, Default)]
+pub struct NCAProbe {
+    pub enabled: bool,
+    /// The position and RGBA values of the last probed cell, None if the cursor
+    /// isn't over the canvas.
+    value: Option<(UVec2, [f32; 4])>,
+    /// The readback and the position of the requested cell while waiting for it.
+    requested: Option<(ReadbackId, UVec2)>,
+}
+
+impl NCAProbe {
+    /// Returns the position and RGBA values of the last probed cell.
+    pub fn value(&self) -> Option<(UVec2, [f32; 4])> {
+        self.value
+    }
+}
+
+// ================================== Systems =================================== //
+
+/// A system that requests the cell under the cursor from the front texture, using
+/// the same canvas position as drawing.
+fn request_probe(
+    mut probe: ResMut<NCAProbe>,
+    mut readback: ResMut<NCAReadback>,
+    input_state: Res<NCAMouseParams>,
+    sim_size: Res<NCASimSize>,
+    front: Res<NCAFrontTexture>,
+) {
+    if !probe.enabled {
+        probe.value = None;
+        return;
+    }
+    if !is_on_canvas(input_state.mouse_pos, sim_size.size) {
+        probe.value = None;
+        return;
+    }
+    if probe.requested.is_some() {
+        return;
+    }
+    let pos = input_state.mouse_pos.as_uvec2();
+    let id = readback.request_region(
+        ReadbackPurpose::Probe,
+        front.handle.clone_weak(),
+        URect::from_corners(pos, pos + UVec2::ONE),
+    );
+    probe.requested = Some((id, pos));
+}
+
+/// A system that stores the values of the probed cells.
+fn receive_probe(
+    mut ev_reader_readback: EventReader<ReadbackComplete>,
+    mut probe: ResMut<NCAProbe>,
+) {
+    for event in ev_reader_readback.read() {
+        let Some((id, pos)) = probe.requested else { continue };
+        if event.id != id {
+            continue;
+        }
+        probe.requested = None;
+        if !probe.enabled {
+            continue;
+        }
+        let Ok(readback) = &event.result else { continue };
+        if let Some(pixel) = decode_pixel(&readback.data, readback.format) {
+            probe.value = Some((pos, pixel));
+        }
+    }
+}
@@ -0,0 +1,266 @@
+//! NCA settings profiles
+
+use bevy::prelude::*;
+use std::{fs, path::{Path, PathBuf}};
+
+use super::{
+    settings::{try_read_settings, write_settings, NCASettings, SaveSettings, SHOWCASE_RULES},
+    ConfigWriteError,
+    UpdateActivationFunction,
+    UpdateFilter,
+};
+
+// ================================= Constants ================================== //
+
+/// The directory containing one JSON file per settings profile.
+const PROFILE_DIR: &str = "profiles";
+
+// =================================== Plugin =================================== //
+
+/// A plugin that manages named settings profiles. Unlike workspaces, a profile only
+/// holds NCA settings, which are copied into the current settings when switching to
+/// it. The presets are left untouched.
+pub(super) struct ProfilePlugin;
+
+impl Plugin for ProfilePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<NCAProfiles>()
+            .add_event::<NewProfile>()
+            .add_event::<SaveProfileAs>()
+            .add_event::<SwitchProfile>()
+            .add_systems(Startup, setup)
+            .add_systems(Update, (on_new_profile, on_save_profile_as, on_switch_profile));
+    }
+}
+
+// ================================ Resources =================================== //
+
+/// A resource holding the available profiles and the one loaded last.
+#[derive(Resource, Debug, Default)]
+pub struct NCAProfiles {
+    selected: Option<String>,
+    available: Vec<String>,
+}
+
+impl NCAProfiles {
+    /// Returns the name of the profile loaded or saved last, or None if no profile
+    /// was used yet.
+    pub fn selected(&self) -> Option<&String> {
+        self.selected.as_ref()
+    }
+
+    /// Returns the names of all available profiles.
+    pub fn available(&self) -> &Vec<String> {
+        &self.available
+    }
+}
+
+// ================================== Events ==================================== //
+
+/// An event that triggers creating a new profile with the default settings and
+/// switching to it.
+#[derive(Event, Debug)]
+pub struct NewProfile {
+    pub name: String,
+}
+
+/// An event that triggers saving the current settings as a profile. Overwrites a
+/// profile of the same name.
+#[derive(Event, Debug)]
+pub struct SaveProfileAs {
+    pub name: String,
+}
+
+/// An event that triggers loading the settings of a profile and updating the NCA.
+#[derive(Event, Debug)]
+pub struct SwitchProfile {
+    pub name: String,
+}
+
+// ================================== Systems =================================== //
+
+/// On startup, this system looks for the available profiles. On the first run,
+/// i.e. if there is no profile directory yet, the bundled profiles are written
+/// first.
+fn setup(
+    mut profiles: ResMut<NCAProfiles>,
+    mut write_error: ResMut<ConfigWriteError>,
+) {
+    if !Path::new(PROFILE_DIR).exists() {
+        info!("Writing bundled profiles.");
+        for (name, settings) in bundled_profiles() {
+            let Some(path) = profile_path(name) else { continue };
+            write_error.report(write_profile(&path, &settings));
+        }
+    }
+    profiles.available = find_profiles();
+}
+
+/// System triggered by the NewProfile event. Writes the default settings to the
+/// file of the new profile and switches to it.
+fn on_new_profile(
+    mut ev_reader_new_profile: EventReader<NewProfile>,
+    mut ev_writer_switch_profile: EventWriter<SwitchProfile>,
+    mut profiles: ResMut<NCAProfiles>,
+    mut write_error: ResMut<ConfigWriteError>,
+) {
+    for event in ev_reader_new_profile.read() {
+        let Some(path) = profile_path(&event.name) else { continue };
+        if path.exists() {
+            warn!("Profile \"{}\" already exists.", event.name.trim());
+            continue;
+        }
+
+        info!("Creating profile \"{}\".", event.name.trim());
+        if !write_error.report(write_profile(&path, &NCASettings::default())) {
+            continue;
+        }
+
+        profiles.available = find_profiles();
+        ev_writer_switch_profile.send(SwitchProfile { name: event.name.trim().to_owned() });
+    }
+}
+
+/// System triggered by the SaveProfileAs event. Writes the current settings to the
+/// file of the profile.
+fn on_save_profile_as(
+    mut ev_reader_save_profile: EventReader<SaveProfileAs>,
+    mut profiles: ResMut<NCAProfiles>,
+    mut write_error: ResMut<ConfigWriteError>,
+    settings: Res<NCASettings>,
+) {
+    for event in ev_reader_save_profile.read() {
+        let Some(path) = profile_path(&event.name) else { continue };
+
+        info!("Saving profile \"{}\".", event.name.trim());
+        if !write_error.report(write_profile(&path, &settings)) {
+            continue;
+        }
+
+        profiles.available = find_profiles();
+        profiles.selected = Some(event.name.trim().to_owned());
+    }
+}
+
+/// System triggered by the SwitchProfile event. Loads the settings of the profile,
+/// saves them as the current settings and updates the NCA. A profile that can't be
+/// read is left as it is and the current settings are kept.
+fn on_switch_profile(
+    mut ev_reader_switch_profile: EventReader<SwitchProfile>,
+    mut ev_writer_update_fn: EventWriter<UpdateActivationFunction>,
+    mut ev_writer_update_filter: EventWriter<UpdateFilter>,
+    mut ev_writer_save_settings: EventWriter<SaveSettings>,
+    mut profiles: ResMut<NCAProfiles>,
+    mut settings: ResMut<NCASettings>,
+) {
+    for event in ev_reader_switch_profile.read() {
+        let Some(path) = profile_path(&event.name) else { continue };
+        if !path.exists() {
+            warn!("Profile \"{}\" doesn't exist.", event.name);
+            profiles.available = find_profiles();
+            continue;
+        }
+
+        info!("Switching to profile \"{}\".", event.name);
+        *settings = match try_read_settings(&path) {
+            Ok(profile) => profile,
+            Err(err) => {
+                warn!("Couldn't switch to profile \"{}\". {}", event.name, err);
+                continue;
+            }
+        };
+        profiles.selected = Some(event.name.clone());
+        ev_writer_update_fn.send(UpdateActivationFunction);
+        ev_writer_update_filter.send(UpdateFilter);
+        ev_writer_save_settings.send(SaveSettings);
+    }
+}
+
+// =================================== Utils ==================================== //
+
+/// Returns the profiles written on the first run: the plain identity settings and
+/// one profile for each of the showcase rules.
+fn bundled_profiles() -> Vec<(&'static str, NCASettings)> {
+    let showcase = SHOWCASE_RULES.iter().map(|(name, filter, activation_fn)| {
+        (*name, NCASettings::with_rule(*filter, activation_fn))
+    });
+    std::iter::once(("Identity", NCASettings::identity()))
+        .chain(showcase)
+        .collect()
+}
+
+/// Returns the path of the file of a profile, or None if the name is invalid.
+fn profile_path(name: &str) -> Option<PathBuf> {
+    let name = name.trim();
+    if name.is_empty() || name.contains(['/', '\\', '.']) {
+        warn!("Invalid profile name \"{}\".", name);
+        return None;
+    }
+    Some(Path::new(PROFILE_DIR).join(format!("{name}.json")))
+}
+
+/// Writes the settings to the file of a profile, creating the profile directory if
+/// necessary. Returns an error message if writing fails.
+fn write_profile(path: &Path, settings: &NCASettings) -> Result<(), String> {
+    fs::create_dir_all(PROFILE_DIR)
+        .map_err(|err| format!("Couldn't create profile directory: {}", err))?;
+    write_settings(path, settings)
+}
+
+/// Returns the sorted names of all profile files in the profile directory.
+fn find_profiles() -> Vec<String> {
+    let Ok(entries) = fs::read_dir(PROFILE_DIR) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "json"))
+        .filter_map(|path| path.file_stem()?.to_str().map(str::to_owned))
+        .collect();
+    names.sort();
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+    use super::super::{
+        bindings::{Action, Binding, KeyBindings},
+        preferences::UIPreferences,
+    };
+
+    #[test]
+    fn switching_profiles_keeps_key_bindings() {
+        // Profiles saved by older versions still hold the default key bindings.
+        let name = format!("Bindings Test {}", std::process::id());
+        let mut profile = serde_json::to_value(NCASettings::identity()).unwrap();
+        profile["key_bindings"] = serde_json::to_value(KeyBindings::default()).unwrap();
+        let path = profile_path(&name).unwrap();
+        fs::create_dir_all(PROFILE_DIR).unwrap();
+        fs::write(&path, profile.to_string()).unwrap();
+
+        let mut preferences = UIPreferences::default();
+        preferences.key_bindings.set(Action::Pause, Binding::Key(KeyCode::KeyP));
+        let mut world = World::new();
+        world.init_resource::<Events<SwitchProfile>>();
+        world.init_resource::<Events<UpdateActivationFunction>>();
+        world.init_resource::<Events<UpdateFilter>>();
+        world.init_resource::<Events<SaveSettings>>();
+        world.init_resource::<NCAProfiles>();
+        world.insert_resource(NCASettings::default());
+        world.insert_resource(preferences.clone());
+        world.send_event(SwitchProfile { name: name.clone() });
+        world.run_system_once(on_switch_profile);
+
+        fs::remove_file(&path).unwrap();
+        let _ = fs::remove_dir(PROFILE_DIR);
+        assert_eq!(world.resource::<NCAProfiles>().selected(), Some(&name));
+        let identity = NCASettings::identity();
+        assert_eq!(world.resource::<NCASettings>().red.activation_fn, identity.red.activation_fn);
+        assert_eq!(*world.resource::<UIPreferences>(), preferences);
+    }
+}
@@ -0,0 +1,60 @@
+//! Random NCA filters
+
+use bevy::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::super::utils::mirror_kernel;
+
+// ================================ Resources =================================== //
+
+/// A resource to fill NCA filters with random weights. Uses a seeded RNG, so that the
+/// same seed produces the same sequence of filters.
+#[derive(Resource, Debug)]
+pub struct FilterRandomizer {
+    /// The smallest random weight.
+    pub min: f32,
+    /// The largest random weight.
+    pub max: f32,
+    /// If true, the random filters are point-symmetric around the center.
+    pub symmetric: bool,
+    seed: u64,
+    rng: StdRng,
+}
+
+impl Default for FilterRandomizer {
+    fn default() -> Self {
+        Self {
+            min: -1.,
+            max: 1.,
+            symmetric: false,
+            seed: 0,
+            rng: StdRng::seed_from_u64(0),
+        }
+    }
+}
+
+impl FilterRandomizer {
+    /// Returns the seed the RNG was last seeded with.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Reseeds the RNG, restarting the sequence of random filters.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// Fills the filter with random weights in the configured range. The filter is
+    /// made point-symmetric if either the randomizer or the channel of the filter
+    /// asks for it.
+    pub fn randomize(&mut self, filter: &mut [f32], symmetric: bool) {
+        let (min, max) = (self.min.min(self.max), self.min.max(self.max));
+        for weight in filter.iter_mut() {
+            *weight = self.rng.gen_range(min..=max);
+        }
+        if self.symmetric || symmetric {
+            mirror_kernel(filter);
+        }
+    }
+}
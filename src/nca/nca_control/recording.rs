@@ -0,0 +1,268 @@
+//! Recording the NCA as an animated GIF
+
+use bevy::{
+    prelude::*,
+    tasks::{block_on, poll_once, AsyncComputeTaskPool, Task},
+    utils::HashMap,
+};
+use color_quant::NeuQuant;
+use image::{
+    codecs::gif::{GifEncoder, Repeat},
+    imageops::{self, FilterType},
+    Delay,
+    Frame,
+    ImageResult,
+    RgbaImage,
+};
+use std::{fs::File, io::BufWriter, path::{Path, PathBuf}};
+
+use super::super::{
+    pipeline::readback::{NCAReadback, ReadbackComplete, ReadbackId, ReadbackPurpose},
+    utils::to_rgba8,
+    NCAFrontTexture,
+};
+
+// =================================== Plugin =================================== //
+
+/// A plugin to record the NCA as an animated GIF. Captures a number of consecutive
+/// frames, quantizes them to the 256 colors supported by GIF and writes them to a
+/// looping GIF file. If a frame can't be read back, the recording is aborted.
+pub(super) struct RecordingPlugin;
+
+impl Plugin for RecordingPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<GifRecorder>()
+            .add_event::<StartGifRecording>()
+            .add_systems(Update, (
+                on_start_gif_recording,
+                record_gif_frames,
+                poll_gif_encoding,
+            ).chain())
+            // The front texture is switched during the update, so only request it
+            // afterwards.
+            .add_systems(PostUpdate, request_gif_frames);
+    }
+}
+
+// ================================ Resources =================================== //
+
+/// Settings for recording GIFs.
+#[derive(Debug, Clone)]
+pub struct GifSettings {
+    /// The number of frames to record.
+    pub frame_count: u32,
+    /// The delay between two frames of the GIF in milliseconds.
+    pub delay_ms: u32,
+    /// The factor by which the frames are scaled down.
+    pub downscale: u32,
+    /// Whether to dither the frames when reducing them to 256 colors.
+    pub dither: bool,
+}
+
+impl Default for GifSettings {
+    fn default() -> Self {
+        Self {
+            frame_count: 60,
+            delay_ms: 40,
+            downscale: 2,
+            dither: true,
+        }
+    }
+}
+
+/// The state of the GIF recorder.
+#[derive(Debug, Default)]
+enum GifRecorderState {
+    #[default]
+    Idle,
+    Recording {
+        path: PathBuf,
+        /// The readbacks of the frames in the order they were requested.
+        requested: Vec<ReadbackId>,
+        /// The frames that arrived by the id of their readback.
+        frames: HashMap<ReadbackId, RgbaImage>,
+    },
+    Encoding(Task<ImageResult<()>>),
+}
+
+/// A resource holding the settings and the state of the GIF recorder.
+#[derive(Resource, Debug, Default)]
+pub struct GifRecorder {
+    pub settings: GifSettings,
+    state: GifRecorderState,
+}
+
+impl GifRecorder {
+    /// Returns true if the recorder is neither recording nor encoding.
+    pub fn is_idle(&self) -> bool {
+        matches!(self.state, GifRecorderState::Idle)
+    }
+
+    /// Returns a short description of what the recorder is doing.
+    pub fn status(&self) -> String {
+        match &self.state {
+            GifRecorderState::Idle => String::from("Idle"),
+            GifRecorderState::Recording { frames, .. } => {
+                format!("Recording frame {}/{}", frames.len(), self.settings.frame_count)
+            }
+            GifRecorderState::Encoding(_) => String::from("Encoding GIF"),
+        }
+    }
+}
+
+// ================================== Events ==================================== //
+
+/// An event that starts recording a GIF to the given path.
+#[derive(Event, Debug)]
+pub struct StartGifRecording {
+    pub path: PathBuf,
+}
+
+// ================================== Systems =================================== //
+
+/// System triggered by the StartGifRecording event. Starts recording if the
+/// recorder is idle and at least one frame is to be recorded.
+fn on_start_gif_recording(
+    mut ev_reader_start_recording: EventReader<StartGifRecording>,
+    mut recorder: ResMut<GifRecorder>,
+) {
+    for event in ev_reader_start_recording.read() {
+        if !recorder.is_idle() {
+            warn!("Already recording a GIF.");
+            continue;
+        }
+        if recorder.settings.frame_count == 0 {
+            warn!("Can't record a GIF without frames.");
+            continue;
+        }
+        info!("Recording GIF to {}.", event.path.display());
+        recorder.state = GifRecorderState::Recording {
+            path: event.path.clone(),
+            requested: Vec::new(),
+            frames: HashMap::new(),
+        };
+    }
+}
+
+/// A system that requests a readback of the front texture each frame while
+/// recording, until all frames are requested.
+fn request_gif_frames(
+    mut readback: ResMut<NCAReadback>,
+    mut recorder: ResMut<GifRecorder>,
+    front: Res<NCAFrontTexture>,
+) {
+    let frame_count = recorder.settings.frame_count as usize;
+    let GifRecorderState::Recording { requested, .. } = &mut recorder.state else {
+        return;
+    };
+    if requested.len() < frame_count {
+        requested.push(readback.request(ReadbackPurpose::GifFrame, front.handle.clone_weak()));
+    }
+}
+
+/// A system that collects the read back frames while recording. Starts encoding
+/// once all frames arrived and aborts the recording if a frame couldn't be read
+/// back.
+fn record_gif_frames(
+    mut ev_reader_readback: EventReader<ReadbackComplete>,
+    mut recorder: ResMut<GifRecorder>,
+) {
+    let settings = recorder.settings.clone();
+    let GifRecorderState::Recording { path, requested, frames } = &mut recorder.state else {
+        return;
+    };
+
+    for event in ev_reader_readback.read() {
+        if !requested.contains(&event.id) {
+            continue;
+        }
+        let frame = event
+            .result
+            .as_ref()
+            .map_err(|err| err.to_string())
+            .and_then(|readback| {
+                let data = to_rgba8(&readback.data, readback.format).ok_or_else(|| {
+                    format!("can't record GIF frames of format {:?}", readback.format)
+                })?;
+                RgbaImage::from_raw(readback.size.x, readback.size.y, data)
+                    .ok_or_else(|| String::from("the frame has the wrong size"))
+            });
+        match frame {
+            Ok(frame) => {
+                frames.insert(event.id, downscale(frame, settings.downscale));
+            }
+            Err(err) => {
+                warn!("Aborted recording the GIF: {}.", err);
+                recorder.state = GifRecorderState::Idle;
+                return;
+            }
+        }
+    }
+
+    if frames.len() >= settings.frame_count as usize {
+        let path = std::mem::take(path);
+        let frames = requested.iter().filter_map(|id| frames.remove(id)).collect::<Vec<_>>();
+        info!("Encoding GIF with {} frames.", frames.len());
+        let task = AsyncComputeTaskPool::get().spawn(async move {
+            encode_gif(&path, frames, settings.delay_ms, settings.dither)
+        });
+        recorder.state = GifRecorderState::Encoding(task);
+    }
+}
+
+/// A system that checks whether encoding the GIF has finished.
+fn poll_gif_encoding(
+    mut recorder: ResMut<GifRecorder>,
+) {
+    let GifRecorderState::Encoding(task) = &mut recorder.state else {
+        return;
+    };
+    if let Some(result) = block_on(poll_once(task)) {
+        match result {
+            Ok(()) => info!("Finished writing GIF."),
+            Err(err) => warn!("Couldn't write GIF: {}", err),
+        }
+        recorder.state = GifRecorderState::Idle;
+    }
+}
+
+// =================================== Utils ==================================== //
+
+/// Scales a frame down by the given factor.
+fn downscale(frame: RgbaImage, factor: u32) -> RgbaImage {
+    if factor <= 1 {
+        return frame;
+    }
+    let width = (frame.width() / factor).max(1);
+    let height = (frame.height() / factor).max(1);
+    imageops::resize(&frame, width, height, FilterType::Nearest)
+}
+
+/// Encodes the frames as a looping GIF and writes it to the given path. If
+/// dithering is enabled, each frame is dithered against a palette of 256 colors
+/// before encoding.
+fn encode_gif(
+    path: &Path,
+    frames: Vec<RgbaImage>,
+    delay_ms: u32,
+    dither: bool,
+) -> ImageResult<()> {
+    let file = File::create(path)?;
+    let mut encoder = GifEncoder::new_with_speed(BufWriter::new(file), 10);
+    encoder.set_repeat(Repeat::Infinite)?;
+
+    for mut frame in frames {
+        if dither {
+            let palette = NeuQuant::new(10, 256, frame.as_raw());
+            imageops::dither(&mut frame, &palette);
+        }
+        encoder.encode_frame(Frame::from_parts(
+            frame,
+            0,
+            0,
+            Delay::from_numer_denom_ms(delay_ms, 1),
+        ))?;
+    }
+    Ok(())
+}
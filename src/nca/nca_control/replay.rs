@@ -0,0 +1,284 @@
+//! Recording and replaying brush strokes
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::PathBuf};
+
+use super::{
+    super::{input::NCAMouseParams, pipeline::draw::NCADrawSettings},
+    history::SnapshotCanvas,
+};
+
+// =================================== Plugin =================================== //
+
+/// A plugin to record the brush strokes and seeds drawn on the canvas together with
+/// the draw settings and their timing, and to replay them later. Recordings can be
+/// saved to and loaded from JSON files. Fills and picked colors aren't recorded,
+/// neither is the canvas the strokes were drawn on.
+pub(super) struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<StrokeReplay>()
+            .add_event::<StartStrokeRecording>()
+            .add_event::<PlayStrokes>()
+            .add_event::<StopStrokeReplay>()
+            .add_event::<SaveStrokes>()
+            .add_event::<LoadStrokes>()
+            // Runs after the input was handled, so that the recorded strokes are
+            // the ones drawn and the replayed ones replace the user's.
+            .add_systems(PostUpdate, (
+                on_replay_event,
+                record_strokes,
+                play_strokes,
+            ).chain());
+    }
+}
+
+// ================================ Resources =================================== //
+
+/// The state of the cursor in a recorded frame.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct RecordedMouse {
+    is_drawing: bool,
+    is_erasing: bool,
+    mouse_pos: [f32; 2],
+    prev_mouse_pos: [f32; 2],
+    speed: f32,
+    place_seed: bool,
+}
+
+/// A frame in which something was drawn.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordedFrame {
+    /// The time since the start of the recording in seconds.
+    time: f64,
+    mouse: RecordedMouse,
+    /// The draw settings, if they changed since the previous recorded frame.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    draw: Option<NCADrawSettings>,
+}
+
+/// The recorded frames, in the order they were drawn.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct StrokeTimeline {
+    frames: Vec<RecordedFrame>,
+}
+
+/// What the replay is doing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReplayState {
+    #[default]
+    Idle,
+    Recording,
+    Playing,
+}
+
+/// A resource holding the recorded strokes and the progress of recording or
+/// replaying them.
+#[derive(Resource, Debug, Default)]
+pub struct StrokeReplay {
+    state: ReplayState,
+    timeline: StrokeTimeline,
+    /// The time the recording or replay started, in seconds since startup.
+    start: f64,
+    /// The index of the next frame to replay.
+    next_frame: usize,
+    /// The draw settings from before the replay, restored once it ends.
+    saved_draw: Option<NCADrawSettings>,
+}
+
+impl StrokeReplay {
+    /// Returns what the replay is doing.
+    pub fn state(&self) -> ReplayState {
+        self.state
+    }
+
+    /// Returns the number of recorded frames in which something was drawn.
+    pub fn frames(&self) -> usize {
+        self.timeline.frames.len()
+    }
+
+    /// Returns the duration of the recording in seconds.
+    pub fn duration(&self) -> f64 {
+        self.timeline.frames.last().map_or(0., |frame| frame.time)
+    }
+}
+
+// ================================== Events ==================================== //
+
+/// An event that discards the recorded strokes and starts recording new ones.
+#[derive(Event, Debug)]
+pub struct StartStrokeRecording;
+
+/// An event that replays the recorded strokes from the beginning.
+#[derive(Event, Debug)]
+pub struct PlayStrokes;
+
+/// An event that stops recording or replaying strokes.
+#[derive(Event, Debug)]
+pub struct StopStrokeReplay;
+
+/// An event that saves the recorded strokes to the given path as JSON.
+#[derive(Event, Debug)]
+pub struct SaveStrokes {
+    pub path: PathBuf,
+}
+
+/// An event that loads recorded strokes from the given JSON file.
+#[derive(Event, Debug)]
+pub struct LoadStrokes {
+    pub path: PathBuf,
+}
+
+/// The event readers for recording, replaying, saving and loading strokes.
+#[derive(SystemParam)]
+struct ReplayRequests<'w, 's> {
+    start_recording: EventReader<'w, 's, StartStrokeRecording>,
+    play: EventReader<'w, 's, PlayStrokes>,
+    stop: EventReader<'w, 's, StopStrokeReplay>,
+    save: EventReader<'w, 's, SaveStrokes>,
+    load: EventReader<'w, 's, LoadStrokes>,
+}
+
+// ================================== Systems =================================== //
+
+/// System triggered by the replay events. Starts and stops recording or replaying
+/// and saves or loads the recorded strokes. The canvas is saved to the history
+/// before a replay, so that it can be undone as a whole.
+fn on_replay_event(
+    mut requests: ReplayRequests,
+    mut ev_writer_snapshot: EventWriter<SnapshotCanvas>,
+    mut replay: ResMut<StrokeReplay>,
+    mut draw_params: ResMut<NCADrawSettings>,
+    time: Res<Time>,
+) {
+    let now = time.elapsed_seconds_f64();
+    if requests.stop.read().count() > 0 {
+        stop(&mut replay, &mut draw_params);
+    }
+    for event in requests.save.read() {
+        let contents = serde_json::to_string(&replay.timeline)
+            .map_err(|err| err.to_string())
+            .and_then(|contents| fs::write(&event.path, contents).map_err(|err| err.to_string()));
+        match contents {
+            Ok(()) => info!("Saved strokes to {}.", event.path.display()),
+            Err(err) => warn!("Couldn't save strokes: {}", err),
+        }
+    }
+    for event in requests.load.read() {
+        let timeline = fs::read_to_string(&event.path)
+            .map_err(|err| err.to_string())
+            .and_then(|contents| {
+                serde_json::from_str::<StrokeTimeline>(&contents).map_err(|err| err.to_string())
+            });
+        match timeline {
+            Ok(timeline) => {
+                info!("Loaded strokes from {}.", event.path.display());
+                stop(&mut replay, &mut draw_params);
+                replay.timeline = timeline;
+            }
+            Err(err) => warn!("Couldn't load strokes: {}", err),
+        }
+    }
+    if requests.start_recording.read().count() > 0 {
+        stop(&mut replay, &mut draw_params);
+        info!("Recording strokes.");
+        replay.state = ReplayState::Recording;
+        replay.timeline = StrokeTimeline::default();
+        replay.start = now;
+    }
+    if requests.play.read().count() > 0 {
+        stop(&mut replay, &mut draw_params);
+        info!("Replaying {} frames of strokes.", replay.frames());
+        replay.state = ReplayState::Playing;
+        replay.start = now;
+        replay.next_frame = 0;
+        replay.saved_draw = Some(*draw_params);
+        ev_writer_snapshot.send(SnapshotCanvas);
+    }
+}
+
+/// A system that records the frames in which something is drawn while recording.
+fn record_strokes(
+    mut replay: ResMut<StrokeReplay>,
+    input_state: Res<NCAMouseParams>,
+    draw_params: Res<NCADrawSettings>,
+    time: Res<Time>,
+) {
+    if replay.state != ReplayState::Recording {
+        return;
+    }
+    // The draw node skips the first frame of a stroke, see NCAMouseParams.
+    let stroking = (input_state.is_drawing || input_state.is_erasing) && !input_state.stroke_start;
+    if !stroking && !input_state.place_seed {
+        return;
+    }
+    let last_draw = replay.timeline.frames.iter().rev().find_map(|frame| frame.draw);
+    let draw = (last_draw != Some(*draw_params)).then_some(*draw_params);
+    let time = time.elapsed_seconds_f64() - replay.start;
+    replay.timeline.frames.push(RecordedFrame {
+        time,
+        mouse: RecordedMouse {
+            is_drawing: input_state.is_drawing,
+            is_erasing: input_state.is_erasing,
+            mouse_pos: input_state.mouse_pos.to_array(),
+            prev_mouse_pos: input_state.prev_mouse_pos.to_array(),
+            speed: input_state.speed,
+            place_seed: input_state.place_seed,
+        },
+        draw,
+    });
+}
+
+/// A system that replaces the user's strokes with the recorded ones while
+/// replaying. Replays at most one recorded frame per frame, once its time has
+/// come, so that every stroke is drawn even if the app runs slower than during
+/// the recording.
+fn play_strokes(
+    mut replay: ResMut<StrokeReplay>,
+    mut input_state: ResMut<NCAMouseParams>,
+    mut draw_params: ResMut<NCADrawSettings>,
+    time: Res<Time>,
+) {
+    if replay.state != ReplayState::Playing {
+        return;
+    }
+    let Some(frame) = replay.timeline.frames.get(replay.next_frame).cloned() else {
+        info!("Finished replaying strokes.");
+        stop(&mut replay, &mut draw_params);
+        return;
+    };
+
+    input_state.is_drawing = false;
+    input_state.is_erasing = false;
+    input_state.stroke_start = false;
+    input_state.place_seed = false;
+    input_state.pick_color = false;
+    if time.elapsed_seconds_f64() - replay.start < frame.time {
+        return;
+    }
+    replay.next_frame += 1;
+    let mouse = frame.mouse;
+    input_state.is_drawing = mouse.is_drawing;
+    input_state.is_erasing = mouse.is_erasing;
+    input_state.mouse_pos = Vec2::from_array(mouse.mouse_pos);
+    input_state.prev_mouse_pos = Vec2::from_array(mouse.prev_mouse_pos);
+    input_state.speed = mouse.speed;
+    input_state.place_seed = mouse.place_seed;
+    if let Some(draw) = frame.draw {
+        *draw_params = draw;
+    }
+}
+
+// =================================== Utils ==================================== //
+
+/// Helper function to stop recording or replaying. Restores the draw settings from
+/// before the replay.
+fn stop(replay: &mut StrokeReplay, draw_params: &mut NCADrawSettings) {
+    if let Some(saved) = replay.saved_draw.take() {
+        *draw_params = saved;
+    }
+    replay.state = ReplayState::Idle;
+}
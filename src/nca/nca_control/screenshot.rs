@@ -0,0 +1,156 @@
+//! Screenshots of the NCA
+
+use bevy::{
+    prelude::*,
+    render::extract_resource::{ExtractResource, ExtractResourcePlugin},
+    tasks::IoTaskPool,
+};
+use image::RgbaImage;
+use std::{
+    fs,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::super::{
+    pipeline::readback::{NCAReadback, ReadbackComplete, ReadbackPurpose},
+    utils::to_rgba8,
+    NCAFrontTexture,
+};
+
+// =================================== Plugin =================================== //
+
+/// A plugin to save screenshots of the NCA as PNG files. Reads back the front
+/// texture at the resolution of the simulation and writes it to a timestamped file.
+/// A time-lapse saves a screenshot every few generations, named by its generation.
+/// The frames of the time-lapse are requested by the NCA node in the render world,
+/// which knows the generation each dispatch ends at.
+pub(super) struct ScreenshotPlugin;
+
+impl Plugin for ScreenshotPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<ScreenshotSettings>()
+            .init_resource::<TimeLapse>()
+            .add_event::<TakeScreenshot>()
+            .add_plugins(ExtractResourcePlugin::<TimeLapse>::default())
+            // The front texture is switched during the update, so only request it
+            // afterwards.
+            .add_systems(PostUpdate, on_take_screenshot)
+            .add_systems(Update, save_screenshots);
+    }
+}
+
+// ================================ Resources =================================== //
+
+/// Settings for saving screenshots.
+#[derive(Resource, Debug, Clone)]
+pub struct ScreenshotSettings {
+    /// The directory the screenshots are saved to.
+    pub directory: PathBuf,
+    /// The beginning of the file names, followed by a timestamp.
+    pub prefix: String,
+}
+
+impl Default for ScreenshotSettings {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("screenshots"),
+            prefix: String::from("nca"),
+        }
+    }
+}
+
+/// Settings of the time-lapse. The frames are read back without blocking the render
+/// world and written in the background. Every frame is a PNG of the whole canvas,
+/// so short intervals fill up the disk quickly.
+#[derive(Resource, ExtractResource, Debug, Clone)]
+pub struct TimeLapse {
+    /// The number of generations between two frames. 0 turns the time-lapse off.
+    /// With several steps per frame, a frame is taken at the first generation
+    /// displayed in each interval.
+    pub interval: u32,
+    /// The directory the frames are saved to.
+    pub directory: PathBuf,
+}
+
+impl Default for TimeLapse {
+    fn default() -> Self {
+        Self {
+            interval: 0,
+            directory: PathBuf::from("timelapse"),
+        }
+    }
+}
+
+// ================================== Events ==================================== //
+
+/// An event that saves a screenshot of the current front texture.
+#[derive(Event, Debug)]
+pub struct TakeScreenshot;
+
+// ================================== Systems =================================== //
+
+/// System triggered by the TakeScreenshot event. Requests a readback of the front
+/// texture.
+fn on_take_screenshot(
+    mut ev_reader_screenshot: EventReader<TakeScreenshot>,
+    mut readback: ResMut<NCAReadback>,
+    front: Res<NCAFrontTexture>,
+) {
+    if ev_reader_screenshot.read().count() > 0 {
+        readback.request(ReadbackPurpose::Screenshot, front.handle.clone_weak());
+    }
+}
+
+/// A system that writes the read back screenshots and frames of the time-lapse to
+/// PNG files in the background.
+fn save_screenshots(
+    mut ev_reader_readback: EventReader<ReadbackComplete>,
+    time_lapse: Res<TimeLapse>,
+    settings: Res<ScreenshotSettings>,
+) {
+    for event in ev_reader_readback.read() {
+        let (directory, file_name) = match event.purpose {
+            ReadbackPurpose::Screenshot => {
+                let timestamp = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|duration| duration.as_millis())
+                    .unwrap_or_default();
+                (settings.directory.clone(), format!("{}_{}.png", settings.prefix, timestamp))
+            }
+            ReadbackPurpose::TimeLapse { generation } => {
+                (time_lapse.directory.clone(), format!("{:010}.png", generation))
+            }
+            _ => continue,
+        };
+        let readback = match &event.result {
+            Ok(readback) => readback,
+            Err(err) => {
+                warn!("Couldn't save {}: {}.", file_name, err);
+                continue;
+            }
+        };
+        let Some(data) = to_rgba8(&readback.data, readback.format) else {
+            warn!("Can't save screenshots of format {:?}.", readback.format);
+            continue;
+        };
+        let Some(image) = RgbaImage::from_raw(readback.size.x, readback.size.y, data)
+        else {
+            continue;
+        };
+
+        let path = directory.join(file_name);
+        IoTaskPool::get()
+            .spawn(async move {
+                let result = fs::create_dir_all(&directory)
+                    .map_err(|err| err.to_string())
+                    .and_then(|_| image.save(&path).map_err(|err| err.to_string()));
+                match result {
+                    Ok(()) => info!("Saved screenshot to {}.", path.display()),
+                    Err(err) => warn!("Couldn't save screenshot: {}", err),
+                }
+            })
+            .detach();
+    }
+}
@@ -1,10 +1,16 @@
 //! NCA settings
 
-use bevy::prelude::*;
+use bevy::{app::AppExit, prelude::*};
 use serde::{Deserialize, Serialize};
-use std::fs;
+use std::{fs, path::Path};
 
-use super::{UpdateActivationFunction, UpdateFilter};
+use super::{
+    ConfigPaths,
+    ConfigWriteError,
+    UpdateActivationFunction,
+    UpdateFilter,
+};
+use super::super::utils::resize_kernel;
 
 // =================================== Plugin =================================== //
 
@@ -15,42 +21,382 @@ impl Plugin for SettingsPlugin {
     fn build(&self, app: &mut App) {
         app
             .init_resource::<NCASettings>()
+            .init_resource::<PendingSettingsWrite>()
             .add_event::<LoadSettings>()
             .add_event::<SaveSettings>()
             .add_systems(Startup, setup)
             .add_systems(Update, (
                 on_load_settings,
                 on_save_settings,
-            ));
+            ))
+            .add_systems(Last, flush_settings_on_exit);
     }
 }
 
 // ================================ Resources =================================== //
 
-/// A struct to hold all relevant data to run the NCA on a single color channel.
+/// The time in seconds the settings have to stay unchanged before saving them
+/// writes the settings file.
+pub(super) const SAVE_DEBOUNCE_SECS: f32 = 0.5;
+
+/// A resource holding the timer of a pending write of the settings file. Every
+/// SaveSettings event restarts it, so that dragging a value in the UI writes the
+/// file once after the drag instead of every frame.
+#[derive(Resource, Debug, Default)]
+struct PendingSettingsWrite(Option<Timer>);
+
+/// The largest supported width and height of a filter kernel.
+pub const MAX_KERNEL_SIZE: u32 = 11;
+
+/// The number of channels of the NCA.
+pub const CHANNELS: usize = 4;
+
+/// The names of the channels of the NCA, in the order of their indices.
+pub const CHANNEL_NAMES: [&str; CHANNELS] = ["Red", "Green", "Blue", "Alpha"];
+
+/// The largest number of hidden channels. They are stored four per tile of their
+/// texture, so large simulations may fit fewer of them.
+pub const MAX_HIDDEN_CHANNELS: usize = 16;
+
+/// Rules that grow patterns out of random noise, each with its name, the 3x3
+/// filter a color channel applies to itself and the activation function. The
+/// first one is used by the default settings.
+pub const SHOWCASE_RULES: [(&str, [f32; 9], &str); 3] = [
+    (
+        "Worms",
+        [0.68, -0.9, 0.68, -0.9, -0.66, -0.9, 0.68, -0.9, 0.68],
+        "return -1./pow(2., (0.6*pow(x, 2.)))+1.;",
+    ),
+    (
+        "Slime Mold",
+        [0.8, -0.85, 0.8, -0.85, -0.2, -0.85, 0.8, -0.85, 0.8],
+        "return -1./(0.89*pow(x, 2.)+1.)+1.;",
+    ),
+    (
+        "Mitosis",
+        [-0.939, 0.88, -0.939, 0.88, 0.4, 0.88, -0.939, 0.88, -0.939],
+        "return -1./(0.9*pow(x, 2.)+1.)+1.;",
+    ),
+];
+
+/// A struct to hold all relevant data to run the NCA on a single channel.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NCAChannel {
-    /// A 3x3-matrix representing the filter of the NCA.
-    pub filter: Mat3,
+    /// The weights of the filters applied to the red, green, blue and alpha channel
+    /// of the neighborhood, followed by the hidden channels. The results are summed
+    /// up. Each filter is a square matrix, row by row. The rows go from top to
+    /// bottom, the columns from right to left.
+    #[serde(default)]
+    pub filters: Vec<Vec<f32>>,
+    /// The single filter of settings written before the channels could interact.
+    /// Is moved into the filters when the settings are read.
+    #[serde(default, skip_serializing)]
+    filter: Option<Vec<f32>>,
+    /// The width and height of the filters. Always odd.
+    #[serde(default = "default_kernel_size")]
+    pub kernel_size: u32,
+    /// If true, edits of the filters in the UI are mirrored across their center.
+    #[serde(default)]
+    pub symmetric: bool,
     /// The activation function as WGSL code.
     pub activation_fn: String,
+    /// The number of discrete levels the output of the channel is quantized to.
+    /// 0 keeps the output continuous, 1 is treated like 2.
+    #[serde(default)]
+    pub levels: u32,
+    /// How the output of the activation function is mapped into the range of the
+    /// channel.
+    #[serde(default)]
+    pub output_mapping: OutputMapping,
+    /// The channel is only recomputed every that many generations and keeps its
+    /// value in between. 0 is treated like 1.
+    #[serde(default = "default_update_divisor")]
+    pub update_divisor: u32,
 }
 
-impl Default for NCAChannel {
-    fn default() -> Self {
+impl NCAChannel {
+    /// Creates the plain channel with the given index, passing on the diagonal of
+    /// its neighborhood. Only the channel itself has a filter, the filters applied
+    /// to the other channels are zero. Hidden channels start without any filter.
+    pub fn new(index: usize) -> Self {
+        let filters = (0..CHANNELS)
+            .map(|i| {
+                if i == index {
+                    vec![1., 0., 0., 0., 1., 0., 0., 0., 1.]
+                } else {
+                    vec![0.; 9]
+                }
+            })
+            .collect();
         Self {
-            filter: Mat3::IDENTITY,
-            activation_fn: "return x;".to_string()
+            filters,
+            filter: None,
+            kernel_size: 3,
+            symmetric: false,
+            activation_fn: "return x;".to_string(),
+            levels: 0,
+            output_mapping: OutputMapping::default(),
+            update_divisor: default_update_divisor(),
+        }
+    }
+
+    /// Creates the channel with the given index running a rule on itself, i.e. the
+    /// 3x3 filter is applied to the channel itself only.
+    pub fn with_rule(index: usize, filter: [f32; 9], activation_fn: &str) -> Self {
+        let mut channel = Self::new(index);
+        channel.filters[index] = filter.to_vec();
+        channel.activation_fn = activation_fn.to_owned();
+        channel
+    }
+
+    /// Changes the size of the filter kernels, keeping the weights around the
+    /// center.
+    pub fn resize_kernel(&mut self, kernel_size: u32) {
+        for filter in self.filters.iter_mut() {
+            *filter = resize_kernel(filter, self.kernel_size, kernel_size);
+        }
+        self.kernel_size = kernel_size;
+    }
+
+    /// Brings the filters of settings written by older versions into the current
+    /// form. Moves a single filter to the given index and fills missing filters
+    /// with zeros, so that there is one filter for each of the given number of
+    /// channels. Kernel sizes that are even or out of range, e.g. from edited
+    /// files, are replaced by the closest odd size up to [`MAX_KERNEL_SIZE`].
+    fn migrate(&mut self, index: usize, channel_count: usize) {
+        self.filters.resize(channel_count, Vec::new());
+        if let Some(filter) = self.filter.take() {
+            if let Some(target) = self.filters.get_mut(index) {
+                *target = filter;
+            }
+        }
+        let kernel_size = valid_kernel_size(self.kernel_size);
+        if kernel_size != self.kernel_size {
+            warn!("Invalid kernel size {}, using {} instead.", self.kernel_size, kernel_size);
+            self.resize_kernel(kernel_size);
+        }
+        let len = (self.kernel_size * self.kernel_size) as usize;
+        for filter in self.filters.iter_mut() {
+            if filter.len() != len {
+                filter.resize(len, 0.);
+            }
         }
     }
 }
 
-/// A resource holding all relevant data to run the NCA on all three color channels.
-#[derive(Resource, Debug, Default, Clone, Serialize, Deserialize)]
+/// How the NCA treats neighbors outside of the simulation.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BoundaryMode {
+    /// The edges wrap around, giving the simulation the topology of a torus.
+    #[default]
+    Wrap,
+    /// Neighbors outside of the simulation take the value of the closest edge cell.
+    Clamp,
+    /// Neighbors outside of the simulation are black.
+    Zero,
+}
+
+/// Which cells of the kernel contribute to the convolution of a cell.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Neighborhood {
+    /// All cells of the kernel.
+    #[default]
+    Moore,
+    /// The cells within the Manhattan distance of the kernel radius, e.g. a 3x3
+    /// kernel without its four corners.
+    VonNeumann,
+    /// The cells picked in the custom neighborhood of the settings.
+    Custom,
+}
+
+/// How the output of an activation function is mapped into the range of a channel.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OutputMapping {
+    /// Values outside of [0, 1] are clamped to the closest bound.
+    #[default]
+    Clamp,
+    /// Only the fractional part is kept, so values wrap around from 1 to 0.
+    Wrap,
+    /// Values are folded back at 0 and 1, like a triangle wave.
+    Fold,
+    /// The output is kept as it is. Only textures of format Rgba16Float can hold
+    /// values outside of [0, 1].
+    Unclamped,
+}
+
+/// The initial state of the NCA after reinitializing.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InitMode {
+    /// Uniform random noise in the color channels.
+    #[default]
+    Noise = 0,
+    Black = 1,
+    White = 2,
+    /// A single white cell in the center, the classic seed for growing NCAs.
+    CenterCell = 3,
+    /// Random colored cells on black, about one in a hundred.
+    SparseDots = 4,
+    /// A gradient from black on the left to white on the right.
+    Gradient = 5,
+}
+
+/// A resource holding all relevant data to run the NCA on all four channels.
+#[derive(Resource, Debug, Clone, Serialize, Deserialize)]
 pub struct NCASettings {
     pub red: NCAChannel,
     pub green: NCAChannel,
     pub blue: NCAChannel,
+    /// A hidden channel that takes part in the simulation like the color channels
+    /// and is displayed as the opacity of the cells.
+    #[serde(default = "default_alpha_channel")]
+    pub alpha: NCAChannel,
+    /// If true, the NCA runs on a single channel. The red channel is applied to the
+    /// red channel of the neighborhood only and its output is shown in all color
+    /// channels. The green and blue channels are kept for switching back.
+    #[serde(default)]
+    pub grayscale: bool,
+    /// How neighbors outside of the simulation are treated.
+    #[serde(default)]
+    pub boundary: BoundaryMode,
+    /// Which cells of the kernels contribute to the convolution.
+    #[serde(default)]
+    pub neighborhood: Neighborhood,
+    /// The offsets of the cells contributing to the convolution in the custom
+    /// neighborhood, as x and y relative to the center cell.
+    #[serde(default)]
+    pub custom_neighborhood: Vec<[i32; 2]>,
+    /// Channels that take part in the convolution and activation like the others,
+    /// but aren't displayed as colors, e.g. to carry the hidden state of growing
+    /// NCAs. At most [`MAX_HIDDEN_CHANNELS`]. They start at zero whenever the NCA is
+    /// initialized. Change their number with the
+    /// [`super::SetHiddenChannels`] event.
+    #[serde(default)]
+    pub hidden: Vec<NCAChannel>,
+    /// The seed of the random initial state, used when the NCA is reinitialized.
+    #[serde(default)]
+    pub seed: u32,
+    /// The initial state of the NCA.
+    #[serde(default)]
+    pub init_mode: InitMode,
+}
+
+impl Default for NCASettings {
+    /// The settings of a new user, running the first of the [`SHOWCASE_RULES`] on
+    /// each color channel.
+    fn default() -> Self {
+        let (_, filter, activation_fn) = SHOWCASE_RULES[0];
+        Self::with_rule(filter, activation_fn)
+    }
+}
+
+impl NCASettings {
+    /// Returns the plain settings, in which every channel passes on the diagonal of
+    /// its neighborhood with the identity as activation function.
+    pub fn identity() -> Self {
+        Self {
+            red: NCAChannel::new(0),
+            green: NCAChannel::new(1),
+            blue: NCAChannel::new(2),
+            alpha: NCAChannel::new(3),
+            grayscale: false,
+            boundary: BoundaryMode::default(),
+            neighborhood: Neighborhood::default(),
+            custom_neighborhood: Vec::new(),
+            hidden: Vec::new(),
+            seed: 0,
+            init_mode: InitMode::default(),
+        }
+    }
+
+    /// Returns the plain settings with each color channel running the rule on
+    /// itself.
+    pub fn with_rule(filter: [f32; 9], activation_fn: &str) -> Self {
+        Self {
+            red: NCAChannel::with_rule(0, filter, activation_fn),
+            green: NCAChannel::with_rule(1, filter, activation_fn),
+            blue: NCAChannel::with_rule(2, filter, activation_fn),
+            ..Self::identity()
+        }
+    }
+
+    /// Returns true if the cell at the offset from the center contributes to the
+    /// convolution of a kernel with the given radius.
+    pub fn in_neighborhood(&self, offset: [i32; 2], radius: i32) -> bool {
+        let [x, y] = offset;
+        if x.abs() > radius || y.abs() > radius {
+            return false;
+        }
+        match self.neighborhood {
+            Neighborhood::Moore => true,
+            Neighborhood::VonNeumann => x.abs() + y.abs() <= radius,
+            Neighborhood::Custom => self.custom_neighborhood.contains(&offset),
+        }
+    }
+
+    /// Returns the channels in the order of their indices.
+    pub fn channels(&self) -> [&NCAChannel; CHANNELS] {
+        [&self.red, &self.green, &self.blue, &self.alpha]
+    }
+
+    /// Returns the channels in the order of their indices.
+    pub fn channels_mut(&mut self) -> [&mut NCAChannel; CHANNELS] {
+        [&mut self.red, &mut self.green, &mut self.blue, &mut self.alpha]
+    }
+
+    /// Returns the number of channels including the hidden ones.
+    pub fn channel_count(&self) -> usize {
+        CHANNELS + self.hidden.len()
+    }
+
+    /// Returns all channels in the order of their indices, the hidden channels
+    /// last.
+    pub fn all_channels(&self) -> Vec<&NCAChannel> {
+        self.channels().into_iter().chain(&self.hidden).collect()
+    }
+
+    /// Returns all channels in the order of their indices, the hidden channels
+    /// last.
+    pub fn all_channels_mut(&mut self) -> Vec<&mut NCAChannel> {
+        [&mut self.red, &mut self.green, &mut self.blue, &mut self.alpha]
+            .into_iter()
+            .chain(&mut self.hidden)
+            .collect()
+    }
+
+    /// Sets the number of hidden channels, at most [`MAX_HIDDEN_CHANNELS`]. New
+    /// hidden channels start without any filter, and the filters of every channel
+    /// are added or removed to match.
+    pub fn set_hidden_channels(&mut self, count: usize) {
+        let count = count.min(MAX_HIDDEN_CHANNELS);
+        while self.hidden.len() < count {
+            self.hidden.push(NCAChannel::new(CHANNELS + self.hidden.len()));
+        }
+        self.hidden.truncate(count);
+        let channel_count = self.channel_count();
+        for (index, channel) in self.all_channels_mut().into_iter().enumerate() {
+            channel.migrate(index, channel_count);
+        }
+    }
+
+    /// Copies the filters of the channel with index `from`, together with their
+    /// size and symmetry, to the channel with index `to`.
+    pub fn copy_filters(&mut self, from: usize, to: usize) {
+        let source = self.all_channels()[from].clone();
+        if let Some(target) = self.all_channels_mut().into_iter().nth(to) {
+            target.filters = source.filters;
+            target.kernel_size = source.kernel_size;
+            target.symmetric = source.symmetric;
+        }
+    }
+
+    /// Copies the activation function of the channel with index `from` to the
+    /// channel with index `to`.
+    pub fn copy_activation_fn(&mut self, from: usize, to: usize) {
+        let source = self.all_channels()[from].activation_fn.clone();
+        if let Some(target) = self.all_channels_mut().into_iter().nth(to) {
+            target.activation_fn = source;
+        }
+    }
 }
 
 // ================================== Events ==================================== //
@@ -59,8 +405,8 @@ pub struct NCASettings {
 #[derive(Event, Debug)]
 pub struct LoadSettings;
 
-/// An event that triggers adding a new actiovation function preset to the available
-/// presets.
+/// An event that saves the settings to the settings file. The file is written once
+/// the settings haven't been saved again for a short time.
 #[derive(Event, Debug)]
 pub struct SaveSettings;
 
@@ -71,8 +417,9 @@ fn setup(
     mut ev_writer_update_fn: EventWriter<UpdateActivationFunction>,
     mut ev_writer_update_filter: EventWriter<UpdateFilter>,
     mut settings: ResMut<NCASettings>,
+    paths: Res<ConfigPaths>,
 ) {
-    *settings = read_settings(String::from("settings.json"));
+    *settings = read_settings(&paths.settings);
     ev_writer_update_fn.send(UpdateActivationFunction);
     ev_writer_update_filter.send(UpdateFilter);
 }
@@ -82,55 +429,164 @@ fn setup(
 fn on_load_settings (
     mut ev_reader_load_settings: EventReader<LoadSettings>,
     mut settings: ResMut<NCASettings>,
+    paths: Res<ConfigPaths>,
 ) {
     for _ in ev_reader_load_settings.read() {
-        *settings = read_settings(String::from("settings.json"));
+        *settings = read_settings(&paths.settings);
     }
 }
 
-/// System triggered by the SaveSettings event. Saves the current settings from the
-/// NCASettings resource to a JSON file.
+/// System triggered by the SaveSettings event. (Re)starts the timer of the pending
+/// write and saves the current settings from the NCASettings resource to a JSON
+/// file once it finishes.
 fn on_save_settings (
     mut ev_reader_save_settings: EventReader<SaveSettings>,
+    mut pending: ResMut<PendingSettingsWrite>,
+    mut write_error: ResMut<ConfigWriteError>,
+    time: Res<Time>,
+    settings: Res<NCASettings>,
+    paths: Res<ConfigPaths>,
+) {
+    if ev_reader_save_settings.read().count() > 0 {
+        pending.0 = Some(Timer::from_seconds(SAVE_DEBOUNCE_SECS, TimerMode::Once));
+    }
+    let Some(timer) = pending.0.as_mut() else { return };
+    if timer.tick(time.delta()).finished() {
+        pending.0 = None;
+        write_error.report(write_settings(&paths.settings, &settings));
+    }
+}
+
+/// A system that writes the pending settings when the app exits, so that the last
+/// changes aren't lost.
+fn flush_settings_on_exit(
+    mut ev_reader_exit: EventReader<AppExit>,
+    mut pending: ResMut<PendingSettingsWrite>,
     settings: Res<NCASettings>,
+    paths: Res<ConfigPaths>,
 ) {
-    for _ in ev_reader_save_settings.read() {
-        write_settings(String::from("settings.json"), &settings);
+    if ev_reader_exit.read().count() > 0 && pending.0.take().is_some() {
+        if let Err(err) = write_settings(&paths.settings, &settings) {
+            warn!("{}", err);
+        }
     }
 }
 
 // =================================== Utils ==================================== //
 
+/// The kernel size of settings written before filters larger than 3x3 were
+/// supported.
+fn default_kernel_size() -> u32 {
+    3
+}
+
+fn default_update_divisor() -> u32 {
+    1
+}
+
+/// Returns the name of the channel with the given index shown in the UI. Hidden
+/// channels are numbered from 1.
+pub fn channel_name(index: usize) -> String {
+    match CHANNEL_NAMES.get(index) {
+        Some(name) => name.to_string(),
+        None => format!("Hidden {}", index + 1 - CHANNELS),
+    }
+}
+
+/// The alpha channel of settings written before it existed.
+fn default_alpha_channel() -> NCAChannel {
+    NCAChannel::new(3)
+}
+
 /// Tries to load NCA settings from the specified file path. Returns the obtained
-/// settings if loading is successful, returns default settings otherwise.
-pub fn read_settings(path: String) -> NCASettings {
+/// settings if loading is successful, returns default settings otherwise. A missing
+/// file is created with the default settings, while a file that can't be parsed is
+/// left as it is, so that a typo in it doesn't wipe the settings.
+pub fn read_settings(path: &Path) -> NCASettings {
     info!("Reading settings.");
-    let contents_res = fs::read_to_string(path.clone());
-    if let Ok(contents) = contents_res {
-        let settings_res = serde_json::from_str::<NCASettings>(&contents);
-        if let Ok(settings) = settings_res {
-            settings
-        } else {
-            info!("Failed to parse settings, returning default value instead.");
-            let settings = NCASettings::default();
-            write_settings(path, &NCASettings::default());
-            settings
+    if path.is_file() {
+        match try_read_settings(path) {
+            Ok(settings) => settings,
+            Err(err) => {
+                warn!("{} Using the default settings instead.", err);
+                NCASettings::default()
+            }
         }
     } else {
         info!("Failed to read settings file, returning default value instead.");
         let settings = NCASettings::default();
-        write_settings(path, &NCASettings::default());
+        if let Err(err) = write_settings(path, &settings) {
+            warn!("{}", err);
+        }
         settings
     }
 }
 
-/// Tries to write the NCA settings to a specified file path. Panics if writing
-/// fails.
-pub fn write_settings(path: String, settings: &NCASettings) {
+/// Reads and parses NCA settings from the specified file path without ever writing
+/// it. Returns an error message if the file can't be read or parsed.
+pub fn try_read_settings(path: &Path) -> Result<NCASettings, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| format!("Couldn't read settings from {}: {}", path.display(), err))?;
+    let mut settings = serde_json::from_str::<NCASettings>(&contents)
+        .map_err(|err| format!("Couldn't parse settings from {}: {}", path.display(), err))?;
+    let hidden = settings.hidden.len();
+    settings.set_hidden_channels(hidden);
+    Ok(settings)
+}
+
+/// Helper function to get the closest odd kernel size from 1 to [`MAX_KERNEL_SIZE`].
+/// Even sizes are rounded down, as they have no center cell.
+fn valid_kernel_size(kernel_size: u32) -> u32 {
+    let kernel_size = kernel_size.clamp(1, MAX_KERNEL_SIZE);
+    if kernel_size.is_multiple_of(2) {
+        kernel_size - 1
+    } else {
+        kernel_size
+    }
+}
+
+/// Tries to write the NCA settings to a specified file path. Returns an error
+/// message if writing fails.
+pub fn write_settings(path: &Path, settings: &NCASettings) -> Result<(), String> {
     info!("Writing settings.");
-    fs::write(
-        path,
-        serde_json::to_string_pretty(settings)
-            .expect("Couldn't deserialize settings."))
-            .expect("Could not write to file.");
-}
\ No newline at end of file
+    let contents = serde_json::to_string_pretty(settings)
+        .map_err(|err| format!("Couldn't serialize settings: {}", err))?;
+    fs::write(path, contents)
+        .map_err(|err| format!("Couldn't write settings to {}: {}", path.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_kernel_sizes_are_replaced() {
+        // The kernel size in the file and the size it is replaced by.
+        let cases = [(0, 1), (1, 1), (4, 3), (5, 5), (MAX_KERNEL_SIZE + 1, MAX_KERNEL_SIZE)];
+        for (kernel_size, expected) in cases {
+            let mut settings = NCASettings::default();
+            settings.red.kernel_size = kernel_size;
+            let len = (kernel_size * kernel_size) as usize;
+            settings.red.filters = vec![vec![1.; len]; CHANNELS];
+            settings.set_hidden_channels(0);
+
+            assert_eq!(settings.red.kernel_size, expected);
+            for filter in &settings.red.filters {
+                assert_eq!(filter.len(), (expected * expected) as usize);
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_settings_files_are_kept() {
+        let path = std::env::temp_dir().join(format!("nca_malformed_{}.json", std::process::id()));
+        let contents = "{ \"seed\": 1, \"red\": ";
+        fs::write(&path, contents).unwrap();
+
+        let settings = read_settings(&path);
+        let kept = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(kept, contents);
+        assert_eq!(settings.seed, NCASettings::default().seed);
+    }
+}
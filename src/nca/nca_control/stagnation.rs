@@ -0,0 +1,237 @@
+//! Detecting simulations that died out
+
+use bevy::{
+    prelude::*,
+    render::{render_resource::TextureFormat, texture::TextureFormatPixelInfo},
+};
+
+use super::{
+    super::{
+        pipeline::readback::{NCAReadback, ReadbackComplete, ReadbackPurpose},
+        utils::decode_pixel,
+        NCAFrontTexture,
+        SimulationControl,
+    },
+    settings::{NCASettings, SaveSettings},
+    Reinitialize,
+};
+
+// =================================== Plugin =================================== //
+
+/// A plugin to detect when the NCA converged to a dead state. While the detector
+/// is enabled, the canvas is read back every few seconds and sampled on a coarse
+/// grid. If the samples are uniform or didn't change for a number of checks in a
+/// row, the NCA counts as stagnant, which is logged and may reinitialize the NCA
+/// with a new seed.
+pub(super) struct StagnationPlugin;
+
+impl Plugin for StagnationPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<StagnationDetector>()
+            .add_systems(Update, receive_stagnation_check)
+            // The front texture is switched during the update, so only request it
+            // afterwards.
+            .add_systems(PostUpdate, request_stagnation_check);
+    }
+}
+
+// ================================= Constants ================================== //
+
+/// The number of samples taken along each side of the canvas.
+const SAMPLES_PER_SIDE: u32 = 64;
+
+// ================================ Resources =================================== //
+
+/// The statistics of the samples of a single check.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StagnationCheck {
+    /// The mean of the color channels of all samples.
+    pub mean: f32,
+    /// The variance of the color channels of all samples around their mean.
+    pub variance: f32,
+    /// The mean absolute change of the color channels since the previous check, or
+    /// None for the first check.
+    pub change: Option<f32>,
+}
+
+/// A resource holding the settings and state of the stagnation detector.
+#[derive(Resource, Debug)]
+pub struct StagnationDetector {
+    pub enabled: bool,
+    /// The time in seconds between two checks.
+    pub interval: f32,
+    /// Samples with a variance below this threshold count as uniform.
+    pub variance_threshold: f32,
+    /// Samples changing less than this threshold since the previous check count as
+    /// static.
+    pub change_threshold: f32,
+    /// The number of stagnant checks in a row after which the NCA counts as
+    /// stagnant.
+    pub checks: u32,
+    /// If true, the NCA is reinitialized with a new seed once it is stagnant.
+    pub auto_reinitialize: bool,
+    /// The time in seconds since the last check.
+    elapsed: f32,
+    /// The color channels of the samples of the previous check.
+    previous: Option<Vec<f32>>,
+    /// The statistics of the last check.
+    last: Option<StagnationCheck>,
+    /// The number of stagnant checks in a row.
+    stagnant_checks: u32,
+}
+
+impl Default for StagnationDetector {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval: 3.,
+            variance_threshold: 1e-4,
+            change_threshold: 1e-4,
+            checks: 3,
+            auto_reinitialize: false,
+            elapsed: 0.,
+            previous: None,
+            last: None,
+            stagnant_checks: 0,
+        }
+    }
+}
+
+impl StagnationDetector {
+    /// Returns the statistics of the last check.
+    pub fn last(&self) -> Option<StagnationCheck> {
+        self.last
+    }
+
+    /// Returns the number of stagnant checks in a row.
+    pub fn stagnant_checks(&self) -> u32 {
+        self.stagnant_checks
+    }
+
+    /// Returns true if the NCA counts as stagnant.
+    pub fn is_stagnant(&self) -> bool {
+        self.stagnant_checks >= self.checks.max(1)
+    }
+
+    /// Forgets the previous checks, e.g. after the NCA was reinitialized.
+    fn reset(&mut self) {
+        self.previous = None;
+        self.last = None;
+        self.stagnant_checks = 0;
+    }
+}
+
+// ================================== Systems =================================== //
+
+/// A system that requests a readback of the front texture whenever the interval
+/// passed while the NCA is running.
+fn request_stagnation_check(
+    mut detector: ResMut<StagnationDetector>,
+    mut readback: ResMut<NCAReadback>,
+    front: Res<NCAFrontTexture>,
+    control: Res<SimulationControl>,
+    time: Res<Time>,
+) {
+    if !detector.enabled {
+        if detector.last.is_some() {
+            detector.reset();
+        }
+        return;
+    }
+    if control.paused {
+        return;
+    }
+    detector.elapsed += time.delta_seconds();
+    if detector.elapsed < detector.interval {
+        return;
+    }
+    detector.elapsed = 0.;
+    readback.request(ReadbackPurpose::Stagnation, front.handle.clone_weak());
+}
+
+/// A system that samples the read back canvas and compares it to the previous
+/// check. Reinitializes the NCA with a new seed once it is stagnant, if enabled.
+fn receive_stagnation_check(
+    mut ev_reader_readback: EventReader<ReadbackComplete>,
+    mut ev_writer_reinitialize: EventWriter<Reinitialize>,
+    mut ev_writer_save_settings: EventWriter<SaveSettings>,
+    mut detector: ResMut<StagnationDetector>,
+    mut params: ResMut<NCASettings>,
+) {
+    for event in ev_reader_readback.read() {
+        if event.purpose != ReadbackPurpose::Stagnation {
+            continue;
+        }
+        if !detector.enabled {
+            continue;
+        }
+        let Ok(readback) = &event.result else { continue };
+        let Some(samples) = sample_canvas(&readback.data, readback.size, readback.format)
+        else {
+            warn!("Can't check canvases of format {:?} for stagnation.", readback.format);
+            continue;
+        };
+
+        let count = samples.len().max(1) as f32;
+        let mean = samples.iter().sum::<f32>() / count;
+        let variance = samples.iter().map(|value| (value - mean).powi(2)).sum::<f32>() / count;
+        let change = detector
+            .previous
+            .as_ref()
+            .filter(|previous| previous.len() == samples.len())
+            .map(|previous| {
+                previous
+                    .iter()
+                    .zip(&samples)
+                    .map(|(previous, value)| (value - previous).abs())
+                    .sum::<f32>() / count
+            });
+        let uniform = variance < detector.variance_threshold;
+        let unchanged = change.is_some_and(|change| change < detector.change_threshold);
+        detector.stagnant_checks = if uniform || unchanged {
+            detector.stagnant_checks + 1
+        } else {
+            0
+        };
+        detector.last = Some(StagnationCheck { mean, variance, change });
+        detector.previous = Some(samples);
+
+        if detector.stagnant_checks != detector.checks.max(1) {
+            continue;
+        }
+        warn!(
+            "The NCA is stagnant, the canvas is {} (mean {:.4}, variance {:.6}).",
+            if uniform { "uniform" } else { "static" },
+            mean,
+            variance,
+        );
+        if detector.auto_reinitialize {
+            info!("Reinitializing stagnant NCA with a new seed.");
+            params.seed = rand::random();
+            ev_writer_save_settings.send(SaveSettings);
+            ev_writer_reinitialize.send(Reinitialize);
+            detector.reset();
+        }
+    }
+}
+
+// =================================== Utils ==================================== //
+
+/// Helper function to sample the color channels of a canvas on a grid of at most
+/// [`SAMPLES_PER_SIDE`] cells along each side. Returns None for formats the NCA
+/// doesn't support.
+fn sample_canvas(data: &[u8], size: UVec2, format: TextureFormat) -> Option<Vec<f32>> {
+    let pixel_size = format.pixel_size();
+    let samples = size.min(UVec2::splat(SAMPLES_PER_SIDE));
+    let mut values = Vec::with_capacity(3 * (samples.x * samples.y) as usize);
+    for y in 0..samples.y {
+        for x in 0..samples.x {
+            let cell = (UVec2::new(x, y) * size + size / 2) / samples;
+            let offset = (cell.y * size.x + cell.x) as usize * pixel_size;
+            let [r, g, b, _] = decode_pixel(data.get(offset..)?, format)?;
+            values.extend([r, g, b]);
+        }
+    }
+    Some(values)
+}
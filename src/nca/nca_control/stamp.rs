@@ -0,0 +1,258 @@
+//! Stamping text onto the canvas
+
+use bevy::{prelude::*, utils::HashMap};
+
+use super::{
+    super::{
+        create_image,
+        pipeline::{
+            nca::NCAImages,
+            readback::{NCAReadback, ReadbackComplete, ReadbackId, ReadbackPurpose},
+        },
+        utils::encode_pixel,
+        NCAFrontTexture,
+        NCASimSize,
+        NCATextureFormat,
+    },
+    history::SnapshotCanvas,
+};
+
+// =================================== Plugin =================================== //
+
+/// A plugin to stamp text onto the center of the canvas, e.g. as a pattern for the
+/// NCA to evolve. The text is rasterized on the CPU with a built-in pixel font. Like
+/// filling, the canvas is read back from the GPU, stamped and uploaded into both
+/// textures of the NCA, so the stamped cells become the live state.
+pub(super) struct StampPlugin;
+
+impl Plugin for StampPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<TextStampSettings>()
+            .init_resource::<PendingStamps>()
+            .add_event::<StampText>()
+            // The front texture is switched during the update, so only request it
+            // afterwards.
+            .add_systems(PostUpdate, on_stamp_text)
+            .add_systems(Update, apply_stamps);
+    }
+}
+
+// ================================= Constants ================================== //
+
+/// The width of a glyph of the built-in font in font pixels.
+const GLYPH_WIDTH: usize = 5;
+
+/// The height of a glyph of the built-in font in font pixels.
+const GLYPH_HEIGHT: usize = 7;
+
+/// The gap between two lines of text in font pixels. Glyphs are separated by a
+/// single column.
+const LINE_GAP: usize = 2;
+
+/// The glyphs of the built-in font, row by row. Lowercase letters are shown as
+/// uppercase ones and characters without a glyph as a question mark.
+const FONT: &[(char, [&str; GLYPH_HEIGHT])] = &[
+    ('A', [".###.", "#...#", "#...#", "#####", "#...#", "#...#", "#...#"]),
+    ('B', ["####.", "#...#", "#...#", "####.", "#...#", "#...#", "####."]),
+    ('C', [".###.", "#...#", "#....", "#....", "#....", "#...#", ".###."]),
+    ('D', ["####.", "#...#", "#...#", "#...#", "#...#", "#...#", "####."]),
+    ('E', ["#####", "#....", "#....", "####.", "#....", "#....", "#####"]),
+    ('F', ["#####", "#....", "#....", "####.", "#....", "#....", "#...."]),
+    ('G', [".###.", "#...#", "#....", "#.###", "#...#", "#...#", ".####"]),
+    ('H', ["#...#", "#...#", "#...#", "#####", "#...#", "#...#", "#...#"]),
+    ('I', [".###.", "..#..", "..#..", "..#..", "..#..", "..#..", ".###."]),
+    ('J', ["..###", "...#.", "...#.", "...#.", "...#.", "#..#.", ".##.."]),
+    ('K', ["#...#", "#..#.", "#.#..", "##...", "#.#..", "#..#.", "#...#"]),
+    ('L', ["#....", "#....", "#....", "#....", "#....", "#....", "#####"]),
+    ('M', ["#...#", "##.##", "#.#.#", "#.#.#", "#...#", "#...#", "#...#"]),
+    ('N', ["#...#", "#...#", "##..#", "#.#.#", "#..##", "#...#", "#...#"]),
+    ('O', [".###.", "#...#", "#...#", "#...#", "#...#", "#...#", ".###."]),
+    ('P', ["####.", "#...#", "#...#", "####.", "#....", "#....", "#...."]),
+    ('Q', [".###.", "#...#", "#...#", "#...#", "#.#.#", "#..#.", ".##.#"]),
+    ('R', ["####.", "#...#", "#...#", "####.", "#.#..", "#..#.", "#...#"]),
+    ('S', [".####", "#....", "#....", ".###.", "....#", "....#", "####."]),
+    ('T', ["#####", "..#..", "..#..", "..#..", "..#..", "..#..", "..#.."]),
+    ('U', ["#...#", "#...#", "#...#", "#...#", "#...#", "#...#", ".###."]),
+    ('V', ["#...#", "#...#", "#...#", "#...#", "#...#", ".#.#.", "..#.."]),
+    ('W', ["#...#", "#...#", "#...#", "#.#.#", "#.#.#", "#.#.#", ".#.#."]),
+    ('X', ["#...#", "#...#", ".#.#.", "..#..", ".#.#.", "#...#", "#...#"]),
+    ('Y', ["#...#", "#...#", ".#.#.", "..#..", "..#..", "..#..", "..#.."]),
+    ('Z', ["#####", "....#", "...#.", "..#..", ".#...", "#....", "#####"]),
+    ('0', [".###.", "#...#", "#..##", "#.#.#", "##..#", "#...#", ".###."]),
+    ('1', ["..#..", ".##..", "..#..", "..#..", "..#..", "..#..", ".###."]),
+    ('2', [".###.", "#...#", "....#", "...#.", "..#..", ".#...", "#####"]),
+    ('3', ["####.", "....#", "....#", ".###.", "....#", "....#", "####."]),
+    ('4', ["...#.", "..##.", ".#.#.", "#..#.", "#####", "...#.", "...#."]),
+    ('5', ["#####", "#....", "####.", "....#", "....#", "#...#", ".###."]),
+    ('6', ["..##.", ".#...", "#....", "####.", "#...#", "#...#", ".###."]),
+    ('7', ["#####", "....#", "...#.", "..#..", ".#...", ".#...", ".#..."]),
+    ('8', [".###.", "#...#", "#...#", ".###.", "#...#", "#...#", ".###."]),
+    ('9', [".###.", "#...#", "#...#", ".####", "....#", "...#.", ".##.."]),
+    (' ', [".....", ".....", ".....", ".....", ".....", ".....", "....."]),
+    ('.', [".....", ".....", ".....", ".....", ".....", ".##..", ".##.."]),
+    (',', [".....", ".....", ".....", ".....", ".##..", "..#..", ".#..."]),
+    ('!', ["..#..", "..#..", "..#..", "..#..", "..#..", ".....", "..#.."]),
+    ('?', [".###.", "#...#", "....#", "...#.", "..#..", ".....", "..#.."]),
+    ('-', [".....", ".....", ".....", "#####", ".....", ".....", "....."]),
+    (':', [".....", ".##..", ".##..", ".....", ".##..", ".##..", "....."]),
+    ('\'', ["..#..", "..#..", ".#...", ".....", ".....", ".....", "....."]),
+    ('(', ["...#.", "..#..", ".#...", ".#...", ".#...", "..#..", "...#."]),
+    (')', [".#...", "..#..", "...#.", "...#.", "...#.", "..#..", ".#..."]),
+    ('/', [".....", "....#", "...#.", "..#..", ".#...", "#....", "....."]),
+    ('+', [".....", "..#..", "..#..", "#####", "..#..", "..#..", "....."]),
+    ('=', [".....", ".....", "#####", ".....", "#####", ".....", "....."]),
+    ('_', [".....", ".....", ".....", ".....", ".....", ".....", "#####"]),
+    ('#', [".#.#.", ".#.#.", "#####", ".#.#.", "#####", ".#.#.", ".#.#."]),
+];
+
+// ================================ Resources =================================== //
+
+/// A resource holding the text stamped by the StampText event and how it looks.
+#[derive(Resource, Debug, Clone)]
+pub struct TextStampSettings {
+    /// The text to stamp, which may span several lines.
+    pub text: String,
+    /// The height of a line of text in cells.
+    pub size: u32,
+    pub color: [f32; 3],
+}
+
+impl Default for TextStampSettings {
+    fn default() -> Self {
+        Self {
+            text: "NCA".to_owned(),
+            size: 70,
+            color: [1., 1., 1.],
+        }
+    }
+}
+
+impl TextStampSettings {
+    /// Returns the number of cells a pixel of the font covers in each direction.
+    pub fn scale(&self) -> usize {
+        (self.size as usize / GLYPH_HEIGHT).max(1)
+    }
+}
+
+/// A resource holding the stamps waiting for the readback of the canvas by the id
+/// of their readback.
+#[derive(Resource, Debug, Default)]
+struct PendingStamps(HashMap<ReadbackId, TextStampSettings>);
+
+// ================================== Events ==================================== //
+
+/// An event that stamps the text of the [`TextStampSettings`] onto the center of
+/// the canvas.
+#[derive(Event, Debug)]
+pub struct StampText;
+
+// ================================== Systems =================================== //
+
+/// System triggered by the StampText event. Saves the canvas to the history,
+/// requests a readback of the front texture and remembers the text at the time of
+/// the request.
+fn on_stamp_text(
+    mut ev_reader_stamp_text: EventReader<StampText>,
+    mut ev_writer_snapshot: EventWriter<SnapshotCanvas>,
+    mut readback: ResMut<NCAReadback>,
+    mut pending: ResMut<PendingStamps>,
+    front: Res<NCAFrontTexture>,
+    settings: Res<TextStampSettings>,
+) {
+    for _ in ev_reader_stamp_text.read() {
+        if settings.text.trim().is_empty() {
+            continue;
+        }
+        info!("Stamping text {:?}.", settings.text);
+        ev_writer_snapshot.send(SnapshotCanvas);
+        let id = readback.request(ReadbackPurpose::Stamp, front.handle.clone_weak());
+        pending.0.insert(id, settings.clone());
+    }
+}
+
+/// A system that stamps the text onto the read back canvases and uploads them into
+/// both textures of the NCA. Text exceeding the canvas is cut off.
+fn apply_stamps(
+    mut ev_reader_readback: EventReader<ReadbackComplete>,
+    mut pending: ResMut<PendingStamps>,
+    mut images: ResMut<Assets<Image>>,
+    nca_images: Res<NCAImages>,
+    sim_size: Res<NCASimSize>,
+    format: Res<NCATextureFormat>,
+) {
+    for event in ev_reader_readback.read() {
+        let Some(stamp) = pending.0.remove(&event.id) else { continue };
+        let Ok(readback) = &event.result else { continue };
+        if readback.size != sim_size.size || readback.format != format.format {
+            continue;
+        }
+
+        let (text_size, covered) = rasterize_text(&stamp.text, stamp.scale());
+        let canvas = sim_size.size.as_ivec2();
+        let origin = (canvas - text_size.as_ivec2()) / 2;
+        let [r, g, b] = stamp.color;
+        let pixel = encode_pixel([r, g, b, 1.], format.format);
+        let mut data = readback.data.clone();
+        for (index, _) in covered.iter().enumerate().filter(|(_, &covered)| covered) {
+            let local = UVec2::new(index as u32 % text_size.x, index as u32 / text_size.x);
+            let cell = origin + local.as_ivec2();
+            if cell.cmplt(IVec2::ZERO).any() || cell.cmpge(canvas).any() {
+                continue;
+            }
+            let offset = (cell.y * canvas.x + cell.x) as usize * pixel.len();
+            data[offset..offset + pixel.len()].copy_from_slice(&pixel);
+        }
+
+        let mut image = create_image(sim_size.size.x, sim_size.size.y, format.format);
+        image.data = data;
+        images.insert(&nca_images.texture_a, image.clone());
+        images.insert(&nca_images.texture_b, image);
+    }
+}
+
+// =================================== Utils ==================================== //
+
+/// Helper function to look up the glyph of a character in the built-in font.
+fn glyph(c: char) -> &'static [&'static str; GLYPH_HEIGHT] {
+    let c = c.to_ascii_uppercase();
+    FONT
+        .iter()
+        .find(|(glyph_char, _)| *glyph_char == c)
+        .or_else(|| FONT.iter().find(|(glyph_char, _)| *glyph_char == '?'))
+        .map(|(_, rows)| rows)
+        .expect("The font has a glyph for unknown characters.")
+}
+
+/// Helper function to rasterize text with the built-in font, each font pixel
+/// covering scale x scale cells. Lines are centered horizontally. Returns the size
+/// of the text in cells and for every cell whether it is covered.
+fn rasterize_text(text: &str, scale: usize) -> (UVec2, Vec<bool>) {
+    let lines: Vec<Vec<char>> = text.lines().map(|line| line.chars().collect()).collect();
+    let columns = lines.iter().map(Vec::len).max().unwrap_or(0).max(1);
+    let line_width = |chars: usize| (chars * (GLYPH_WIDTH + 1)).saturating_sub(1);
+    let width = line_width(columns) * scale;
+    let height = (lines.len().max(1) * (GLYPH_HEIGHT + LINE_GAP) - LINE_GAP) * scale;
+
+    let mut covered = vec![false; width * height];
+    for (line_index, line) in lines.iter().enumerate() {
+        let indent = (line_width(columns) - line_width(line.len())) / 2;
+        let top = line_index * (GLYPH_HEIGHT + LINE_GAP);
+        for (char_index, &c) in line.iter().enumerate() {
+            let left = indent + char_index * (GLYPH_WIDTH + 1);
+            for (row, pixels) in glyph(c).iter().enumerate() {
+                for (column, pixel) in pixels.bytes().enumerate() {
+                    if pixel != b'#' {
+                        continue;
+                    }
+                    let (x, y) = ((left + column) * scale, (top + row) * scale);
+                    for dy in 0..scale {
+                        let start = (y + dy) * width + x;
+                        covered[start..start + scale].fill(true);
+                    }
+                }
+            }
+        }
+    }
+    (UVec2::new(width as u32, height as u32), covered)
+}
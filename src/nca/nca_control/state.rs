@@ -0,0 +1,357 @@
+//! Saving and restoring the state of the NCA
+
+use bevy::{
+    prelude::*,
+    render::{render_resource::TextureFormat, texture::TextureFormatPixelInfo},
+};
+use std::path::PathBuf;
+
+use super::{
+    super::{
+        pipeline::{
+            nca::NCAImages,
+            readback::{NCAReadback, ReadbackComplete, ReadbackData, ReadbackId, ReadbackPurpose},
+        },
+        utils::{decode_pixel, encode_pixel},
+        NCAFrontTexture,
+        NCAPipelineMonitor,
+        NCAPipelineState,
+        NCASimSize,
+        NCATextureFormat,
+    },
+    settings::NCASettings,
+};
+
+// =================================== Plugin =================================== //
+
+/// A plugin to save the state of the NCA to a file and restore it later. The file
+/// holds the raw contents of the front texture and of the texture of the hidden
+/// channels together with their format and size, so the restored state is
+/// identical to the saved one, whatever the texture format.
+pub(super) struct StatePlugin;
+
+impl Plugin for StatePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<PendingStateSaves>()
+            .add_event::<SaveState>()
+            .add_event::<LoadState>()
+            // The front texture is switched during the update, so only request it
+            // afterwards.
+            .add_systems(PostUpdate, on_save_state)
+            .add_systems(Update, (write_states, on_load_state));
+    }
+}
+
+// ================================= Constants ================================== //
+
+/// The bytes every state file begins with.
+const STATE_MAGIC: &[u8; 8] = b"NCASTATE";
+
+/// The version of the layout of state files.
+const STATE_VERSION: u32 = 1;
+
+// ================================ Resources =================================== //
+
+/// The state of the NCA as saved to a file.
+#[derive(Debug, Clone, PartialEq)]
+struct SavedState {
+    /// The contents of the front texture.
+    color: ReadbackData,
+    /// The contents of the texture of the hidden channels belonging to the front
+    /// texture.
+    hidden: ReadbackData,
+}
+
+/// A state waiting for the readbacks of its textures.
+#[derive(Debug)]
+struct PendingStateSave {
+    path: PathBuf,
+    color: (ReadbackId, Option<ReadbackData>),
+    hidden: (ReadbackId, Option<ReadbackData>),
+}
+
+/// A resource holding the states waiting to be read back.
+#[derive(Resource, Debug, Default)]
+struct PendingStateSaves(Vec<PendingStateSave>);
+
+// ================================== Events ==================================== //
+
+/// An event that saves the current state of the NCA to the given path.
+#[derive(Event, Debug)]
+pub struct SaveState {
+    pub path: PathBuf,
+}
+
+/// An event that restores the state of the NCA from the given path.
+#[derive(Event, Debug)]
+pub struct LoadState {
+    pub path: PathBuf,
+}
+
+// ================================== Systems =================================== //
+
+/// System triggered by the SaveState event. Requests a readback of the front
+/// texture and of the hidden channels belonging to it.
+fn on_save_state(
+    mut ev_reader_save_state: EventReader<SaveState>,
+    mut readback: ResMut<NCAReadback>,
+    mut pending: ResMut<PendingStateSaves>,
+    front: Res<NCAFrontTexture>,
+    nca_images: Res<NCAImages>,
+) {
+    for event in ev_reader_save_state.read() {
+        let hidden = nca_images.hidden_of(&front.handle).clone_weak();
+        pending.0.push(PendingStateSave {
+            path: event.path.clone(),
+            color: (readback.request(ReadbackPurpose::State, front.handle.clone_weak()), None),
+            hidden: (readback.request(ReadbackPurpose::State, hidden), None),
+        });
+    }
+}
+
+/// A system that writes the states to their files once both of their textures were
+/// read back.
+fn write_states(
+    mut ev_reader_readback: EventReader<ReadbackComplete>,
+    mut pending: ResMut<PendingStateSaves>,
+) {
+    for event in ev_reader_readback.read() {
+        let Some(index) = pending.0.iter().position(|save| {
+            save.color.0 == event.id || save.hidden.0 == event.id
+        }) else {
+            continue;
+        };
+        let readback = match &event.result {
+            Ok(readback) => readback,
+            Err(err) => {
+                let save = pending.0.remove(index);
+                warn!("Couldn't save state to {}: {}.", save.path.display(), err);
+                continue;
+            }
+        };
+
+        let save = &mut pending.0[index];
+        if save.color.0 == event.id {
+            save.color.1 = Some(readback.clone());
+        } else {
+            save.hidden.1 = Some(readback.clone());
+        }
+        let (Some(color), Some(hidden)) = (&save.color.1, &save.hidden.1) else {
+            continue;
+        };
+
+        let state = SavedState { color: color.clone(), hidden: hidden.clone() };
+        let path = pending.0.remove(index).path;
+        let written = encode_state(&state)
+            .and_then(|bytes| std::fs::write(&path, bytes).map_err(|err| err.to_string()));
+        match written {
+            Ok(()) => info!("Saved state to {}.", path.display()),
+            Err(err) => warn!("Couldn't save state: {}", err),
+        }
+    }
+}
+
+/// System triggered by the LoadState event. Reads the state from the file and
+/// replaces both textures of the NCA and of the hidden channels with it, so that
+/// the NCA continues from the restored state without reinitializing. States saved
+/// with another texture format are converted. Hidden channels saved with a
+/// different number of them are reset instead.
+fn on_load_state(
+    mut ev_reader_load_state: EventReader<LoadState>,
+    mut images: ResMut<Assets<Image>>,
+    nca_images: Res<NCAImages>,
+    monitor: Res<NCAPipelineMonitor>,
+    sim_size: Res<NCASimSize>,
+    format: Res<NCATextureFormat>,
+    params: Res<NCASettings>,
+) {
+    for event in ev_reader_load_state.read() {
+        if monitor.status().state != NCAPipelineState::Running {
+            warn!("Can only load a state while the NCA is running.");
+            continue;
+        }
+        let state = match std::fs::read(&event.path)
+            .map_err(|err| err.to_string())
+            .and_then(|bytes| decode_state(&bytes))
+        {
+            Ok(state) => state,
+            Err(err) => {
+                warn!("Couldn't load state: {}", err);
+                continue;
+            }
+        };
+        if state.color.size != sim_size.size {
+            warn!(
+                "State has size {}, but the simulation has size {}.",
+                state.color.size,
+                sim_size.size,
+            );
+            continue;
+        }
+        let Some(data) = convert_pixels(&state.color.data, state.color.format, format.format)
+        else {
+            warn!("Can't load states of format {:?}.", state.color.format);
+            continue;
+        };
+
+        info!("Loading state from {}.", event.path.display());
+        let canvas = ReadbackData { size: sim_size.size, format: format.format, data };
+        nca_images.upload_canvas(&mut images, canvas);
+        if !nca_images.upload_hidden(&mut images, state.hidden, sim_size.size, params.hidden.len()) {
+            warn!("The state was saved with other hidden channels, resetting them.");
+        }
+    }
+}
+
+// =================================== Utils ==================================== //
+
+/// Helper function to encode a state into the bytes of a state file. The file
+/// begins with [`STATE_MAGIC`] and [`STATE_VERSION`], followed by the format, width,
+/// height and pixel data of the front texture and of the hidden channels. Numbers
+/// are stored as little endian u32. Returns a message explaining why the state
+/// can't be saved otherwise.
+fn encode_state(state: &SavedState) -> Result<Vec<u8>, String> {
+    let mut bytes = STATE_MAGIC.to_vec();
+    bytes.extend(STATE_VERSION.to_le_bytes());
+    for texture in [&state.color, &state.hidden] {
+        bytes.extend(format_code(texture.format)?.to_le_bytes());
+        bytes.extend(texture.size.x.to_le_bytes());
+        bytes.extend(texture.size.y.to_le_bytes());
+        bytes.extend(&texture.data);
+    }
+    Ok(bytes)
+}
+
+/// Helper function to decode the bytes of a state file written by
+/// [`encode_state`]. Returns a message explaining why the file is invalid
+/// otherwise.
+fn decode_state(bytes: &[u8]) -> Result<SavedState, String> {
+    let mut rest = bytes
+        .strip_prefix(STATE_MAGIC.as_slice())
+        .ok_or_else(|| "Not a state file.".to_owned())?;
+    let version = read_u32(&mut rest)?;
+    if version != STATE_VERSION {
+        return Err(format!("Unsupported version {version} of the state file."));
+    }
+    let color = read_texture(&mut rest)?;
+    let hidden = read_texture(&mut rest)?;
+    if !rest.is_empty() {
+        return Err("The state file has trailing data.".to_owned());
+    }
+    Ok(SavedState { color, hidden })
+}
+
+/// Helper function to read the format, size and pixel data of a texture from the
+/// beginning of the bytes of a state file and advance past them.
+fn read_texture(bytes: &mut &[u8]) -> Result<ReadbackData, String> {
+    let code = read_u32(bytes)?;
+    let format = format_from_code(code)
+        .ok_or_else(|| format!("Unknown texture format {code} in the state file."))?;
+    let size = UVec2::new(read_u32(bytes)?, read_u32(bytes)?);
+    let len = size.x as usize * size.y as usize * format.pixel_size();
+    if bytes.len() < len {
+        return Err("The state file is truncated.".to_owned());
+    }
+    let (data, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(ReadbackData { size, format, data: data.to_vec() })
+}
+
+/// Helper function to read a little endian u32 from the beginning of the bytes of
+/// a state file and advance past it.
+fn read_u32(bytes: &mut &[u8]) -> Result<u32, String> {
+    let (value, rest) = bytes
+        .split_first_chunk::<4>()
+        .ok_or_else(|| "The state file is truncated.".to_owned())?;
+    *bytes = rest;
+    Ok(u32::from_le_bytes(*value))
+}
+
+/// Helper function to get the number identifying a texture format in state files.
+/// Returns an error for formats state files can't hold.
+fn format_code(format: TextureFormat) -> Result<u32, String> {
+    match format {
+        TextureFormat::Rgba8Unorm => Ok(0),
+        TextureFormat::Rgba16Float => Ok(1),
+        _ => Err(format!("Can't save textures of format {format:?}.")),
+    }
+}
+
+/// Helper function to get the texture format identified by a number in state
+/// files.
+fn format_from_code(code: u32) -> Option<TextureFormat> {
+    match code {
+        0 => Some(TextureFormat::Rgba8Unorm),
+        1 => Some(TextureFormat::Rgba16Float),
+        _ => None,
+    }
+}
+
+/// Helper function to convert texture data from one format to another, pixel by
+/// pixel. Returns None for formats the NCA doesn't support.
+fn convert_pixels(data: &[u8], from: TextureFormat, to: TextureFormat) -> Option<Vec<u8>> {
+    if from == to {
+        return Some(data.to_vec());
+    }
+    let mut converted = Vec::with_capacity(data.len() / from.pixel_size() * to.pixel_size());
+    for pixel in data.chunks_exact(from.pixel_size()) {
+        converted.extend(encode_pixel(decode_pixel(pixel, from)?, to));
+    }
+    Some(converted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::super::HIDDEN_TEXTURE_FORMAT;
+
+    #[test]
+    fn state_round_trip() {
+        let size = UVec2::new(3, 2);
+        // Half precision values that don't survive a round trip through 8 bits.
+        let color: Vec<u8> = (0..size.x * size.y * 4)
+            .flat_map(|i| (0x3000 + i as u16 * 7).to_le_bytes())
+            .collect();
+        let hidden: Vec<u8> = (0..size.x * size.y * 4)
+            .flat_map(|i| (0xb800 + i as u16 * 3).to_le_bytes())
+            .collect();
+        let state = SavedState {
+            color: ReadbackData { size, format: TextureFormat::Rgba16Float, data: color },
+            hidden: ReadbackData { size, format: HIDDEN_TEXTURE_FORMAT, data: hidden },
+        };
+
+        let bytes = encode_state(&state).unwrap();
+        assert_eq!(decode_state(&bytes), Ok(state));
+        assert!(decode_state(&bytes[..bytes.len() - 1]).is_err());
+        assert!(decode_state(b"NCASTATF").is_err());
+    }
+
+    #[test]
+    fn state_round_trip_without_hidden_channels() {
+        let state = SavedState {
+            color: ReadbackData {
+                size: UVec2::new(2, 2),
+                format: TextureFormat::Rgba8Unorm,
+                data: (0..16).collect(),
+            },
+            hidden: ReadbackData {
+                size: UVec2::ONE,
+                format: HIDDEN_TEXTURE_FORMAT,
+                data: vec![0; 8],
+            },
+        };
+        assert_eq!(decode_state(&encode_state(&state).unwrap()), Ok(state));
+    }
+
+    #[test]
+    fn state_with_unknown_format_is_not_encoded() {
+        let texture = ReadbackData {
+            size: UVec2::ONE,
+            format: TextureFormat::Rgba32Float,
+            data: vec![0; 16],
+        };
+        let state = SavedState { color: texture.clone(), hidden: texture };
+        assert!(encode_state(&state).is_err());
+    }
+}
@@ -0,0 +1,260 @@
+//! NCA workspaces
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{fs, path::Path};
+
+use crate::camera::CameraZoomLimits;
+use super::{
+    super::pipeline::draw::NCADrawSettings,
+    presets::{read_presets, write_presets, NCAPresets},
+    settings::{read_settings, write_settings, NCASettings},
+    ConfigPaths,
+    ConfigWriteError,
+    UpdateActivationFunction,
+    UpdateFilter,
+};
+
+// ================================= Constants ================================== //
+
+/// The directory containing one subdirectory per named workspace.
+const WORKSPACE_DIR: &str = "workspaces";
+
+// =================================== Plugin =================================== //
+
+/// A plugin that manages named workspaces. Each workspace is a directory bundling
+/// its own settings and presets, together with the brush settings and a bookmark of
+/// the camera view. The default workspace uses the files in the working directory,
+/// or the settings file given on launch with the brush and camera view next to it.
+pub(super) struct WorkspacePlugin;
+
+impl Plugin for WorkspacePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<NCAWorkspaces>()
+            .add_event::<NewWorkspace>()
+            .add_event::<SwitchWorkspace>()
+            .add_systems(Startup, setup)
+            .add_systems(Update, (on_new_workspace, on_switch_workspace));
+    }
+}
+
+// ================================ Resources =================================== //
+
+/// A resource holding the available workspaces and the active one.
+#[derive(Resource, Debug, Default)]
+pub struct NCAWorkspaces {
+    active: Option<String>,
+    available: Vec<String>,
+    /// The paths of the default workspace as set on startup, e.g. to the settings
+    /// file given on the command line.
+    default_paths: ConfigPaths,
+}
+
+impl NCAWorkspaces {
+    /// Returns the name of the active workspace, or None if the default workspace
+    /// is active.
+    pub fn active(&self) -> Option<&String> {
+        self.active.as_ref()
+    }
+
+    /// Returns the names of all available named workspaces.
+    pub fn available(&self) -> &Vec<String> {
+        &self.available
+    }
+}
+
+// ================================== Events ==================================== //
+
+/// An event that triggers creating a new workspace from the current settings,
+/// presets, brush and camera view and switching to it.
+#[derive(Event, Debug)]
+pub struct NewWorkspace {
+    pub name: String,
+}
+
+/// An event that triggers switching to another workspace. A name of None switches
+/// to the default workspace.
+#[derive(Event, Debug)]
+pub struct SwitchWorkspace {
+    pub name: Option<String>,
+}
+
+/// The view of the camera saved in a workspace.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct CameraBookmark {
+    translation: [f32; 2],
+    /// The scale of the orthographic projection.
+    scale: f32,
+}
+
+/// The configuration stored in a workspace, i.e. the settings, presets, brush and
+/// camera view together with the paths they are written to.
+#[derive(SystemParam)]
+struct WorkspaceConfig<'w, 's> {
+    paths: ResMut<'w, ConfigPaths>,
+    settings: ResMut<'w, NCASettings>,
+    presets: ResMut<'w, NCAPresets>,
+    draw_settings: ResMut<'w, NCADrawSettings>,
+    zoom_limits: Res<'w, CameraZoomLimits>,
+    camera_query: Query<
+        'w,
+        's,
+        (&'static mut Transform, &'static mut OrthographicProjection),
+        With<Camera>,
+    >,
+    write_error: ResMut<'w, ConfigWriteError>,
+}
+
+impl WorkspaceConfig<'_, '_> {
+    /// Writes the settings, presets, brush and camera view to the given paths.
+    fn save(&mut self, paths: &ConfigPaths) {
+        self.write_error.report(write_settings(&paths.settings, &self.settings));
+        self.write_error.report(write_presets(&paths.presets, &self.presets));
+        self.write_error.report(write_json(&paths.brush, &*self.draw_settings, "brush"));
+        if let Ok((transform, ortho)) = self.camera_query.get_single() {
+            let bookmark = CameraBookmark {
+                translation: transform.translation.truncate().to_array(),
+                scale: ortho.scale,
+            };
+            self.write_error.report(write_json(&paths.camera, &bookmark, "camera view"));
+        }
+    }
+
+    /// Reads the settings, presets, brush and camera view from the current paths.
+    /// The brush and camera view are kept if the workspace has none saved, e.g.
+    /// because it was created before they were part of workspaces.
+    fn load(&mut self) {
+        *self.settings = read_settings(&self.paths.settings);
+        *self.presets = read_presets(&self.paths.presets);
+        if let Some(brush) = read_json::<NCADrawSettings>(&self.paths.brush, "brush") {
+            *self.draw_settings = NCADrawSettings {
+                is_drawing: self.draw_settings.is_drawing,
+                ..brush
+            };
+        }
+        let bookmark = read_json::<CameraBookmark>(&self.paths.camera, "camera view");
+        if let (Some(bookmark), Ok((mut transform, mut ortho))) =
+            (bookmark, self.camera_query.get_single_mut())
+        {
+            let translation = Vec2::from_array(bookmark.translation);
+            transform.translation = translation.extend(transform.translation.z);
+            ortho.scale = self.zoom_limits.clamp(bookmark.scale);
+        }
+    }
+}
+
+// ================================== Systems =================================== //
+
+/// On startup, this system looks for the available workspaces and keeps the paths
+/// of the default workspace, which the launch options are already applied to.
+fn setup(
+    mut workspaces: ResMut<NCAWorkspaces>,
+    paths: Res<ConfigPaths>,
+) {
+    workspaces.available = find_workspaces();
+    workspaces.default_paths = paths.clone();
+}
+
+/// System triggered by the NewWorkspace event. Creates the directory of the new
+/// workspace, copies the current settings, presets, brush and camera view into it
+/// and switches to it.
+fn on_new_workspace(
+    mut ev_reader_new_workspace: EventReader<NewWorkspace>,
+    mut ev_writer_switch_workspace: EventWriter<SwitchWorkspace>,
+    mut workspaces: ResMut<NCAWorkspaces>,
+    mut config: WorkspaceConfig,
+) {
+    for event in ev_reader_new_workspace.read() {
+        let name = event.name.trim();
+        if name.is_empty() || name.contains(['/', '\\', '.']) {
+            warn!("Invalid workspace name \"{}\".", event.name);
+            continue;
+        }
+
+        let dir = Path::new(WORKSPACE_DIR).join(name);
+        if dir.exists() {
+            warn!("Workspace \"{}\" already exists.", name);
+            continue;
+        }
+
+        info!("Creating workspace \"{}\".", name);
+        if let Err(err) = fs::create_dir_all(&dir) {
+            warn!("Couldn't create workspace directory: {}", err);
+            continue;
+        }
+        config.save(&ConfigPaths::in_dir(&dir));
+
+        workspaces.available = find_workspaces();
+        ev_writer_switch_workspace.send(SwitchWorkspace { name: Some(name.to_owned()) });
+    }
+}
+
+/// System triggered by the SwitchWorkspace event. Saves the settings, presets,
+/// brush and camera view to the current workspace, then loads them from the new one
+/// and updates the NCA.
+fn on_switch_workspace(
+    mut ev_reader_switch_workspace: EventReader<SwitchWorkspace>,
+    mut ev_writer_update_fn: EventWriter<UpdateActivationFunction>,
+    mut ev_writer_update_filter: EventWriter<UpdateFilter>,
+    mut workspaces: ResMut<NCAWorkspaces>,
+    mut config: WorkspaceConfig,
+) {
+    for event in ev_reader_switch_workspace.read() {
+        if event.name == workspaces.active {
+            continue;
+        }
+        info!("Switching to workspace \"{}\".", event.name.as_deref().unwrap_or("Default"));
+
+        let paths = config.paths.clone();
+        config.save(&paths);
+
+        *config.paths = match &event.name {
+            Some(name) => ConfigPaths::in_dir(&Path::new(WORKSPACE_DIR).join(name)),
+            None => workspaces.default_paths.clone(),
+        };
+        workspaces.active.clone_from(&event.name);
+
+        config.load();
+        ev_writer_update_fn.send(UpdateActivationFunction);
+        ev_writer_update_filter.send(UpdateFilter);
+    }
+}
+
+// =================================== Utils ==================================== //
+
+/// Returns the sorted names of all subdirectories of the workspace directory.
+fn find_workspaces() -> Vec<String> {
+    let Ok(entries) = fs::read_dir(WORKSPACE_DIR) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect();
+    names.sort();
+    names
+}
+
+/// Helper function to read a value saved in a workspace from a JSON file. Returns
+/// None if the file is missing or can't be parsed.
+fn read_json<T: DeserializeOwned>(path: &Path, name: &str) -> Option<T> {
+    let contents = fs::read_to_string(path).ok()?;
+    match serde_json::from_str(&contents) {
+        Ok(value) => Some(value),
+        Err(err) => {
+            warn!("Couldn't parse {} from {}: {}", name, path.display(), err);
+            None
+        }
+    }
+}
+
+/// Helper function to write a value saved in a workspace to a JSON file. Returns an
+/// error message if writing fails.
+fn write_json<T: Serialize>(path: &Path, value: &T, name: &str) -> Result<(), String> {
+    let contents = serde_json::to_string_pretty(value)
+        .map_err(|err| format!("Couldn't serialize {}: {}", name, err))?;
+    fs::write(path, contents)
+        .map_err(|err| format!("Couldn't write {} to {}: {}", name, path.display(), err))
+}
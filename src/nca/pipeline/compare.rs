@@ -0,0 +1,453 @@
+//! Comparing two rules side by side
+
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::*,
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_asset::RenderAssets,
+        render_graph::{Node, NodeRunError, RenderGraphContext, RenderLabel},
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice},
+        texture::GpuImage,
+        Render,
+        RenderApp,
+        RenderSet,
+    },
+};
+use std::sync::{Arc, Mutex};
+
+use crate::NCA_COMPARE_SHADER_HANDLE;
+use super::{
+    super::{
+        create_filter_buffers,
+        create_hidden_image,
+        create_image,
+        nca_control::{build_shader_source, mask::NCAFreezeMask, settings::NCASettings},
+        NCABuffers,
+        NCASimSize,
+        NCATextureFormat,
+        NCAWorkgroupSize,
+    },
+    nca::{
+        bind_group_after,
+        filter_bind_group_entry,
+        mask_bind_group_entry,
+        nca_pipeline_descriptor,
+        step_bind_group_entry,
+        texture_bind_group_entry,
+        workgroup_count,
+        NCABindGroupResources,
+        NCACurrentBindGroup,
+        NCADispatch,
+        NCAImages,
+        NCAPipeline,
+        NCAStepBuffer,
+    },
+};
+
+// =================================== Plugin =================================== //
+
+/// A plugin to compare two rules side by side. Starting a comparison freezes the
+/// current rule and runs it on a copy of the canvas in a second set of textures.
+/// Changes of the rule only apply to the canvas, so both rules evolve from the same
+/// state with the same number of steps. Whenever the NCA is initialized, the
+/// comparison continues from the same initial state. Strokes are only drawn onto
+/// the canvas.
+pub(super) struct ComparePlugin;
+
+impl Plugin for ComparePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<NCACompare>()
+            .add_plugins(ExtractResourcePlugin::<NCACompare>::default())
+            .add_event::<StartComparison>()
+            .add_event::<RestartComparison>()
+            .add_event::<StopComparison>()
+            .add_systems(Update, (
+                on_start_comparison,
+                on_restart_comparison,
+                on_stop_comparison,
+                stop_on_canvas_change,
+            ));
+
+        app.sub_app_mut(RenderApp)
+            .add_systems(Render, queue_compare_bind_group.in_set(RenderSet::Queue));
+    }
+}
+
+// ================================ Resources =================================== //
+
+/// A resource holding the state of an A/B comparison.
+#[derive(Resource, ExtractResource, Default, Clone)]
+pub struct NCACompare {
+    /// The textures of the frozen rule while comparing.
+    images: Option<NCAImages>,
+    /// The filters of the frozen rule while comparing.
+    buffers: Option<NCABuffers>,
+    /// The frozen rule while comparing.
+    settings: Option<NCASettings>,
+    /// Counts the requests to copy the canvas into the comparison.
+    sync_requests: u64,
+    /// The index of the bind group the comparison used last, reported by its node.
+    current: Arc<Mutex<usize>>,
+}
+
+impl NCACompare {
+    /// Returns true while comparing.
+    pub fn is_active(&self) -> bool {
+        self.images.is_some()
+    }
+
+    /// Returns the frozen rule while comparing.
+    pub fn settings(&self) -> Option<&NCASettings> {
+        self.settings.as_ref()
+    }
+
+    /// Returns the texture holding the current state of the frozen rule while
+    /// comparing.
+    pub fn front_texture(&self) -> Option<&Handle<Image>> {
+        let current = *self.current.lock().unwrap();
+        self.images.as_ref().map(|images| images.written_by(current))
+    }
+
+    /// Returns the texture holding the current state of the hidden channels of the
+    /// frozen rule while comparing.
+    pub fn front_hidden_texture(&self) -> Option<&Handle<Image>> {
+        let current = *self.current.lock().unwrap();
+        self.images
+            .as_ref()
+            .map(|images| images.hidden_of(images.written_by(current)))
+    }
+}
+
+/// A resource in the render world holding the two bind groups of the comparison.
+#[derive(Resource)]
+struct NCACompareBindGroup([BindGroup; 2]);
+
+// ================================== Events ==================================== //
+
+/// An event that freezes the current rule and starts comparing it to the rule of
+/// the canvas, from a copy of the canvas.
+#[derive(Event, Debug)]
+pub struct StartComparison;
+
+/// An event that copies the canvas into the comparison, so that both rules evolve
+/// from the same state again.
+#[derive(Event, Debug)]
+pub struct RestartComparison;
+
+/// An event that stops comparing and drops the textures of the frozen rule.
+#[derive(Event, Debug)]
+pub struct StopComparison;
+
+/// The assets created when a comparison starts, i.e. the textures and the shader of
+/// the frozen rule.
+#[derive(SystemParam)]
+struct ComparisonAssets<'w> {
+    images: ResMut<'w, Assets<Image>>,
+    shaders: ResMut<'w, Assets<Shader>>,
+}
+
+// ================================== Systems =================================== //
+
+/// System triggered by the StartComparison event. Creates the textures and filter
+/// buffers of the frozen rule and generates its shader. The canvas is copied into
+/// the textures by the render world.
+fn on_start_comparison(
+    mut ev_reader_start: EventReader<StartComparison>,
+    mut compare: ResMut<NCACompare>,
+    assets: ComparisonAssets,
+    render_device: Res<RenderDevice>,
+    settings: Res<NCASettings>,
+    sim_size: Res<NCASimSize>,
+    format: Res<NCATextureFormat>,
+) {
+    if ev_reader_start.read().count() == 0 {
+        return;
+    }
+    let ComparisonAssets { mut images, mut shaders } = assets;
+    info!("Starting A/B comparison.");
+    let size = sim_size.size;
+    let texture_a = images.add(create_image(size.x, size.y, format.format));
+    let texture_b = images.add(create_image(size.x, size.y, format.format));
+    let hidden_a = images.add(create_hidden_image(size, settings.hidden.len()));
+    let hidden_b = images.add(create_hidden_image(size, settings.hidden.len()));
+    shaders.insert(
+        &NCA_COMPARE_SHADER_HANDLE,
+        Shader::from_wgsl(build_shader_source(&settings), "nca_compare.wgsl"),
+    );
+    *compare = NCACompare {
+        images: Some(NCAImages { texture_a, texture_b, hidden_a, hidden_b }),
+        buffers: Some(create_filter_buffers(&settings, &render_device)),
+        settings: Some(settings.clone()),
+        sync_requests: compare.sync_requests + 1,
+        current: Arc::default(),
+    };
+}
+
+/// System triggered by the RestartComparison event. Requests copying the canvas
+/// into the comparison.
+fn on_restart_comparison(
+    mut ev_reader_restart: EventReader<RestartComparison>,
+    mut compare: ResMut<NCACompare>,
+) {
+    if ev_reader_restart.read().count() > 0 && compare.is_active() {
+        compare.sync_requests += 1;
+    }
+}
+
+/// System triggered by the StopComparison event.
+fn on_stop_comparison(
+    mut ev_reader_stop: EventReader<StopComparison>,
+    mut compare: ResMut<NCACompare>,
+) {
+    if ev_reader_stop.read().count() > 0 && compare.is_active() {
+        info!("Stopping A/B comparison.");
+        stop(&mut compare);
+    }
+}
+
+/// A system that stops comparing when the size or format of the canvas changes, as
+/// the textures of the comparison don't match it anymore.
+fn stop_on_canvas_change(
+    mut compare: ResMut<NCACompare>,
+    sim_size: Res<NCASimSize>,
+    format: Res<NCATextureFormat>,
+) {
+    if compare.is_active() && (sim_size.is_changed() || format.is_changed()) {
+        info!("Stopping A/B comparison, the canvas changed.");
+        stop(&mut compare);
+    }
+}
+
+/// A system that creates the bind groups of the comparison while comparing. Like
+/// the bind groups of the NCA, the first one reads from texture A and writes to
+/// texture B, the second one the other way around. Both rules share the freeze
+/// mask and the time of the NCA.
+fn queue_compare_bind_group(
+    mut commands: Commands,
+    resources: NCABindGroupResources,
+    compare: Res<NCACompare>,
+    gpu_images: Res<RenderAssets<GpuImage>>,
+    mask: Res<NCAFreezeMask>,
+) {
+    let NCABindGroupResources { render_device, pipeline, buffers: nca_buffers, step_buffer } =
+        resources;
+    let (Some(images), Some(buffers)) = (&compare.images, &compare.buffers) else {
+        commands.remove_resource::<NCACompareBindGroup>();
+        return;
+    };
+    let (Some(view_a), Some(view_b)) =
+        (gpu_images.get(&images.texture_a), gpu_images.get(&images.texture_b))
+    else {
+        commands.remove_resource::<NCACompareBindGroup>();
+        return;
+    };
+    let (Some(hidden_a), Some(hidden_b)) =
+        (gpu_images.get(&images.hidden_a), gpu_images.get(&images.hidden_b))
+    else {
+        commands.remove_resource::<NCACompareBindGroup>();
+        return;
+    };
+    let Some(mask_view) = gpu_images.get(mask.bound_image()) else {
+        commands.remove_resource::<NCACompareBindGroup>();
+        return;
+    };
+    if view_a.texture_format != pipeline.texture_format {
+        commands.remove_resource::<NCACompareBindGroup>();
+        return;
+    }
+    let bind_groups = [
+        (view_a, view_b, hidden_a, hidden_b),
+        (view_b, view_a, hidden_b, hidden_a),
+    ];
+    let bind_groups = bind_groups.map(|(view_in, view_out, hidden_in, hidden_out)| {
+        render_device.create_bind_group(
+            Some("NCA Compare Bind Group"),
+            &pipeline.texture_bind_group_layout,
+            &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&view_in.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(&view_out.texture_view),
+                },
+                filter_bind_group_entry(2, &buffers.buffer_red),
+                filter_bind_group_entry(3, &buffers.buffer_green),
+                filter_bind_group_entry(4, &buffers.buffer_blue),
+                filter_bind_group_entry(5, &buffers.buffer_alpha),
+                filter_bind_group_entry(6, &buffers.buffer_init),
+                step_bind_group_entry(7, &step_buffer),
+                mask_bind_group_entry(8, mask_view),
+                filter_bind_group_entry(9, &nca_buffers.buffer_time),
+                texture_bind_group_entry(10, hidden_in),
+                texture_bind_group_entry(11, hidden_out),
+                filter_bind_group_entry(12, &buffers.buffer_hidden),
+            ],
+        )
+    });
+    commands.insert_resource(NCACompareBindGroup(bind_groups));
+}
+
+// ================================== Nodes ===================================== //
+
+/// A label for the node in the rendering graph running the frozen rule.
+#[derive(RenderLabel, Debug, PartialEq, Eq, Clone, Hash)]
+pub struct NCACompareLabel;
+
+/// What the compare node does in the current frame.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum CompareDispatch {
+    #[default]
+    Idle,
+    /// Copies the current state of the canvas into the comparison.
+    Sync,
+    /// Runs the given number of update steps.
+    Update(u32),
+}
+
+/// The node in the rendering graph running the frozen rule. Runs after the NCA
+/// node, with as many steps as the NCA.
+#[derive(Debug, Default)]
+pub(super) struct NCACompareNode {
+    /// The update pipeline of the frozen rule together with the workgroup size and
+    /// texture format it was built with.
+    pipeline: Option<(CachedComputePipelineId, UVec2, TextureFormat)>,
+    dispatch: CompareDispatch,
+    /// The index of the bind group used last.
+    index: usize,
+    /// The number of requests to copy the canvas handled so far.
+    sync_requests: u64,
+}
+
+impl Node for NCACompareNode {
+    fn update(&mut self, world: &mut World) {
+        self.dispatch = CompareDispatch::Idle;
+        let compare = world.resource::<NCACompare>();
+        if !compare.is_active() || !world.contains_resource::<NCACompareBindGroup>() {
+            return;
+        }
+
+        let workgroup_size = world.resource::<NCAWorkgroupSize>().size;
+        let nca_pipeline = world.resource::<NCAPipeline>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let texture_format = nca_pipeline.texture_format;
+        let outdated = self.pipeline.is_none_or(|(_, size, format)| {
+            size != workgroup_size || format != texture_format
+        });
+        if outdated {
+            let id = pipeline_cache.queue_compute_pipeline(nca_pipeline_descriptor(
+                "NCA Compare Pipeline",
+                "update",
+                &nca_pipeline.texture_bind_group_layout,
+                &NCA_COMPARE_SHADER_HANDLE,
+                workgroup_size,
+                texture_format,
+            ));
+            self.pipeline = Some((id, workgroup_size, texture_format));
+        }
+
+        // Wait for the pipeline before copying the canvas, so that the comparison
+        // doesn't fall behind while it is loading.
+        let ready = self
+            .pipeline
+            .is_some_and(|(id, ..)| pipeline_cache.get_compute_pipeline(id).is_some());
+        if !ready {
+            return;
+        }
+
+        // Copy the canvas when requested and whenever the NCA was initialized, so
+        // that both rules start from the same state.
+        let nca_dispatch = *world.resource::<NCADispatch>();
+        if compare.sync_requests != self.sync_requests || nca_dispatch.init {
+            self.sync_requests = compare.sync_requests;
+            self.dispatch = CompareDispatch::Sync;
+            self.index = 0;
+        } else {
+            self.dispatch = CompareDispatch::Update(nca_dispatch.steps);
+            self.index = bind_group_after(self.index, nca_dispatch.steps);
+        }
+        *compare.current.lock().unwrap() = self.index;
+    }
+
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let compare = world.resource::<NCACompare>();
+        let Some(images) = &compare.images else { return Ok(()) };
+        let gpu_images = world.resource::<RenderAssets<GpuImage>>();
+
+        match self.dispatch {
+            CompareDispatch::Idle => {}
+            CompareDispatch::Sync => {
+                let nca_images = world.resource::<NCAImages>();
+                let current = nca_images.written_by(world.resource::<NCACurrentBindGroup>().0);
+                let target = images.written_by(0);
+                // The hidden channels are copied as well, unless only one of the rules
+                // has any.
+                let copies = [
+                    (current, target),
+                    (nca_images.hidden_of(current), images.hidden_of(target)),
+                ];
+                for (source, target) in copies {
+                    let (Some(source), Some(target)) =
+                        (gpu_images.get(source), gpu_images.get(target))
+                    else {
+                        continue;
+                    };
+                    if source.texture.size() != target.texture.size()
+                        || source.texture_format != target.texture_format
+                    {
+                        continue;
+                    }
+                    render_context.command_encoder().copy_texture_to_texture(
+                        source.texture.as_image_copy(),
+                        target.texture.as_image_copy(),
+                        source.texture.size(),
+                    );
+                }
+            }
+            CompareDispatch::Update(steps) => {
+                let Some((id, workgroup_size, _)) = self.pipeline else { return Ok(()) };
+                let pipeline_cache = world.resource::<PipelineCache>();
+                let Some(update_pipeline) = pipeline_cache.get_compute_pipeline(id) else {
+                    return Ok(());
+                };
+                let bind_groups = &world.resource::<NCACompareBindGroup>().0;
+                let step_buffer = world.resource::<NCAStepBuffer>();
+                let sim_size = world.resource::<NCASimSize>().size;
+                let workgroup_count = workgroup_count(sim_size, workgroup_size);
+
+                let mut pass = render_context
+                    .command_encoder()
+                    .begin_compute_pass(&ComputePassDescriptor::default());
+                pass.set_pipeline(update_pipeline);
+                // The same order of bind groups and generations as in the NCA node.
+                for step in (0..steps).rev() {
+                    let bind_group = &bind_groups[bind_group_after(self.index, step)];
+                    pass.set_bind_group(0, bind_group, &[step_buffer.offset(steps - 1 - step)]);
+                    pass.dispatch_workgroups(workgroup_count.x, workgroup_count.y, 1);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// =================================== Utils ==================================== //
+
+/// Helper function to stop comparing. Keeps counting the requests to copy the
+/// canvas, so that the next comparison starts with a copy.
+fn stop(compare: &mut NCACompare) {
+    *compare = NCACompare {
+        sync_requests: compare.sync_requests,
+        ..default()
+    };
+}
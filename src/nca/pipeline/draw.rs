@@ -1,44 +1,207 @@
 //! The rendering pipeline for drawing on screen
 
 use bevy::{
+    core::FrameCount,
+    ecs::system::SystemParam,
     prelude::*,
     render::{
         extract_resource::ExtractResource,
         render_asset::RenderAssets,
         render_graph::{Node, NodeRunError, RenderGraphContext, RenderLabel},
         render_resource::*,
-        renderer::{RenderContext, RenderDevice},
-        texture::GpuImage,
+        renderer::{RenderAdapter, RenderContext, RenderDevice, RenderQueue},
+        texture::{FallbackImage, GpuImage},
         Render,
         RenderSet,
     },
 };
+use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
+use wgpu::TextureFormatFeatureFlags;
 
 use super::{
-    super::input::NCAMouseParams,
-    nca::{NCABindGroup, NCAImages},
+    super::{
+        input::NCAMouseParams,
+        nca_control::brush::NCABrushImage,
+        NCASimSize,
+        NCATextureFormat,
+    },
+    nca::{
+        texture_format_shader_defs,
+        workgroup_count,
+        workgroup_size_shader_defs,
+        NCACurrentBindGroup,
+        NCAImages,
+        NCAPreviousBindGroup,
+    },
 };
 
 // =================================== Plugin =================================== //
 
-#[derive(Resource, ExtractResource, Debug, Clone, Copy)]
+/// The tools available for drawing on screen.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DrawTool {
+    /// Freehand drawing with the brush while the mouse button is held.
+    #[default]
+    Brush,
+    /// Places a single seed with its own size and color per click.
+    Seed,
+    /// Picks the color of the clicked cell as the brush color.
+    Eyedropper,
+    /// Fills the connected region of similar color around the clicked cell with the
+    /// brush color.
+    Fill,
+    /// Paints the freeze mask with the brush instead of the canvas. Erasing
+    /// unfreezes the cells again.
+    Mask,
+}
+
+/// When strokes are drawn relative to the update of the NCA in a frame.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DrawOrder {
+    /// Strokes are drawn onto the state the NCA updates in the same frame. They
+    /// evolve immediately and are never displayed as drawn.
+    BeforeUpdate,
+    /// Strokes are drawn onto the updated state. They are displayed as drawn for a
+    /// frame before the NCA evolves them.
+    #[default]
+    AfterUpdate,
+}
+
+/// The symmetry strokes and seeds are duplicated with.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DrawSymmetry {
+    #[default]
+    None,
+    /// Mirrors across the vertical center line of the canvas.
+    MirrorX,
+    /// Mirrors across the horizontal center line of the canvas.
+    MirrorY,
+    /// Mirrors across both center lines.
+    Both,
+    /// Rotates around the center of the canvas into the given number of copies,
+    /// at most [`DrawSymmetry::MAX_COPIES`].
+    Radial(u32),
+}
+
+impl DrawSymmetry {
+    /// The largest number of copies a stroke is duplicated into.
+    pub const MAX_COPIES: u32 = 16;
+
+    /// Returns the copies of a point on a canvas of the given size, starting with
+    /// the point itself.
+    pub fn copies(&self, point: Vec2, sim_size: Vec2) -> Vec<Vec2> {
+        let mirrored = sim_size - point;
+        match *self {
+            Self::None => vec![point],
+            Self::MirrorX => vec![point, Vec2::new(mirrored.x, point.y)],
+            Self::MirrorY => vec![point, Vec2::new(point.x, mirrored.y)],
+            Self::Both => vec![
+                point,
+                Vec2::new(mirrored.x, point.y),
+                Vec2::new(point.x, mirrored.y),
+                mirrored,
+            ],
+            Self::Radial(copies) => {
+                let copies = copies.clamp(1, Self::MAX_COPIES);
+                let center = sim_size / 2.;
+                (0..copies)
+                    .map(|i| {
+                        let angle = std::f32::consts::TAU * i as f32 / copies as f32;
+                        center + Vec2::from_angle(angle).rotate(point - center)
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// The settings of the drawing tools. Fields missing from a brush file, e.g. one
+/// written before they existed, take their default value.
+#[derive(Resource, ExtractResource, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct NCADrawSettings {
     pub is_drawing: bool,
 
+    pub tool: DrawTool,
+
     pub brush_size: f32,
+    /// The shape of the brush: 0 is a circle, 1 a square, 2 a ring, 3 a triangle,
+    /// 4 a horizontal line, 5 the brush image of [`NCABrushImage`] and 6 a circle of
+    /// random noise. 7 to 9 stamp seed patterns in the brush color: a bright dot
+    /// with a soft ring, a cross and a blob fading out from its center.
     pub brush_type: u32,
     pub brush_color: [f32; 3],
+    /// How much the brush color replaces the existing color, from 0 to 1.
+    pub brush_opacity: f32,
+    /// How sharp the edge of the circle and noise brush is, from 0 to 1. Lower
+    /// values fade the brush out towards its radius.
+    pub brush_hardness: f32,
+    /// The scale the brush image is stamped with.
+    pub brush_image_scale: f32,
+    /// The red, green and blue channels the brush and eraser write to. The other
+    /// channels keep their values.
+    pub channel_mask: [bool; 3],
+    /// If true, the brush size changes with the speed of the cursor.
+    pub speed_size: bool,
+    /// How strongly the speed of the cursor changes the brush size. Positive values
+    /// thin the brush when moving fast, negative values thicken it.
+    pub speed_sensitivity: f32,
+    /// The smallest and largest brush size when it changes with the speed.
+    pub speed_size_range: [f32; 2],
+    /// The symmetry strokes and seeds are duplicated with.
+    pub symmetry: DrawSymmetry,
+    /// When strokes are drawn relative to the update of the NCA.
+    pub order: DrawOrder,
+    /// If true, the brush paints the erase color instead of the brush color.
+    pub erase: bool,
+    /// The color painted when erasing.
+    pub erase_color: [f32; 3],
+    /// How much the color channels of a cell may differ from the clicked one to be
+    /// filled by the fill tool, from 0 to 1.
+    pub fill_tolerance: f32,
+
+    pub seed_size: f32,
+    pub seed_color: [f32; 3],
 }
 
 impl Default for NCADrawSettings {
     fn default() -> Self {
         Self {
             is_drawing: true,
+            tool: DrawTool::Brush,
             brush_size: 10.,
             brush_type: 0,
             brush_color: [1., 1., 1.],
+            brush_opacity: 1.,
+            brush_hardness: 1.,
+            brush_image_scale: 1.,
+            channel_mask: [true; 3],
+            speed_size: false,
+            speed_sensitivity: 0.5,
+            speed_size_range: [1., 300.],
+            symmetry: DrawSymmetry::None,
+            order: DrawOrder::AfterUpdate,
+            erase: false,
+            erase_color: [0., 0., 0.],
+            fill_tolerance: 0.1,
+            seed_size: 3.,
+            seed_color: [1., 1., 1.],
+        }
+    }
+}
+
+impl NCADrawSettings {
+    /// Returns the brush size for a cursor moving with the given speed in cells per
+    /// second. The size halves or doubles about every 1400 cells per second at a
+    /// sensitivity of 1.
+    pub fn brush_size_at_speed(&self, speed: f32) -> f32 {
+        if !self.speed_size {
+            return self.brush_size;
         }
+        let [min, max] = self.speed_size_range;
+        let scale = (-self.speed_sensitivity * speed / 2000.).exp();
+        (self.brush_size * scale).clamp(min.max(1.), max.max(min).max(1.))
     }
 }
 
@@ -54,6 +217,19 @@ impl Plugin for NCADrawPipelinePlugin {
 
 // ================================= Constants ================================== //
 
+/// The workgroup size of the draw shader. Unlike the one of the NCA, it isn't tuned,
+/// as drawing only takes a fraction of the frame.
+const DRAW_WORKGROUP_SIZE: UVec2 = UVec2::splat(crate::WORKGROUP_SIZE);
+
+/// The largest brush size selectable in the UI or with the mouse wheel.
+pub const MAX_BRUSH_SIZE: f32 = 300.;
+
+/// The brush type of the noise brush.
+const NOISE_BRUSH: u32 = 6;
+
+/// The brush types stamping seed patterns.
+const PATTERN_BRUSHES: std::ops::RangeInclusive<u32> = 7..=9;
+
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 #[repr(C)]
 struct NCAPushConstants {
@@ -62,16 +238,26 @@ struct NCAPushConstants {
 
     brush_size: f32,
     brush_type: u32,
-    brush_color: [f32; 3]
+    brush_color: [f32; 3],
+    brush_opacity: f32,
+    brush_hardness: f32,
+    image_scale: f32,
+    /// The written color channels, one bit per channel starting with red.
+    channel_mask: u32,
+    /// Counts the frames, so that the noise brush changes every frame.
+    frame: u32,
 }
 
 impl NCAPushConstants {
+    /// Creates the push constants for an opaque and hard brush writing all color
+    /// channels.
     pub fn new(
         draw_start: Vec2,
         draw_end: Vec2,
         brush_size: f32,
         brush_type: u32,
         brush_color: [f32; 3],
+        frame: u32,
     ) -> Self {
         Self {
             draw_start: draw_start.to_array(),
@@ -79,87 +265,306 @@ impl NCAPushConstants {
             brush_size,
             brush_type,
             brush_color,
+            brush_opacity: 1.,
+            brush_hardness: 1.,
+            image_scale: 1.,
+            channel_mask: 0b111,
+            frame,
+        }
+    }
+
+    /// Applies the opacity, hardness, image scale and written channels of the brush
+    /// in the draw settings.
+    pub fn with_brush(self, draw_params: &NCADrawSettings) -> Self {
+        Self {
+            brush_opacity: draw_params.brush_opacity,
+            brush_hardness: draw_params.brush_hardness,
+            image_scale: draw_params.brush_image_scale,
+            channel_mask: draw_params
+                .channel_mask
+                .iter()
+                .enumerate()
+                .map(|(i, &written)| (written as u32) << i)
+                .sum(),
+            ..self
         }
     }
 }
 
 // ================================== Pipeline ================================== //
 
+/// A resource in the render world holding the asset path of the shader for drawing
+/// on screen.
+#[derive(Resource, Debug, Clone)]
+pub(super) struct NCADrawShader {
+    pub path: String,
+}
+
 /// A resource holding the rendering pipeline data for drawing on screen.
 #[derive(Resource)]
 pub(super) struct NCADrawPipeline {
     draw_pipeline: CachedComputePipelineId,
     draw_bind_group_layout: BindGroupLayout,
+    /// The texture format the pipeline was built with.
+    texture_format: TextureFormat,
+    /// The buffer holding the brush parameters if the device doesn't support push
+    /// constants or read-write storage textures. It holds the parameters of every
+    /// copy of a symmetric stroke, one per stride.
+    params_buffer: Option<Buffer>,
+    /// The offset between the parameters of two copies in the buffer.
+    params_stride: u64,
+}
+
+/// Helper function to check whether the device can draw onto the canvas in place,
+/// with push constants and a read-write storage texture. Only drawing has a fallback
+/// for other devices, the playground itself still targets native platforms.
+fn supports_in_place_drawing(world: &World, texture_format: TextureFormat) -> bool {
+    let push_constants = world
+        .resource::<RenderDevice>()
+        .features()
+        .contains(WgpuFeatures::PUSH_CONSTANTS);
+    let read_write = world
+        .resource::<RenderAdapter>()
+        .get_texture_format_features(texture_format)
+        .flags
+        .contains(TextureFormatFeatureFlags::STORAGE_READ_WRITE);
+    push_constants && read_write
 }
 
 impl FromWorld for NCADrawPipeline {
     fn from_world(world: &mut World) -> Self {
         let pipeline_cache = world.resource::<PipelineCache>();
+        let texture_format = world
+            .get_resource::<NCATextureFormat>()
+            .copied()
+            .unwrap_or_default()
+            .format;
+
+        let fallback = !supports_in_place_drawing(world, texture_format);
+        if fallback {
+            info!("Drawing without push constants and read-write storage textures.");
+        }
 
-        let draw_bind_group_layout =
-            world
-                .resource::<RenderDevice>()
-                .create_bind_group_layout(
-                    Some("NCA Draw Bind Group Layout"),
-                    &[BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: ShaderStages::COMPUTE,
-                        ty: BindingType::StorageTexture {
-                            access: StorageTextureAccess::ReadWrite,
-                            format: TextureFormat::Rgba8Unorm,
-                            view_dimension: TextureViewDimension::D2,
-                        },
-                        count: None,
-                    }],
-                );
+        let storage_texture = |binding, access| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::StorageTexture {
+                access,
+                format: texture_format,
+                view_dimension: TextureViewDimension::D2,
+            },
+            count: None,
+        };
+        let brush_image = BindGroupLayoutEntry {
+            binding: 3,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable: true },
+                view_dimension: TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let layout_entries = if fallback {
+            vec![
+                storage_texture(0, StorageTextureAccess::ReadOnly),
+                storage_texture(1, StorageTextureAccess::WriteOnly),
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: BufferSize::new(
+                            std::mem::size_of::<NCAPushConstants>() as u64,
+                        ),
+                    },
+                    count: None,
+                },
+                brush_image,
+            ]
+        } else {
+            vec![storage_texture(0, StorageTextureAccess::ReadWrite), brush_image]
+        };
+
+        let render_device = world.resource::<RenderDevice>();
+        let draw_bind_group_layout = render_device
+            .create_bind_group_layout(Some("NCA Draw Bind Group Layout"), &layout_entries);
+        let params_stride = render_device.limits().min_uniform_buffer_offset_alignment as u64;
+        let params_buffer = fallback.then(|| {
+            render_device.create_buffer(&BufferDescriptor {
+                label: Some("NCA Draw Params Buffer"),
+                size: params_stride * DrawSymmetry::MAX_COPIES as u64,
+                usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        });
+
+        let mut shader_defs = texture_format_shader_defs(texture_format);
+        shader_defs.extend(workgroup_size_shader_defs(DRAW_WORKGROUP_SIZE));
+        let mut push_constant_ranges = Vec::new();
+        if fallback {
+            shader_defs.push("DRAW_FALLBACK".into());
+        } else {
+            push_constant_ranges.push(PushConstantRange {
+                stages: ShaderStages::COMPUTE,
+                range: 0..std::mem::size_of::<NCAPushConstants>() as u32,
+            });
+        }
 
-        let brush_shader = world.resource::<AssetServer>().load("shaders/draw.wgsl");
+        let path = world.resource::<NCADrawShader>().path.clone();
+        let brush_shader = world.resource::<AssetServer>().load(path);
 
         let draw_pipeline = pipeline_cache.queue_compute_pipeline(
                 ComputePipelineDescriptor {
                 shader: brush_shader,
-                shader_defs: vec![],
+                shader_defs,
                 entry_point: Cow::from("draw"),
                 layout: vec![draw_bind_group_layout.clone()],
                 label: Some(std::borrow::Cow::Borrowed("NCA Draw Pipeline")),
-                push_constant_ranges: [PushConstantRange {
-                    stages: ShaderStages::COMPUTE,
-                    range: 0..std::mem::size_of::<NCAPushConstants>() as u32,
-                }]
-                .to_vec(),
+                push_constant_ranges,
             }
         );
 
         Self {
             draw_pipeline,
             draw_bind_group_layout,
+            texture_format,
+            params_buffer,
+            params_stride,
         }
     }
 }
 
 // ================================== BindGroup ================================== //
 
-/// A resource holding the bind groups corresponding to the texture.
+/// A resource holding the bind groups corresponding to the two textures. Each one
+/// targets the texture written by the NCA bind group of the same index.
 #[derive(Resource)]
-struct NCADrawBindGroup(BindGroup);
+struct NCADrawBindGroup([BindGroup; 2]);
+
+/// A resource holding the texture drawn into when the canvas can't be drawn onto
+/// in place. It is copied back onto the canvas after drawing.
+#[derive(Resource, Clone)]
+struct NCADrawScratch {
+    texture: Texture,
+    view: TextureView,
+}
+
+/// The textures bound for drawing, i.e. the textures of the NCA and the image of the
+/// image brush, together with the fallback for a missing brush image.
+#[derive(SystemParam)]
+struct DrawTextures<'w> {
+    gpu_images: Res<'w, RenderAssets<GpuImage>>,
+    nca_images: Res<'w, NCAImages>,
+    brush_image: Res<'w, NCABrushImage>,
+    fallback_image: Res<'w, FallbackImage>,
+}
 
 fn queue_draw_bind_group(
     mut commands: Commands,
     render_device: Res<RenderDevice>,
     pipeline: Res<NCADrawPipeline>,
-    gpu_images: Res<RenderAssets<GpuImage>>,
-    nca_images: Res<NCAImages>,
+    textures: DrawTextures,
+    texture_format: Res<NCATextureFormat>,
+    scratch: Option<Res<NCADrawScratch>>,
 ) {
-    let view = &gpu_images.get(&nca_images.texture_a).unwrap();
-    let draw_bind_group = render_device.create_bind_group(
-        Some("NCA Draw Bind Group"),
-        &pipeline.draw_bind_group_layout,
-        &[BindGroupEntry {
-            binding: 0,
-            resource: BindingResource::TextureView(&view.texture_view),
-        }],
-    );
-    commands.insert_resource(NCADrawBindGroup(draw_bind_group));
+    let DrawTextures { gpu_images, nca_images, brush_image, fallback_image } = textures;
+    // Wait for the draw node to rebuild the pipeline for a new texture format.
+    if texture_format.format != pipeline.texture_format {
+        return;
+    }
+    // Without a brush image, or while it is being uploaded, the image brush stamps
+    // the fallback image, a single white cell.
+    let brush_image = brush_image
+        .handle
+        .as_ref()
+        .and_then(|handle| gpu_images.get(handle))
+        .map_or(&fallback_image.d2.texture_view, |image| &image.texture_view);
+
+    let Some(params_buffer) = &pipeline.params_buffer else {
+        let draw_bind_groups = [0, 1].map(|index| {
+            let view = &gpu_images.get(nca_images.written_by(index)).unwrap();
+            render_device.create_bind_group(
+                Some("NCA Draw Bind Group"),
+                &pipeline.draw_bind_group_layout,
+                &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(&view.texture_view),
+                    },
+                    BindGroupEntry {
+                        binding: 3,
+                        resource: BindingResource::TextureView(brush_image),
+                    },
+                ],
+            )
+        });
+        commands.insert_resource(NCADrawBindGroup(draw_bind_groups));
+        return;
+    };
+
+    // Recreate the scratch texture if the canvas was resized or changed its format.
+    let size = gpu_images.get(nca_images.written_by(0)).unwrap().size;
+    let scratch = scratch.filter(|scratch| {
+        scratch.texture.width() == size.x
+            && scratch.texture.height() == size.y
+            && scratch.texture.format() == pipeline.texture_format
+    });
+    let scratch = match scratch {
+        Some(scratch) => scratch.clone(),
+        None => {
+            let texture = render_device.create_texture(&TextureDescriptor {
+                label: Some("NCA Draw Scratch Texture"),
+                size: Extent3d {
+                    width: size.x,
+                    height: size.y,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: pipeline.texture_format,
+                usage: TextureUsages::STORAGE_BINDING | TextureUsages::COPY_SRC,
+                view_formats: &[],
+            });
+            let view = texture.create_view(&TextureViewDescriptor::default());
+            let scratch = NCADrawScratch { texture, view };
+            commands.insert_resource(scratch.clone());
+            scratch
+        }
+    };
+
+    let draw_bind_groups = [0, 1].map(|index| {
+        let view = &gpu_images.get(nca_images.written_by(index)).unwrap();
+        render_device.create_bind_group(
+            Some("NCA Draw Bind Group"),
+            &pipeline.draw_bind_group_layout,
+            &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&view.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(&scratch.view),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::Buffer(BufferBinding {
+                        buffer: params_buffer,
+                        offset: 0,
+                        size: BufferSize::new(std::mem::size_of::<NCAPushConstants>() as u64),
+                    }),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::TextureView(brush_image),
+                },
+            ],
+        )
+    });
+    commands.insert_resource(NCADrawBindGroup(draw_bind_groups));
 }
 
 // ================================== Nodes ================================== //
@@ -190,6 +595,14 @@ impl Default for NCADrawNode {
 
 impl Node for NCADrawNode {
     fn update(&mut self, world: &mut World) {
+        let texture_format = world.resource::<NCATextureFormat>().format;
+        if texture_format != world.resource::<NCADrawPipeline>().texture_format {
+            info!("Rebuilding NCA draw pipeline with texture format {:?}.", texture_format);
+            let pipeline = NCADrawPipeline::from_world(world);
+            world.insert_resource(pipeline);
+            self.state = NCADrawState::Loading;
+        }
+
         let pipeline_cache = world.resource::<PipelineCache>();
         let pipeline = world.resource::<NCADrawPipeline>();
 
@@ -212,47 +625,336 @@ impl Node for NCADrawNode {
         world: &World,
     ) -> Result<(), NodeRunError> {
         let params = &world.resource::<NCAMouseParams>();
+        let draw_params = &world.resource::<NCADrawSettings>();
+        let frame = world.resource::<FrameCount>().0;
+
+        let pc = if params.place_seed {
+            NCAPushConstants::new(
+                params.mouse_pos,
+                params.mouse_pos,
+                draw_params.seed_size,
+                0,
+                draw_params.seed_color,
+                frame,
+            )
+        } else if (params.is_drawing || params.is_erasing)
+            && !params.stroke_start
+            && draw_params.tool != DrawTool::Mask
+        {
+            // Erasing paints the erase color with the regular brush. The noise and
+            // pattern brushes erase with a circle.
+            let erasing = params.is_erasing || draw_params.erase;
+            let (color, brush_type) = match (erasing, draw_params.brush_type) {
+                (true, NOISE_BRUSH) => (draw_params.erase_color, 0),
+                (true, brush_type) if PATTERN_BRUSHES.contains(&brush_type) => {
+                    (draw_params.erase_color, 0)
+                }
+                (true, brush_type) => (draw_params.erase_color, brush_type),
+                (false, brush_type) => (draw_params.brush_color, brush_type),
+            };
+            NCAPushConstants::new(
+                params.mouse_pos,
+                params.prev_mouse_pos,
+                draw_params.brush_size_at_speed(params.speed),
+                brush_type,
+                color,
+                frame,
+            )
+            .with_brush(draw_params)
+        } else {
+            return Ok(());
+        };
+
+        // Duplicate the stroke according to the draw symmetry.
+        let sim_size = world.resource::<NCASimSize>().size;
+        let starts = draw_params.symmetry.copies(Vec2::from(pc.draw_start), sim_size.as_vec2());
+        let ends = draw_params.symmetry.copies(Vec2::from(pc.draw_end), sim_size.as_vec2());
+        let pcs: Vec<NCAPushConstants> = starts
+            .into_iter()
+            .zip(ends)
+            .map(|(start, end)| NCAPushConstants {
+                draw_start: start.to_array(),
+                draw_end: end.to_array(),
+                ..pc
+            })
+            .collect();
+
+        // Draw onto the current state, so that the next update continues from it.
+        // Before the update, that is the state this frame's update reads from.
+        let index = match draw_params.order {
+            DrawOrder::BeforeUpdate => world.resource::<NCAPreviousBindGroup>().0,
+            DrawOrder::AfterUpdate => world.resource::<NCACurrentBindGroup>().0,
+        };
+        let draw_bind_group = &world.resource::<NCADrawBindGroup>().0[index];
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let pipeline = world.resource::<NCADrawPipeline>();
+        let workgroup_count = workgroup_count(sim_size, DRAW_WORKGROUP_SIZE);
 
-        if params.is_drawing {
-            let draw_params = &world.resource::<NCADrawSettings>();
-            let texture_bind_group = &world.resource::<NCABindGroup>().0;
-            let draw_bind_group = &world.resource::<NCADrawBindGroup>().0;
-            let pipeline_cache = world.resource::<PipelineCache>();
-            let pipeline = world.resource::<NCADrawPipeline>();
+        let NCADrawState::Update = self.state else {
+            return Ok(());
+        };
+        let draw_pipeline = pipeline_cache
+            .get_compute_pipeline(pipeline.draw_pipeline)
+            .unwrap();
 
+        let Some(params_buffer) = &pipeline.params_buffer else {
             let mut pass = render_context
                 .command_encoder()
                 .begin_compute_pass(&ComputePassDescriptor::default());
 
-            pass.set_bind_group(0, &texture_bind_group[1], &[]);
-
-            match self.state {
-                NCADrawState::Loading => {}
-                NCADrawState::Update => {
-                    let draw_pipeline = pipeline_cache
-                        .get_compute_pipeline(pipeline.draw_pipeline)
-                        .unwrap();
-                    let pc =
-                        NCAPushConstants::new(
-                            params.mouse_pos,
-                            params.prev_mouse_pos,
-                            draw_params.brush_size,
-                            draw_params.brush_type,
-                            draw_params.brush_color,
-                        );
-
-                    pass.set_pipeline(draw_pipeline);
-                    pass.set_bind_group(0, draw_bind_group, &[]);
-                    pass.set_push_constants(0, bytemuck::cast_slice(&[pc]));
-                    pass.dispatch_workgroups(
-                        crate::SIM_SIZE.0 / crate::WORKGROUP_SIZE,
-                        crate::SIM_SIZE.1 / crate::WORKGROUP_SIZE,
-                        1,
-                    );
-                }
+            pass.set_pipeline(draw_pipeline);
+            pass.set_bind_group(0, draw_bind_group, &[]);
+            for pc in &pcs {
+                pass.set_push_constants(0, bytemuck::cast_slice(&[*pc]));
+                pass.dispatch_workgroups(workgroup_count.x, workgroup_count.y, 1);
             }
+            return Ok(());
+        };
+
+        // Without in place drawing, every copy is drawn into the scratch texture and
+        // copied back onto the canvas before the next one.
+        let render_queue = world.resource::<RenderQueue>();
+        let scratch = world.resource::<NCADrawScratch>();
+        let target = world
+            .resource::<RenderAssets<GpuImage>>()
+            .get(world.resource::<NCAImages>().written_by(index))
+            .unwrap();
+        for (index, pc) in pcs.iter().enumerate() {
+            let offset = index as u64 * pipeline.params_stride;
+            render_queue.write_buffer(params_buffer, offset, bytemuck::cast_slice(&[*pc]));
+            {
+                let mut pass = render_context
+                    .command_encoder()
+                    .begin_compute_pass(&ComputePassDescriptor::default());
+
+                pass.set_pipeline(draw_pipeline);
+                pass.set_bind_group(0, draw_bind_group, &[offset as u32]);
+                pass.dispatch_workgroups(workgroup_count.x, workgroup_count.y, 1);
+            }
+            render_context.command_encoder().copy_texture_to_texture(
+                scratch.texture.as_image_copy(),
+                target.texture.as_image_copy(),
+                scratch.texture.size(),
+            );
         }
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::nca::resolve_shader_defs;
+    use super::*;
+
+    /// Reads the source of the draw shader.
+    fn draw_shader_source() -> String {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("assets")
+            .join(crate::DRAW_SHADER_ASSET_PATH);
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn draw_shader_matches_the_push_constants() {
+        let source = draw_shader_source();
+        for format in NCATextureFormat::SUPPORTED {
+            for fallback in [false, true] {
+                let mut shader_defs = texture_format_shader_defs(format);
+                shader_defs.extend(workgroup_size_shader_defs(DRAW_WORKGROUP_SIZE));
+                if fallback {
+                    shader_defs.push("DRAW_FALLBACK".into());
+                }
+                let resolved = resolve_shader_defs(&source, &shader_defs);
+                let module = naga::front::wgsl::parse_str(&resolved)
+                    .unwrap_or_else(|err| panic!("{}", err.emit_to_string(&resolved)));
+                naga::valid::Validator::new(
+                    naga::valid::ValidationFlags::all(),
+                    naga::valid::Capabilities::all(),
+                )
+                .validate(&module)
+                .unwrap_or_else(|err| panic!("{}", err.emit_to_string(&resolved)));
+
+                // The fallback reads the parameters from a uniform buffer, which has
+                // to hold them with the same layout as the push constants.
+                let (_, pc) = module
+                    .global_variables
+                    .iter()
+                    .find(|(_, var)| var.name.as_deref() == Some("pc"))
+                    .unwrap();
+                let expected = if fallback {
+                    naga::AddressSpace::Uniform
+                } else {
+                    naga::AddressSpace::PushConstant
+                };
+                assert_eq!(pc.space, expected);
+                let mut layouter = naga::proc::Layouter::default();
+                layouter.update(module.to_ctx()).unwrap();
+                assert_eq!(
+                    layouter[pc.ty].size as usize,
+                    std::mem::size_of::<NCAPushConstants>(),
+                );
+            }
+        }
+    }
+
+    /// Runs `closest_point_on_line` of the draw shader on the GPU for the segments
+    /// from v to w and the points p, given as `[v, w, p]`. Returns the closest
+    /// points. Panics without an adapter that can run compute shaders.
+    fn run_closest_point_on_line(cases: &[[Vec2; 3]]) -> Vec<Vec2> {
+        let source = draw_shader_source();
+        let start = source.find("fn closest_point_on_line").unwrap();
+        let end = start + source[start..].find("\n}").unwrap() + 2;
+        let shader = format!(
+            "@group(0) @binding(0)
+            var<storage, read> cases: array<vec2<f32>>;
+            @group(0) @binding(1)
+            var<storage, read_write> points: array<vec2<f32>>;
+
+            @compute @workgroup_size(1, 1, 1)
+            fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {{
+                let i = invocation_id.x;
+                let v = cases[3u * i];
+                let w = cases[3u * i + 1u];
+                points[i] = closest_point_on_line(v, w, cases[3u * i + 2u]);
+            }}
+
+            {}",
+            &source[start..end],
+        );
+
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+        let adapter = bevy::tasks::block_on(
+            instance.request_adapter(&wgpu::RequestAdapterOptions::default()),
+        )
+        .expect("No GPU adapter found.");
+        assert!(
+            adapter
+                .get_downlevel_capabilities()
+                .flags
+                .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS),
+            "The adapter can't run compute shaders.",
+        );
+        let (device, queue) =
+            bevy::tasks::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default(), None))
+                .expect("Couldn't create a device.");
+
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(shader.into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: None,
+            module: &module,
+            entry_point: "main",
+            compilation_options: Default::default(),
+        });
+
+        let size = (cases.len() * std::mem::size_of::<Vec2>()) as u64;
+        let cases_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: 3 * size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        queue.write_buffer(&cases_buffer, 0, bytemuck::cast_slice(cases));
+        let points_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: cases_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: points_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        {
+            let mut pass = encoder.begin_compute_pass(&Default::default());
+            pass.set_pipeline(&pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(cases.len() as u32, 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(&points_buffer, 0, &staging_buffer, 0, size);
+        queue.submit([encoder.finish()]);
+
+        let slice = staging_buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |result| result.unwrap());
+        device.poll(wgpu::Maintain::Wait);
+        let points = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+        points
+    }
+
+    /// Checks the closest points the draw shader computes on the GPU. Run it with
+    /// `cargo test -- --ignored` on a machine with a GPU.
+    #[test]
+    #[ignore = "needs a GPU adapter that can run compute shaders"]
+    fn closest_point_on_line_in_shader() {
+        let v = Vec2::new(2., 1.);
+        let w = Vec2::new(10., 5.);
+        let u = Vec2::new(3., 4.);
+        // The segment, the point and the closest point on the segment.
+        let cases = [
+            (v, w, v, v),
+            (v, w, w, w),
+            (v, w, Vec2::new(6., 3.), Vec2::new(6., 3.)),
+            // Perpendicular to the midpoint.
+            (v, w, Vec2::new(4., 7.), Vec2::new(6., 3.)),
+            // Beyond the ends.
+            (
+                Vec2::ZERO,
+                Vec2::new(4., 0.),
+                Vec2::new(-3., 2.),
+                Vec2::ZERO,
+            ),
+            (
+                Vec2::ZERO,
+                Vec2::new(4., 0.),
+                Vec2::new(9., -1.),
+                Vec2::new(4., 0.),
+            ),
+            // A degenerate segment.
+            (u, u, Vec2::new(-1., 8.), u),
+        ];
+        let inputs: Vec<_> = cases.iter().map(|&(v, w, p, _)| [v, w, p]).collect();
+        let points = run_closest_point_on_line(&inputs);
+        for ((v, w, p, expected), point) in cases.into_iter().zip(points) {
+            assert!(
+                point.abs_diff_eq(expected, 1e-5),
+                "{point} instead of {expected} for {p} and the segment from {v} to {w}",
+            );
+        }
+    }
+
+    #[test]
+    fn brush_files_without_newer_fields_still_parse() {
+        let brush = r#"{ "brush_size": 4.0, "brush_color": [1.0, 0.0, 0.0] }"#;
+        let settings: NCADrawSettings = serde_json::from_str(brush).unwrap();
+        assert_eq!(
+            settings,
+            NCADrawSettings {
+                brush_size: 4.,
+                brush_color: [1., 0., 0.],
+                ..Default::default()
+            },
+        );
+    }
+}
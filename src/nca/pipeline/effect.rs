@@ -0,0 +1,324 @@
+//! One-shot image effects applied to the canvas
+
+use bevy::{
+    prelude::*,
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_asset::RenderAssets,
+        render_graph::{Node, NodeRunError, RenderGraphContext, RenderLabel},
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice},
+        texture::GpuImage,
+    },
+};
+use std::borrow::Cow;
+
+use crate::EFFECT_SHADER_ASSET_PATH;
+use super::{
+    super::{NCASimSize, NCATextureFormat, HIDDEN_TEXTURE_FORMAT},
+    nca::{
+        texture_format_shader_defs,
+        workgroup_count,
+        workgroup_size_shader_defs,
+        NCACurrentBindGroup,
+        NCAImages,
+    },
+};
+
+// =================================== Plugin =================================== //
+
+/// A plugin to apply one-shot effects to the canvas, e.g. inverting its colors or
+/// flipping it. An effect reads the current state and writes the other texture of
+/// the NCA, which is then copied back onto the current state, and likewise for the
+/// hidden channels. The next update thus continues from the changed state, whether
+/// the NCA runs or is paused.
+pub(super) struct EffectPlugin;
+
+impl Plugin for EffectPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<NCAEffectRequest>()
+            .add_plugins(ExtractResourcePlugin::<NCAEffectRequest>::default());
+    }
+}
+
+// ================================= Constants ================================== //
+
+/// The workgroup size of the effect shader. Like the one of the draw shader, it
+/// isn't tuned, as effects are applied once.
+const EFFECT_WORKGROUP_SIZE: UVec2 = UVec2::splat(crate::WORKGROUP_SIZE);
+
+// ================================ Resources =================================== //
+
+/// The effects that can be applied to the canvas. Effects on the colors only change
+/// the color channels and keep the alpha channel and the hidden channels, while
+/// flips move whole cells, including their alpha and hidden channels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageEffect {
+    /// Inverts the color channels.
+    Invert,
+    /// Replaces the color channels with their luminance.
+    Grayscale,
+    /// Shifts the color channels, RGB becomes GBR.
+    SwapChannels,
+    /// Adds the brightness to the color channels after scaling them by the
+    /// contrast around the middle gray.
+    BrightnessContrast { brightness: f32, contrast: f32 },
+    /// Mirrors the canvas across its vertical center line.
+    FlipHorizontal,
+    /// Mirrors the canvas across its horizontal center line.
+    FlipVertical,
+}
+
+impl ImageEffect {
+    /// Returns the index of the effect in the shader.
+    fn index(self) -> u32 {
+        match self {
+            ImageEffect::Invert => 0,
+            ImageEffect::Grayscale => 1,
+            ImageEffect::SwapChannels => 2,
+            ImageEffect::BrightnessContrast { .. } => 3,
+            ImageEffect::FlipHorizontal => 4,
+            ImageEffect::FlipVertical => 5,
+        }
+    }
+}
+
+/// The parameters of an effect, as read by the shader.
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+struct EffectParams {
+    effect: u32,
+    brightness: f32,
+    contrast: f32,
+    _padding: f32,
+}
+
+impl From<ImageEffect> for EffectParams {
+    fn from(effect: ImageEffect) -> Self {
+        let (brightness, contrast) = match effect {
+            ImageEffect::BrightnessContrast { brightness, contrast } => (brightness, contrast),
+            _ => (0., 1.),
+        };
+        Self {
+            effect: effect.index(),
+            brightness,
+            contrast,
+            _padding: 0.,
+        }
+    }
+}
+
+/// A resource holding the last requested effect. The effect node applies it once
+/// for every change of the count of requests it sees.
+#[derive(Resource, ExtractResource, Debug, Default, Clone)]
+pub struct NCAEffectRequest {
+    effect: Option<ImageEffect>,
+    requests: u64,
+}
+
+impl NCAEffectRequest {
+    /// Requests applying the effect to the canvas in this frame.
+    pub fn request(&mut self, effect: ImageEffect) {
+        self.effect = Some(effect);
+        self.requests += 1;
+    }
+}
+
+// ================================== Nodes ===================================== //
+
+/// A label for the node in the rendering graph applying effects to the canvas.
+#[derive(RenderLabel, Debug, PartialEq, Eq, Clone, Hash)]
+pub struct NCAEffectLabel;
+
+/// The pipeline of the effect node together with the texture format it was built
+/// with.
+#[derive(Debug)]
+struct EffectPipeline {
+    id: CachedComputePipelineId,
+    bind_group_layout: BindGroupLayout,
+    texture_format: TextureFormat,
+}
+
+/// The node in the rendering graph applying effects to the canvas. Runs after
+/// the update, drawing and reading back of the canvas, so that a snapshot for
+/// undoing the effect is read before it.
+#[derive(Debug, Default)]
+pub(super) struct NCAEffectNode {
+    pipeline: Option<EffectPipeline>,
+    /// The effect applied in the current frame.
+    dispatch: Option<ImageEffect>,
+    /// The number of requests handled so far.
+    requests: u64,
+}
+
+impl Node for NCAEffectNode {
+    fn update(&mut self, world: &mut World) {
+        self.dispatch = None;
+        let texture_format = world.resource::<NCATextureFormat>().format;
+        let outdated = self
+            .pipeline
+            .as_ref()
+            .is_none_or(|pipeline| pipeline.texture_format != texture_format);
+        if outdated {
+            self.pipeline = Some(create_effect_pipeline(world, texture_format));
+        }
+
+        // Effects requested while the pipeline is loading are applied once it is
+        // ready.
+        let request = world.resource::<NCAEffectRequest>();
+        let ready = self.pipeline.as_ref().is_some_and(|pipeline| {
+            world.resource::<PipelineCache>().get_compute_pipeline(pipeline.id).is_some()
+        });
+        if request.requests != self.requests && ready {
+            self.requests = request.requests;
+            self.dispatch = request.effect;
+        }
+    }
+
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let (Some(effect), Some(pipeline)) = (self.dispatch, &self.pipeline) else {
+            return Ok(());
+        };
+        let Some(effect_pipeline) =
+            world.resource::<PipelineCache>().get_compute_pipeline(pipeline.id)
+        else {
+            return Ok(());
+        };
+        let nca_images = world.resource::<NCAImages>();
+        let current = world.resource::<NCACurrentBindGroup>().0;
+        let gpu_images = world.resource::<RenderAssets<GpuImage>>();
+        let front = nca_images.written_by(current);
+        let back = nca_images.written_by(1 - current);
+        let (Some(front), Some(back), Some(hidden_front), Some(hidden_back)) = (
+            gpu_images.get(front),
+            gpu_images.get(back),
+            gpu_images.get(nca_images.hidden_of(front)),
+            gpu_images.get(nca_images.hidden_of(back)),
+        ) else {
+            return Ok(());
+        };
+        if front.texture_format != pipeline.texture_format {
+            return Ok(());
+        }
+
+        let render_device = render_context.render_device().clone();
+        let params_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            label: Some("NCA Effect Params Buffer"),
+            contents: bytemuck::cast_slice(&[EffectParams::from(effect)]),
+            usage: BufferUsages::UNIFORM,
+        });
+        let bind_group = render_device.create_bind_group(
+            Some("NCA Effect Bind Group"),
+            &pipeline.bind_group_layout,
+            &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&front.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(&back.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::TextureView(&hidden_front.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: BindingResource::TextureView(&hidden_back.texture_view),
+                },
+            ],
+        );
+
+        let sim_size = world.resource::<NCASimSize>().size;
+        let workgroup_count = workgroup_count(sim_size, EFFECT_WORKGROUP_SIZE);
+        {
+            let mut pass = render_context
+                .command_encoder()
+                .begin_compute_pass(&ComputePassDescriptor {
+                    label: Some("NCA Effect Pass"),
+                    ..default()
+                });
+            pass.set_pipeline(effect_pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(workgroup_count.x, workgroup_count.y, 1);
+        }
+        // The NCA reads the current state in the next update.
+        render_context.command_encoder().copy_texture_to_texture(
+            back.texture.as_image_copy(),
+            front.texture.as_image_copy(),
+            back.texture.size(),
+        );
+        render_context.command_encoder().copy_texture_to_texture(
+            hidden_back.texture.as_image_copy(),
+            hidden_front.texture.as_image_copy(),
+            hidden_back.texture.size(),
+        );
+
+        Ok(())
+    }
+}
+
+// =================================== Utils ==================================== //
+
+/// Helper function to create the bind group layout of the effect shader and queue
+/// its pipeline for the given texture format.
+fn create_effect_pipeline(world: &World, texture_format: TextureFormat) -> EffectPipeline {
+    let storage_texture = |binding, access, format| BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::StorageTexture {
+            access,
+            format,
+            view_dimension: TextureViewDimension::D2,
+        },
+        count: None,
+    };
+    let bind_group_layout = world.resource::<RenderDevice>().create_bind_group_layout(
+        Some("NCA Effect Bind Group Layout"),
+        &[
+            storage_texture(0, StorageTextureAccess::ReadOnly, texture_format),
+            storage_texture(1, StorageTextureAccess::WriteOnly, texture_format),
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: BufferSize::new(
+                        std::mem::size_of::<EffectParams>() as u64,
+                    ),
+                },
+                count: None,
+            },
+            storage_texture(3, StorageTextureAccess::ReadOnly, HIDDEN_TEXTURE_FORMAT),
+            storage_texture(4, StorageTextureAccess::WriteOnly, HIDDEN_TEXTURE_FORMAT),
+        ],
+    );
+
+    let mut shader_defs = texture_format_shader_defs(texture_format);
+    shader_defs.extend(workgroup_size_shader_defs(EFFECT_WORKGROUP_SIZE));
+    let shader = world.resource::<AssetServer>().load(EFFECT_SHADER_ASSET_PATH);
+    let id = world.resource::<PipelineCache>().queue_compute_pipeline(
+        ComputePipelineDescriptor {
+            label: Some(Cow::Borrowed("NCA Effect Pipeline")),
+            layout: vec![bind_group_layout.clone()],
+            push_constant_ranges: Vec::new(),
+            shader,
+            shader_defs,
+            entry_point: Cow::from("apply"),
+        }
+    );
+
+    EffectPipeline { id, bind_group_layout, texture_format }
+}
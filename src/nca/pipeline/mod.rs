@@ -1,43 +1,160 @@
 //! Manages all rendering pipelines
 
+pub mod compare;
 pub mod draw;
+pub mod effect;
 pub mod nca;
+pub mod readback;
+pub mod tuner;
 
-use bevy::{prelude::*, render::{render_graph::RenderGraph, RenderApp}};
+use bevy::{
+    prelude::*,
+    render::{
+        render_graph::RenderGraph,
+        render_resource::Source,
+        Render,
+        RenderApp,
+        RenderSet,
+    },
+};
 
-use draw::{NCADrawLabel, NCADrawNode, NCADrawPipeline, NCADrawPipelinePlugin};
-use nca::{NCALabel, NCANode, NCAPipeline, NCAPipelinePlugin};
+use compare::{ComparePlugin, NCACompareLabel, NCACompareNode};
+use draw::{
+    DrawOrder,
+    NCADrawLabel,
+    NCADrawNode,
+    NCADrawPipeline,
+    NCADrawPipelinePlugin,
+    NCADrawSettings,
+    NCADrawShader,
+};
+use effect::{EffectPlugin, NCAEffectLabel, NCAEffectNode};
+use nca::{
+    derives_workgroup_size,
+    NCALabel,
+    NCANode,
+    NCAPipeline,
+    NCAPipelinePlugin,
+    NCAStepBuffer,
+};
+use readback::{NCAReadbackLabel, NCAReadbackNode, ReadbackPlugin};
+use tuner::WorkgroupTunerPlugin;
 
 // =================================== Plugin =================================== //
 
 /// A plugin to manage to manage the two rendering pipelines: for the neural cellular
-/// automaton and for letting the user draw on screen.
-pub(super) struct PipelinesPlugin;
+/// automaton and for letting the user draw on screen. Also provides reading the
+/// textures back from the GPU, tuning the workgroup size of the NCA, comparing two
+/// rules side by side and applying one-shot effects to the canvas. Checks that the
+/// compute shaders take their workgroup size from the pipelines, so that it matches
+/// the dispatched workgroups.
+pub(super) struct PipelinesPlugin {
+    /// The asset path of the shader for drawing on the NCA.
+    pub draw_shader: String,
+}
 
 impl Plugin for PipelinesPlugin {
     fn build(&self, app: &mut App) {
+        app
+            .add_plugins((ReadbackPlugin, WorkgroupTunerPlugin, ComparePlugin, EffectPlugin))
+            .add_systems(Update, check_shader_workgroup_sizes);
+
         // The rendering pipelines are only relevant for the rendering world. So we
         // only need to add our plugins to the rendering sub app.
         let render_app = app.sub_app_mut(RenderApp);
 
         // Add all pipeline plugins:
         render_app
+            .insert_resource(NCADrawShader { path: self.draw_shader.clone() })
+            .init_resource::<AppliedDrawOrder>()
             .add_plugins((
                 NCAPipelinePlugin,
                 NCADrawPipelinePlugin,
-            ));
+            ))
+            .add_systems(Render, apply_draw_order.in_set(RenderSet::Prepare));
         
         // Build render graph:
         let mut render_graph = render_app.world_mut().resource_mut::<RenderGraph>();
         render_graph.add_node(NCALabel, NCANode::default());
         render_graph.add_node(NCADrawLabel, NCADrawNode::default());
-        render_graph.add_node_edge(NCALabel, bevy::render::graph::CameraDriverLabel);
+        render_graph.add_node(NCAReadbackLabel, NCAReadbackNode);
+        render_graph.add_node(NCACompareLabel, NCACompareNode::default());
+        render_graph.add_node(NCAEffectLabel, NCAEffectNode::default());
+        // Drawing needs to know which texture the NCA wrote to in this frame. The
+        // edge between the two is reversed when drawing before the update.
+        render_graph.add_node_edge(NCALabel, NCADrawLabel);
         render_graph.add_node_edge(NCADrawLabel, bevy::render::graph::CameraDriverLabel);
+        render_graph.add_node_edge(NCALabel, NCAReadbackLabel);
+        render_graph.add_node_edge(NCADrawLabel, NCAReadbackLabel);
+        render_graph.add_node_edge(NCAReadbackLabel, bevy::render::graph::CameraDriverLabel);
+        // The comparison runs as many steps as the NCA and copies its canvas.
+        render_graph.add_node_edge(NCALabel, NCACompareLabel);
+        render_graph.add_node_edge(NCACompareLabel, bevy::render::graph::CameraDriverLabel);
+        // Effects change the canvas after it was read back, e.g. for undoing them.
+        render_graph.add_node_edge(NCAReadbackLabel, NCAEffectLabel);
+        render_graph.add_node_edge(NCAEffectLabel, bevy::render::graph::CameraDriverLabel);
     }
     
     fn finish(&self, app: &mut App) {
         let render_app = app.sub_app_mut(RenderApp);
         render_app.init_resource::<NCAPipeline>();
+        render_app.init_resource::<NCAStepBuffer>();
         render_app.init_resource::<NCADrawPipeline>();
     }
-}
\ No newline at end of file
+}
+// ================================ Resources =================================== //
+
+/// A resource in the render world holding the draw order the render graph is built
+/// for.
+#[derive(Resource, Debug, Default)]
+struct AppliedDrawOrder(DrawOrder);
+
+// ================================== Systems =================================== //
+
+/// A system that reports loaded or changed shaders which hard-code their workgroup
+/// size, e.g. an edited draw shader. Their workgroups may not cover the canvas.
+fn check_shader_workgroup_sizes(
+    mut ev_reader_shader: EventReader<AssetEvent<Shader>>,
+    shaders: Res<Assets<Shader>>,
+) {
+    for event in ev_reader_shader.read() {
+        let (AssetEvent::Added { id } | AssetEvent::Modified { id }) = event else {
+            continue;
+        };
+        let Some(shader) = shaders.get(*id) else { continue };
+        if let Source::Wgsl(source) = &shader.source {
+            if !derives_workgroup_size(source) {
+                error!(
+                    "Shader {} hard-codes its workgroup size, which may not match the \
+                    dispatched workgroups.",
+                    shader.path,
+                );
+            }
+        }
+    }
+}
+
+/// A system that reverses the edge between the NCA and the draw node in the render
+/// graph when the draw order changes, so that strokes are drawn before or after the
+/// update of the NCA.
+fn apply_draw_order(
+    draw_settings: Res<NCADrawSettings>,
+    mut applied: ResMut<AppliedDrawOrder>,
+    mut render_graph: ResMut<RenderGraph>,
+) {
+    if draw_settings.order == applied.0 {
+        return;
+    }
+    let result = match draw_settings.order {
+        DrawOrder::BeforeUpdate => render_graph
+            .remove_node_edge(NCALabel, NCADrawLabel)
+            .and_then(|_| render_graph.try_add_node_edge(NCADrawLabel, NCALabel)),
+        DrawOrder::AfterUpdate => render_graph
+            .remove_node_edge(NCADrawLabel, NCALabel)
+            .and_then(|_| render_graph.try_add_node_edge(NCALabel, NCADrawLabel)),
+    };
+    if let Err(err) = result {
+        error!("Couldn't change the draw order: {}", err);
+    }
+    applied.0 = draw_settings.order;
+}
@@ -1,13 +1,14 @@
 //! The NCA rendering pipeline
 
 use bevy::{
+    ecs::system::SystemParam,
     prelude::*,
     render::{
         extract_resource::ExtractResource,
         render_asset::RenderAssets,
         render_graph::{Node, NodeRunError, RenderGraphContext, RenderLabel},
         render_resource::*,
-        renderer::{RenderContext, RenderDevice},
+        renderer::{RenderContext, RenderDevice, RenderQueue},
         texture::GpuImage,
         Render,
         RenderSet,
@@ -15,32 +16,26 @@ use bevy::{
 };
 use std::borrow::Cow;
 
-use crate::{SHADER_ASSET_PATH, SIM_SIZE, WORKGROUP_SIZE};
-use super::super::{NCABuffers, ReinitPipeline};
-
-// ================================= Constants ================================== //
-
-/// Holds the NCA filter data for writing to the shader buffer.
-/// 
-/// Due to memory alignment requirements of wgsl, each channel holds 12 float,
-/// even though a filter is a 3x3-matrix with only 9 entries. ...
-#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-#[repr(C)]
-pub struct NCAFilter {
-    pub red: [f32; 12],
-    pub green: [f32; 12],
-    pub blue: [f32; 12],
-}
-
-impl NCAFilter {
-    pub fn empty() -> Self {
-        Self {
-            red: [0., 0., 0., 0., 0., 0., 0., 0., 0., 0., 0., 0.],
-            green: [0., 0., 0., 0., 0., 0., 0., 0., 0., 0., 0., 0.],
-            blue: [0., 0., 0., 0., 0., 0., 0., 0., 0., 0., 0., 0.],
-        }
-    }
-}
+use crate::{MAX_STEPS_PER_FRAME, NCA_SHADER_HANDLE};
+use super::{
+    super::{
+        create_hidden_image,
+        create_image,
+        hidden_image_size,
+        nca_control::{mask::NCAFreezeMask, screenshot::TimeLapse},
+        NCABuffers,
+        NCAInitParams,
+        NCAPipelineMonitor,
+        NCAPipelineState,
+        NCASimSize,
+        NCATextureFormat,
+        NCAWorkgroupSize,
+        ReinitPipeline,
+        SimulationControl,
+        HIDDEN_TEXTURE_FORMAT,
+    },
+    readback::{request_render_readback, ReadbackData, ReadbackPurpose},
+};
 
 // =================================== Plugin =================================== //
 
@@ -50,10 +45,20 @@ pub struct NCAPipelinePlugin;
 impl Plugin for NCAPipelinePlugin {
     fn build(&self, render_app: &mut App) {
         render_app
+            .init_resource::<NCACurrentBindGroup>()
+            .init_resource::<NCAPreviousBindGroup>()
+            .init_resource::<NCADispatch>()
             .add_systems(Render, queue_nca_bind_group.in_set(RenderSet::Queue));
     }
 }
 
+// ================================= Constants ================================== //
+
+/// The workgroup size attribute every compute shader has to use. The size is filled
+/// in from the shader defs of [`workgroup_size_shader_defs`].
+pub const WORKGROUP_SIZE_ATTRIBUTE: &str =
+    "@workgroup_size(#{WORKGROUP_SIZE_X}, #{WORKGROUP_SIZE_Y}, 1)";
+
 // ================================== Pipeline ================================== //
 
 /// A resource holding the rendering pipeline data for the NCA.
@@ -61,68 +66,58 @@ impl Plugin for NCAPipelinePlugin {
 pub struct NCAPipeline {
     init_pipeline: CachedComputePipelineId,
     update_pipeline: CachedComputePipelineId,
-    texture_bind_group_layout: BindGroupLayout,
+    pub(super) texture_bind_group_layout: BindGroupLayout,
+    pub(super) shader: Handle<Shader>,
+    /// The workgroup size the pipelines were built with.
+    workgroup_size: UVec2,
+    /// The texture format the pipelines were built with.
+    pub(super) texture_format: TextureFormat,
 }
 
 impl FromWorld for NCAPipeline {
     fn from_world(world: &mut World) -> Self {
         let render_device = world.resource::<RenderDevice>();
-        
+        let texture_format = world
+            .get_resource::<NCATextureFormat>()
+            .copied()
+            .unwrap_or_default()
+            .format;
+
         let texture_bind_group_layout = render_device.create_bind_group_layout(
             Some("NCA Bind Group Layout"),
-            &[
-                BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::StorageTexture {
-                        access: StorageTextureAccess::ReadOnly,
-                        format: TextureFormat::Rgba8Unorm,
-                        view_dimension: TextureViewDimension::D2,
-                    },
-                    count: None,
-                },
-                BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::StorageTexture {
-                        access: StorageTextureAccess::WriteOnly,
-                        format: TextureFormat::Rgba8Unorm,
-                        view_dimension: TextureViewDimension::D2,
-                    },
-                    count: None,
-                },
-                filter_layout_entry(2),
-                filter_layout_entry(3),
-                filter_layout_entry(4),
-            ],
+            &nca_layout_entries(texture_format),
         );
 
-        let shader = world.load_asset(SHADER_ASSET_PATH);
+        let shader = NCA_SHADER_HANDLE;
+        let workgroup_size = world
+            .get_resource::<NCAWorkgroupSize>()
+            .copied()
+            .unwrap_or_default()
+            .size;
         let pipeline_cache = world.resource::<PipelineCache>();
 
-        let init_pipeline = pipeline_cache.queue_compute_pipeline(
-            ComputePipelineDescriptor {
-                label: Some(std::borrow::Cow::Borrowed("NCA Init Pipeline")),
-                layout: vec![texture_bind_group_layout.clone()],
-                push_constant_ranges: Vec::new(),
-                shader: shader.clone(),
-                shader_defs: vec![],
-                entry_point: Cow::from("init"),
-            }
-        );
-        let update_pipeline = pipeline_cache.queue_compute_pipeline(
-            ComputePipelineDescriptor {
-                label: Some(std::borrow::Cow::Borrowed("NCA Update Pipeline")),
-                layout: vec![texture_bind_group_layout.clone()],
-                push_constant_ranges: Vec::new(),
-                shader,
-                shader_defs: vec![],
-                entry_point: Cow::from("update"),
-            }
-        );
+        let init_pipeline = pipeline_cache.queue_compute_pipeline(nca_pipeline_descriptor(
+            "NCA Init Pipeline",
+            "init",
+            &texture_bind_group_layout,
+            &shader,
+            workgroup_size,
+            texture_format,
+        ));
+        let update_pipeline = pipeline_cache.queue_compute_pipeline(nca_pipeline_descriptor(
+            "NCA Update Pipeline",
+            "update",
+            &texture_bind_group_layout,
+            &shader,
+            workgroup_size,
+            texture_format,
+        ));
 
         Self {
             texture_bind_group_layout,
+            shader,
+            workgroup_size,
+            texture_format,
             init_pipeline,
             update_pipeline,
         }
@@ -131,27 +126,175 @@ impl FromWorld for NCAPipeline {
 
 // ================================== BindGroup ================================== //
 
-/// An asset holding the image handles to the two textures.
+/// An asset holding the image handles to the two textures. The NCA alternates
+/// between reading from one and writing to the other, [`super::super::NCAFrontTexture`]
+/// points to the one holding the current state. The hidden channels alternate
+/// between their own two textures alongside.
 #[derive(Asset, Resource, ExtractResource, TypePath, AsBindGroup, Debug, Clone)]
-pub(crate) struct NCAImages{
+pub struct NCAImages{
     pub texture_a: Handle<Image>,
     pub texture_b: Handle<Image>,
+    pub hidden_a: Handle<Image>,
+    pub hidden_b: Handle<Image>,
+}
+
+impl NCAImages {
+    /// Returns the texture the bind group of the given index writes to. The first
+    /// bind group reads from texture A and writes to texture B, the second one the
+    /// other way around.
+    pub fn written_by(&self, index: usize) -> &Handle<Image> {
+        if index.is_multiple_of(2) {
+            &self.texture_b
+        } else {
+            &self.texture_a
+        }
+    }
+
+    /// Returns the texture of the hidden channels belonging to the given texture,
+    /// i.e. the one written together with it.
+    pub fn hidden_of(&self, texture: &Handle<Image>) -> &Handle<Image> {
+        if *texture == self.texture_b {
+            &self.hidden_b
+        } else {
+            &self.hidden_a
+        }
+    }
+
+    /// Uploads the canvas into both textures, so that the NCA continues from it
+    /// whichever texture is in front. The canvas has to match the size of the
+    /// simulation and the texture format.
+    pub fn upload_canvas(&self, images: &mut Assets<Image>, canvas: ReadbackData) {
+        let mut image = create_image(canvas.size.x, canvas.size.y, canvas.format);
+        image.data = canvas.data;
+        images.insert(&self.texture_a, image.clone());
+        images.insert(&self.texture_b, image);
+    }
+
+    /// Uploads the hidden channels into both of their textures if they were saved
+    /// with the given number of hidden channels of a simulation of the given size.
+    /// Resets them otherwise and returns false.
+    pub fn upload_hidden(
+        &self,
+        images: &mut Assets<Image>,
+        hidden: ReadbackData,
+        sim_size: UVec2,
+        hidden_channels: usize,
+    ) -> bool {
+        let mut image = create_hidden_image(sim_size, hidden_channels);
+        let restored = hidden.size == hidden_image_size(sim_size, hidden_channels)
+            && hidden.format == HIDDEN_TEXTURE_FORMAT;
+        if restored {
+            image.data = hidden.data;
+        }
+        images.insert(&self.hidden_a, image.clone());
+        images.insert(&self.hidden_b, image);
+        restored
+    }
 }
 
 /// A resource holding the two bind groups corresponding to the two textures.
 #[derive(Resource)]
 pub struct NCABindGroup(pub [BindGroup; 2]);
 
+/// A resource in the render world holding the generation of every update step of a
+/// frame as passed to the shader. Each step reads its generation at its own dynamic
+/// offset, so that all steps of a frame can be recorded into a single pass.
+#[derive(Resource)]
+pub struct NCAStepBuffer {
+    buffer: Buffer,
+    /// The distance between the generations of two steps in bytes.
+    stride: u64,
+}
+
+impl FromWorld for NCAStepBuffer {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let stride = render_device.limits().min_uniform_buffer_offset_alignment as u64;
+        let buffer = render_device.create_buffer(&BufferDescriptor {
+            label: Some("NCA Step Params"),
+            size: stride * MAX_STEPS_PER_FRAME as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Self { buffer, stride }
+    }
+}
+
+impl NCAStepBuffer {
+    /// Writes the generations of the given number of steps, starting at the given
+    /// generation. The generation wraps around at the range of a u32.
+    fn write(&self, render_queue: &RenderQueue, generation: u64, steps: u32) {
+        let stride = self.stride as usize;
+        let mut data = vec![0; stride * steps as usize];
+        for step in 0..steps as usize {
+            let value = (generation + step as u64) as u32;
+            data[step * stride..step * stride + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        render_queue.write_buffer(&self.buffer, 0, &data);
+    }
+
+    /// Returns the dynamic offset of the generation of the given step of a frame.
+    pub(super) fn offset(&self, step: u32) -> u32 {
+        (step as u64 * self.stride) as u32
+    }
+}
+
+/// A resource in the render world holding the index of the bind group the NCA used
+/// last. The texture it wrote to holds the current state, which the next update
+/// reads from. Set by the NCA node.
+#[derive(Resource, Debug, Default, Clone, Copy)]
+pub struct NCACurrentBindGroup(pub usize);
+
+/// A resource in the render world holding the index of the bind group the NCA had
+/// used last before the update of the current frame. The texture it wrote to holds
+/// the state the update of the current frame reads from. Set by the NCA node.
+#[derive(Resource, Debug, Default, Clone, Copy)]
+pub struct NCAPreviousBindGroup(pub usize);
+
+/// A resource in the render world holding what the NCA node dispatches in the
+/// current frame. Set by the NCA node.
+#[derive(Resource, Debug, Default, Clone, Copy)]
+pub struct NCADispatch {
+    /// True if the initial state is written in this frame.
+    pub init: bool,
+    /// The number of update steps run in this frame.
+    pub steps: u32,
+}
+
+/// The resources of the render world every bind group of the NCA update pipeline is
+/// created from, i.e. the pipeline with its layout and the buffers of the NCA.
+#[derive(SystemParam)]
+pub(super) struct NCABindGroupResources<'w> {
+    pub(super) render_device: Res<'w, RenderDevice>,
+    pub(super) pipeline: Res<'w, NCAPipeline>,
+    pub(super) buffers: Res<'w, NCABuffers>,
+    pub(super) step_buffer: Res<'w, NCAStepBuffer>,
+}
+
+/// A system that creates the bind groups of the NCA. After the texture format
+/// changed, the old bind groups are kept until the NCA node rebuilt the pipeline
+/// for the new format, as the textures don't match the old layout.
 fn queue_nca_bind_group(
     mut commands: Commands,
-    render_device: Res<RenderDevice>,
-    buffers: Res<NCABuffers>,
-    pipeline: Res<NCAPipeline>,
+    resources: NCABindGroupResources,
     gpu_images: Res<RenderAssets<GpuImage>>,
     game_of_life_image: Res<NCAImages>,
+    mask: Res<NCAFreezeMask>,
+    texture_format: Res<NCATextureFormat>,
 ) {
+    let NCABindGroupResources { render_device, pipeline, buffers, step_buffer } = resources;
+    if texture_format.format != pipeline.texture_format {
+        return;
+    }
     let view_a = gpu_images.get(&game_of_life_image.texture_a).unwrap();
     let view_b = gpu_images.get(&game_of_life_image.texture_b).unwrap();
+    let (Some(hidden_a), Some(hidden_b)) = (
+        gpu_images.get(&game_of_life_image.hidden_a),
+        gpu_images.get(&game_of_life_image.hidden_b),
+    ) else {
+        return;
+    };
+    let Some(mask_view) = gpu_images.get(mask.bound_image()) else { return };
     let bind_group_0 = render_device.create_bind_group(
         None,
         &pipeline.texture_bind_group_layout,
@@ -167,6 +310,14 @@ fn queue_nca_bind_group(
             filter_bind_group_entry(2, &buffers.buffer_red),
             filter_bind_group_entry(3, &buffers.buffer_green),
             filter_bind_group_entry(4, &buffers.buffer_blue),
+            filter_bind_group_entry(5, &buffers.buffer_alpha),
+            filter_bind_group_entry(6, &buffers.buffer_init),
+            step_bind_group_entry(7, &step_buffer),
+            mask_bind_group_entry(8, mask_view),
+            filter_bind_group_entry(9, &buffers.buffer_time),
+            texture_bind_group_entry(10, hidden_a),
+            texture_bind_group_entry(11, hidden_b),
+            filter_bind_group_entry(12, &buffers.buffer_hidden),
         ],
     );
     let bind_group_1 = render_device.create_bind_group(
@@ -184,6 +335,14 @@ fn queue_nca_bind_group(
             filter_bind_group_entry(2, &buffers.buffer_red),
             filter_bind_group_entry(3, &buffers.buffer_green),
             filter_bind_group_entry(4, &buffers.buffer_blue),
+            filter_bind_group_entry(5, &buffers.buffer_alpha),
+            filter_bind_group_entry(6, &buffers.buffer_init),
+            step_bind_group_entry(7, &step_buffer),
+            mask_bind_group_entry(8, mask_view),
+            filter_bind_group_entry(9, &buffers.buffer_time),
+            texture_bind_group_entry(10, hidden_b),
+            texture_bind_group_entry(11, hidden_a),
+            filter_bind_group_entry(12, &buffers.buffer_hidden),
         ],
     );
     commands.insert_resource(NCABindGroup([bind_group_0, bind_group_1]));
@@ -201,30 +360,85 @@ enum NCAState {
     #[default]
     Loading,
     Init,
+    /// Running with the index of the bind group used last.
     Update(usize),
+    /// Waiting for a rebuilt update pipeline, without resetting the textures.
+    Resuming(usize),
 }
 
 /// The NCA node in the rendering graph.
 #[derive(Debug, Default)]
 pub(super) struct NCANode {
     state: NCAState,
+    /// The number of update steps to run in the current frame.
+    steps: u32,
+    /// The number of reinitialization requests handled so far.
+    reinit_requests: u64,
+}
+
+impl NCANode {
+    /// Reports the first compilation error of the init and update pipelines to the
+    /// monitor, or clears it once both pipelines compiled. Shaders that are still
+    /// loading are not an error.
+    fn report_pipeline_errors(
+        pipeline_cache: &PipelineCache,
+        pipeline: &NCAPipeline,
+        monitor: &NCAPipelineMonitor,
+    ) {
+        let states = [pipeline.init_pipeline, pipeline.update_pipeline]
+            .map(|id| pipeline_cache.get_compute_pipeline_state(id));
+        let error = states.iter().find_map(|state| match state {
+            CachedPipelineState::Err(PipelineCacheError::ShaderNotLoaded(_)) => None,
+            CachedPipelineState::Err(err) => Some(err.to_string()),
+            _ => None,
+        });
+        if let Some(error) = error {
+            if monitor.error().as_ref() != Some(&error) {
+                warn!("NCA pipeline failed to compile: {}", error);
+                monitor.set_error(Some(error));
+            }
+        } else if states.iter().all(|state| matches!(state, CachedPipelineState::Ok(_))) {
+            monitor.set_error(None);
+        }
+    }
 }
 
 impl Node for NCANode {
     fn update(&mut self, world: &mut World) {
-        
-        let reinit = world.resource::<ReinitPipeline>().reinit;
-        if reinit {
+        // Requests made while the pipeline is still loading restart the loading, the
+        // NCA is initialized once the pipeline is ready.
+        let reinit_requests = world.resource::<ReinitPipeline>().requests();
+        if reinit_requests != self.reinit_requests {
             info!("Reinitializing NCA pipeline.");
+            self.reinit_requests = reinit_requests;
             world.init_resource::<NCAPipeline>();
+            world.resource::<NCAPipelineMonitor>().reinitialized();
             self.state = NCAState::Loading;
         }
 
-        let reinit = &mut world.resource_mut::<ReinitPipeline>().reinit;
-        *reinit = false;
+        let workgroup_size = world.resource::<NCAWorkgroupSize>().size;
+        let texture_format = world.resource::<NCATextureFormat>().format;
+        let pipeline = world.resource::<NCAPipeline>();
+        if workgroup_size != pipeline.workgroup_size
+            || texture_format != pipeline.texture_format
+        {
+            info!(
+                "Rebuilding NCA pipeline with workgroup size {} and texture format {:?}.",
+                workgroup_size,
+                texture_format,
+            );
+            let pipeline = NCAPipeline::from_world(world);
+            world.insert_resource(pipeline);
+            if let NCAState::Update(index) = self.state {
+                self.state = NCAState::Resuming(index);
+            }
+        }
 
         let pipeline = world.resource::<NCAPipeline>();
         let pipeline_cache = world.resource::<PipelineCache>();
+        let monitor = world.resource::<NCAPipelineMonitor>();
+        let steps = world.resource::<SimulationControl>().steps;
+        Self::report_pipeline_errors(pipeline_cache, pipeline, monitor);
 
         match self.state {
             NCAState::Loading => {
@@ -240,16 +454,73 @@ impl Node for NCANode {
                     pipeline_cache.get_compute_pipeline_state(pipeline.update_pipeline)
                 {
                     info!("Updated NCA pipeline from initial state.");
-                    self.state = NCAState::Update(1);
+                    // The initial state was written with the first bind group.
+                    self.state = NCAState::Update(0);
+                    monitor.set_state(NCAPipelineState::Running);
                 }
             }
-            NCAState::Update(0) => {
-                self.state = NCAState::Update(1);
+            NCAState::Resuming(index) => {
+                if let CachedPipelineState::Ok(_) =
+                    pipeline_cache.get_compute_pipeline_state(pipeline.update_pipeline)
+                {
+                    info!("Resumed NCA pipeline.");
+                    self.state = NCAState::Update(index);
+                }
+            }
+            NCAState::Update(_) if pipeline_cache
+                .get_compute_pipeline(pipeline.update_pipeline)
+                .is_none() =>
+            {
+                info!("NCA pipeline changed, waiting for it to load.");
+                self.state = NCAState::Loading;
+                monitor.set_state(NCAPipelineState::Loading);
             }
-            NCAState::Update(1) => {
-                self.state = NCAState::Update(0);
+            NCAState::Update(_) => {}
+        }
+
+        self.steps = 0;
+        let mut time_lapse_generation = None;
+        if let NCAState::Update(index) = self.state {
+            self.steps = steps;
+            self.state = NCAState::Update(bind_group_after(index, steps));
+            let generation = monitor.status().generation;
+            let step_buffer = world.resource::<NCAStepBuffer>();
+            let render_queue = world.resource::<RenderQueue>();
+            step_buffer.write(render_queue, generation, steps);
+            let buffers = world.resource::<NCABuffers>();
+            let time = world.resource::<SimulationControl>().time;
+            render_queue.write_buffer(&buffers.buffer_time, 0, bytemuck::bytes_of(&time));
+            monitor.advanced(steps);
+
+            let interval = world.get_resource::<TimeLapse>().map_or(0, |time_lapse| {
+                time_lapse.interval as u64
+            });
+            let next = generation + steps as u64;
+            if interval > 0 && next / interval > generation / interval {
+                time_lapse_generation = Some(next);
             }
-            NCAState::Update(_) => unreachable!(),
+        }
+
+        // The initial state is written with the first bind group.
+        let current = match self.state {
+            NCAState::Update(index) | NCAState::Resuming(index) => index,
+            NCAState::Loading | NCAState::Init => 0,
+        };
+        monitor.set_current_bind_group(current);
+        world.insert_resource(NCACurrentBindGroup(current));
+        // Every step switches the bind group.
+        world.insert_resource(NCAPreviousBindGroup(bind_group_after(current, self.steps)));
+        world.insert_resource(NCADispatch {
+            init: self.state == NCAState::Init,
+            steps: self.steps,
+        });
+
+        // A frame of the time-lapse is read back right after the dispatches that
+        // crossed into a new interval, while the front texture still holds the
+        // generation it is named by.
+        if let Some(generation) = time_lapse_generation {
+            let front = world.resource::<NCAImages>().written_by(current).clone_weak();
+            request_render_readback(world, ReadbackPurpose::TimeLapse { generation }, front);
         }
     }
 
@@ -259,46 +530,49 @@ impl Node for NCANode {
         render_context: &mut RenderContext,
         world: &World,
     ) -> Result<(), NodeRunError> {
-        let reinit = &world.resource::<ReinitPipeline>().reinit;
-        if *reinit {
-            info!("Running, but is paused.");
-            return Ok(());
-        }
-
         let texture_bind_group = &world.resource::<NCABindGroup>().0;
+        let step_buffer = world.resource::<NCAStepBuffer>();
         let pipeline_cache = world.resource::<PipelineCache>();
         let pipeline = world.resource::<NCAPipeline>();
+        let sim_size = world.resource::<NCASimSize>().size;
 
         let mut pass = render_context
             .command_encoder()
             .begin_compute_pass(&ComputePassDescriptor::default());
 
+        let workgroup_count = workgroup_count(sim_size, pipeline.workgroup_size);
         match self.state {
-            NCAState::Loading => {}
+            NCAState::Loading | NCAState::Resuming(_) => {}
             NCAState::Init => {
                 if let Some(init_pipeline) = pipeline_cache.get_compute_pipeline(pipeline.init_pipeline) {
-                    pass.set_bind_group(0, &texture_bind_group[0], &[]);
+                    pass.set_bind_group(0, &texture_bind_group[0], &[step_buffer.offset(0)]);
                     pass.set_pipeline(init_pipeline);
-                    pass.dispatch_workgroups(
-                        SIM_SIZE.0 / WORKGROUP_SIZE,
-                        SIM_SIZE.1 / WORKGROUP_SIZE,
-                        1,
-                    );
+                    pass.dispatch_workgroups(workgroup_count.x, workgroup_count.y, 1);
                 } else {
                     return Ok(());
                 }
             }
             NCAState::Update(index) => {
-                let update_pipeline = pipeline_cache
-                    .get_compute_pipeline(pipeline.update_pipeline)
-                    .unwrap();
-                pass.set_bind_group(0, &texture_bind_group[index], &[]);
+                let Some(update_pipeline) =
+                    pipeline_cache.get_compute_pipeline(pipeline.update_pipeline)
+                else {
+                    return Ok(());
+                };
                 pass.set_pipeline(update_pipeline);
-                pass.dispatch_workgroups(
-                    SIM_SIZE.0 / WORKGROUP_SIZE,
-                    SIM_SIZE.1 / WORKGROUP_SIZE,
-                    1,
-                );
+                // All steps share this pass instead of beginning a pass per step.
+                // Dispatches in one pass still see the writes of the previous one,
+                // so the result is the same. `measure_steps_in_one_pass` compares the
+                // timings of both on the GPU it runs on.
+                //
+                // Alternate the bind groups, so that the last step uses the one of
+                // the current state. The first step dispatched reads the first
+                // generation.
+                for step in (0..self.steps).rev() {
+                    let bind_group = &texture_bind_group[bind_group_after(index, step)];
+                    let offset = step_buffer.offset(self.steps - 1 - step);
+                    pass.set_bind_group(0, bind_group, &[offset]);
+                    pass.dispatch_workgroups(workgroup_count.x, workgroup_count.y, 1);
+                }
             }
         }
 
@@ -308,26 +582,522 @@ impl Node for NCANode {
 
 // =================================== Utils ==================================== //
 
-/// Creates a BindGroupEntry for one NCA filter for passing to the shader.
-fn filter_bind_group_entry(binding: u32, buffer: &Buffer) -> BindGroupEntry<'_> {
+/// Creates the descriptor of an NCA compute pipeline with the given entry point,
+/// workgroup size and texture format.
+pub(super) fn nca_pipeline_descriptor(
+    label: &'static str,
+    entry_point: &'static str,
+    layout: &BindGroupLayout,
+    shader: &Handle<Shader>,
+    workgroup_size: UVec2,
+    texture_format: TextureFormat,
+) -> ComputePipelineDescriptor {
+    ComputePipelineDescriptor {
+        label: Some(Cow::from(label)),
+        layout: vec![layout.clone()],
+        push_constant_ranges: Vec::new(),
+        shader: shader.clone(),
+        shader_defs: nca_shader_defs(workgroup_size, texture_format),
+        entry_point: Cow::from(entry_point),
+    }
+}
+
+/// Returns the shader defs the NCA shader is compiled with for the given workgroup
+/// size and texture format.
+pub fn nca_shader_defs(workgroup_size: UVec2, texture_format: TextureFormat) -> Vec<ShaderDefVal> {
+    let mut shader_defs = workgroup_size_shader_defs(workgroup_size);
+    shader_defs.extend(texture_format_shader_defs(texture_format));
+    shader_defs
+}
+
+/// Resolves the shader defs in a WGSL source like the shader preprocessor does,
+/// producing plain WGSL. Keeps only the active branches of `#ifdef`, `#ifndef` and
+/// `#else` blocks and fills in the `#{NAME}` placeholders with the values of the
+/// defs. Used to check and export the NCA shader as it runs.
+pub fn resolve_shader_defs(source: &str, shader_defs: &[ShaderDefVal]) -> String {
+    let defined = |name: &str| shader_defs.iter().any(|def| match def {
+        ShaderDefVal::Bool(def, value) => def == name && *value,
+        ShaderDefVal::Int(def, _) | ShaderDefVal::UInt(def, _) => def == name,
+    });
+    let substitute = |line: &str| shader_defs.iter().fold(line.to_owned(), |line, def| {
+        let (name, value) = match def {
+            ShaderDefVal::Bool(name, value) => (name, value.to_string()),
+            ShaderDefVal::Int(name, value) => (name, value.to_string()),
+            ShaderDefVal::UInt(name, value) => (name, value.to_string()),
+        };
+        line.replace(&format!("#{{{name}}}"), &value)
+    });
+
+    // Whether each enclosing block is in its active branch.
+    let mut branches: Vec<bool> = Vec::new();
+    let mut resolved = String::with_capacity(source.len());
+    for line in source.lines() {
+        let directive = line.trim();
+        if let Some(name) = directive.strip_prefix("#ifdef ") {
+            branches.push(defined(name.trim()));
+        } else if let Some(name) = directive.strip_prefix("#ifndef ") {
+            branches.push(!defined(name.trim()));
+        } else if directive == "#else" {
+            if let Some(branch) = branches.last_mut() {
+                *branch = !*branch;
+            }
+        } else if directive == "#endif" {
+            branches.pop();
+        } else if branches.iter().all(|branch| *branch) {
+            resolved += &substitute(line);
+            resolved.push('\n');
+        }
+    }
+    resolved
+}
+
+/// Returns the shader defs filling in the [`WORKGROUP_SIZE_ATTRIBUTE`] of the
+/// shaders. Pipelines are dispatched with the same size, so that the workgroup
+/// count always matches the attribute.
+pub(super) fn workgroup_size_shader_defs(workgroup_size: UVec2) -> Vec<ShaderDefVal> {
+    vec![
+        ShaderDefVal::UInt("WORKGROUP_SIZE_X".into(), workgroup_size.x),
+        ShaderDefVal::UInt("WORKGROUP_SIZE_Y".into(), workgroup_size.y),
+    ]
+}
+
+/// Returns true if all compute entry points of a WGSL shader take their workgroup
+/// size from the shader defs, i.e. use the [`WORKGROUP_SIZE_ATTRIBUTE`].
+pub fn derives_workgroup_size(source: &str) -> bool {
+    source.matches("@workgroup_size(").count()
+        == source.matches(WORKGROUP_SIZE_ATTRIBUTE).count()
+}
+
+/// Returns the shader defs selecting the storage texture format in the shaders.
+/// Rgba8Unorm is used when no def is set.
+pub(super) fn texture_format_shader_defs(texture_format: TextureFormat) -> Vec<ShaderDefVal> {
+    match texture_format {
+        TextureFormat::Rgba16Float => vec!["TEXTURE_FORMAT_RGBA16FLOAT".into()],
+        _ => Vec::new(),
+    }
+}
+
+/// Returns the index of the bind group used last after the given number of update
+/// steps, starting after the bind group of the given index. Every step switches the
+/// bind group, so the texture holding the current state is the one
+/// [`NCAImages::written_by`] the returned index.
+pub fn bind_group_after(index: usize, steps: u32) -> usize {
+    (index + steps as usize) % 2
+}
+
+/// Returns the number of workgroups needed to cover a simulation of the given size.
+/// Rounds up, so that sizes not divisible by the workgroup size are covered as well.
+/// The shaders skip the invocations outside of the textures.
+pub(super) fn workgroup_count(sim_size: UVec2, workgroup_size: UVec2) -> UVec2 {
+    (sim_size + workgroup_size - UVec2::ONE) / workgroup_size
+}
+
+/// Creates a BindGroupEntry for one NCA filter, or another buffer of the NCA, for
+/// passing to the shader.
+pub(super) fn filter_bind_group_entry(binding: u32, buffer: &Buffer) -> BindGroupEntry<'_> {
     BindGroupEntry {
         binding,
         resource: buffer.as_entire_binding(),
     }
 }
 
+/// Creates a BindGroupEntry for the freeze mask of the NCA.
+pub(super) fn mask_bind_group_entry(binding: u32, mask: &GpuImage) -> BindGroupEntry<'_> {
+    BindGroupEntry {
+        binding,
+        resource: BindingResource::TextureView(&mask.texture_view),
+    }
+}
+
+/// Creates a BindGroupEntry for a texture of the hidden channels of the NCA.
+pub(super) fn texture_bind_group_entry(binding: u32, image: &GpuImage) -> BindGroupEntry<'_> {
+    BindGroupEntry {
+        binding,
+        resource: BindingResource::TextureView(&image.texture_view),
+    }
+}
+
+/// Creates a BindGroupEntry for the generation of a single update step, which is
+/// selected by a dynamic offset.
+pub(super) fn step_bind_group_entry(binding: u32, step_buffer: &NCAStepBuffer) -> BindGroupEntry<'_> {
+    BindGroupEntry {
+        binding,
+        resource: BindingResource::Buffer(BufferBinding {
+            buffer: &step_buffer.buffer,
+            offset: 0,
+            size: BufferSize::new(std::mem::size_of::<u32>() as _),
+        }),
+    }
+}
+
+/// Returns the entries of the bind group layout shared by the NCA pipelines for the
+/// given texture format.
+pub(super) fn nca_layout_entries(texture_format: TextureFormat) -> [BindGroupLayoutEntry; 13] {
+    [
+        BindGroupLayoutEntry {
+            binding: 0,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::StorageTexture {
+                access: StorageTextureAccess::ReadOnly,
+                format: texture_format,
+                view_dimension: TextureViewDimension::D2,
+            },
+            count: None,
+        },
+        BindGroupLayoutEntry {
+            binding: 1,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::StorageTexture {
+                access: StorageTextureAccess::WriteOnly,
+                format: texture_format,
+                view_dimension: TextureViewDimension::D2,
+            },
+            count: None,
+        },
+        filter_layout_entry(2),
+        filter_layout_entry(3),
+        filter_layout_entry(4),
+        filter_layout_entry(5),
+        BindGroupLayoutEntry {
+            binding: 6,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: BufferSize::new(std::mem::size_of::<NCAInitParams>() as _),
+            },
+            count: None,
+        },
+        BindGroupLayoutEntry {
+            binding: 7,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: true,
+                min_binding_size: BufferSize::new(std::mem::size_of::<u32>() as _),
+            },
+            count: None,
+        },
+        BindGroupLayoutEntry {
+            binding: 8,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable: false },
+                view_dimension: TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        },
+        BindGroupLayoutEntry {
+            binding: 9,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: BufferSize::new(std::mem::size_of::<f32>() as _),
+            },
+            count: None,
+        },
+        BindGroupLayoutEntry {
+            binding: 10,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::StorageTexture {
+                access: StorageTextureAccess::ReadOnly,
+                format: HIDDEN_TEXTURE_FORMAT,
+                view_dimension: TextureViewDimension::D2,
+            },
+            count: None,
+        },
+        BindGroupLayoutEntry {
+            binding: 11,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::StorageTexture {
+                access: StorageTextureAccess::WriteOnly,
+                format: HIDDEN_TEXTURE_FORMAT,
+                view_dimension: TextureViewDimension::D2,
+            },
+            count: None,
+        },
+        filter_layout_entry(12),
+    ]
+}
+
 /// Creates a BindGroupLayoutEntry for one NCA filter for passing to the shader.
+/// The filters are storage buffers, as their size depends on the kernel size.
 fn filter_layout_entry(binding: u32) -> BindGroupLayoutEntry {
     BindGroupLayoutEntry {
         binding,
         visibility: ShaderStages::COMPUTE,
         ty: BindingType::Buffer { 
-            ty: BufferBindingType::Uniform,
+            ty: BufferBindingType::Storage { read_only: true },
             has_dynamic_offset: false,
-            min_binding_size: BufferSize::new(
-                (std::mem::size_of::<NCAFilter>() / 3) as _,
-            ),
+            min_binding_size: BufferSize::new(std::mem::size_of::<f32>() as _),
         },
         count: None,
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::nca_control::{build_shader_source, settings::NCASettings};
+    use super::*;
+
+    #[test]
+    fn single_steps_agree_with_steps_in_one_frame() {
+        let images = NCAImages {
+            texture_a: Handle::weak_from_u128(1),
+            texture_b: Handle::weak_from_u128(2),
+            hidden_a: Handle::weak_from_u128(3),
+            hidden_b: Handle::weak_from_u128(4),
+        };
+        for start in 0..2 {
+            let mut index = start;
+            for steps in 1..=8 {
+                let previous = images.written_by(index).clone();
+                index = bind_group_after(index, 1);
+                assert_ne!(*images.written_by(index), previous);
+                assert_eq!(index, bind_group_after(start, steps));
+            }
+        }
+        assert_eq!(images.written_by(bind_group_after(0, 0)), &images.texture_b);
+        assert_eq!(images.written_by(bind_group_after(0, 1)), &images.texture_a);
+    }
+
+    /// Runs the given number of update steps of the NCA shader with default
+    /// settings on textures of the given size and returns how long the GPU took,
+    /// taking the median of a few runs. The steps are recorded into a single
+    /// compute pass like in the NCA node, or into a pass per step otherwise.
+    fn time_update_steps(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        sim_size: UVec2,
+        steps: u32,
+        one_pass: bool,
+    ) -> std::time::Duration {
+        let texture_format = TextureFormat::Rgba8Unorm;
+        let workgroup_size = UVec2::splat(8);
+        let source = build_shader_source(&NCASettings::default());
+        let shader_defs = nca_shader_defs(workgroup_size, texture_format);
+        let resolved = resolve_shader_defs(&source, &shader_defs);
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(resolved.into()),
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &nca_layout_entries(texture_format),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: Some(
+                &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: None,
+                    bind_group_layouts: &[&layout],
+                    push_constant_ranges: &[],
+                }),
+            ),
+            module: &module,
+            entry_point: "update",
+            compilation_options: Default::default(),
+        });
+
+        let texture = |size: UVec2, format, usage| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: None,
+                    size: Extent3d {
+                        width: size.x,
+                        height: size.y,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: TextureDimension::D2,
+                    format,
+                    usage,
+                    view_formats: &[],
+                })
+                .create_view(&TextureViewDescriptor::default())
+        };
+        let views =
+            [0, 1].map(|_| texture(sim_size, texture_format, TextureUsages::STORAGE_BINDING));
+        let hidden_size = hidden_image_size(sim_size, 0);
+        let hidden_views = [0, 1].map(|_| {
+            texture(
+                hidden_size,
+                HIDDEN_TEXTURE_FORMAT,
+                TextureUsages::STORAGE_BINDING,
+            )
+        });
+        let mask = texture(
+            UVec2::ONE,
+            TextureFormat::R8Unorm,
+            TextureUsages::TEXTURE_BINDING,
+        );
+        let buffer = |size, usage| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: None,
+                size,
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+        let filter = buffer(256, BufferUsages::STORAGE);
+        let uniform = buffer(256, BufferUsages::UNIFORM);
+        // Every step reads its generation at its own offset, like in the NCA node.
+        let stride = device.limits().min_uniform_buffer_offset_alignment;
+        let step_buffer = buffer(u64::from(stride * steps), BufferUsages::UNIFORM);
+        fn buffer_entry(binding: u32, buffer: &wgpu::Buffer) -> BindGroupEntry<'_> {
+            BindGroupEntry {
+                binding,
+                resource: buffer.as_entire_binding(),
+            }
+        }
+        fn view_entry(binding: u32, view: &wgpu::TextureView) -> BindGroupEntry<'_> {
+            BindGroupEntry {
+                binding,
+                resource: BindingResource::TextureView(view),
+            }
+        }
+        let bind_groups = [(0, 1), (1, 0)].map(|(input, output)| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &layout,
+                entries: &[
+                    view_entry(0, &views[input]),
+                    view_entry(1, &views[output]),
+                    buffer_entry(2, &filter),
+                    buffer_entry(3, &filter),
+                    buffer_entry(4, &filter),
+                    buffer_entry(5, &filter),
+                    buffer_entry(6, &uniform),
+                    BindGroupEntry {
+                        binding: 7,
+                        resource: BindingResource::Buffer(BufferBinding {
+                            buffer: &step_buffer,
+                            offset: 0,
+                            size: BufferSize::new(16),
+                        }),
+                    },
+                    view_entry(8, &mask),
+                    buffer_entry(9, &uniform),
+                    view_entry(10, &hidden_views[input]),
+                    view_entry(11, &hidden_views[output]),
+                    buffer_entry(12, &filter),
+                ],
+            })
+        });
+
+        let workgroup_count = workgroup_count(sim_size, workgroup_size);
+        let mut times: Vec<_> = (0..7)
+            .map(|_| {
+                let mut encoder = device.create_command_encoder(&Default::default());
+                if one_pass {
+                    let mut pass = encoder.begin_compute_pass(&Default::default());
+                    pass.set_pipeline(&pipeline);
+                    for step in 0..steps {
+                        pass.set_bind_group(0, &bind_groups[step as usize % 2], &[step * stride]);
+                        pass.dispatch_workgroups(workgroup_count.x, workgroup_count.y, 1);
+                    }
+                } else {
+                    for step in 0..steps {
+                        let mut pass = encoder.begin_compute_pass(&Default::default());
+                        pass.set_pipeline(&pipeline);
+                        pass.set_bind_group(0, &bind_groups[step as usize % 2], &[step * stride]);
+                        pass.dispatch_workgroups(workgroup_count.x, workgroup_count.y, 1);
+                    }
+                }
+                device.poll(wgpu::Maintain::Wait);
+                let start = std::time::Instant::now();
+                queue.submit([encoder.finish()]);
+                device.poll(wgpu::Maintain::Wait);
+                start.elapsed()
+            })
+            .collect();
+        times.sort();
+        times[times.len() / 2]
+    }
+
+    /// Compares recording the update steps of a frame into one compute pass with a
+    /// pass per step. Run it with `cargo test -- --ignored --nocapture` to see the
+    /// timings, as they depend on the GPU and aren't checked.
+    #[test]
+    #[ignore = "needs a GPU adapter and only measures timings"]
+    fn measure_steps_in_one_pass() {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+        let adapter = bevy::tasks::block_on(
+            instance.request_adapter(&wgpu::RequestAdapterOptions::default()),
+        )
+        .expect("No GPU adapter found.");
+        let descriptor = wgpu::DeviceDescriptor {
+            label: None,
+            required_features: adapter.features(),
+            required_limits: adapter.limits(),
+        };
+        let (device, queue) = bevy::tasks::block_on(adapter.request_device(&descriptor, None))
+            .expect("Couldn't create a device.");
+        println!("Adapter: {:?}", adapter.get_info());
+
+        for sim_size in [UVec2::splat(64), UVec2::splat(512)] {
+            for steps in [1, 4, MAX_STEPS_PER_FRAME] {
+                let per_step = time_update_steps(&device, &queue, sim_size, steps, false);
+                let one_pass = time_update_steps(&device, &queue, sim_size, steps, true);
+                println!(
+                    "{sim_size}, {steps} steps: {per_step:?} with a pass per step, \
+                    {one_pass:?} in one pass ({:.2}x)",
+                    per_step.as_secs_f64() / one_pass.as_secs_f64(),
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn workgroup_count_rounds_up() {
+        // The simulation size, the workgroup size and the expected workgroup count.
+        let cases = [
+            (UVec2::new(1000, 700), UVec2::splat(8), UVec2::new(125, 88)),
+            (UVec2::new(1024, 768), UVec2::splat(16), UVec2::new(64, 48)),
+            (UVec2::new(100, 100), UVec2::new(32, 2), UVec2::new(4, 50)),
+            (UVec2::new(1, 1), UVec2::new(64, 1), UVec2::ONE),
+        ];
+        for (sim_size, workgroup_size, expected) in cases {
+            assert_eq!(
+                workgroup_count(sim_size, workgroup_size),
+                expected,
+                "{sim_size} with workgroups of {workgroup_size}",
+            );
+        }
+    }
+
+    #[test]
+    fn workgroups_cover_sim_size() {
+        let source = build_shader_source(&NCASettings::default());
+        let sim_sizes = [
+            UVec2::new(1000, 700),
+            UVec2::new(1024, 768),
+            UVec2::new(37, 5),
+        ];
+        let workgroup_sizes = [UVec2::splat(8), UVec2::splat(16), UVec2::new(32, 2)];
+        for workgroup_size in workgroup_sizes {
+            for format in NCATextureFormat::SUPPORTED {
+                let shader_defs = nca_shader_defs(workgroup_size, format);
+                let resolved = resolve_shader_defs(&source, &shader_defs);
+                let module = naga::front::wgsl::parse_str(&resolved)
+                    .unwrap_or_else(|err| panic!("{}", err.emit_to_string(&resolved)));
+                for entry_point in &module.entry_points {
+                    let [x, y, _] = entry_point.workgroup_size;
+                    let shader_size = UVec2::new(x, y);
+                    for sim_size in sim_sizes {
+                        let covered = workgroup_count(sim_size, workgroup_size) * shader_size;
+                        assert!(
+                            covered.cmpge(sim_size).all(),
+                            "{} covers {covered} of {sim_size}",
+                            entry_point.name,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,447 @@
+//! Reading textures of the NCA back from the GPU
+
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssets,
+        render_graph::{Node, NodeRunError, RenderGraphContext, RenderLabel},
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice},
+        texture::{GpuImage, TextureFormatPixelInfo},
+        MainWorld,
+        Render,
+        RenderApp,
+        RenderSet,
+    },
+};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+        Mutex,
+    },
+};
+
+// =================================== Plugin =================================== //
+
+/// A plugin to read textures back from the GPU. Textures are requested via the
+/// [`NCAReadback`] resource in the main world, or by the render graph nodes via
+/// [`request_render_readback`]. They are copied to a buffer after
+/// the NCA and drawing passes, mapped without blocking the render world and sent
+/// back to the main world as a [`ReadbackComplete`] event. Every request gets an id
+/// and exactly one ReadbackComplete event carrying either the data or the reason the
+/// readback failed, so consumers never wait for a readback that was dropped.
+pub(super) struct ReadbackPlugin;
+
+impl Plugin for ReadbackPlugin {
+    fn build(&self, app: &mut App) {
+        let results = ReadbackResults::default();
+
+        app
+            .init_resource::<NCAReadback>()
+            .insert_resource(results.clone())
+            .add_event::<ReadbackComplete>()
+            .add_systems(PreUpdate, receive_readbacks);
+
+        app.sub_app_mut(RenderApp)
+            .insert_resource(results)
+            .init_resource::<PendingReadbacks>()
+            .add_systems(ExtractSchedule, extract_readback_requests)
+            .add_systems(Render, (
+                prepare_readback_buffers.in_set(RenderSet::PrepareResources),
+                map_readback_buffers.in_set(RenderSet::Cleanup),
+            ));
+    }
+}
+
+// ================================ Resources =================================== //
+
+/// The purpose of a readback, used by the consumers to pick their results from the
+/// ReadbackComplete events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadbackPurpose {
+    /// A frame of a GIF recording.
+    GifFrame,
+    /// A screenshot saved as PNG.
+    Screenshot,
+    /// A frame of a time-lapse saved as PNG, with the generation the front texture
+    /// held when it was read back.
+    TimeLapse { generation: u64 },
+    /// The state of the NCA to be restored later.
+    State,
+    /// The pixel under the cursor picked as the brush color.
+    Eyedropper,
+    /// A snapshot of the canvas for undoing and redoing brush strokes.
+    History,
+    /// The canvas to be filled with the fill tool.
+    Fill,
+    /// The pixel under the cursor shown by the probe.
+    Probe,
+    /// The canvas to stamp text onto.
+    Stamp,
+    /// A check of the canvas for stagnation.
+    Stagnation,
+}
+
+/// The id of a readback, used by the consumers to match the ReadbackComplete events
+/// to their requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ReadbackId(u64);
+
+impl ReadbackId {
+    /// Returns a new unique id. Ids are shared between the main and the render world,
+    /// so readbacks can be requested from both.
+    pub fn next() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// A request to read a texture back from the GPU.
+#[derive(Debug, Clone)]
+pub struct ReadbackRequest {
+    pub id: ReadbackId,
+    pub purpose: ReadbackPurpose,
+    pub image: Handle<Image>,
+    /// The region of the texture to read back, or None for the whole texture.
+    pub region: Option<URect>,
+}
+
+/// A resource collecting the readback requests of the current frame.
+#[derive(Resource, Debug, Default)]
+pub struct NCAReadback {
+    requests: Vec<ReadbackRequest>,
+}
+
+impl NCAReadback {
+    /// Requests reading back the given image at the end of the current frame.
+    /// Returns the id of the ReadbackComplete event answering the request.
+    pub fn request(&mut self, purpose: ReadbackPurpose, image: Handle<Image>) -> ReadbackId {
+        let id = ReadbackId::next();
+        self.requests.push(ReadbackRequest { id, purpose, image, region: None });
+        id
+    }
+
+    /// Requests reading back a region of the given image at the end of the current
+    /// frame. The region is clipped to the image. Returns the id of the
+    /// ReadbackComplete event answering the request.
+    pub fn request_region(
+        &mut self,
+        purpose: ReadbackPurpose,
+        image: Handle<Image>,
+        region: URect,
+    ) -> ReadbackId {
+        let id = ReadbackId::next();
+        self.requests.push(ReadbackRequest { id, purpose, image, region: Some(region) });
+        id
+    }
+}
+
+/// Results of finished readbacks, shared between the main and the render world.
+#[derive(Resource, Debug, Default, Clone)]
+struct ReadbackResults(Arc<Mutex<Vec<ReadbackComplete>>>);
+
+/// A texture copy waiting to be executed or mapped.
+#[derive(Debug)]
+struct ReadbackCopy {
+    id: ReadbackId,
+    purpose: ReadbackPurpose,
+    image: Handle<Image>,
+    buffer: Buffer,
+    origin: UVec2,
+    size: UVec2,
+    format: TextureFormat,
+    padded_bytes_per_row: u32,
+}
+
+/// The state of mapping a readback buffer. None while mapping, otherwise whether
+/// mapping was successful.
+type MapState = Arc<Mutex<Option<bool>>>;
+
+/// A resource in the render world holding all readbacks in progress.
+#[derive(Resource, Debug, Default)]
+struct PendingReadbacks {
+    /// Requests extracted from the main world without a buffer yet.
+    queued: Vec<ReadbackRequest>,
+    /// Readbacks to be copied to their buffer by the readback node.
+    to_copy: Vec<ReadbackCopy>,
+    /// Readbacks whose buffers are being mapped.
+    in_flight: Vec<(ReadbackCopy, MapState)>,
+    /// Readbacks the readback node couldn't copy, as their image was no longer on
+    /// the GPU.
+    skipped: Mutex<Vec<ReadbackId>>,
+}
+
+// ================================== Events ==================================== //
+
+/// An event sent when a readback has finished or failed. Sent exactly once for
+/// every request.
+#[derive(Event, Debug)]
+pub struct ReadbackComplete {
+    /// The id returned when requesting the readback.
+    pub id: ReadbackId,
+    pub purpose: ReadbackPurpose,
+    pub result: Result<ReadbackData, ReadbackError>,
+}
+
+/// The pixels of a texture read back from the GPU. Also holds the textures saved
+/// to be restored later, e.g. in the history or in state files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadbackData {
+    /// The size of the read back region in pixels.
+    pub size: UVec2,
+    /// The format of the texture.
+    pub format: TextureFormat,
+    /// The pixel data, row by row without padding.
+    pub data: Vec<u8>,
+}
+
+/// The reason a readback failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadbackError {
+    /// The image was not on the GPU when the readback was prepared.
+    NotOnGpu,
+    /// The requested region doesn't overlap the image.
+    OutOfBounds,
+    /// Mapping the readback buffer failed.
+    MapFailed,
+}
+
+impl fmt::Display for ReadbackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            ReadbackError::NotOnGpu => "the image is not on the GPU",
+            ReadbackError::OutOfBounds => "the region is outside of the image",
+            ReadbackError::MapFailed => "the readback buffer couldn't be mapped",
+        };
+        f.write_str(reason)
+    }
+}
+
+// ================================== Systems =================================== //
+
+/// A system that sends the finished readbacks as events in the main world.
+fn receive_readbacks(
+    results: Res<ReadbackResults>,
+    mut ev_writer_readback: EventWriter<ReadbackComplete>,
+) {
+    ev_writer_readback.send_batch(results.0.lock().unwrap().drain(..));
+}
+
+/// A system that moves the readback requests from the main world to the render
+/// world.
+fn extract_readback_requests(
+    mut main_world: ResMut<MainWorld>,
+    mut pending: ResMut<PendingReadbacks>,
+) {
+    if let Some(mut readback) = main_world.get_resource_mut::<NCAReadback>() {
+        pending.queued.append(&mut readback.requests);
+    }
+}
+
+/// A system that creates a buffer for each requested readback.
+fn prepare_readback_buffers(
+    render_device: Res<RenderDevice>,
+    gpu_images: Res<RenderAssets<GpuImage>>,
+    results: Res<ReadbackResults>,
+    mut pending: ResMut<PendingReadbacks>,
+) {
+    for request in std::mem::take(&mut pending.queued) {
+        prepare_readback(request, &render_device, &gpu_images, &results, &mut pending);
+    }
+}
+
+/// A system that maps the buffers copied to in this frame and collects the data of
+/// all buffers that finished mapping. Readbacks skipped by the readback node are
+/// answered with an error instead.
+fn map_readback_buffers(
+    render_device: Res<RenderDevice>,
+    results: Res<ReadbackResults>,
+    mut pending: ResMut<PendingReadbacks>,
+) {
+    let pending = pending.as_mut();
+    let skipped = std::mem::take(pending.skipped.get_mut().unwrap());
+    for copy in pending.to_copy.drain(..) {
+        if skipped.contains(&copy.id) {
+            warn!("Readback of {:?} failed: {}.", copy.purpose, ReadbackError::NotOnGpu);
+            results.0.lock().unwrap().push(ReadbackComplete {
+                id: copy.id,
+                purpose: copy.purpose,
+                result: Err(ReadbackError::NotOnGpu),
+            });
+            continue;
+        }
+        let state = MapState::default();
+        let callback_state = state.clone();
+        copy.buffer.slice(..).map_async(MapMode::Read, move |result| {
+            *callback_state.lock().unwrap() = Some(result.is_ok());
+        });
+        pending.in_flight.push((copy, state));
+    }
+
+    if pending.in_flight.is_empty() {
+        return;
+    }
+    render_device.poll(Maintain::Poll);
+
+    pending.in_flight.retain(|(copy, state)| {
+        let result = match *state.lock().unwrap() {
+            None => return true,
+            Some(false) => {
+                warn!("Readback of {:?} failed: {}.", copy.purpose, ReadbackError::MapFailed);
+                Err(ReadbackError::MapFailed)
+            }
+            Some(true) => {
+                let data = copy.buffer.slice(..).get_mapped_range();
+                let bytes_per_row = copy.size.x as usize * copy.format.pixel_size();
+                let mut pixels = Vec::with_capacity(bytes_per_row * copy.size.y as usize);
+                for row in data.chunks(copy.padded_bytes_per_row as usize) {
+                    pixels.extend_from_slice(&row[..bytes_per_row]);
+                }
+                drop(data);
+                copy.buffer.unmap();
+
+                Ok(ReadbackData { size: copy.size, format: copy.format, data: pixels })
+            }
+        };
+        results.0.lock().unwrap().push(ReadbackComplete {
+            id: copy.id,
+            purpose: copy.purpose,
+            result,
+        });
+        false
+    });
+}
+
+// ================================== Nodes ================================== //
+
+/// A label for the node in the rendering graph copying textures to readback
+/// buffers.
+#[derive(RenderLabel, Debug, PartialEq, Eq, Clone, Hash)]
+pub struct NCAReadbackLabel;
+
+/// The node copying the requested textures to their readback buffers.
+#[derive(Debug, Default)]
+pub(super) struct NCAReadbackNode;
+
+impl Node for NCAReadbackNode {
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let pending = world.resource::<PendingReadbacks>();
+        let gpu_images = world.resource::<RenderAssets<GpuImage>>();
+
+        for copy in &pending.to_copy {
+            let Some(gpu_image) = gpu_images.get(&copy.image) else {
+                pending.skipped.lock().unwrap().push(copy.id);
+                continue;
+            };
+            render_context.command_encoder().copy_texture_to_buffer(
+                ImageCopyTexture {
+                    texture: &gpu_image.texture,
+                    mip_level: 0,
+                    origin: Origin3d { x: copy.origin.x, y: copy.origin.y, z: 0 },
+                    aspect: TextureAspect::All,
+                },
+                ImageCopyBuffer {
+                    buffer: &copy.buffer,
+                    layout: ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: Some(copy.padded_bytes_per_row),
+                        rows_per_image: None,
+                    },
+                },
+                Extent3d {
+                    width: copy.size.x,
+                    height: copy.size.y,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        Ok(())
+    }
+}
+
+// =================================== Utils ==================================== //
+
+/// Requests reading back the given image from within the render world at the end of
+/// the current frame. Meant for the render graph nodes, which update after the
+/// requests of the main world were prepared, so that the texture is copied right
+/// after the dispatches of the frame. Returns the id of the ReadbackComplete event
+/// answering the request.
+pub(super) fn request_render_readback(
+    world: &mut World,
+    purpose: ReadbackPurpose,
+    image: Handle<Image>,
+) -> ReadbackId {
+    let id = ReadbackId::next();
+    let request = ReadbackRequest { id, purpose, image, region: None };
+    world.resource_scope(|world, mut pending: Mut<PendingReadbacks>| {
+        prepare_readback(
+            request,
+            world.resource::<RenderDevice>(),
+            world.resource::<RenderAssets<GpuImage>>(),
+            world.resource::<ReadbackResults>(),
+            &mut pending,
+        );
+    });
+    id
+}
+
+/// Helper function to create the buffer of a readback and queue it for copying.
+/// Requests that can't be read back are answered with an error right away.
+fn prepare_readback(
+    request: ReadbackRequest,
+    render_device: &RenderDevice,
+    gpu_images: &RenderAssets<GpuImage>,
+    results: &ReadbackResults,
+    pending: &mut PendingReadbacks,
+) {
+    let fail = |error: ReadbackError| {
+        warn!("Readback of {:?} failed: {}.", request.purpose, error);
+        results.0.lock().unwrap().push(ReadbackComplete {
+            id: request.id,
+            purpose: request.purpose,
+            result: Err(error),
+        });
+    };
+
+    let Some(gpu_image) = gpu_images.get(&request.image) else {
+        fail(ReadbackError::NotOnGpu);
+        return;
+    };
+
+    let texture = URect::from_corners(UVec2::ZERO, gpu_image.size);
+    let region = request
+        .region
+        .map_or(texture, |region| region.intersect(texture));
+    if region.is_empty() {
+        fail(ReadbackError::OutOfBounds);
+        return;
+    }
+
+    let bytes_per_row = region.width() as usize * gpu_image.texture_format.pixel_size();
+    let padded_bytes_per_row = RenderDevice::align_copy_bytes_per_row(bytes_per_row);
+    let buffer = render_device.create_buffer(&BufferDescriptor {
+        label: Some("NCA Readback Buffer"),
+        size: (padded_bytes_per_row * region.height() as usize) as u64,
+        usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    pending.to_copy.push(ReadbackCopy {
+        id: request.id,
+        purpose: request.purpose,
+        image: request.image,
+        buffer,
+        origin: region.min,
+        size: region.size(),
+        format: gpu_image.texture_format,
+        padded_bytes_per_row: padded_bytes_per_row as u32,
+    });
+}
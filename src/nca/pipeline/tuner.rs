@@ -0,0 +1,512 @@
+//! Benchmark-driven tuning of the workgroup size of the NCA
+
+use bevy::{
+    prelude::*,
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_resource::*,
+        renderer::{RenderDevice, RenderQueue},
+        Render,
+        RenderApp,
+        RenderSet,
+    },
+};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use super::super::{
+    hidden_image_size,
+    nca_control::settings::NCASettings,
+    NCABuffers,
+    NCASimSize,
+    NCAWorkgroupSize,
+    HIDDEN_TEXTURE_FORMAT,
+};
+use super::nca::{
+    filter_bind_group_entry,
+    nca_pipeline_descriptor,
+    step_bind_group_entry,
+    workgroup_count,
+    NCABindGroupResources,
+    NCAPipeline,
+    NCAStepBuffer,
+};
+
+// ================================= Constants ================================== //
+
+/// The workgroup sizes tried by the tuner. Sizes exceeding the limits of the GPU are
+/// skipped.
+const WORKGROUP_CANDIDATES: [(u32, u32); 12] = [
+    (4, 4),
+    (8, 4),
+    (4, 8),
+    (8, 8),
+    (16, 4),
+    (16, 8),
+    (32, 4),
+    (32, 8),
+    (64, 2),
+    (64, 4),
+    (128, 1),
+    (256, 1),
+];
+
+/// The number of update steps timed for each workgroup size.
+const BENCHMARK_DISPATCHES: u32 = 64;
+
+// =================================== Plugin =================================== //
+
+/// A plugin to find the fastest workgroup size for the NCA on the current GPU. On
+/// the [`AutoTuneWorkgroupSize`] event, the update pipeline is built for a number
+/// of workgroup sizes in the render world. Each is timed over a fixed number of
+/// dispatches on scratch textures and the fastest one is applied to the
+/// [`NCAWorkgroupSize`] resource.
+pub(super) struct WorkgroupTunerPlugin;
+
+impl Plugin for WorkgroupTunerPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<WorkgroupTuner>()
+            .add_event::<AutoTuneWorkgroupSize>()
+            .add_plugins(ExtractResourcePlugin::<WorkgroupTuner>::default())
+            .add_systems(Update, (
+                on_auto_tune_workgroup_size,
+                share_hidden_channels,
+                apply_tuning_results,
+            ).chain());
+
+        app.sub_app_mut(RenderApp)
+            .init_resource::<WorkgroupBenchmark>()
+            .add_systems(Render, run_workgroup_benchmark.in_set(RenderSet::Cleanup));
+    }
+}
+
+// ================================ Resources =================================== //
+
+/// The state of the workgroup size tuner.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TunerState {
+    #[default]
+    Idle,
+    /// Tuning was requested, but the render world has not started yet.
+    Requested,
+    /// The workgroup sizes are being benchmarked.
+    Running,
+    /// All workgroup sizes were benchmarked and the results wait to be applied.
+    Finished,
+}
+
+/// The result of benchmarking one workgroup size.
+#[derive(Debug, Clone, Copy)]
+pub struct WorkgroupBenchmarkResult {
+    pub workgroup_size: UVec2,
+    /// The time the update steps took, or None if the pipeline failed to build.
+    pub time: Option<Duration>,
+}
+
+/// The status of the workgroup size tuner.
+#[derive(Debug, Default, Clone)]
+pub struct TunerStatus {
+    pub state: TunerState,
+    /// The number of workgroup sizes being benchmarked.
+    pub candidates: usize,
+    /// The results of the workgroup sizes benchmarked so far.
+    pub results: Vec<WorkgroupBenchmarkResult>,
+    /// True if the benchmark was aborted because the canvas changed.
+    pub aborted: bool,
+    /// The number of hidden channels of the NCA, which sets the size of the scratch
+    /// textures of the hidden channels.
+    pub hidden_channels: usize,
+}
+
+impl TunerStatus {
+    /// Returns the fastest workgroup size benchmarked so far.
+    pub fn fastest(&self) -> Option<UVec2> {
+        self.results
+            .iter()
+            .filter_map(|result| Some((result.workgroup_size, result.time?)))
+            .min_by_key(|(_, time)| *time)
+            .map(|(workgroup_size, _)| workgroup_size)
+    }
+}
+
+/// A resource to run the workgroup size tuner. The status is shared with the render
+/// world, which runs the benchmarks and reports the results back.
+#[derive(Resource, ExtractResource, Debug, Default, Clone)]
+pub struct WorkgroupTuner {
+    status: Arc<Mutex<TunerStatus>>,
+}
+
+impl WorkgroupTuner {
+    /// Returns the status of the tuner.
+    pub fn status(&self) -> TunerStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Returns true if the tuner is not running.
+    pub fn is_idle(&self) -> bool {
+        self.status.lock().unwrap().state == TunerState::Idle
+    }
+}
+
+/// A resource in the render world holding the benchmark in progress.
+#[derive(Resource, Default)]
+struct WorkgroupBenchmark {
+    /// The workgroup sizes still to benchmark.
+    queued: VecDeque<UVec2>,
+    /// The workgroup size being benchmarked and its pipeline.
+    current: Option<(UVec2, CachedComputePipelineId)>,
+    /// The scratch textures the workgroup sizes are benchmarked on.
+    canvas: Option<BenchmarkCanvas>,
+}
+
+/// The scratch textures of a benchmark, together with the layout, texture format,
+/// simulation size and number of hidden channels they were created for.
+struct BenchmarkCanvas {
+    layout: BindGroupLayout,
+    texture_format: TextureFormat,
+    sim_size: UVec2,
+    hidden_channels: usize,
+    /// Bind groups of two scratch textures, so that the simulation is not touched.
+    bind_groups: [BindGroup; 2],
+}
+
+impl BenchmarkCanvas {
+    /// Returns true if the canvas still matches the pipeline, the simulation size
+    /// and the number of hidden channels, i.e. none of them changed since the
+    /// benchmark started.
+    fn matches(&self, pipeline: &NCAPipeline, sim_size: UVec2, hidden_channels: usize) -> bool {
+        self.layout.id() == pipeline.texture_bind_group_layout.id()
+            && self.texture_format == pipeline.texture_format
+            && self.sim_size == sim_size
+            && self.hidden_channels == hidden_channels
+    }
+}
+
+// ================================== Events ==================================== //
+
+/// An event to benchmark a set of workgroup sizes and apply the fastest one.
+#[derive(Event, Debug)]
+pub struct AutoTuneWorkgroupSize;
+
+// ================================== Systems =================================== //
+
+/// System triggered by the AutoTuneWorkgroupSize event. Requests the render world
+/// to start benchmarking if the tuner is idle.
+fn on_auto_tune_workgroup_size(
+    mut ev_reader_auto_tune: EventReader<AutoTuneWorkgroupSize>,
+    tuner: Res<WorkgroupTuner>,
+    settings: Res<NCASettings>,
+) {
+    for _ in ev_reader_auto_tune.read() {
+        let mut status = tuner.status.lock().unwrap();
+        if status.state != TunerState::Idle {
+            warn!("Already tuning the workgroup size.");
+            continue;
+        }
+        info!("Tuning workgroup size.");
+        *status = TunerStatus {
+            state: TunerState::Requested,
+            hidden_channels: settings.hidden.len(),
+            ..default()
+        };
+    }
+}
+
+/// A system that shares the number of hidden channels with the render world, so
+/// that a running benchmark is aborted if it changes.
+fn share_hidden_channels(tuner: Res<WorkgroupTuner>, settings: Res<NCASettings>) {
+    if settings.is_changed() {
+        tuner.status.lock().unwrap().hidden_channels = settings.hidden.len();
+    }
+}
+
+/// A system that applies the fastest workgroup size once the benchmarks finished.
+fn apply_tuning_results(
+    tuner: Res<WorkgroupTuner>,
+    mut workgroup_size: ResMut<NCAWorkgroupSize>,
+) {
+    let mut status = tuner.status.lock().unwrap();
+    if status.state != TunerState::Finished {
+        return;
+    }
+    status.state = TunerState::Idle;
+    if status.aborted {
+        warn!("Aborted tuning the workgroup size, the canvas changed.");
+        return;
+    }
+
+    match status.fastest() {
+        Some(fastest) => {
+            info!("Fastest workgroup size is {}.", fastest);
+            workgroup_size.size = fastest;
+        }
+        None => warn!("None of the workgroup sizes could be benchmarked."),
+    }
+}
+
+/// A system in the render world that benchmarks one workgroup size at a time. Queues
+/// the update pipeline for the next workgroup size and times it once it is built.
+/// Aborts without a result if the texture format, the size of the simulation or the
+/// number of hidden channels changes, as the scratch textures don't match the
+/// pipelines anymore.
+fn run_workgroup_benchmark(
+    tuner: Res<WorkgroupTuner>,
+    mut benchmark: ResMut<WorkgroupBenchmark>,
+    resources: NCABindGroupResources,
+    pipeline_cache: Res<PipelineCache>,
+    sim_size: Res<NCASimSize>,
+    render_queue: Res<RenderQueue>,
+) {
+    let NCABindGroupResources { render_device, pipeline, buffers, step_buffer } = resources;
+    let mut status = tuner.status.lock().unwrap();
+    match status.state {
+        TunerState::Requested => {
+            benchmark.queued = workgroup_candidates(&render_device.limits());
+            benchmark.canvas = Some(BenchmarkCanvas {
+                layout: pipeline.texture_bind_group_layout.clone(),
+                texture_format: pipeline.texture_format,
+                sim_size: sim_size.size,
+                hidden_channels: status.hidden_channels,
+                bind_groups: create_scratch_bind_groups(
+                    &render_device,
+                    &pipeline.texture_bind_group_layout,
+                    &buffers,
+                    &step_buffer,
+                    sim_size.size,
+                    status.hidden_channels,
+                    pipeline.texture_format,
+                ),
+            });
+            status.candidates = benchmark.queued.len();
+            status.state = TunerState::Running;
+        }
+        TunerState::Running => {}
+        TunerState::Idle | TunerState::Finished => return,
+    }
+
+    let benchmark = &mut *benchmark;
+    let Some(canvas) = &benchmark.canvas else { return };
+    if !canvas.matches(&pipeline, sim_size.size, status.hidden_channels) {
+        *benchmark = WorkgroupBenchmark::default();
+        status.results.clear();
+        status.aborted = true;
+        status.state = TunerState::Finished;
+        return;
+    }
+
+    let (workgroup_size, pipeline_id) = match benchmark.current {
+        Some(current) => current,
+        None => {
+            let Some(workgroup_size) = benchmark.queued.pop_front() else {
+                *benchmark = WorkgroupBenchmark::default();
+                status.state = TunerState::Finished;
+                return;
+            };
+            let pipeline_id = pipeline_cache.queue_compute_pipeline(nca_pipeline_descriptor(
+                "NCA Benchmark Pipeline",
+                "update",
+                &canvas.layout,
+                &pipeline.shader,
+                workgroup_size,
+                canvas.texture_format,
+            ));
+            benchmark.current = Some((workgroup_size, pipeline_id));
+            return;
+        }
+    };
+
+    let time = match pipeline_cache.get_compute_pipeline_state(pipeline_id) {
+        CachedPipelineState::Ok(Pipeline::ComputePipeline(compute_pipeline)) => {
+            Some(time_dispatches(
+                &render_device,
+                &render_queue,
+                compute_pipeline,
+                &canvas.bind_groups,
+                canvas.sim_size,
+                workgroup_size,
+            ))
+        }
+        CachedPipelineState::Err(err) => {
+            warn!("Couldn't build pipeline with workgroup size {}: {}", workgroup_size, err);
+            None
+        }
+        _ => return,
+    };
+
+    if let Some(time) = time {
+        info!("Workgroup size {} took {:?}.", workgroup_size, time);
+    }
+    status.results.push(WorkgroupBenchmarkResult { workgroup_size, time });
+    benchmark.current = None;
+}
+
+// =================================== Utils ==================================== //
+
+/// Returns the workgroup sizes that fit into the limits of the GPU.
+fn workgroup_candidates(limits: &WgpuLimits) -> VecDeque<UVec2> {
+    WORKGROUP_CANDIDATES
+        .iter()
+        .map(|&(x, y)| UVec2::new(x, y))
+        .filter(|size| {
+            size.x <= limits.max_compute_workgroup_size_x
+                && size.y <= limits.max_compute_workgroup_size_y
+                && size.x * size.y <= limits.max_compute_invocations_per_workgroup
+        })
+        .collect()
+}
+
+/// Returns the sizes of the scratch textures of a benchmark, two of the simulation
+/// size followed by two sized like the textures of the hidden channels.
+fn scratch_texture_sizes(sim_size: UVec2, hidden_channels: usize) -> [UVec2; 4] {
+    let hidden_size = hidden_image_size(sim_size, hidden_channels);
+    [sim_size, sim_size, hidden_size, hidden_size]
+}
+
+/// Creates two scratch textures of the simulation size and texture format, along
+/// with two for the hidden channels, and returns bind groups reading from one and
+/// writing to the other. No cell is frozen during the benchmark.
+fn create_scratch_bind_groups(
+    render_device: &RenderDevice,
+    layout: &BindGroupLayout,
+    buffers: &NCABuffers,
+    step_buffer: &NCAStepBuffer,
+    sim_size: UVec2,
+    hidden_channels: usize,
+    texture_format: TextureFormat,
+) -> [BindGroup; 2] {
+    let formats = [
+        texture_format,
+        texture_format,
+        HIDDEN_TEXTURE_FORMAT,
+        HIDDEN_TEXTURE_FORMAT,
+    ];
+    let sizes = scratch_texture_sizes(sim_size, hidden_channels);
+    let [view_a, view_b, hidden_a, hidden_b] = [0, 1, 2, 3].map(|i| {
+        render_device
+            .create_texture(&TextureDescriptor {
+                label: Some("NCA Benchmark Texture"),
+                size: Extent3d {
+                    width: sizes[i].x,
+                    height: sizes[i].y,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: formats[i],
+                usage: TextureUsages::STORAGE_BINDING,
+                view_formats: &[],
+            })
+            .create_view(&TextureViewDescriptor::default())
+    });
+    let empty_mask = render_device
+        .create_texture(&TextureDescriptor {
+            label: Some("NCA Benchmark Mask"),
+            size: Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::R8Unorm,
+            usage: TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        })
+        .create_view(&TextureViewDescriptor::default());
+
+    [
+        (&view_a, &view_b, &hidden_a, &hidden_b),
+        (&view_b, &view_a, &hidden_b, &hidden_a),
+    ]
+    .map(|(view_in, view_out, hidden_in, hidden_out)| {
+        render_device.create_bind_group(
+            Some("NCA Benchmark Bind Group"),
+            layout,
+            &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(view_in),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(view_out),
+                },
+                filter_bind_group_entry(2, &buffers.buffer_red),
+                filter_bind_group_entry(3, &buffers.buffer_green),
+                filter_bind_group_entry(4, &buffers.buffer_blue),
+                filter_bind_group_entry(5, &buffers.buffer_alpha),
+                filter_bind_group_entry(6, &buffers.buffer_init),
+                step_bind_group_entry(7, step_buffer),
+                BindGroupEntry {
+                    binding: 8,
+                    resource: BindingResource::TextureView(&empty_mask),
+                },
+                filter_bind_group_entry(9, &buffers.buffer_time),
+                BindGroupEntry {
+                    binding: 10,
+                    resource: BindingResource::TextureView(hidden_in),
+                },
+                BindGroupEntry {
+                    binding: 11,
+                    resource: BindingResource::TextureView(hidden_out),
+                },
+                filter_bind_group_entry(12, &buffers.buffer_hidden),
+            ],
+        )
+    })
+}
+
+/// Runs a number of update steps with the given pipeline and returns the time it
+/// took the GPU to finish them. Waits for all previous work first.
+fn time_dispatches(
+    render_device: &RenderDevice,
+    render_queue: &RenderQueue,
+    pipeline: &ComputePipeline,
+    bind_groups: &[BindGroup; 2],
+    sim_size: UVec2,
+    workgroup_size: UVec2,
+) -> Duration {
+    let workgroup_count = workgroup_count(sim_size, workgroup_size);
+    let mut encoder = render_device.create_command_encoder(&CommandEncoderDescriptor {
+        label: Some("NCA Benchmark Encoder"),
+    });
+    {
+        let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor::default());
+        pass.set_pipeline(pipeline);
+        for step in 0..BENCHMARK_DISPATCHES {
+            pass.set_bind_group(0, &bind_groups[step as usize % 2], &[0]);
+            pass.dispatch_workgroups(workgroup_count.x, workgroup_count.y, 1);
+        }
+    }
+
+    render_device.poll(Maintain::Wait);
+    let start = Instant::now();
+    render_queue.submit([encoder.finish()]);
+    render_device.poll(Maintain::Wait);
+    start.elapsed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nca::{create_hidden_image, nca_control::settings::MAX_HIDDEN_CHANNELS};
+
+    #[test]
+    fn scratch_textures_match_the_hidden_textures() {
+        for sim_size in [UVec2::new(64, 48), UVec2::new(7, 13)] {
+            for hidden_channels in 0..=MAX_HIDDEN_CHANNELS {
+                let [canvas_a, canvas_b, hidden_a, hidden_b] =
+                    scratch_texture_sizes(sim_size, hidden_channels);
+                let hidden = create_hidden_image(sim_size, hidden_channels).size();
+                assert_eq!([canvas_a, canvas_b], [sim_size; 2]);
+                assert_eq!([hidden_a, hidden_b], [hidden; 2], "{hidden_channels} hidden channels");
+            }
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! UI for key bindings
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use super::super::nca_control::{
+    bindings::{Action, Binding, KeyBindings},
+    preferences::{SavePreferences, UIPreferences},
+};
+use super::ui_visible;
+
+// =================================== Plugin =================================== //
+
+/// A plugin providing a UI window to bind the actions of the app to other keys or
+/// mouse buttons.
+pub(super) struct UIBindingsPlugin;
+
+impl Plugin for UIBindingsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Update, key_bindings_ui.run_if(ui_visible));
+    }
+}
+
+// ================================== Systems =================================== //
+
+/// A system that creates and manages the UI window for key bindings. Clicking the
+/// binding of an action waits for the next key or mouse button to bind it to,
+/// Escape cancels. Actions bound to the same key or mouse button are listed as
+/// conflicts.
+fn key_bindings_ui(
+    mut contexts: EguiContexts,
+    mut preferences: ResMut<UIPreferences>,
+    keys: Res<ButtonInput<KeyCode>>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    mut ev_writer_save_preferences: EventWriter<SavePreferences>,
+    mut listening: Local<Option<Action>>,
+) {
+    if let Some(action) = *listening {
+        if keys.just_pressed(KeyCode::Escape) {
+            *listening = None;
+        } else if let Some(binding) = Binding::just_pressed(&keys, &mouse) {
+            preferences.key_bindings.set(action, binding);
+            *listening = None;
+            for (first, second) in preferences.key_bindings.conflicts() {
+                warn!(
+                    "{} and {} are both bound to {}.",
+                    first.name(),
+                    second.name(),
+                    preferences.key_bindings.get(first),
+                );
+            }
+            ev_writer_save_preferences.send(SavePreferences);
+        }
+    }
+
+    egui::Window::new("Key Bindings").show(contexts.ctx_mut(), |ui| {
+        egui::Grid::new("Key Bindings Grid")
+            .num_columns(2)
+            .spacing([40.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                for action in Action::ALL {
+                    ui.label(action.name());
+                    let text = if *listening == Some(action) {
+                        String::from("Press a key...")
+                    } else {
+                        preferences.key_bindings.get(action).to_string()
+                    };
+                    if ui.button(text).on_hover_text("Escape cancels.").clicked() {
+                        *listening = Some(action);
+                    }
+                    ui.end_row();
+                }
+            });
+
+        for (first, second) in preferences.key_bindings.conflicts() {
+            ui.colored_label(
+                egui::Color32::YELLOW,
+                format!(
+                    "{} and {} are both bound to {}.",
+                    first.name(),
+                    second.name(),
+                    preferences.key_bindings.get(first),
+                ),
+            );
+        }
+
+        if ui.button("Reset To Defaults").clicked() {
+            preferences.key_bindings = KeyBindings::default();
+            *listening = None;
+            ev_writer_save_preferences.send(SavePreferences);
+        }
+    });
+}
@@ -0,0 +1,79 @@
+//! UI overlay for frame diagnostics
+
+use bevy::{
+    diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin},
+    prelude::*,
+};
+use bevy_egui::{egui, EguiContexts};
+
+// =================================== Plugin =================================== //
+
+/// A plugin providing an overlay with the current frame rate and frame time,
+/// toggled with F3. Hidden by default.
+pub(super) struct UIDiagnosticsPlugin;
+
+impl Plugin for UIDiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<FrameTimeDiagnosticsPlugin>() {
+            app.add_plugins(FrameTimeDiagnosticsPlugin);
+        }
+        app
+            .init_resource::<DiagnosticsOverlay>()
+            .add_systems(Update, (toggle_overlay, diagnostics_ui));
+    }
+}
+
+// ================================ Resources =================================== //
+
+/// A resource holding whether the diagnostics overlay is shown.
+#[derive(Resource, Debug, Default)]
+pub struct DiagnosticsOverlay {
+    pub visible: bool,
+}
+
+// ================================== Systems =================================== //
+
+/// A system that shows or hides the overlay when F3 is pressed.
+fn toggle_overlay(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut overlay: ResMut<DiagnosticsOverlay>,
+) {
+    if keys.just_pressed(KeyCode::F3) {
+        overlay.visible = !overlay.visible;
+    }
+}
+
+/// A system that shows the smoothed frame rate and the average frame time in the
+/// top right corner. The overlay is not interactable, so that it never takes the
+/// pointer input away from drawing.
+fn diagnostics_ui(
+    mut contexts: EguiContexts,
+    overlay: Res<DiagnosticsOverlay>,
+    diagnostics: Res<DiagnosticsStore>,
+) {
+    if !overlay.visible {
+        return;
+    }
+    let fps = diagnostics
+        .get(&FrameTimeDiagnosticsPlugin::FPS)
+        .and_then(|fps| fps.smoothed());
+    let frame_time = diagnostics
+        .get(&FrameTimeDiagnosticsPlugin::FRAME_TIME)
+        .and_then(|frame_time| frame_time.average());
+
+    egui::Area::new(egui::Id::new("Diagnostics Overlay"))
+        .anchor(egui::Align2::RIGHT_TOP, [-10., 10.])
+        .interactable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                ui.monospace(match fps {
+                    Some(fps) => format!("FPS: {fps:.0}"),
+                    None => String::from("FPS: -"),
+                });
+                ui.monospace(match frame_time {
+                    Some(frame_time) => format!("Frame Time: {frame_time:.2} ms"),
+                    None => String::from("Frame Time: -"),
+                });
+            });
+        });
+}
@@ -1,9 +1,34 @@
 //! UI for settings relating to drawing on screen
 
-use bevy::prelude::*;
+use bevy::{ecs::system::SystemParam, prelude::*};
 use bevy_egui::{egui::{self, color_picker::color_edit_button_rgb}, EguiContexts};
+use dialog::DialogBox;
+use std::path::PathBuf;
 
-use super::super::pipeline::draw::NCADrawSettings;
+use super::super::{
+    nca_control::{
+        brush::LoadBrushImage,
+        effects::ApplyEffect,
+        history::{DrawHistory, Redo, Undo},
+        mask::{ClearMask, NCAFreezeMask},
+        replay::{
+            LoadStrokes,
+            PlayStrokes,
+            ReplayState,
+            SaveStrokes,
+            StartStrokeRecording,
+            StopStrokeReplay,
+            StrokeReplay,
+        },
+        stamp::{StampText, TextStampSettings},
+        ClearCanvas,
+    },
+    pipeline::{
+        draw::{DrawOrder, DrawSymmetry, DrawTool, NCADrawSettings, MAX_BRUSH_SIZE},
+        effect::ImageEffect,
+    },
+};
+use super::ui_visible;
 
 // =================================== Plugin =================================== //
 
@@ -13,28 +38,115 @@ pub(super) struct UIDrawPlugin;
 impl Plugin for UIDrawPlugin {
     fn build(&self, app: &mut App) {
         app
-            .add_systems(Update, draw_settings_ui);
+            .add_systems(Update, draw_settings_ui.run_if(ui_visible));
     }
 }
 
+// ================================== Events ==================================== //
+
+/// The event writers to record and replay strokes from the UI.
+#[derive(SystemParam)]
+struct ReplayEvents<'w> {
+    record: EventWriter<'w, StartStrokeRecording>,
+    play: EventWriter<'w, PlayStrokes>,
+    stop: EventWriter<'w, StopStrokeReplay>,
+    save: EventWriter<'w, SaveStrokes>,
+    load: EventWriter<'w, LoadStrokes>,
+}
+
+/// The history of strokes and the event writers to undo and redo them from the UI.
+#[derive(SystemParam)]
+struct HistoryControls<'w> {
+    history: ResMut<'w, DrawHistory>,
+    undo: EventWriter<'w, Undo>,
+    redo: EventWriter<'w, Redo>,
+}
+
+/// The freeze mask and the settings of the text stamp, together with the event
+/// writers to change the canvas from the UI.
+#[derive(SystemParam)]
+struct CanvasControls<'w> {
+    mask: ResMut<'w, NCAFreezeMask>,
+    text_stamp: ResMut<'w, TextStampSettings>,
+    clear_canvas: EventWriter<'w, ClearCanvas>,
+    load_brush_image: EventWriter<'w, LoadBrushImage>,
+    apply_effect: EventWriter<'w, ApplyEffect>,
+    clear_mask: EventWriter<'w, ClearMask>,
+    stamp_text: EventWriter<'w, StampText>,
+}
+
 // ================================== Systems =================================== //
 
 /// A system that creates and manages the UI window for draw settings. Lets the user
-/// change the brush size, type and color.
+/// choose the drawing tool, e.g. the eyedropper to pick the brush color from the
+/// canvas or the fill tool with its tolerance, and change the brush size, type,
+/// color, opacity and hardness, load an image to stamp with the image brush, change
+/// the brush size with the speed of the cursor, draw symmetrically, erase with the
+/// brush, draw before or after the update of the NCA as well as change the size and
+/// color of seeds. Also lets the user undo and redo strokes, clear the canvas,
+/// apply effects like inverting the colors to it, stamp text onto it, freeze the
+/// cells painted with the mask tool and record, replay, save and load strokes.
 fn draw_settings_ui(
     mut contexts: EguiContexts,
     mut draw_params: ResMut<NCADrawSettings>,
+    history: HistoryControls,
+    canvas: CanvasControls,
+    mut ev_writer_replay: ReplayEvents,
+    replay: Res<StrokeReplay>,
+    mut brightness_contrast: Local<Option<[f32; 2]>>,
 ) {
+    let HistoryControls {
+        mut history,
+        undo: mut ev_writer_undo,
+        redo: mut ev_writer_redo,
+    } = history;
+    let CanvasControls {
+        mut mask,
+        mut text_stamp,
+        clear_canvas: mut ev_writer_clear_canvas,
+        load_brush_image: mut ev_writer_load_brush_image,
+        apply_effect: mut ev_writer_apply_effect,
+        clear_mask: mut ev_writer_clear_mask,
+        stamp_text: mut ev_writer_stamp_text,
+    } = canvas;
+    let [brightness, contrast] = brightness_contrast.get_or_insert([0., 1.]);
     egui::Window::new("Draw Settings").show(contexts.ctx_mut(), |ui| {
         egui::Grid::new("Draw Grid")
             .num_columns(2)
             .spacing([40.0, 4.0])
             .striped(true)
             .show(ui, |ui| {
+                egui::ComboBox::from_id_source("Draw Tool")
+                .selected_text(match draw_params.tool {
+                    DrawTool::Brush => "Brush",
+                    DrawTool::Seed => "Seed",
+                    DrawTool::Eyedropper => "Eyedropper",
+                    DrawTool::Fill => "Fill",
+                    DrawTool::Mask => "Mask",
+                })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut draw_params.tool, DrawTool::Brush, "Brush");
+                    ui.selectable_value(&mut draw_params.tool, DrawTool::Seed, "Seed");
+                    ui.selectable_value(
+                        &mut draw_params.tool,
+                        DrawTool::Eyedropper,
+                        "Eyedropper",
+                    )
+                        .on_hover_text("Click the canvas to pick the brush color.");
+                    ui
+                        .selectable_value(&mut draw_params.tool, DrawTool::Fill, "Fill")
+                        .on_hover_text("Click the canvas to fill a region with the brush color.");
+                    ui
+                        .selectable_value(&mut draw_params.tool, DrawTool::Mask, "Mask")
+                        .on_hover_text("Paint the cells to freeze, erase to unfreeze them.");
+                });
+                ui.label("Tool");
+                ui.end_row();
+
                 ui.add(egui::DragValue::new(
                     &mut draw_params.brush_size,
-                    ).range(0..=300).clamp_to_range(true)
-                );
+                    ).range(0.0..=MAX_BRUSH_SIZE).clamp_to_range(true)
+                ).on_hover_text("Ctrl or Shift and the mouse wheel over the canvas");
                 ui.label("Brush Size");
                 ui.end_row();
 
@@ -42,17 +154,334 @@ fn draw_settings_ui(
                 .selected_text(match draw_params.brush_type {
                     0 => "Circle",
                     1 => "Square",
+                    2 => "Ring",
+                    3 => "Triangle",
+                    4 => "Line",
+                    5 => "Image",
+                    6 => "Noise",
+                    7 => "Spot Seed",
+                    8 => "Cross Seed",
+                    9 => "Blob Seed",
                     _ => "",
                 })
                 .show_ui(ui, |ui| {
                     ui.selectable_value(&mut draw_params.brush_type, 0, "Circle");
                     ui.selectable_value(&mut draw_params.brush_type, 1, "Square");
+                    ui.selectable_value(&mut draw_params.brush_type, 2, "Ring");
+                    ui.selectable_value(&mut draw_params.brush_type, 3, "Triangle");
+                    ui.selectable_value(&mut draw_params.brush_type, 4, "Line");
+                    ui.selectable_value(&mut draw_params.brush_type, 5, "Image");
+                    ui.selectable_value(&mut draw_params.brush_type, 6, "Noise");
+                    ui.separator();
+                    ui.selectable_value(&mut draw_params.brush_type, 7, "Spot Seed")
+                        .on_hover_text("A bright dot with a soft ring around it.");
+                    ui.selectable_value(&mut draw_params.brush_type, 8, "Cross Seed")
+                        .on_hover_text("A cross fading out towards the ends of its arms.");
+                    ui.selectable_value(&mut draw_params.brush_type, 9, "Blob Seed")
+                        .on_hover_text("A blob fading out from its center.");
                 });
                 ui.label("Brush Type");
                 ui.end_row();
 
-                color_edit_button_rgb(ui, &mut draw_params.brush_color);
+                ui.add_enabled_ui(draw_params.brush_type == 5, |ui| {
+                    ui.horizontal(|ui| {
+                        if ui.button("Load Brush Image").clicked() {
+                            let path_option = dialog::FileSelection::new("Please choose an image")
+                                .title("Load Brush Image")
+                                .mode(dialog::FileSelectionMode::Open)
+                                .show()
+                                .expect("Couldn't display dialog box.");
+                            if let Some(path) = path_option {
+                                ev_writer_load_brush_image
+                                    .send(LoadBrushImage { path: PathBuf::from(path) });
+                            } else {
+                                info!("Cancelled loading brush image.");
+                            }
+                        }
+                        ui
+                            .add(egui::DragValue::new(&mut draw_params.brush_image_scale)
+                                .range(0.1..=16.)
+                                .speed(0.05))
+                            .on_hover_text("The scale the image is stamped with.");
+                    });
+                });
+                ui.label("Brush Image");
+                ui.end_row();
+
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_id_source("Draw Symmetry")
+                    .selected_text(match draw_params.symmetry {
+                        DrawSymmetry::None => "None",
+                        DrawSymmetry::MirrorX => "Mirror X",
+                        DrawSymmetry::MirrorY => "Mirror Y",
+                        DrawSymmetry::Both => "Both",
+                        DrawSymmetry::Radial(_) => "Radial",
+                    })
+                    .show_ui(ui, |ui| {
+                        let symmetry = &mut draw_params.symmetry;
+                        let copies = match *symmetry {
+                            DrawSymmetry::Radial(copies) => copies,
+                            _ => 6,
+                        };
+                        ui.selectable_value(symmetry, DrawSymmetry::None, "None");
+                        ui.selectable_value(symmetry, DrawSymmetry::MirrorX, "Mirror X");
+                        ui.selectable_value(symmetry, DrawSymmetry::MirrorY, "Mirror Y");
+                        ui.selectable_value(symmetry, DrawSymmetry::Both, "Both");
+                        ui.selectable_value(symmetry, DrawSymmetry::Radial(copies), "Radial");
+                    });
+                    if let DrawSymmetry::Radial(copies) = &mut draw_params.symmetry {
+                        ui
+                            .add(egui::DragValue::new(copies).range(2..=DrawSymmetry::MAX_COPIES))
+                            .on_hover_text("The number of rotated copies.");
+                    }
+                });
+                ui.label("Symmetry")
+                    .on_hover_text("Duplicates strokes and seeds around the center of the canvas.");
+                ui.end_row();
+
+                egui::ComboBox::from_id_source("Draw Order")
+                .selected_text(match draw_params.order {
+                    DrawOrder::BeforeUpdate => "Before Update",
+                    DrawOrder::AfterUpdate => "After Update",
+                })
+                .show_ui(ui, |ui| {
+                    ui
+                        .selectable_value(
+                            &mut draw_params.order,
+                            DrawOrder::BeforeUpdate,
+                            "Before Update",
+                        )
+                        .on_hover_text("Strokes evolve immediately, before they are displayed.");
+                    ui
+                        .selectable_value(
+                            &mut draw_params.order,
+                            DrawOrder::AfterUpdate,
+                            "After Update",
+                        )
+                        .on_hover_text("Strokes are displayed as drawn for a frame.");
+                });
+                ui.label("Draw Order");
+                ui.end_row();
+
+                ui.horizontal(|ui| {
+                    color_edit_button_rgb(ui, &mut draw_params.brush_color);
+                    let channels = draw_params.channel_mask.iter_mut().zip(["R", "G", "B"]);
+                    for (written, name) in channels {
+                        ui.checkbox(written, name);
+                    }
+                })
+                    .response
+                    .on_hover_text("The brush only writes the checked color channels.");
                 ui.label("Brush Color");
+                ui.end_row();
+
+                ui.add(egui::Slider::new(&mut draw_params.brush_opacity, 0.0..=1.0));
+                ui.label("Brush Opacity");
+                ui.end_row();
+
+                ui
+                    .add(egui::Slider::new(&mut draw_params.brush_hardness, 0.0..=1.0))
+                    .on_hover_text("Lower values feather the edge of the circle brush.");
+                ui.label("Brush Hardness");
+                ui.end_row();
+
+                ui
+                    .add(egui::Checkbox::without_text(&mut draw_params.speed_size))
+                    .on_hover_text("Changes the brush size with the speed of the cursor.");
+                ui.label("Speed Size");
+                ui.end_row();
+
+                ui.add_enabled(
+                    draw_params.speed_size,
+                    egui::Slider::new(&mut draw_params.speed_sensitivity, -2.0..=2.0),
+                )
+                    .on_hover_text("Positive values thin fast strokes, negative ones thicken.");
+                ui.label("Speed Sensitivity");
+                ui.end_row();
+
+                ui.add_enabled_ui(draw_params.speed_size, |ui| {
+                    ui.horizontal(|ui| {
+                        let [min, max] = &mut draw_params.speed_size_range;
+                        ui.add(egui::DragValue::new(min).range(1..=300));
+                        ui.label("-");
+                        ui.add(egui::DragValue::new(max).range(1..=300));
+                    });
+                });
+                ui.label("Speed Size Range");
+                ui.end_row();
+
+                ui
+                    .add(egui::Checkbox::without_text(&mut draw_params.erase))
+                    .on_hover_text("Erase with the right mouse button at any time.");
+                ui.label("Erase");
+                ui.end_row();
+
+                color_edit_button_rgb(ui, &mut draw_params.erase_color);
+                ui.label("Erase Color");
+                ui.end_row();
+
+                ui
+                    .add(egui::Slider::new(&mut draw_params.fill_tolerance, 0.0..=1.0))
+                    .on_hover_text("How much the color of a cell may differ to be filled.");
+                ui.label("Fill Tolerance");
+                ui.end_row();
+
+                ui.add(egui::DragValue::new(
+                    &mut draw_params.seed_size,
+                    ).range(0..=300).clamp_to_range(true)
+                );
+                ui.label("Seed Size");
+                ui.end_row();
+
+                color_edit_button_rgb(ui, &mut draw_params.seed_color);
+                ui.label("Seed Color");
+                ui.end_row();
+
+                ui
+                    .button("Clear")
+                    .on_hover_text("Fills the canvas with the background color (Delete)")
+                    .clicked()
+                    .then(|| ev_writer_clear_canvas.send(ClearCanvas));
+                ui.label("Clear Canvas");
+                ui.end_row();
+
+                ui
+                    .add(egui::Checkbox::without_text(&mut mask.enabled))
+                    .on_hover_text("Cells painted with the mask tool keep their values.");
+                ui.label("Freeze Masked Cells");
+                ui.end_row();
+
+                ui
+                    .button("Clear")
+                    .clicked()
+                    .then(|| ev_writer_clear_mask.send(ClearMask));
+                ui.label("Clear Mask");
+            });
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui
+                .add_enabled(history.can_undo(), egui::Button::new("Undo"))
+                .on_hover_text("Ctrl+Z")
+                .clicked()
+                .then(|| ev_writer_undo.send(Undo));
+            ui
+                .add_enabled(history.can_redo(), egui::Button::new("Redo"))
+                .on_hover_text("Ctrl+Y")
+                .clicked()
+                .then(|| ev_writer_redo.send(Redo));
+            let mut depth = history.depth();
+            let changed = ui
+                .add(egui::DragValue::new(&mut depth).range(1..=64))
+                .on_hover_text("Each step holds a copy of the whole canvas.")
+                .changed();
+            if changed {
+                history.set_depth(depth);
+            }
+            ui.label("Undo Steps");
+        });
+        ui.menu_button("Effects", |ui| {
+            let effects = [
+                (ImageEffect::Invert, "Invert Colors"),
+                (ImageEffect::Grayscale, "Grayscale"),
+                (ImageEffect::SwapChannels, "Swap Channels (RGB to GBR)"),
+                (ImageEffect::FlipHorizontal, "Flip Horizontally"),
+                (ImageEffect::FlipVertical, "Flip Vertically"),
+            ];
+            for (effect, name) in effects {
+                if ui.button(name).clicked() {
+                    ev_writer_apply_effect.send(ApplyEffect { effect });
+                    ui.close_menu();
+                }
+            }
+            ui.separator();
+            ui.add(egui::Slider::new(brightness, -1.0..=1.0).text("Brightness"));
+            ui.add(egui::Slider::new(contrast, 0.0..=4.0).text("Contrast"));
+            if ui.button("Adjust Brightness And Contrast").clicked() {
+                ev_writer_apply_effect.send(ApplyEffect {
+                    effect: ImageEffect::BrightnessContrast {
+                        brightness: *brightness,
+                        contrast: *contrast,
+                    },
+                });
+                ui.close_menu();
+            }
+        })
+            .response
+            .on_hover_text("Applies to the current canvas and can be undone.");
+        egui::CollapsingHeader::new("Stroke Replay").show(ui, |ui| {
+            ui.label(match replay.state() {
+                ReplayState::Idle => format!(
+                    "{} frames of strokes over {:.1} s",
+                    replay.frames(),
+                    replay.duration(),
+                ),
+                ReplayState::Recording => format!("Recording... {} frames", replay.frames()),
+                ReplayState::Playing => String::from("Replaying..."),
+            });
+            ui.horizontal(|ui| {
+                let idle = replay.state() == ReplayState::Idle;
+                if ui
+                    .add_enabled(idle, egui::Button::new("Record"))
+                    .on_hover_text("Discards the recorded strokes.")
+                    .clicked()
+                {
+                    ev_writer_replay.record.send(StartStrokeRecording);
+                }
+                if ui
+                    .add_enabled(idle && replay.frames() > 0, egui::Button::new("Play"))
+                    .on_hover_text("Draws the recorded strokes onto the current canvas.")
+                    .clicked()
+                {
+                    ev_writer_replay.play.send(PlayStrokes);
+                }
+                if ui.add_enabled(!idle, egui::Button::new("Stop")).clicked() {
+                    ev_writer_replay.stop.send(StopStrokeReplay);
+                }
+            });
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(replay.frames() > 0, egui::Button::new("Save Strokes"))
+                    .clicked()
+                {
+                    let path_option = dialog::FileSelection::new("Please choose a file")
+                        .title("Save Strokes")
+                        .mode(dialog::FileSelectionMode::Save)
+                        .show()
+                        .expect("Couldn't display dialog box.");
+                    if let Some(path) = path_option {
+                        ev_writer_replay.save.send(SaveStrokes { path: PathBuf::from(path) });
+                    } else {
+                        info!("Cancelled saving strokes.");
+                    }
+                }
+                if ui.button("Load Strokes").clicked() {
+                    let path_option = dialog::FileSelection::new("Please choose strokes")
+                        .title("Load Strokes")
+                        .mode(dialog::FileSelectionMode::Open)
+                        .show()
+                        .expect("Couldn't display dialog box.");
+                    if let Some(path) = path_option {
+                        ev_writer_replay.load.send(LoadStrokes { path: PathBuf::from(path) });
+                    } else {
+                        info!("Cancelled loading strokes.");
+                    }
+                }
+            });
+        });
+        egui::CollapsingHeader::new("Stamp Text").show(ui, |ui| {
+            ui.text_edit_multiline(&mut text_stamp.text);
+            ui
+                .add(egui::Slider::new(&mut text_stamp.size, 7..=350).text("Font Size"))
+                .on_hover_text("The height of a line in cells, rounded to the pixels of the font.");
+            ui.horizontal(|ui| {
+                color_edit_button_rgb(ui, &mut text_stamp.color);
+                ui.label("Text Color");
             });
+            ui
+                .button("Stamp")
+                .on_hover_text("Stamps the text onto the center of the canvas.")
+                .clicked()
+                .then(|| ev_writer_stamp_text.send(StampText));
+        });
     });
 }
\ No newline at end of file
@@ -0,0 +1,201 @@
+//! UI for exporting the NCA
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_egui::{egui, EguiContexts};
+use dialog::DialogBox;
+use std::path::PathBuf;
+
+use super::super::{
+    nca_control::{
+        recording::{GifRecorder, StartGifRecording},
+        screenshot::{ScreenshotSettings, TakeScreenshot, TimeLapse},
+        state::{LoadState, SaveState},
+        ExportShader,
+    },
+    NCASimSize,
+};
+use super::ui_visible;
+
+// =================================== Plugin =================================== //
+
+/// A plugin providing a UI window to export the NCA, e.g. by saving a screenshot,
+/// a time-lapse or recording a GIF, to save and restore its state and to export its
+/// shader.
+pub(super) struct UIExportPlugin;
+
+impl Plugin for UIExportPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Update, export_ui.run_if(ui_visible));
+    }
+}
+
+/// The event writers to export the NCA from the UI.
+#[derive(SystemParam)]
+struct ExportEvents<'w> {
+    start_recording: EventWriter<'w, StartGifRecording>,
+    screenshot: EventWriter<'w, TakeScreenshot>,
+    save_state: EventWriter<'w, SaveState>,
+    load_state: EventWriter<'w, LoadState>,
+    export_shader: EventWriter<'w, ExportShader>,
+}
+
+// ================================== Systems =================================== //
+
+/// A system that creates and manages the UI window for exporting the NCA. Lets the
+/// user save screenshots, set up a time-lapse, change the GIF settings, start a
+/// recording, save or load the state of the NCA and export the generated shader.
+fn export_ui(
+    mut contexts: EguiContexts,
+    mut recorder: ResMut<GifRecorder>,
+    mut screenshots: ResMut<ScreenshotSettings>,
+    mut time_lapse: ResMut<TimeLapse>,
+    sim_size: Res<NCASimSize>,
+    mut events: ExportEvents,
+) {
+    egui::Window::new("Export").show(contexts.ctx_mut(), |ui| {
+        egui::CollapsingHeader::new("Simulation State").show(ui, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Save State").clicked() {
+                    let path_option = dialog::FileSelection::new("Please choose a file")
+                        .title("Save State")
+                        .mode(dialog::FileSelectionMode::Save)
+                        .show()
+                        .expect("Couldn't display dialog box.");
+                    if let Some(path) = path_option {
+                        events.save_state.send(SaveState { path: PathBuf::from(path) });
+                    } else {
+                        info!("Cancelled saving state.");
+                    }
+                }
+                if ui.button("Load State").clicked() {
+                    let path_option = dialog::FileSelection::new("Please choose a state")
+                        .title("Load State")
+                        .mode(dialog::FileSelectionMode::Open)
+                        .show()
+                        .expect("Couldn't display dialog box.");
+                    if let Some(path) = path_option {
+                        events.load_state.send(LoadState { path: PathBuf::from(path) });
+                    } else {
+                        info!("Cancelled loading state.");
+                    }
+                }
+            });
+        });
+        egui::CollapsingHeader::new("Shader").show(ui, |ui| {
+            if ui.button("Export Shader").clicked() {
+                let path_option = dialog::FileSelection::new("Please choose a file")
+                    .title("Export Shader")
+                    .mode(dialog::FileSelectionMode::Save)
+                    .show()
+                    .expect("Couldn't display dialog box.");
+                if let Some(path) = path_option {
+                    events.export_shader.send(ExportShader { path: PathBuf::from(path) });
+                } else {
+                    info!("Cancelled exporting shader.");
+                }
+            }
+            ui.label("Writes the WGSL of the current settings, e.g. for bug reports.");
+        });
+        egui::CollapsingHeader::new("Screenshot").show(ui, |ui| {
+            egui::Grid::new("Screenshot Grid")
+                .num_columns(2)
+                .spacing([40.0, 4.0])
+                .striped(true)
+                .show(ui, |ui| {
+                    let mut directory = screenshots.directory.display().to_string();
+                    if ui.text_edit_singleline(&mut directory).changed() {
+                        screenshots.directory = PathBuf::from(directory);
+                    }
+                    ui.label("Directory");
+                    ui.end_row();
+
+                    ui.text_edit_singleline(&mut screenshots.prefix);
+                    ui.label("File Prefix");
+                    ui.end_row();
+                });
+            if ui.button("Save Screenshot").on_hover_text("F12").clicked() {
+                events.screenshot.send(TakeScreenshot);
+            }
+        });
+        egui::CollapsingHeader::new("Time-Lapse").show(ui, |ui| {
+            egui::Grid::new("Time-Lapse Grid")
+                .num_columns(2)
+                .spacing([40.0, 4.0])
+                .striped(true)
+                .show(ui, |ui| {
+                    let mut enabled = time_lapse.interval > 0;
+                    if ui.add(egui::Checkbox::without_text(&mut enabled)).changed() {
+                        time_lapse.interval = if enabled { 100 } else { 0 };
+                    }
+                    ui.label("Enabled");
+                    ui.end_row();
+
+                    ui.add_enabled(enabled, egui::DragValue::new(&mut time_lapse.interval)
+                        .range(1..=100_000));
+                    ui.label("Generations Per Frame");
+                    ui.end_row();
+
+                    let mut directory = time_lapse.directory.display().to_string();
+                    if ui.text_edit_singleline(&mut directory).changed() {
+                        time_lapse.directory = PathBuf::from(directory);
+                    }
+                    ui.label("Directory");
+                    ui.end_row();
+                });
+            // PNGs of NCAs hardly compress, so estimate with the uncompressed size.
+            let megabytes = sim_size.size.x as f32 * sim_size.size.y as f32 * 4. / 1e6;
+            ui.colored_label(
+                egui::Color32::YELLOW,
+                format!("Each frame takes up to {:.1} MB of disk space.", megabytes),
+            );
+        });
+        egui::CollapsingHeader::new("GIF Recording").show(ui, |ui| {
+            let idle = recorder.is_idle();
+            egui::Grid::new("GIF Grid")
+                .num_columns(2)
+                .spacing([40.0, 4.0])
+                .striped(true)
+                .show(ui, |ui| {
+                    let settings = &mut recorder.settings;
+                    ui.add_enabled(idle, egui::DragValue::new(&mut settings.frame_count)
+                        .range(1..=1000));
+                    ui.label("Frames");
+                    ui.end_row();
+
+                    ui.add_enabled(idle, egui::DragValue::new(&mut settings.delay_ms)
+                        .range(10..=1000)
+                        .suffix(" ms"));
+                    ui.label("Frame Delay");
+                    ui.end_row();
+
+                    ui.add_enabled(idle, egui::DragValue::new(&mut settings.downscale)
+                        .range(1..=16));
+                    ui.label("Downscale Factor");
+                    ui.end_row();
+
+                    ui.add_enabled(idle, egui::Checkbox::without_text(&mut settings.dither));
+                    ui.label("Dithering");
+                    ui.end_row();
+                });
+
+            ui.horizontal(|ui| {
+                if ui.add_enabled(idle, egui::Button::new("Record GIF")).clicked() {
+                    let path_option = dialog::FileSelection::new("Please choose a file")
+                        .title("Save GIF")
+                        .mode(dialog::FileSelectionMode::Save)
+                        .show()
+                        .expect("Couldn't display dialog box.");
+                    if let Some(path) = path_option {
+                        events.start_recording.send(StartGifRecording {
+                            path: PathBuf::from(path),
+                        });
+                    } else {
+                        info!("Cancelled recording GIF.");
+                    }
+                }
+                ui.label(recorder.status());
+            });
+        });
+    });
+}
@@ -1,24 +1,110 @@
 //! UI support
 
+pub mod bindings;
+pub mod diagnostics;
 pub mod draw;
+pub mod export;
 pub mod nca;
+pub mod performance;
+pub mod profiles;
+pub mod stagnation;
+pub mod theme;
+pub mod view;
+pub mod workspaces;
 
 use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use super::nca_control::ConfigWriteError;
 
 // =================================== Plugin =================================== //
 
 /// A plugin that provides a UI (based on Bevy's EGUI integration) to interact with the
-/// NCA. It comprises a UI-plugin for controlling the settings of the NCA itself and
-/// another one to control the settings for drawing on the texture.
+/// NCA. It comprises a UI-plugin for controlling the settings of the NCA itself,
+/// another one to control the settings for drawing on the texture, one to switch
+/// between workspaces, one to switch between settings profiles, one to export the
+/// NCA, one to tune its performance, one to detect when it died out, one to select
+/// the UI theme, one to change the key bindings and one to control the view of the
+/// camera. An overlay with the frame rate can be toggled with F3, all windows can
+/// be hidden with H. Failed writes of the settings or presets file are shown in a
+/// window of their own.
 pub(super) struct UIPlugin;
 
 impl Plugin for UIPlugin {
     fn build(&self, app: &mut App) {
         app
             .add_plugins((
-                bevy_egui::EguiPlugin,
+                bindings::UIBindingsPlugin,
+                diagnostics::UIDiagnosticsPlugin,
                 draw::UIDrawPlugin,
+                export::UIExportPlugin,
                 nca::UINCAPlugin,
+                performance::UIPerformancePlugin,
+                profiles::UIProfilePlugin,
+                stagnation::UIStagnationPlugin,
+                theme::UIThemePlugin,
+                view::UIViewPlugin,
+                workspaces::UIWorkspacePlugin,
+            ))
+            .init_resource::<UIVisibility>()
+            .add_systems(Update, (
+                toggle_ui_visibility,
+                config_write_error_ui.run_if(ui_visible),
             ));
     }
+}
+
+// ================================ Resources =================================== //
+
+/// A resource holding whether the UI windows are shown. Hiding them leaves the
+/// whole canvas for drawing and for clean captures. The windows keep their
+/// positions while hidden.
+#[derive(Resource, Debug)]
+pub struct UIVisibility {
+    pub visible: bool,
+}
+
+impl Default for UIVisibility {
+    fn default() -> Self {
+        Self { visible: true }
+    }
+}
+
+// ================================== Systems =================================== //
+
+/// A system that shows or hides all UI windows when H is pressed, unless the UI is
+/// using the keyboard.
+fn toggle_ui_visibility(
+    mut contexts: EguiContexts,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut visibility: ResMut<UIVisibility>,
+) {
+    if keys.just_pressed(KeyCode::KeyH) && !contexts.ctx_mut().wants_keyboard_input() {
+        visibility.visible = !visibility.visible;
+    }
+}
+
+/// A system that shows the last failed write of the settings or presets file until
+/// the user dismisses it.
+fn config_write_error_ui(
+    mut contexts: EguiContexts,
+    mut write_error: ResMut<ConfigWriteError>,
+) {
+    let Some(message) = write_error.message.clone() else { return };
+    egui::Window::new("Saving Failed")
+        .collapsible(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.label(message);
+            ui.label("The NCA keeps running, but the changes are only kept in memory.");
+            if ui.button("Dismiss").clicked() {
+                write_error.message = None;
+            }
+        });
+}
+
+// =================================== Utils ==================================== //
+
+/// A run condition for the systems showing UI windows.
+fn ui_visible(visibility: Res<UIVisibility>) -> bool {
+    visibility.visible
 }
\ No newline at end of file
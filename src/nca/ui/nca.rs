@@ -1,30 +1,83 @@
 //! UI for settings relating to the NCA
 
-use bevy::prelude::*;
+use bevy::{ecs::system::SystemParam, prelude::*};
 use bevy_egui::{egui, EguiContexts};
 use dialog::DialogBox;
+use std::path::PathBuf;
 
 use super::super::{
     nca_control::{
-        presets::{AddPresetFilter, AddPresetFn, NCAPresets},
-        settings::{NCAChannel, NCASettings, SaveSettings},
+        presets::{
+            AddPresetFilter,
+            AddPresetFn,
+            ExportPresets,
+            ImportPresets,
+            NCAPresets,
+            PresetKind,
+            RemovePresetFilter,
+            RemovePresetFn,
+            RenamePreset,
+        },
+        randomize::FilterRandomizer,
+        settings::{
+            channel_name,
+            BoundaryMode,
+            InitMode,
+            NCAChannel,
+            NCASettings,
+            Neighborhood,
+            OutputMapping,
+            SaveSettings,
+            CHANNELS,
+            MAX_HIDDEN_CHANNELS,
+            MAX_KERNEL_SIZE,
+        },
+        ActivationFnErrors,
+        LastGoodShader,
         Reinitialize,
+        RevertShader,
+        SetHiddenChannels,
+        ShaderWatchdog,
+        StepOnce,
         UpdateActivationFunction,
         UpdateFilter,
     },
-    utils::{array_to_mat3, mat3_to_array}
+    pipeline::compare::{NCACompare, RestartComparison, StartComparison, StopComparison},
+    utils::{flip_kernel, kernel_size, mirror_kernel, rotate_kernel},
+    NCAPipelineMonitor,
+    NCAPipelineState,
+    SimulationControl,
 };
+use super::ui_visible;
+use crate::MAX_STEPS_PER_FRAME;
 
 // =================================== Plugin =================================== //
 
 /// A plugin providing a UI window to control the NCA settings.
 /// The user can:
-///     -change the filter of the NCA in each color channel, which are
-///     displayed as drag values in a 3x3-formation (corresponding to the structure
-///     of the filter matrix).
+///     -change the filters of the NCA in each color channel and the alpha channel,
+///     which are displayed as drag values in an NxN-formation (corresponding to the
+///     structure of the filter matrix) next to a heatmap of the weights, as well as
+///     the size of the filters. Each channel has a filter for every input channel,
+///     one of which is edited at a time.
 ///     -change the activation function by writing a function f32 -> f32 in WGSL
-///     inside a multiline text edit.
-///     -save and load presets for both, filters and activation functions.
+///     inside a multiline text edit, which can use the time t of the NCA.
+///     -fill the filters with random weights from a seeded RNG.
+///     -quantize the output of each channel to a number of discrete levels and
+///     choose whether it is clamped, wrapped, folded or left unclamped.
+///     -choose whether the edges of the simulation wrap around, clamp or are black.
+///     -choose the neighborhood of the cells, the full kernel, a von Neumann
+///     neighborhood or custom cells picked in a grid.
+///     -run a single channel shown in grayscale instead of the three color channels.
+///     -add up to sixteen hidden channels, which take part in the convolution and
+///     activation like the other channels without being displayed as colors.
+///     -save and load presets for both, filters and activation functions, and
+///     export or import them as preset packs.
+///     -pause and resume the simulation, advance it by a single step or change the
+///     number of steps per frame.
+///     -choose the initial state and the seed of the random initial state used when
+///     reinitializing.
+///     -freeze the current rule and compare it side by side to changes of the rule.
 pub(super) struct UINCAPlugin;
 
 impl Plugin for UINCAPlugin {
@@ -33,13 +86,18 @@ impl Plugin for UINCAPlugin {
             .add_event::<FilterChanged>()
             .add_event::<FunctionChanged>()
             .add_systems(Update, (
-                nca_settings_ui,
+                nca_settings_ui.run_if(ui_visible),
                 on_update_filter,
                 on_update_function
             ));
     }
 }
 
+// ================================= Constants ================================== //
+
+/// The side length of the heatmap of a filter in points.
+const HEATMAP_SIZE: f32 = 72.;
+
 // ================================== Events ==================================== //
 
 /// Event sent when an NCA filter was changed in the UI.
@@ -50,51 +108,272 @@ struct FilterChanged;
 #[derive(Event, Debug)]
 struct FunctionChanged;
 
+/// The event writers to manage the presets from the UI.
+#[derive(SystemParam)]
+struct PresetEvents<'w> {
+    add_filter: EventWriter<'w, AddPresetFilter>,
+    add_fn: EventWriter<'w, AddPresetFn>,
+    remove_filter: EventWriter<'w, RemovePresetFilter>,
+    remove_fn: EventWriter<'w, RemovePresetFn>,
+    rename: EventWriter<'w, RenamePreset>,
+    export: EventWriter<'w, ExportPresets>,
+    import: EventWriter<'w, ImportPresets>,
+}
+
+/// The event writers to control the simulation from the UI.
+#[derive(SystemParam)]
+struct SimulationEvents<'w> {
+    reinitialize: EventWriter<'w, Reinitialize>,
+    revert_shader: EventWriter<'w, RevertShader>,
+    set_hidden_channels: EventWriter<'w, SetHiddenChannels>,
+    step_once: EventWriter<'w, StepOnce>,
+    start_comparison: EventWriter<'w, StartComparison>,
+    restart_comparison: EventWriter<'w, RestartComparison>,
+    stop_comparison: EventWriter<'w, StopComparison>,
+}
+
+/// The control of the simulation and the comparison, together with the event
+/// writers to change them from the UI.
+#[derive(SystemParam)]
+struct SimulationControls<'w> {
+    control: ResMut<'w, SimulationControl>,
+    compare: Res<'w, NCACompare>,
+    events: SimulationEvents<'w>,
+}
+
+/// The event writers to manage the presets and to inform the NCA control about
+/// settings changed in the UI.
+#[derive(SystemParam)]
+struct SettingsEvents<'w> {
+    preset: PresetEvents<'w>,
+    filter_changed: EventWriter<'w, FilterChanged>,
+    function_changed: EventWriter<'w, FunctionChanged>,
+    save_settings: EventWriter<'w, SaveSettings>,
+}
+
+/// The state of the generated shader, i.e. whether it compiled, the last working
+/// one, the watchdog reverting to it and the errors of the activation functions.
+#[derive(SystemParam)]
+struct ShaderStatus<'w> {
+    monitor: Res<'w, NCAPipelineMonitor>,
+    last_good: Res<'w, LastGoodShader>,
+    watchdog: ResMut<'w, ShaderWatchdog>,
+    errors: Res<'w, ActivationFnErrors>,
+}
+
+/// The presets and the randomizer used in the UI of the channels, together with the
+/// input channel whose filter is edited in each of them.
+#[derive(SystemParam)]
+struct ChannelTools<'w, 's> {
+    presets: Res<'w, NCAPresets>,
+    randomizer: ResMut<'w, FilterRandomizer>,
+    inputs: Local<'s, Vec<usize>>,
+}
+
 // ================================== Systems =================================== //
 
 /// A system to ...
 fn nca_settings_ui(
     mut contexts: EguiContexts,
     mut params: ResMut<NCASettings>,
-    presets: Res<NCAPresets>,
-    mut ev_writer_safe_filter: EventWriter<AddPresetFilter>,
-    mut ev_writer_safe_fn: EventWriter<AddPresetFn>,
-    mut ev_writer_filter_changed: EventWriter<FilterChanged>,
-    mut ev_writer_function_changed: EventWriter<FunctionChanged>,
-    mut ev_writer_reinitialize: EventWriter<Reinitialize>,
+    mut events: SettingsEvents,
+    simulation: SimulationControls,
+    shader: ShaderStatus,
+    tools: ChannelTools,
 ) {
+    let SimulationControls { mut control, compare, events: mut simulation_events } = simulation;
+    let ShaderStatus { monitor, last_good, mut watchdog, errors } = shader;
+    let ChannelTools { presets, mut randomizer, mut inputs } = tools;
+    inputs.resize(params.channel_count(), 0);
     egui::Window::new("NCA Settings").show(contexts.ctx_mut(), |ui| {
         ui.spacing_mut().interact_size = bevy_egui::egui::Vec2::new(50., 20.);
+        if let Some(error) = monitor.error() {
+            ui.colored_label(egui::Color32::RED, "The NCA shader failed to compile:");
+            egui::ScrollArea::vertical()
+                .id_source("Shader Error Scroll")
+                .max_height(120.)
+                .show(ui, |ui| {
+                    ui.label(egui::RichText::new(error).monospace().color(egui::Color32::RED));
+                });
+            ui.separator();
+        }
+        ui
+            .checkbox(&mut params.grayscale, "Grayscale")
+            .on_hover_text(
+                "Runs the red channel on its own and shows it in all colors. The green and \
+                blue channels are kept for switching back.",
+            )
+            .changed()
+            .then(|| events.function_changed.send(FunctionChanged));
+        let mut hidden = params.hidden.len();
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source("Hidden Channels Box")
+                .selected_text(hidden.to_string())
+                .show_ui(ui, |ui| {
+                    for count in 0..=MAX_HIDDEN_CHANNELS {
+                        ui.selectable_value(&mut hidden, count, count.to_string());
+                    }
+                });
+            ui.label("Hidden Channels").on_hover_text(format!(
+                "Channels taking part in the NCA without being displayed as colors. \
+                At most {MAX_HIDDEN_CHANNELS}, stored four to a tile below each other \
+                in one texture, so tall simulations fit fewer of them. Changing their \
+                number reinitializes the NCA.",
+            ));
+        });
+        if hidden != params.hidden.len() {
+            simulation_events.set_hidden_channels.send(SetHiddenChannels { count: hidden });
+        }
+        let mut copy = None;
         egui::Grid::new("Main Grid")
             .num_columns(1)
             .spacing([40.0, 4.0])
             .striped(true)
             .show(ui, |ui| {
-                for i in 0..3 {
-                    let (channel, label) = match i {
-                        0 => (&mut params.red, "Red Channel"),
-                        1 => (&mut params.green, "Green Channel"),
-                        2 => (&mut params.blue, "Blue Channel"),
-                        _ => unreachable!(),
+                let grayscale = params.grayscale;
+                let channel_count = params.channel_count();
+                for i in 0..channel_count {
+                    if grayscale && (i == 1 || i == 2) {
+                        continue;
+                    }
+                    let (channel, label, error) = match i {
+                        0 if grayscale => (&mut params.red, "Gray Channel".to_owned(), &errors.red),
+                        0 => (&mut params.red, "Red Channel".to_owned(), &errors.red),
+                        1 => (&mut params.green, "Green Channel".to_owned(), &errors.green),
+                        2 => (&mut params.blue, "Blue Channel".to_owned(), &errors.blue),
+                        3 => (&mut params.alpha, "Alpha Channel".to_owned(), &errors.alpha),
+                        _ => (
+                            &mut params.hidden[i - CHANNELS],
+                            format!("{} Channel", channel_name(i)),
+                            errors.hidden.get(i - CHANNELS).unwrap_or(&None),
+                        ),
+                    };
+                    // The gray channel is only applied to the red channel of the
+                    // neighborhood, besides the hidden channels.
+                    let input_options: Vec<usize> = (0..channel_count)
+                        .filter(|&input| !(grayscale && i == 0 && (1..CHANNELS).contains(&input)))
+                        .collect();
+                    let info = ChannelInfo {
+                        index: i,
+                        channel_count,
+                        label: &label,
+                        error: error.as_deref(),
+                        input_options: &input_options,
                     };
-                    channel_ui(
-                        ui,
-                        channel,
-                        label,
-                        &presets,
-                        &mut ev_writer_safe_filter,
-                        &mut ev_writer_safe_fn,
-                        &mut ev_writer_filter_changed,
-                        &mut ev_writer_function_changed,
-                    );
+                    let mut context = ChannelContext {
+                        presets: &presets,
+                        randomizer: &mut randomizer,
+                        events: &mut events,
+                    };
+                    let channel_copy = channel_ui(ui, channel, &mut inputs[i], &info, &mut context);
+                    copy = copy.or(channel_copy);
                     ui.end_row();
                 }
                 
             });
+        if let Some(copy) = copy {
+            apply_channel_copy(
+                &mut params,
+                copy,
+                &mut events.filter_changed,
+                &mut events.function_changed,
+            );
+        }
+        randomizer_ui(ui, &mut randomizer);
+        preset_pack_ui(ui, &mut events.preset);
         ui.separator();
-        if ui.button("Reinitialize").clicked() {
-            ev_writer_reinitialize.send(Reinitialize);
+        if boundary_ui(ui, &mut params.boundary) {
+            events.function_changed.send(FunctionChanged);
+        }
+        if neighborhood_ui(ui, &mut params) {
+            events.function_changed.send(FunctionChanged);
+        }
+        if all_output_mappings_ui(ui, &mut params) {
+            events.function_changed.send(FunctionChanged);
+        }
+        if monitor.status().state == NCAPipelineState::Stuck {
+            ui.colored_label(
+                egui::Color32::RED,
+                "The NCA shader failed to load. Check the activation functions.",
+            );
+            ui
+                .add_enabled(
+                    last_good.settings.is_some(),
+                    egui::Button::new("Revert To Last Working Shader"),
+                )
+                .clicked()
+                .then(|| simulation_events.revert_shader.send(RevertShader));
+        }
+        ui.horizontal(|ui| {
+            ui
+                .checkbox(&mut watchdog.enabled, "Auto Revert")
+                .on_hover_text("Reverts to the last working shader if a changed one doesn't load.");
+            ui
+                .add(egui::DragValue::new(&mut watchdog.timeout).range(1..=60).suffix(" s"))
+                .on_hover_text("The time a shader may take to load.");
+        });
+        ui.label(format!("Generation: {}", monitor.status().generation));
+        if init_mode_ui(ui, &mut params.init_mode) {
+            events.save_settings.send(SaveSettings);
+            simulation_events.reinitialize.send(Reinitialize);
         }
+        ui.horizontal(|ui| {
+            ui
+                .add(egui::DragValue::new(&mut params.seed))
+                .on_hover_text("The seed of the random initial state.")
+                .changed()
+                .then(|| events.save_settings.send(SaveSettings));
+            ui.label("Seed");
+            if ui
+                .button("New Seed")
+                .on_hover_text("Picks a random seed and reinitializes the NCA.")
+                .clicked()
+            {
+                params.seed = rand::random();
+                events.save_settings.send(SaveSettings);
+                simulation_events.reinitialize.send(Reinitialize);
+            }
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Reinitialize").clicked() {
+                simulation_events.reinitialize.send(Reinitialize);
+            }
+            ui
+                .checkbox(&mut control.paused, "Paused")
+                .on_hover_text("Toggle with the spacebar.");
+            ui
+                .add_enabled(control.paused, egui::Button::new("Step"))
+                .on_hover_text("Advances the paused NCA by a single step.")
+                .clicked()
+                .then(|| simulation_events.step_once.send(StepOnce));
+            ui.add(
+                egui::DragValue::new(&mut control.steps_per_frame)
+                    .range(1..=MAX_STEPS_PER_FRAME),
+            );
+            ui.label("Steps Per Frame");
+        });
+        ui.horizontal(|ui| {
+            if !compare.is_active() {
+                ui
+                    .button("Compare A/B")
+                    .on_hover_text(
+                        "Freezes the current rule and runs it next to the canvas from the \
+                        same state. Changes of the rule only apply to the canvas.",
+                    )
+                    .clicked()
+                    .then(|| simulation_events.start_comparison.send(StartComparison));
+                return;
+            }
+            ui
+                .button("Restart Comparison")
+                .on_hover_text("Copies the canvas into the comparison.")
+                .clicked()
+                .then(|| simulation_events.restart_comparison.send(RestartComparison));
+            ui
+                .button("Stop Comparison")
+                .clicked()
+                .then(|| simulation_events.stop_comparison.send(StopComparison));
+        });
     });
 }
 
@@ -129,50 +408,334 @@ fn on_update_function(
 
 // =================================== Utils ==================================== //
 
-fn channel_ui(
-    ui: &mut bevy_egui::egui::Ui,
-    channel: &mut NCAChannel,
-    label: &str,
-    presets: &Res<NCAPresets>,
-    ev_writer_safe_filter: &mut EventWriter<AddPresetFilter>,
-    ev_writer_safe_fn: &mut EventWriter<AddPresetFn>,
+/// The part of a channel that is copied to other channels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CopiedPart {
+    Filters,
+    ActivationFn,
+}
+
+/// A copy of a part of a channel requested in the UI.
+#[derive(Debug, Clone, Copy)]
+struct ChannelCopy {
+    part: CopiedPart,
+    from: usize,
+    /// The channel to copy to, or None to copy to all other channels.
+    to: Option<usize>,
+}
+
+/// Applies a copy requested in the UI to the settings and informs the NCA control
+/// about the changes.
+fn apply_channel_copy(
+    params: &mut NCASettings,
+    copy: ChannelCopy,
     ev_writer_filter_changed: &mut EventWriter<FilterChanged>,
     ev_writer_function_changed: &mut EventWriter<FunctionChanged>,
 ) {
+    let targets = (0..params.channel_count())
+        .filter(|&to| to != copy.from && copy.to.is_none_or(|target| target == to));
+    for to in targets {
+        match copy.part {
+            CopiedPart::Filters => params.copy_filters(copy.from, to),
+            CopiedPart::ActivationFn => params.copy_activation_fn(copy.from, to),
+        }
+    }
+    // The loop bounds of the shader depend on the kernel size, so copied filters
+    // may change the shader as well.
+    if copy.part == CopiedPart::Filters {
+        ev_writer_filter_changed.send(FilterChanged);
+    }
+    ev_writer_function_changed.send(FunctionChanged);
+}
+
+/// Shows the buttons to copy the filters or activation function of a channel to
+/// all other channels or to paste them from another channel. Returns the requested
+/// copy.
+fn channel_copy_ui(
+    ui: &mut bevy_egui::egui::Ui,
+    index: usize,
+    channel_count: usize,
+) -> Option<ChannelCopy> {
+    let mut copy = None;
+    ui.horizontal(|ui| {
+        if ui.button("Copy Filters To All").clicked() {
+            copy = Some(ChannelCopy { part: CopiedPart::Filters, from: index, to: None });
+        }
+        if ui.button("Copy Function To All").clicked() {
+            copy = Some(ChannelCopy { part: CopiedPart::ActivationFn, from: index, to: None });
+        }
+        ui.menu_button("Paste From", |ui| {
+            for from in (0..channel_count).filter(|&i| i != index) {
+                let name = channel_name(from);
+                if ui.button(format!("{name} Filters")).clicked() {
+                    copy = Some(ChannelCopy { part: CopiedPart::Filters, from, to: Some(index) });
+                    ui.close_menu();
+                }
+                if ui.button(format!("{name} Function")).clicked() {
+                    copy = Some(ChannelCopy {
+                        part: CopiedPart::ActivationFn,
+                        from,
+                        to: Some(index),
+                    });
+                    ui.close_menu();
+                }
+            }
+        });
+    });
+    copy
+}
+
+/// What the UI of a single channel shows besides the settings of the channel.
+struct ChannelInfo<'a> {
+    index: usize,
+    channel_count: usize,
+    label: &'a str,
+    /// The error of the activation function of the channel, if it was rejected.
+    error: Option<&'a str>,
+    /// The input channels the filters of the channel can be edited for.
+    input_options: &'a [usize],
+}
+
+/// The presets, the randomizer and the event writers shared by the UI of all
+/// channels.
+struct ChannelContext<'a, 'w> {
+    presets: &'a NCAPresets,
+    randomizer: &'a mut FilterRandomizer,
+    events: &'a mut SettingsEvents<'w>,
+}
+
+/// Shows the settings of a single channel. Returns a copy of parts of the channel
+/// requested in the UI, which needs access to the other channels.
+fn channel_ui(
+    ui: &mut bevy_egui::egui::Ui,
+    channel: &mut NCAChannel,
+    input: &mut usize,
+    info: &ChannelInfo,
+    context: &mut ChannelContext,
+) -> Option<ChannelCopy> {
+    let label = info.label;
     egui::CollapsingHeader::new(label).show(ui, |ui| {
         ui.heading(label);
-        fitler_ui(
-            ui,
-            &mut channel.filter,
-            label,
-            presets,
-            ev_writer_filter_changed,
-            ev_writer_safe_filter
-        );
-        activation_fn_ui(
-            ui,
-            &mut channel.activation_fn,
-            label,
-            presets,
-            ev_writer_function_changed,
-            ev_writer_safe_fn,
-        );
+        let copy = channel_copy_ui(ui, info.index, info.channel_count);
+        fitler_ui(ui, channel, input, info.input_options, label, context);
+        activation_fn_ui(ui, &mut channel.activation_fn, label, info.error, context);
+        let ev_writer_function_changed = &mut context.events.function_changed;
+        ui
+            .add(egui::Slider::new(&mut channel.levels, 0..=64).text("Output Levels"))
+            .on_hover_text("Quantizes the output of the channel. 0 keeps it continuous.")
+            .changed()
+            .then(|| ev_writer_function_changed.send(FunctionChanged));
+        let previous = channel.output_mapping;
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source(format!("{label} Output Mapping Box"))
+                .selected_text(output_mapping_name(channel.output_mapping))
+                .show_ui(ui, |ui| {
+                    for mapping in OUTPUT_MAPPINGS {
+                        let name = output_mapping_name(mapping);
+                        ui.selectable_value(&mut channel.output_mapping, mapping, name);
+                    }
+                });
+            ui.label("Output Mapping");
+        });
+        if channel.output_mapping != previous {
+            ev_writer_function_changed.send(FunctionChanged);
+        }
+        ui
+            .add(egui::Slider::new(&mut channel.update_divisor, 1..=16).text("Update Divisor"))
+            .on_hover_text("Recomputes the channel only every that many generations.")
+            .changed()
+            .then(|| ev_writer_function_changed.send(FunctionChanged));
+        copy
+    }).body_returned.flatten()
+}
+
+/// The output mappings selectable in the UI.
+const OUTPUT_MAPPINGS: [OutputMapping; 4] = [
+    OutputMapping::Clamp,
+    OutputMapping::Wrap,
+    OutputMapping::Fold,
+    OutputMapping::Unclamped,
+];
+
+/// Shows a combo box to set the output mapping of all channels at once. Returns
+/// true if the output mappings were changed.
+fn all_output_mappings_ui(ui: &mut bevy_egui::egui::Ui, params: &mut NCASettings) -> bool {
+    let first = params.red.output_mapping;
+    let shared = params
+        .all_channels()
+        .iter()
+        .all(|channel| channel.output_mapping == first)
+        .then_some(first);
+    let mut selected = shared;
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_id_source("Output Mapping Box")
+            .selected_text(selected.map_or("Mixed", output_mapping_name))
+            .show_ui(ui, |ui| {
+                for mapping in OUTPUT_MAPPINGS {
+                    ui.selectable_value(&mut selected, Some(mapping), output_mapping_name(mapping));
+                }
+            });
+        ui.label("Output Mapping")
+            .on_hover_text("Unclamped values only persist with the Rgba16Float texture format.");
     });
+    let Some(mapping) = selected.filter(|_| selected != shared) else {
+        return false;
+    };
+    for channel in params.all_channels_mut() {
+        channel.output_mapping = mapping;
+    }
+    true
+}
+
+/// Shows a combo box to select how neighbors outside of the simulation are treated.
+/// Returns true if the boundary mode was changed.
+fn boundary_ui(ui: &mut bevy_egui::egui::Ui, boundary: &mut BoundaryMode) -> bool {
+    let previous = *boundary;
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_id_source("Boundary Box")
+            .selected_text(boundary_name(*boundary))
+            .show_ui(ui, |ui| {
+                for mode in [BoundaryMode::Wrap, BoundaryMode::Clamp, BoundaryMode::Zero] {
+                    ui.selectable_value(boundary, mode, boundary_name(mode));
+                }
+            });
+        ui.label("Boundary");
+    });
+    *boundary != previous
+}
+
+/// Shows a combo box to select the neighborhood of the cells and, for custom
+/// neighborhoods, a grid of the kernel to pick its cells. Returns true if the
+/// neighborhood was changed.
+fn neighborhood_ui(ui: &mut bevy_egui::egui::Ui, params: &mut NCASettings) -> bool {
+    let previous = params.neighborhood;
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_id_source("Neighborhood Box")
+            .selected_text(neighborhood_name(params.neighborhood))
+            .show_ui(ui, |ui| {
+                for neighborhood in
+                    [Neighborhood::Moore, Neighborhood::VonNeumann, Neighborhood::Custom]
+                {
+                    let name = neighborhood_name(neighborhood);
+                    ui.selectable_value(&mut params.neighborhood, neighborhood, name);
+                }
+            });
+        ui.label("Neighborhood");
+    });
+    let mut changed = params.neighborhood != previous;
+    if params.neighborhood != Neighborhood::Custom {
+        return changed;
+    }
+
+    // The grid covers the largest kernel, smaller kernels only use its center.
+    let size = params
+        .all_channels()
+        .iter()
+        .map(|channel| channel.kernel_size)
+        .max()
+        .unwrap_or(1);
+    let radius = (size / 2) as i32;
+    egui::Grid::new("Neighborhood Grid")
+        .spacing([2.0, 2.0])
+        .show(ui, |ui| {
+            for dy in -radius..=radius {
+                for dx in -radius..=radius {
+                    let offset = [dx, dy];
+                    let mut picked = params.custom_neighborhood.contains(&offset);
+                    if ui.checkbox(&mut picked, "").changed() {
+                        if picked {
+                            params.custom_neighborhood.push(offset);
+                        } else {
+                            params.custom_neighborhood.retain(|&other| other != offset);
+                        }
+                        changed = true;
+                    }
+                }
+                ui.end_row();
+            }
+        });
+    changed
+}
+
+/// Shows a combo box to select the initial state of the NCA. Returns true if the
+/// initial state was changed.
+fn init_mode_ui(ui: &mut bevy_egui::egui::Ui, init_mode: &mut InitMode) -> bool {
+    let previous = *init_mode;
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_id_source("Init Mode Box")
+            .selected_text(init_mode_name(*init_mode))
+            .show_ui(ui, |ui| {
+                for mode in [
+                    InitMode::Noise,
+                    InitMode::Black,
+                    InitMode::White,
+                    InitMode::CenterCell,
+                    InitMode::SparseDots,
+                    InitMode::Gradient,
+                ] {
+                    ui.selectable_value(init_mode, mode, init_mode_name(mode));
+                }
+            });
+        ui.label("Initial State");
+    });
+    *init_mode != previous
+}
+
+/// Returns the name of an initial state shown in the UI.
+fn init_mode_name(init_mode: InitMode) -> &'static str {
+    match init_mode {
+        InitMode::Noise => "Noise",
+        InitMode::Black => "Black",
+        InitMode::White => "White",
+        InitMode::CenterCell => "Center Cell",
+        InitMode::SparseDots => "Sparse Dots",
+        InitMode::Gradient => "Gradient",
+    }
+}
+
+/// Returns the name of an output mapping shown in the UI.
+fn output_mapping_name(mapping: OutputMapping) -> &'static str {
+    match mapping {
+        OutputMapping::Clamp => "Clamp",
+        OutputMapping::Wrap => "Wrap",
+        OutputMapping::Fold => "Fold",
+        OutputMapping::Unclamped => "Unclamped",
+    }
+}
+
+/// Returns the name of a boundary mode shown in the UI.
+fn boundary_name(boundary: BoundaryMode) -> &'static str {
+    match boundary {
+        BoundaryMode::Wrap => "Wrap",
+        BoundaryMode::Clamp => "Clamp",
+        BoundaryMode::Zero => "Zero",
+    }
+}
+
+/// Returns the name of a neighborhood shown in the UI.
+fn neighborhood_name(neighborhood: Neighborhood) -> &'static str {
+    match neighborhood {
+        Neighborhood::Moore => "Moore",
+        Neighborhood::VonNeumann => "Von Neumann",
+        Neighborhood::Custom => "Custom",
+    }
 }
 
 fn activation_fn_ui(
     ui: &mut bevy_egui::egui::Ui,
     activation_fn: &mut String,
     label: &str,
-    presets: &Res<NCAPresets>,
-    ev_writer_function_changed: &mut EventWriter<FunctionChanged>,
-    ev_writer_safe_fn: &mut EventWriter<AddPresetFn>,
-
+    error: Option<&str>,
+    context: &mut ChannelContext,
 ) {
     ui
         .text_edit_multiline(activation_fn)
         .changed()
-        .then(|| ev_writer_function_changed.send(FunctionChanged));
+        .then(|| context.events.function_changed.send(FunctionChanged));
+    ui.weak("x is the convolution, t the time in seconds since initializing, e.g. sin(x + t).");
+    if let Some(error) = error {
+        ui.colored_label(egui::Color32::RED, egui::RichText::new(error).monospace());
+    }
 
     ui.horizontal(|ui| {
         if ui.button("Safe As Preset").clicked() {
@@ -181,7 +744,7 @@ fn activation_fn_ui(
                 .show()
                 .expect("Couldn't display dialog box.");
             if let Some(name) = name_option {
-                ev_writer_safe_fn.send(AddPresetFn {
+                context.events.preset.add_fn.send(AddPresetFn {
                     name_and_function: (name, activation_fn.clone())
                 });
             } else {
@@ -189,17 +752,16 @@ fn activation_fn_ui(
             }
             
         }
-        let mut selected: Option<String> = None;
-        egui::ComboBox::from_id_source(label.to_owned() + " Function Preset Box")
-            .selected_text("Load Preset")
-            .show_ui(ui, |ui| {
-                for name_and_fn in presets.activation_fn_presets() {
-                    ui.selectable_value(&mut selected, Some(name_and_fn.1.clone()), name_and_fn.0.clone());
-                }
-            });
+        let selected = preset_box(
+            ui,
+            label.to_owned() + " Function Preset Box",
+            context.presets.activation_fn_presets(),
+            PresetKind::ActivationFn,
+            &mut context.events.preset,
+        );
         if let Some(preset_fn) = selected {
-            *activation_fn = preset_fn;
-            ev_writer_function_changed.send(FunctionChanged);
+            activation_fn.clone_from(&context.presets.activation_fn_presets()[preset_fn].1);
+            context.events.function_changed.send(FunctionChanged);
         }
     });
 }
@@ -207,33 +769,111 @@ fn activation_fn_ui(
 /// System to ...
 fn fitler_ui(
     ui: &mut bevy_egui::egui::Ui,
-    filter: &mut Mat3,
+    channel: &mut NCAChannel,
+    input: &mut usize,
+    input_options: &[usize],
     label: &str,
-    presets: &Res<NCAPresets>,
-    ev_writer_filter_changed: &mut EventWriter<FilterChanged>,
-    ev_writer_safe_filter: &mut EventWriter<AddPresetFilter>,
+    context: &mut ChannelContext,
 ) {
-    let mut flag = false;
-    egui::Grid::new(label.to_owned() + " Grid")
-        .num_columns(3)
-        .spacing([40.0, 4.0])
-        .striped(true)
-        .show(ui, |ui| {
-            for j in 0..3 {
-                for k in 0..3 {
-                    
-                    ui
-                        .add(egui::DragValue::new(
-                            &mut filter.col_mut(j)[k],
-                        ).speed(0.002))
-                        .changed()
-                        .then(|| flag = true );
+    let mut kernel = channel.kernel_size;
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_id_source(label.to_owned() + " Kernel Size Box")
+            .selected_text(format!("{kernel}x{kernel}"))
+            .show_ui(ui, |ui| {
+                for size in (1..=MAX_KERNEL_SIZE).step_by(2) {
+                    ui.selectable_value(&mut kernel, size, format!("{size}x{size}"));
                 }
-                ui.end_row();
+            });
+        if ui.button("Randomize").clicked() {
+            context.randomizer.randomize(&mut channel.filters[*input], channel.symmetric);
+            context.events.filter_changed.send(FilterChanged);
+        }
+    });
+    if kernel != channel.kernel_size {
+        channel.resize_kernel(kernel);
+        // The loop bounds of the shader depend on the kernel size.
+        context.events.filter_changed.send(FilterChanged);
+        context.events.function_changed.send(FunctionChanged);
+    }
+
+    if !input_options.contains(input) {
+        *input = input_options[0];
+    }
+    if input_options.len() > 1 {
+        ui.horizontal_wrapped(|ui| {
+            for &i in input_options {
+                ui.selectable_value(input, i, channel_name(i));
             }
+            ui
+                .label("Input")
+                .on_hover_text("The channel of the neighborhood the filter is applied to.");
         });
+    }
+
+    let mut flag = false;
+    let size = channel.kernel_size as usize;
+    let symmetric = channel.symmetric;
+    let filter = &mut channel.filters[*input];
+    let len = filter.len();
+    ui.horizontal(|ui| {
+        egui::Grid::new(label.to_owned() + " Grid")
+            .num_columns(size)
+            .spacing([40.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                for i in 0..len {
+                    if ui
+                        .add(egui::DragValue::new(&mut filter[i]).speed(0.002))
+                        .changed()
+                    {
+                        // Mirror the edit to the opposite cell. The center is its own
+                        // opposite.
+                        if symmetric {
+                            filter[len - 1 - i] = filter[i];
+                        }
+                        flag = true;
+                    }
+                    if (i + 1) % size == 0 {
+                        ui.end_row();
+                    }
+                }
+            });
+        filter_heatmap(ui, filter, size);
+    });
+
+    ui.horizontal(|ui| {
+        let kernel = channel.kernel_size;
+        let filter = &mut channel.filters[*input];
+        let rotate_cw = ui.button("Rotate CW").clicked();
+        let rotate_ccw = ui.button("Rotate CCW").clicked();
+        let flip_h = ui.button("Flip H").clicked();
+        let flip_v = ui.button("Flip V").clicked();
+        let transformed = if rotate_cw || rotate_ccw {
+            Some(rotate_kernel(filter, kernel, rotate_cw))
+        } else if flip_h || flip_v {
+            Some(flip_kernel(filter, kernel, flip_h))
+        } else {
+            None
+        };
+        if let Some(transformed) = transformed {
+            *filter = transformed;
+            flag = true;
+        }
+    });
+
+    if ui
+        .checkbox(&mut channel.symmetric, "Symmetric")
+        .on_hover_text("Keeps the filters point-symmetric around their center.")
+        .changed()
+        && channel.symmetric
+    {
+        for filter in channel.filters.iter_mut() {
+            mirror_kernel(filter);
+        }
+        flag = true;
+    }
     if flag {
-        ev_writer_filter_changed.send(FilterChanged);
+        context.events.filter_changed.send(FilterChanged);
     }
 
     ui.horizontal(|ui| {
@@ -243,25 +883,168 @@ fn fitler_ui(
                 .show()
                 .expect("Couldn't display dialog box.");
             if let Some(name) = name_option {
-                ev_writer_safe_filter.send(AddPresetFilter {
-                    name_and_filter: (name, mat3_to_array(*filter))
+                context.events.preset.add_filter.send(AddPresetFilter {
+                    name_and_filter: (name, channel.filters[*input].clone())
                 });
             } else {
                 info!("Cancelled saving filter preset.");
             }
         }
-        let mut selected: Option<[f32; 9]> = None;
-        egui::ComboBox::from_id_source(label.to_owned() + " Filter Preset Box")
-            .selected_text("Load Preset")
-            .show_ui(ui, |ui| {
-                for name_and_filter in presets.filter_presets() {
-                    ui.selectable_value(&mut selected, Some(name_and_filter.1), name_and_filter.0.clone());
-                }
-            });
+        let selected = preset_box(
+            ui,
+            label.to_owned() + " Filter Preset Box",
+            context.presets.filter_presets(),
+            PresetKind::Filter,
+            &mut context.events.preset,
+        );
         
         if let Some(preset_filter) = selected {
-            *filter = array_to_mat3(preset_filter);
-            ev_writer_filter_changed.send(FilterChanged);
+            let preset_filter = context.presets.filter_presets()[preset_filter].1.clone();
+            let Some(preset_size) = kernel_size(preset_filter.len()) else {
+                warn!("Filter preset has {} weights, which is not an odd square.", preset_filter.len());
+                return;
+            };
+            if preset_size != channel.kernel_size {
+                channel.resize_kernel(preset_size);
+                context.events.function_changed.send(FunctionChanged);
+            }
+            channel.filters[*input] = preset_filter;
+            context.events.filter_changed.send(FilterChanged);
         }
     });
-}
\ No newline at end of file
+}
+
+/// Paints the filter as a heatmap, one square per weight. Positive weights are red,
+/// negative ones blue, normalized by the largest absolute weight.
+fn filter_heatmap(ui: &mut bevy_egui::egui::Ui, filter: &[f32], size: usize) {
+    let side = (HEATMAP_SIZE / size as f32).floor().max(1.);
+    let (rect, response) = ui.allocate_exact_size(
+        egui::Vec2::splat(side * size as f32),
+        egui::Sense::hover(),
+    );
+    let max = filter.iter().fold(0f32, |max, weight| max.max(weight.abs()));
+    let painter = ui.painter_at(rect);
+    for (i, &weight) in filter.iter().enumerate() {
+        let t = if max > 0. { weight / max } else { 0. };
+        let intensity = (t.abs() * 255.).round() as u8;
+        let color = if t >= 0. {
+            egui::Color32::from_rgb(intensity, 0, 0)
+        } else {
+            egui::Color32::from_rgb(0, 0, intensity)
+        };
+        let min = rect.min + egui::vec2((i % size) as f32, (i / size) as f32) * side;
+        painter.rect_filled(egui::Rect::from_min_size(min, egui::Vec2::splat(side)), 0., color);
+    }
+    response.on_hover_text(format!("Red is positive, blue negative, up to ±{max:.3}."));
+}
+
+/// Shows a combo box listing the presets with buttons to rename and delete each of
+/// them. Returns the index of the preset selected for loading.
+fn preset_box<T>(
+    ui: &mut bevy_egui::egui::Ui,
+    id: String,
+    presets: &[(String, T)],
+    kind: PresetKind,
+    preset_events: &mut PresetEvents,
+) -> Option<usize> {
+    let mut selected = None;
+    egui::ComboBox::from_id_source(id)
+        .selected_text("Load Preset")
+        .show_ui(ui, |ui| {
+            for (index, (name, _)) in presets.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    if ui.selectable_label(false, name).clicked() {
+                        selected = Some(index);
+                    }
+                    if ui.small_button("✏").on_hover_text("Rename").clicked() {
+                        let name_option = dialog::Input::new("Please enter new preset name")
+                            .title("Rename Preset")
+                            .default(name)
+                            .show()
+                            .expect("Couldn't display dialog box.");
+                        if let Some(name) = name_option {
+                            preset_events.rename.send(RenamePreset { kind, index, name });
+                        } else {
+                            info!("Cancelled renaming preset.");
+                        }
+                    }
+                    if ui.small_button("🗑").on_hover_text("Delete").clicked() {
+                        match kind {
+                            PresetKind::Filter => {
+                                preset_events.remove_filter.send(RemovePresetFilter { index });
+                            }
+                            PresetKind::ActivationFn => {
+                                preset_events.remove_fn.send(RemovePresetFn { index });
+                            }
+                        }
+                    }
+                });
+            }
+        });
+    selected
+}
+
+/// Shows buttons to export the presets to and import presets from a preset pack.
+fn preset_pack_ui(
+    ui: &mut bevy_egui::egui::Ui,
+    preset_events: &mut PresetEvents,
+) {
+    ui.horizontal(|ui| {
+        if ui.button("Export Presets").clicked() {
+            let path_option = dialog::FileSelection::new("Please choose a file")
+                .title("Export Presets")
+                .mode(dialog::FileSelectionMode::Save)
+                .show()
+                .expect("Couldn't display dialog box.");
+            if let Some(path) = path_option {
+                preset_events.export.send(ExportPresets { path: PathBuf::from(path) });
+            } else {
+                info!("Cancelled exporting presets.");
+            }
+        }
+        if ui.button("Import Presets").clicked() {
+            let path_option = dialog::FileSelection::new("Please choose a preset pack")
+                .title("Import Presets")
+                .mode(dialog::FileSelectionMode::Open)
+                .show()
+                .expect("Couldn't display dialog box.");
+            if let Some(path) = path_option {
+                preset_events.import.send(ImportPresets { path: PathBuf::from(path) });
+            } else {
+                info!("Cancelled importing presets.");
+            }
+        }
+    });
+}
+
+/// Shows the settings for randomizing filters.
+fn randomizer_ui(
+    ui: &mut bevy_egui::egui::Ui,
+    randomizer: &mut FilterRandomizer,
+) {
+    egui::CollapsingHeader::new("Randomize Filters").show(ui, |ui| {
+        egui::Grid::new("Randomizer Grid")
+            .num_columns(2)
+            .spacing([40.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.add(egui::DragValue::new(&mut randomizer.min).speed(0.01));
+                    ui.add(egui::DragValue::new(&mut randomizer.max).speed(0.01));
+                });
+                ui.label("Range");
+                ui.end_row();
+
+                ui.add(egui::Checkbox::without_text(&mut randomizer.symmetric));
+                ui.label("Symmetric");
+                ui.end_row();
+
+                let mut seed = randomizer.seed();
+                if ui.add(egui::DragValue::new(&mut seed)).changed() {
+                    randomizer.set_seed(seed);
+                }
+                ui.label("Seed");
+                ui.end_row();
+            });
+    });
+}
@@ -0,0 +1,272 @@
+//! UI for tuning the performance of the NCA
+
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::*,
+    render::render_resource::TextureFormat,
+    window::{PresentMode, PrimaryWindow},
+};
+use bevy_egui::{egui, EguiContexts};
+
+use super::super::{
+    nca_control::{ChangeTextureFormat, ResizeSimulation},
+    pipeline::tuner::{AutoTuneWorkgroupSize, TunerState, WorkgroupTuner},
+    NCASimSize,
+    NCATextureFormat,
+    NCAWorkgroupSize,
+};
+use super::ui_visible;
+
+// =================================== Plugin =================================== //
+
+/// A plugin providing a UI window to tune the performance of the NCA, i.e. the
+/// resolution and texture format of the simulation, the workgroup size and the
+/// present mode of the window.
+pub(super) struct UIPerformancePlugin;
+
+impl Plugin for UIPerformancePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Update, performance_ui.run_if(ui_visible));
+    }
+}
+
+// ================================= Constants ================================== //
+
+/// The resolutions that can be applied with a single click, with their names.
+const RESOLUTION_PRESETS: [(&str, UVec2); 4] = [
+    ("256²", UVec2::new(256, 256)),
+    ("512²", UVec2::new(512, 512)),
+    ("1024²", UVec2::new(1024, 1024)),
+    ("1920x1080", UVec2::new(1920, 1080)),
+];
+
+/// The present modes selectable in the UI.
+const PRESENT_MODES: [PresentMode; 3] = [
+    PresentMode::Fifo,
+    PresentMode::Mailbox,
+    PresentMode::Immediate,
+];
+
+/// The sides of the square workgroup sizes selectable in the UI.
+const WORKGROUP_SIZES: [u32; 3] = [4, 8, 16];
+
+/// The smallest and largest supported resolution.
+const MIN_RESOLUTION: UVec2 = UVec2::new(16, 16);
+const MAX_RESOLUTION: UVec2 = UVec2::new(7680, 4320);
+
+/// The size of the simulation together with the resolution edited in the UI, which
+/// is only applied on request.
+#[derive(SystemParam)]
+struct ResolutionEditor<'w, 's> {
+    sim_size: Res<'w, NCASimSize>,
+    resolution: Local<'s, Option<UVec2>>,
+    lock_aspect: Local<'s, bool>,
+}
+
+/// The event writers to tune the performance from the UI.
+#[derive(SystemParam)]
+struct PerformanceEvents<'w> {
+    auto_tune: EventWriter<'w, AutoTuneWorkgroupSize>,
+    resize: EventWriter<'w, ResizeSimulation>,
+    change_format: EventWriter<'w, ChangeTextureFormat>,
+}
+
+// ================================== Systems =================================== //
+
+/// A system that creates and manages the UI window for tuning the performance. Lets
+/// the user change the resolution and the texture format of the simulation, pick
+/// the workgroup size and benchmark a set of workgroup sizes to pick the fastest
+/// one. Editing one side of the resolution adjusts the other one if the aspect
+/// ratio is locked. Also lets the user switch the present mode of the window, e.g.
+/// to see how fast the NCA runs without vsync.
+fn performance_ui(
+    mut contexts: EguiContexts,
+    tuner: Res<WorkgroupTuner>,
+    mut workgroup_size: ResMut<NCAWorkgroupSize>,
+    texture_format: Res<NCATextureFormat>,
+    editor: ResolutionEditor,
+    mut events: PerformanceEvents,
+    mut window_query: Query<&mut Window, With<PrimaryWindow>>,
+) {
+    let ResolutionEditor { sim_size, mut resolution, mut lock_aspect } = editor;
+    let status = tuner.status();
+    if sim_size.is_changed() {
+        *resolution = None;
+    }
+    let resolution = resolution.get_or_insert(sim_size.size);
+    egui::Window::new("Performance").show(contexts.ctx_mut(), |ui| {
+        // The benchmark of the workgroup sizes runs on textures of the current size
+        // and format.
+        ui.add_enabled_ui(tuner.is_idle(), |ui| {
+            ui.horizontal(|ui| {
+                let previous = *resolution;
+                let width = ui.add(
+                    egui::DragValue::new(&mut resolution.x)
+                        .range(MIN_RESOLUTION.x..=MAX_RESOLUTION.x),
+                );
+                ui.label("x");
+                let height = ui.add(
+                    egui::DragValue::new(&mut resolution.y)
+                        .range(MIN_RESOLUTION.y..=MAX_RESOLUTION.y),
+                );
+                ui.label("Resolution");
+                if *lock_aspect && width.changed() {
+                    resolution.y = scale_side(previous.y, resolution.x, previous.x)
+                        .clamp(MIN_RESOLUTION.y, MAX_RESOLUTION.y);
+                } else if *lock_aspect && height.changed() {
+                    resolution.x = scale_side(previous.x, resolution.y, previous.y)
+                        .clamp(MIN_RESOLUTION.x, MAX_RESOLUTION.x);
+                }
+            });
+            ui
+                .checkbox(&mut lock_aspect, "Lock Aspect Ratio")
+                .on_hover_text("Keeps the ratio of width and height when editing one of them.");
+            ui.horizontal(|ui| {
+                for (name, size) in RESOLUTION_PRESETS {
+                    if ui.add_enabled(size != sim_size.size, egui::Button::new(name)).clicked() {
+                        *resolution = size;
+                        events.resize.send(ResizeSimulation { size });
+                    }
+                }
+            });
+            ui.horizontal(|ui| {
+                let changed = *resolution != sim_size.size;
+                ui
+                    .add_enabled(changed, egui::Button::new("Apply"))
+                    .on_hover_text("Recreates the textures and reinitializes the NCA.")
+                    .clicked()
+                    .then(|| events.resize.send(ResizeSimulation { size: *resolution }));
+                ui
+                    .add_enabled(changed, egui::Button::new("Cancel"))
+                    .clicked()
+                    .then(|| *resolution = sim_size.size);
+            });
+            ui.horizontal(|ui| {
+                let mut format = texture_format.format;
+                egui::ComboBox::from_id_source("Texture Format Box")
+                    .selected_text(texture_format_name(format))
+                    .show_ui(ui, |ui| {
+                        for supported in NCATextureFormat::SUPPORTED {
+                            let name = texture_format_name(supported);
+                            ui.selectable_value(&mut format, supported, name);
+                        }
+                    });
+                ui.label("Texture Format")
+                    .on_hover_text("Changing it recreates the textures and reinitializes the NCA.");
+                if format != texture_format.format {
+                    events.change_format.send(ChangeTextureFormat { format });
+                }
+            });
+        });
+        if let Ok(mut window) = window_query.get_single_mut() {
+            ui.horizontal(|ui| {
+                let mut present_mode = window.present_mode;
+                egui::ComboBox::from_id_source("Present Mode Box")
+                    .selected_text(present_mode_name(present_mode))
+                    .show_ui(ui, |ui| {
+                        for mode in PRESENT_MODES {
+                            ui.selectable_value(&mut present_mode, mode, present_mode_name(mode));
+                        }
+                    });
+                ui.label("Present Mode").on_hover_text(
+                    "Vsync caps the frame rate at the refresh rate of the display. Mailbox \
+                    renders as fast as possible without tearing, where supported. Immediate \
+                    doesn't wait at all and may show torn frames.",
+                );
+                if present_mode != window.present_mode {
+                    window.present_mode = present_mode;
+                }
+            });
+        }
+        ui.separator();
+
+        ui.horizontal(|ui| {
+            let mut size = workgroup_size.size;
+            ui.add_enabled_ui(tuner.is_idle(), |ui| {
+                egui::ComboBox::from_id_source("Workgroup Size Box")
+                    .selected_text(format!("{}x{}", size.x, size.y))
+                    .show_ui(ui, |ui| {
+                        for side in WORKGROUP_SIZES {
+                            let option = UVec2::splat(side);
+                            ui.selectable_value(&mut size, option, format!("{side}x{side}"));
+                        }
+                    });
+            });
+            ui.label("Workgroup Size")
+                .on_hover_text("Changing it rebuilds the NCA pipeline.");
+            if size != workgroup_size.size {
+                workgroup_size.size = size;
+            }
+        });
+
+        ui.horizontal(|ui| {
+            if ui.add_enabled(tuner.is_idle(), egui::Button::new("Auto-Tune")).clicked() {
+                events.auto_tune.send(AutoTuneWorkgroupSize);
+            }
+            ui.label(match status.state {
+                TunerState::Idle | TunerState::Finished => String::from("Idle"),
+                TunerState::Requested => String::from("Starting"),
+                TunerState::Running => format!(
+                    "Benchmarking {}/{}",
+                    status.results.len() + 1,
+                    status.candidates,
+                ),
+            });
+        });
+
+        if status.results.is_empty() {
+            return;
+        }
+        let fastest = status.fastest();
+        egui::Grid::new("Tuner Grid")
+            .num_columns(2)
+            .spacing([40.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                for result in &status.results {
+                    let size = format!(
+                        "{}x{}",
+                        result.workgroup_size.x,
+                        result.workgroup_size.y,
+                    );
+                    if Some(result.workgroup_size) == fastest {
+                        ui.strong(size);
+                    } else {
+                        ui.label(size);
+                    }
+                    ui.label(match result.time {
+                        Some(time) => format!("{:.2} ms", time.as_secs_f64() * 1000.),
+                        None => String::from("Failed"),
+                    });
+                    ui.end_row();
+                }
+            });
+    });
+}
+
+// =================================== Utils ==================================== //
+
+/// Scales a side of the resolution by the ratio of the new and old length of the
+/// other side, rounding to the nearest pixel.
+fn scale_side(side: u32, new_other: u32, old_other: u32) -> u32 {
+    (side as f32 * new_other as f32 / old_other.max(1) as f32).round() as u32
+}
+
+/// Returns the name of a present mode shown in the UI.
+fn present_mode_name(mode: PresentMode) -> &'static str {
+    match mode {
+        PresentMode::Fifo => "Vsync",
+        PresentMode::Mailbox => "Mailbox",
+        PresentMode::Immediate => "Immediate",
+        _ => "Automatic",
+    }
+}
+
+/// Returns the name of a texture format shown in the UI.
+fn texture_format_name(format: TextureFormat) -> &'static str {
+    match format {
+        TextureFormat::Rgba16Float => "16-bit Float",
+        _ => "8-bit",
+    }
+}
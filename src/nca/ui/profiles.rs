@@ -0,0 +1,86 @@
+//! UI for switching between settings profiles
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use dialog::DialogBox;
+
+use super::super::nca_control::profiles::{NCAProfiles, NewProfile, SaveProfileAs, SwitchProfile};
+use super::ui_visible;
+
+// =================================== Plugin =================================== //
+
+/// A plugin providing a UI window to switch between settings profiles and to create
+/// new ones.
+pub(super) struct UIProfilePlugin;
+
+impl Plugin for UIProfilePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Update, profile_ui.run_if(ui_visible));
+    }
+}
+
+// ================================== Systems =================================== //
+
+/// A system that creates and manages the UI window for profiles. Lets the user pick
+/// a profile and switch to it, i.e. load it into the current settings, create a new
+/// profile with the default settings and save the current settings as a profile.
+fn profile_ui(
+    mut contexts: EguiContexts,
+    profiles: Res<NCAProfiles>,
+    mut picked: Local<Option<String>>,
+    mut ev_writer_new_profile: EventWriter<NewProfile>,
+    mut ev_writer_save_profile: EventWriter<SaveProfileAs>,
+    mut ev_writer_switch_profile: EventWriter<SwitchProfile>,
+) {
+    egui::Window::new("Profiles").show(contexts.ctx_mut(), |ui| {
+        if profiles.is_changed() {
+            *picked = profiles.selected().cloned();
+        }
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source("Profile Box")
+                .selected_text(picked.as_deref().unwrap_or("None"))
+                .show_ui(ui, |ui| {
+                    for name in profiles.available() {
+                        ui.selectable_value(&mut *picked, Some(name.clone()), name.clone());
+                    }
+                });
+            if ui
+                .add_enabled(picked.is_some(), egui::Button::new("Switch"))
+                .on_hover_text("Loads the profile into the current settings.")
+                .clicked()
+            {
+                if let Some(name) = picked.clone() {
+                    ev_writer_switch_profile.send(SwitchProfile { name });
+                }
+            }
+        });
+
+        ui.horizontal(|ui| {
+            if ui.button("New Profile").clicked() {
+                if let Some(name) = ask_profile_name() {
+                    ev_writer_new_profile.send(NewProfile { name });
+                } else {
+                    info!("Cancelled creating profile.");
+                }
+            }
+            if ui.button("Save As").clicked() {
+                if let Some(name) = ask_profile_name() {
+                    ev_writer_save_profile.send(SaveProfileAs { name });
+                } else {
+                    info!("Cancelled saving profile.");
+                }
+            }
+        });
+    });
+}
+
+// =================================== Utils ==================================== //
+
+/// Asks the user for the name of a profile in a dialog box.
+fn ask_profile_name() -> Option<String> {
+    dialog::Input::new("Please enter profile name")
+        .title("Profile Name")
+        .show()
+        .expect("Couldn't display dialog box.")
+}
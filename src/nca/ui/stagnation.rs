@@ -0,0 +1,82 @@
+//! UI for detecting simulations that died out
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use super::super::nca_control::stagnation::StagnationDetector;
+use super::ui_visible;
+
+// =================================== Plugin =================================== //
+
+/// A plugin providing a UI window to control the stagnation detector, which reports
+/// when the NCA converged to a uniform or static canvas.
+pub(super) struct UIStagnationPlugin;
+
+impl Plugin for UIStagnationPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Update, stagnation_ui.run_if(ui_visible));
+    }
+}
+
+// ================================== Systems =================================== //
+
+/// A system that creates and manages the UI window for the stagnation detector. Lets
+/// the user enable it, set the interval between checks, the thresholds below which
+/// the canvas counts as uniform or static and the number of checks in a row until
+/// the NCA counts as stagnant, and choose to reinitialize stagnant NCAs with a new
+/// seed. Shows the statistics of the last check.
+fn stagnation_ui(
+    mut contexts: EguiContexts,
+    mut detector: ResMut<StagnationDetector>,
+) {
+    egui::Window::new("Stagnation").show(contexts.ctx_mut(), |ui| {
+        ui
+            .checkbox(&mut detector.enabled, "Detect Stagnation")
+            .on_hover_text("Reads the canvas back every few seconds to check whether it died out.");
+        ui.add_enabled_ui(detector.enabled, |ui| {
+            ui.add(
+                egui::Slider::new(&mut detector.interval, 1.0..=30.0)
+                    .text("Interval")
+                    .suffix(" s"),
+            );
+            ui
+                .add(
+                    egui::Slider::new(&mut detector.variance_threshold, 0.0..=0.01)
+                        .logarithmic(true)
+                        .text("Uniform Below"),
+                )
+                .on_hover_text("The variance of the colors below which the canvas is uniform.");
+            ui
+                .add(
+                    egui::Slider::new(&mut detector.change_threshold, 0.0..=0.01)
+                        .logarithmic(true)
+                        .text("Static Below"),
+                )
+                .on_hover_text("The mean change between two checks below which it is static.");
+            ui.add(egui::Slider::new(&mut detector.checks, 1..=10).text("Checks"));
+            ui.checkbox(&mut detector.auto_reinitialize, "Auto Reinitialize")
+                .on_hover_text("Reinitializes a stagnant NCA with a new seed.");
+
+            let Some(check) = detector.last() else {
+                ui.label("No check yet.");
+                return;
+            };
+            ui.monospace(format!(
+                "Mean {:.4}  Variance {:.6}\nChange {}",
+                check.mean,
+                check.variance,
+                check.change.map_or(String::from("-"), |change| format!("{change:.6}")),
+            ));
+            if detector.is_stagnant() {
+                ui.colored_label(egui::Color32::RED, "The NCA is stagnant.");
+            } else if detector.stagnant_checks() > 0 {
+                ui.label(format!(
+                    "Stagnant for {} of {} checks.",
+                    detector.stagnant_checks(),
+                    detector.checks,
+                ));
+            }
+        });
+    });
+}
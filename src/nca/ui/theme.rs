@@ -0,0 +1,175 @@
+//! UI theme
+
+use bevy::{
+    prelude::*,
+    window::{PrimaryWindow, WindowTheme, WindowThemeChanged},
+};
+use bevy_egui::{egui::{self, color_picker::color_edit_button_rgb}, EguiContexts};
+
+use super::super::nca_control::preferences::{
+    SavePreferences,
+    UIAccent,
+    UIPreferences,
+    UITheme,
+};
+use super::ui_visible;
+
+// =================================== Plugin =================================== //
+
+/// A plugin that applies the UI theme and accent color from the UI preferences to
+/// the EGUI context, clears the window with the background color if selected and
+/// provides a UI window to change them.
+pub(super) struct UIThemePlugin;
+
+impl Plugin for UIThemePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Update, (
+                apply_theme,
+                apply_background,
+                theme_ui.run_if(ui_visible),
+            ));
+    }
+}
+
+// ================================== Systems =================================== //
+
+/// A system that sets the visuals of the EGUI context whenever the selected theme or
+/// accent color, or the theme of the operating system if followed, changes.
+fn apply_theme(
+    mut contexts: EguiContexts,
+    mut ev_reader_theme_changed: EventReader<WindowThemeChanged>,
+    preferences: Res<UIPreferences>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    mut system_theme: Local<Option<WindowTheme>>,
+    mut applied_theme: Local<Option<(WindowTheme, UIAccent)>>,
+) {
+    if system_theme.is_none() {
+        *system_theme = window_query
+            .get_single()
+            .ok()
+            .and_then(|window| window.window_theme);
+    }
+    for event in ev_reader_theme_changed.read() {
+        *system_theme = Some(event.theme);
+    }
+
+    let theme = match preferences.theme {
+        UITheme::Dark => WindowTheme::Dark,
+        UITheme::Light => WindowTheme::Light,
+        UITheme::System => system_theme.unwrap_or(WindowTheme::Dark),
+    };
+
+    if *applied_theme != Some((theme, preferences.accent)) {
+        let mut visuals = match theme {
+            WindowTheme::Dark => egui::Visuals::dark(),
+            WindowTheme::Light => egui::Visuals::light(),
+        };
+        if let Some(accent) = accent_color(preferences.accent) {
+            visuals.selection.bg_fill = accent;
+            visuals.selection.stroke.color = visuals.strong_text_color();
+            visuals.hyperlink_color = accent;
+            visuals.widgets.hovered.bg_stroke.color = accent;
+        }
+        contexts.ctx_mut().set_visuals(visuals);
+        *applied_theme = Some((theme, preferences.accent));
+    }
+}
+
+/// A system that clears the window with the background color if the preferences
+/// say so, and with the clear color the app was started with otherwise.
+fn apply_background(
+    mut clear_color: ResMut<ClearColor>,
+    preferences: Res<UIPreferences>,
+    mut window_color: Local<Option<Color>>,
+) {
+    let window_color = *window_color.get_or_insert(clear_color.0);
+    let color = if preferences.background_fills_window {
+        let [r, g, b] = preferences.background_color;
+        Color::srgb(r, g, b)
+    } else {
+        window_color
+    };
+    if clear_color.0 != color {
+        clear_color.0 = color;
+    }
+}
+
+/// A system that creates and manages the UI window to select the theme and the
+/// background color.
+fn theme_ui(
+    mut contexts: EguiContexts,
+    mut preferences: ResMut<UIPreferences>,
+    mut ev_writer_save_preferences: EventWriter<SavePreferences>,
+) {
+    egui::Window::new("Appearance").show(contexts.ctx_mut(), |ui| {
+        let mut theme = preferences.theme;
+        egui::ComboBox::from_id_source("Theme Box")
+            .selected_text(match theme {
+                UITheme::Dark => "Dark",
+                UITheme::Light => "Light",
+                UITheme::System => "System",
+            })
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut theme, UITheme::Dark, "Dark");
+                ui.selectable_value(&mut theme, UITheme::Light, "Light");
+                ui.selectable_value(&mut theme, UITheme::System, "System");
+            });
+        let mut accent = preferences.accent;
+        egui::ComboBox::from_id_source("Accent Box")
+            .selected_text(accent_name(accent))
+            .show_ui(ui, |ui| {
+                for option in [
+                    UIAccent::Default,
+                    UIAccent::Blue,
+                    UIAccent::Green,
+                    UIAccent::Orange,
+                    UIAccent::Purple,
+                ] {
+                    ui.selectable_value(&mut accent, option, accent_name(option));
+                }
+            });
+        if theme != preferences.theme || accent != preferences.accent {
+            preferences.theme = theme;
+            preferences.accent = accent;
+            ev_writer_save_preferences.send(SavePreferences);
+        }
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            let background = color_edit_button_rgb(ui, &mut preferences.background_color)
+                .on_hover_text("The color blank canvases are filled with");
+            ui.label("Background");
+            let fills_window = ui
+                .checkbox(&mut preferences.background_fills_window, "Fill Window")
+                .on_hover_text("Clears the window around the canvas with the background color");
+            if background.changed() || fills_window.changed() {
+                ev_writer_save_preferences.send(SavePreferences);
+            }
+        });
+    });
+}
+
+// =================================== Utils ==================================== //
+
+/// Returns the name of an accent color shown in the UI.
+fn accent_name(accent: UIAccent) -> &'static str {
+    match accent {
+        UIAccent::Default => "Default Accent",
+        UIAccent::Blue => "Blue",
+        UIAccent::Green => "Green",
+        UIAccent::Orange => "Orange",
+        UIAccent::Purple => "Purple",
+    }
+}
+
+/// Returns the color of an accent, or None to keep the one of the theme.
+fn accent_color(accent: UIAccent) -> Option<egui::Color32> {
+    match accent {
+        UIAccent::Default => None,
+        UIAccent::Blue => Some(egui::Color32::from_rgb(40, 110, 200)),
+        UIAccent::Green => Some(egui::Color32::from_rgb(50, 150, 80)),
+        UIAccent::Orange => Some(egui::Color32::from_rgb(210, 120, 30)),
+        UIAccent::Purple => Some(egui::Color32::from_rgb(130, 80, 190)),
+    }
+}
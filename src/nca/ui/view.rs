@@ -0,0 +1,372 @@
+//! UI for controlling the view of the camera
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_egui::{egui, EguiContexts};
+
+use crate::camera::{
+    zoom_percent,
+    zoom_scale,
+    CameraZoomLimits,
+    FitCamera,
+    ResetCamera,
+};
+use super::{
+    super::{
+        nca_control::{
+            probe::NCAProbe,
+            settings::{channel_name, NCASettings, CHANNEL_NAMES},
+        },
+        DisplayBlend,
+        DisplayChannels,
+        DisplayGrid,
+        DisplayPalette,
+        DisplaySymmetry,
+        NCADisplaySize,
+        NCASimSize,
+        Palette,
+        PaletteSource,
+    },
+    ui_visible,
+};
+
+// =================================== Plugin =================================== //
+
+/// A plugin providing a UI window to control the view of the camera.
+pub(super) struct UIViewPlugin;
+
+impl Plugin for UIViewPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Update, view_ui.run_if(ui_visible));
+    }
+}
+
+/// The settings the NCA is displayed with, as changed from the UI.
+#[derive(SystemParam)]
+struct DisplaySettings<'w> {
+    display_size: ResMut<'w, NCADisplaySize>,
+    symmetry: ResMut<'w, DisplaySymmetry>,
+    palette: ResMut<'w, DisplayPalette>,
+    blend: ResMut<'w, DisplayBlend>,
+    channels: ResMut<'w, DisplayChannels>,
+    grid: ResMut<'w, DisplayGrid>,
+}
+
+/// The camera together with its zoom limits and the events moving it to a new view.
+#[derive(SystemParam)]
+struct CameraControls<'w, 's> {
+    reset: EventWriter<'w, ResetCamera>,
+    fit: EventWriter<'w, FitCamera>,
+    query: Query<'w, 's, &'static mut OrthographicProjection, With<Camera>>,
+    zoom_limits: ResMut<'w, CameraZoomLimits>,
+}
+
+// ================================== Systems =================================== //
+
+/// A system that creates and manages the UI window for the view. Lets the user
+/// reset the camera to its default view or fit the simulation into the window, set
+/// the zoom and its limits, overlay a grid of the cells, set the displayed size of the simulation,
+/// mirror the display, choose the displayed channels among all channels including
+/// the hidden ones, choose how they are combined and color it through a palette.
+/// Also shows the values of the cell under the cursor while probing.
+fn view_ui(
+    mut contexts: EguiContexts,
+    display: DisplaySettings,
+    camera: CameraControls,
+    mut probe: ResMut<NCAProbe>,
+    sim_size: Res<NCASimSize>,
+    settings: Res<NCASettings>,
+) {
+    let DisplaySettings {
+        mut display_size,
+        mut symmetry,
+        mut palette,
+        mut blend,
+        mut channels,
+        mut grid,
+    } = display;
+    let CameraControls {
+        reset: mut ev_writer_reset_camera,
+        fit: mut ev_writer_fit_camera,
+        query: mut camera_query,
+        mut zoom_limits,
+    } = camera;
+    egui::Window::new("View").show(contexts.ctx_mut(), |ui| {
+        ui.horizontal(|ui| {
+            if ui.button("Reset View").on_hover_text("R").clicked() {
+                ev_writer_reset_camera.send(ResetCamera);
+            }
+            if ui.button("Fit To Window").clicked() {
+                ev_writer_fit_camera.send(FitCamera);
+            }
+        });
+        if let Ok(mut ortho) = camera_query.get_single_mut() {
+            ui.horizontal(|ui| {
+                let current = zoom_percent(ortho.scale);
+                let mut percent = current;
+                let (min_scale, max_scale) = zoom_limits.range();
+                ui.add(egui::DragValue::new(&mut percent)
+                    .range(zoom_percent(max_scale)..=zoom_percent(min_scale))
+                    .max_decimals(0)
+                    .suffix("%"));
+                ui.label("Zoom");
+                for preset in [100., 200.] {
+                    if ui.button(format!("{preset}%")).clicked() {
+                        percent = preset;
+                    }
+                }
+                if percent != current {
+                    ortho.scale = zoom_scale(percent, &zoom_limits);
+                }
+            });
+        }
+        egui::CollapsingHeader::new("Zoom Limits").show(ui, |ui| {
+            // The limits are shown as zoom in percent, the smallest scale is the
+            // largest zoom.
+            let mut smallest = zoom_percent(zoom_limits.max_scale);
+            let mut largest = zoom_percent(zoom_limits.min_scale);
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(&mut smallest)
+                    .range(0.1..=largest)
+                    .max_decimals(1)
+                    .suffix("%"));
+                ui.label("to");
+                ui.add(egui::DragValue::new(&mut largest)
+                    .range(smallest..=100_000.)
+                    .max_decimals(0)
+                    .suffix("%"));
+                ui.label("Zoom Range");
+            });
+            if smallest != zoom_percent(zoom_limits.max_scale) {
+                zoom_limits.max_scale = 100. / smallest;
+            }
+            if largest != zoom_percent(zoom_limits.min_scale) {
+                zoom_limits.min_scale = 100. / largest;
+            }
+            let mut adapt = zoom_limits.adapt_to_simulation;
+            ui.checkbox(&mut adapt, "Adapt To Simulation").on_hover_text(
+                "Widens the range, so that the whole simulation fits into the window and \
+                single cells can be inspected.",
+            );
+            if adapt != zoom_limits.adapt_to_simulation {
+                zoom_limits.adapt_to_simulation = adapt;
+            }
+        });
+        let mut selected = *grid;
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut selected.enabled, "Pixel Grid")
+                .on_hover_text("Outlines the cells once they are large enough on screen.");
+            ui.add_enabled(selected.enabled, egui::DragValue::new(&mut selected.min_cell_size)
+                .range(2..=64)
+                .suffix(" px"));
+            ui.label("Minimum Cell Size");
+        });
+        if selected != *grid {
+            *grid = selected;
+        }
+        ui.separator();
+
+        let mut custom = display_size.size.is_some();
+        ui.checkbox(&mut custom, "Custom Display Size")
+            .on_hover_text("Otherwise every cell is displayed as one unit.");
+        if custom != display_size.size.is_some() {
+            display_size.size = custom.then(|| sim_size.size.as_vec2());
+        }
+        let mut size = display_size.resolve(sim_size.size);
+        ui.add_enabled_ui(custom, |ui| {
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(&mut size.x).range(1..=15360));
+                ui.label("x");
+                ui.add(egui::DragValue::new(&mut size.y).range(1..=8640));
+                ui.label("Display Size");
+            });
+            if ui.button("Match Aspect Ratio")
+                .on_hover_text("Keeps the width and scales the height to the simulation.")
+                .clicked()
+            {
+                size.y = size.x * sim_size.size.y as f32 / sim_size.size.x as f32;
+            }
+        });
+        if custom && display_size.size != Some(size) {
+            display_size.size = Some(size);
+        }
+        ui.separator();
+
+        let mut selected = *symmetry;
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source("Symmetry Box")
+                .selected_text(symmetry_name(selected))
+                .show_ui(ui, |ui| {
+                    let folds = match selected {
+                        DisplaySymmetry::Radial(folds) => folds,
+                        _ => 6,
+                    };
+                    for option in [
+                        DisplaySymmetry::None,
+                        DisplaySymmetry::MirrorX,
+                        DisplaySymmetry::MirrorY,
+                        DisplaySymmetry::Quad,
+                        DisplaySymmetry::Radial(folds),
+                    ] {
+                        ui.selectable_value(&mut selected, option, symmetry_name(option));
+                    }
+                });
+            ui.label("Symmetry")
+                .on_hover_text("Mirrors the display, the simulation itself is unchanged.");
+        });
+        if let DisplaySymmetry::Radial(folds) = &mut selected {
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(folds).range(2..=32));
+                ui.label("Folds");
+            });
+        }
+        if selected != *symmetry {
+            *symmetry = selected;
+        }
+        ui.separator();
+
+        let mut selected = *channels;
+        for (index, source) in selected.sources.iter_mut().enumerate() {
+            // Hidden channels that were removed fall back to the color channel.
+            if *source >= settings.channel_count() {
+                *source = index;
+            }
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_source(("Display Channel Box", index))
+                    .selected_text(channel_name(*source))
+                    .show_ui(ui, |ui| {
+                        for option in 0..settings.channel_count() {
+                            ui.selectable_value(source, option, channel_name(option));
+                        }
+                    });
+                ui.label(format!("Shown As {}", CHANNEL_NAMES[index]))
+                    .on_hover_text("Chooses the channel displayed in this color.");
+            });
+        }
+        if selected != *channels {
+            *channels = selected;
+        }
+
+        let mut selected = *blend;
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source("Blend Box")
+                .selected_text(blend_name(selected))
+                .show_ui(ui, |ui| {
+                    for option in [DisplayBlend::Rgb, DisplayBlend::Max, DisplayBlend::Additive] {
+                        ui.selectable_value(&mut selected, option, blend_name(option));
+                    }
+                });
+            ui.label("Channel Blend").on_hover_text(
+                "Combines the color channels for display. Max and Additive show a gray \
+                value, which a palette of the luminance maps through its colors.",
+            );
+        });
+        if selected != *blend {
+            *blend = selected;
+        }
+
+        let mut selected = *palette;
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source("Palette Box")
+                .selected_text(palette_name(selected.palette))
+                .show_ui(ui, |ui| {
+                    let gradient = match selected.palette {
+                        Some(gradient @ Palette::Gradient(..)) => gradient,
+                        _ => Palette::Gradient([0., 0., 0.], [1., 1., 1.]),
+                    };
+                    let options = std::iter::once(None)
+                        .chain(Palette::BUILT_IN.map(Some))
+                        .chain([Some(gradient)]);
+                    for option in options {
+                        ui.selectable_value(&mut selected.palette, option, palette_name(option));
+                    }
+                });
+            ui.label("Palette")
+                .on_hover_text("Maps a scalar of every cell through a color ramp for display.");
+        });
+        if let Some(Palette::Gradient(start, end)) = &mut selected.palette {
+            ui.horizontal(|ui| {
+                ui.color_edit_button_rgb(start);
+                ui.color_edit_button_rgb(end);
+                ui.label("Gradient");
+            });
+        }
+        ui.add_enabled_ui(selected.palette.is_some(), |ui| {
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_source("Palette Source Box")
+                    .selected_text(palette_source_name(selected.source))
+                    .show_ui(ui, |ui| {
+                        let options = std::iter::once(PaletteSource::Luminance)
+                            .chain((0..4).map(PaletteSource::Channel));
+                        for option in options {
+                            let name = palette_source_name(option);
+                            ui.selectable_value(&mut selected.source, option, name);
+                        }
+                    });
+                ui.label("Palette Source");
+            });
+        });
+        if selected != *palette {
+            *palette = selected;
+        }
+        ui.separator();
+
+        ui.checkbox(&mut probe.enabled, "Probe")
+            .on_hover_text("Shows the values of the cell under the cursor.");
+        if probe.enabled {
+            ui.monospace(match probe.value() {
+                Some((pos, [r, g, b, a])) => format!(
+                    "({}, {})\nR {r:.4}  G {g:.4}\nB {b:.4}  A {a:.4}",
+                    pos.x,
+                    pos.y,
+                ),
+                None => String::from("Cursor outside of the canvas"),
+            });
+        }
+    });
+}
+
+// =================================== Utils ==================================== //
+
+/// Returns the name of a display symmetry shown in the UI.
+fn symmetry_name(symmetry: DisplaySymmetry) -> &'static str {
+    match symmetry {
+        DisplaySymmetry::None => "None",
+        DisplaySymmetry::MirrorX => "Mirror X",
+        DisplaySymmetry::MirrorY => "Mirror Y",
+        DisplaySymmetry::Quad => "Quad",
+        DisplaySymmetry::Radial(_) => "Radial",
+    }
+}
+
+/// Returns the name of a display blend shown in the UI.
+fn blend_name(blend: DisplayBlend) -> &'static str {
+    match blend {
+        DisplayBlend::Rgb => "RGB",
+        DisplayBlend::Max => "Max",
+        DisplayBlend::Additive => "Additive",
+    }
+}
+
+/// Returns the name of a display palette shown in the UI.
+fn palette_name(palette: Option<Palette>) -> &'static str {
+    match palette {
+        None => "RGB",
+        Some(Palette::Grayscale) => "Grayscale",
+        Some(Palette::Viridis) => "Viridis",
+        Some(Palette::Magma) => "Magma",
+        Some(Palette::Inferno) => "Inferno",
+        Some(Palette::Gradient(..)) => "Gradient",
+    }
+}
+
+/// Returns the name of a palette source shown in the UI.
+fn palette_source_name(source: PaletteSource) -> &'static str {
+    match source {
+        PaletteSource::Luminance => "Luminance",
+        PaletteSource::Channel(0) => "Red",
+        PaletteSource::Channel(1) => "Green",
+        PaletteSource::Channel(2) => "Blue",
+        PaletteSource::Channel(_) => "Alpha",
+    }
+}
@@ -0,0 +1,62 @@
+//! UI for switching between workspaces
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use dialog::DialogBox;
+
+use super::super::nca_control::workspaces::{NCAWorkspaces, NewWorkspace, SwitchWorkspace};
+use super::ui_visible;
+
+// =================================== Plugin =================================== //
+
+/// A plugin providing a UI window to switch between workspaces and to create new
+/// ones.
+pub(super) struct UIWorkspacePlugin;
+
+impl Plugin for UIWorkspacePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Update, workspace_ui.run_if(ui_visible));
+    }
+}
+
+// ================================== Systems =================================== //
+
+/// A system that creates and manages the UI window for workspaces. Lets the user
+/// select the active workspace and create a new one from the current settings,
+/// presets, brush and camera view.
+fn workspace_ui(
+    mut contexts: EguiContexts,
+    workspaces: Res<NCAWorkspaces>,
+    mut ev_writer_new_workspace: EventWriter<NewWorkspace>,
+    mut ev_writer_switch_workspace: EventWriter<SwitchWorkspace>,
+) {
+    egui::Window::new("Workspaces").show(contexts.ctx_mut(), |ui| {
+        ui.horizontal(|ui| {
+            let mut selected = workspaces.active().cloned();
+            egui::ComboBox::from_id_source("Workspace Box")
+                .selected_text(workspaces.active().map_or("Default", |name| name.as_str()))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut selected, None, "Default");
+                    for name in workspaces.available() {
+                        ui.selectable_value(&mut selected, Some(name.clone()), name.clone());
+                    }
+                });
+            if selected.as_ref() != workspaces.active() {
+                ev_writer_switch_workspace.send(SwitchWorkspace { name: selected });
+            }
+
+            if ui.button("New Workspace").clicked() {
+                let name_option = dialog::Input::new("Please enter workspace name")
+                    .title("Workspace Name")
+                    .show()
+                    .expect("Couldn't display dialog box.");
+                if let Some(name) = name_option {
+                    ev_writer_new_workspace.send(NewWorkspace { name });
+                } else {
+                    info!("Cancelled creating workspace.");
+                }
+            }
+        });
+    });
+}
@@ -1,27 +1,203 @@
-//! Matrix utils
+//! Filter and texture data utils
 
-use bevy::math::{Mat3, Vec3};
+use bevy::render::render_resource::TextureFormat;
+use half::f16;
 
-pub fn array_to_mat3(array: [f32; 9]) -> Mat3 {
-    Mat3 {
-        x_axis: Vec3::new(array[0], array[1], array[2]),
-        y_axis: Vec3::new(array[3], array[4], array[5]),
-        z_axis: Vec3::new(array[6], array[7], array[8]),
+/// Resizes a square filter kernel of the given size to a new size. The weights
+/// stay aligned at the center, weights outside the old kernel are set to zero.
+pub fn resize_kernel(filter: &[f32], size: u32, new_size: u32) -> Vec<f32> {
+    let (size, new_size) = (size as i32, new_size as i32);
+    let offset = (new_size - size) / 2;
+    let mut resized = vec![0.; (new_size * new_size) as usize];
+    for row in 0..new_size {
+        for col in 0..new_size {
+            let (old_row, old_col) = (row - offset, col - offset);
+            if (0..size).contains(&old_row) && (0..size).contains(&old_col) {
+                resized[(row * new_size + col) as usize] = filter
+                    .get((old_row * size + old_col) as usize)
+                    .copied()
+                    .unwrap_or(0.);
+            }
+        }
     }
+    resized
 }
 
-pub fn mat3_to_array(mat: Mat3) -> [f32; 9] {
-    [
-        mat.x_axis[0], mat.x_axis[1], mat.x_axis[2],
-        mat.y_axis[0], mat.y_axis[1], mat.y_axis[2],
-        mat.z_axis[0], mat.z_axis[1], mat.z_axis[2],
-    ]
+/// Rotates a square filter kernel of the given size by 90 degrees, clockwise as
+/// its rows are laid out from top to bottom.
+pub fn rotate_kernel(filter: &[f32], size: u32, clockwise: bool) -> Vec<f32> {
+    let size = size as usize;
+    let mut rotated = vec![0.; size * size];
+    for row in 0..size {
+        for col in 0..size {
+            let (old_row, old_col) = if clockwise {
+                (size - 1 - col, row)
+            } else {
+                (col, size - 1 - row)
+            };
+            rotated[row * size + col] = filter
+                .get(old_row * size + old_col)
+                .copied()
+                .unwrap_or(0.);
+        }
+    }
+    rotated
+}
+
+/// Mirrors a square filter kernel of the given size, horizontally by reversing
+/// its rows or vertically by reversing their order.
+pub fn flip_kernel(filter: &[f32], size: u32, horizontal: bool) -> Vec<f32> {
+    let size = size as usize;
+    let mut flipped = vec![0.; size * size];
+    for row in 0..size {
+        for col in 0..size {
+            let (old_row, old_col) = if horizontal {
+                (row, size - 1 - col)
+            } else {
+                (size - 1 - row, col)
+            };
+            flipped[row * size + col] = filter
+                .get(old_row * size + old_col)
+                .copied()
+                .unwrap_or(0.);
+        }
+    }
+    flipped
+}
+
+/// Makes a square filter kernel point-symmetric around its center by copying the
+/// weights of its first half to the opposite cells.
+pub fn mirror_kernel(filter: &mut [f32]) {
+    let len = filter.len();
+    for i in 0..len / 2 {
+        filter[len - 1 - i] = filter[i];
+    }
+}
+
+/// Returns the size of a square filter kernel with the given number of weights, or
+/// None if the weights don't form a square with an odd side length.
+pub fn kernel_size(len: usize) -> Option<u32> {
+    let size = (len as f64).sqrt().round() as usize;
+    (size * size == len && size % 2 == 1).then_some(size as u32)
+}
+
+/// Encodes an RGBA color as the bytes of a single pixel of the given texture
+/// format. Formats other than Rgba16Float are encoded as RGBA8.
+pub fn encode_pixel(color: [f32; 4], format: TextureFormat) -> Vec<u8> {
+    match format {
+        TextureFormat::Rgba16Float => color
+            .iter()
+            .flat_map(|&value| f16::from_f32(value).to_le_bytes())
+            .collect(),
+        _ => color.iter().map(|&value| unorm_to_u8(value)).collect(),
+    }
+}
+
+/// Decodes the first pixel of texture data of the given format into its RGBA
+/// values. Returns None for formats the NCA doesn't support or too little data.
+pub fn decode_pixel(data: &[u8], format: TextureFormat) -> Option<[f32; 4]> {
+    match format {
+        TextureFormat::Rgba8Unorm => {
+            let pixel = data.get(..4)?;
+            Some([0, 1, 2, 3].map(|i| pixel[i] as f32 / 255.))
+        }
+        TextureFormat::Rgba16Float => {
+            let pixel = data.get(..8)?;
+            Some([0, 1, 2, 3].map(|i| {
+                f16::from_le_bytes([pixel[2 * i], pixel[2 * i + 1]]).to_f32()
+            }))
+        }
+        _ => None,
+    }
+}
+
+/// Converts texture data of the given format to RGBA8, e.g. for saving it as an
+/// image. Returns None for formats the NCA doesn't support.
+pub fn to_rgba8(data: &[u8], format: TextureFormat) -> Option<Vec<u8>> {
+    match format {
+        TextureFormat::Rgba8Unorm => Some(data.to_vec()),
+        TextureFormat::Rgba16Float => Some(
+            data
+                .chunks_exact(2)
+                .map(|bytes| unorm_to_u8(f16::from_le_bytes([bytes[0], bytes[1]]).to_f32()))
+                .collect(),
+        ),
+        _ => None,
+    }
 }
 
-pub fn mat3_to_buffer_array(mat: Mat3) -> [f32; 12] {
-    [
-        mat.x_axis[2], mat.y_axis[2], mat.z_axis[2], 0.,
-        mat.x_axis[1], mat.y_axis[1], mat.z_axis[1], 0.,
-        mat.x_axis[0], mat.y_axis[0], mat.z_axis[0], 0.,
-    ]
-}
\ No newline at end of file
+/// Converts a value in [0, 1] to an 8-bit channel, clamping values outside.
+fn unorm_to_u8(value: f32) -> u8 {
+    (value.clamp(0., 1.) * 255.).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a kernel of the given size with distinct weights.
+    fn numbered_kernel(size: u32) -> Vec<f32> {
+        (1..=size * size).map(|weight| weight as f32).collect()
+    }
+
+    #[test]
+    fn rotate_kernel_returns_to_original() {
+        for size in [3, 5] {
+            let kernel = numbered_kernel(size);
+            let mut rotated = kernel.clone();
+            for _ in 0..4 {
+                rotated = rotate_kernel(&rotated, size, true);
+            }
+            assert_eq!(rotated, kernel);
+
+            let rotated = rotate_kernel(&kernel, size, true);
+            assert_ne!(rotated, kernel);
+            assert_eq!(rotate_kernel(&rotated, size, false), kernel);
+        }
+    }
+
+    #[test]
+    fn flip_kernel_returns_to_original() {
+        for size in [3, 5] {
+            let kernel = numbered_kernel(size);
+            for horizontal in [true, false] {
+                let flipped = flip_kernel(&kernel, size, horizontal);
+                assert_ne!(flipped, kernel);
+                assert_eq!(flip_kernel(&flipped, size, horizontal), kernel);
+            }
+        }
+    }
+
+    #[test]
+    fn pixel_round_trip() {
+        let color = [0.25, -1.5, 1000., 0.1];
+        let format = TextureFormat::Rgba16Float;
+        let decoded = decode_pixel(&encode_pixel(color, format), format);
+        assert_eq!(decoded, Some(color.map(|value| f16::from_f32(value).to_f32())));
+
+        let format = TextureFormat::Rgba8Unorm;
+        let decoded = decode_pixel(&encode_pixel(color, format), format);
+        assert_eq!(decoded, Some([64. / 255., 0., 1., 26. / 255.]));
+    }
+
+    #[test]
+    fn mirror_kernel_is_point_symmetric() {
+        let mut kernel = numbered_kernel(3);
+        mirror_kernel(&mut kernel);
+        assert_eq!(kernel, vec![1., 2., 3., 4., 5., 4., 3., 2., 1.]);
+        assert_eq!(rotate_kernel(&rotate_kernel(&kernel, 3, true), 3, true), kernel);
+    }
+
+    #[test]
+    fn rotate_kernel_clockwise() {
+        let kernel = numbered_kernel(3);
+        assert_eq!(
+            rotate_kernel(&kernel, 3, true),
+            vec![7., 4., 1., 8., 5., 2., 9., 6., 3.],
+        );
+        assert_eq!(
+            flip_kernel(&kernel, 3, true),
+            vec![3., 2., 1., 6., 5., 4., 9., 8., 7.],
+        );
+    }
+}